@@ -1,28 +1,494 @@
+use crate::config::{ApiConfig, Config, GenerationConfig, HarmCategory, Threshold, VertexConfig};
+use crate::{sse, vertex};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{Bytes, BytesMut};
 use color_eyre::Result;
 use futures_util::StreamExt;
 use reqwest::Client;
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
 use std::env;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 use tokio::sync::mpsc::UnboundedSender;
 
+pub const MODEL: &str = "gemini-3-flash-preview";
+pub const EMBEDDING_MODEL: &str = "gemini-embedding-001";
+/// Model of `/imagine` unless `[images]` names another
+pub const IMAGE_MODEL: &str = "gemini-2.5-flash-image";
+/// Size of embedding vectors, well below the model's 3072 to keep local indexes compact
+const EMBEDDING_DIMENSIONS: usize = 768;
+/// Texts per embedding request; the Gemini API takes at most 100
+pub const EMBEDDING_BATCH: usize = 100;
+/// Prefixes estimated below this many tokens are sent inline: smaller caches are rejected
+const MIN_CACHE_TOKENS: usize = 1024;
+/// A context cache this close to expiring is replaced rather than used
+const CACHE_MARGIN: Duration = Duration::from_secs(60);
+
+/// Key from the keyring, config file or setup screen, used when `GEMINI_API_KEY` is unset
+static STORED_KEY: RwLock<Option<String>> = RwLock::new(None);
+/// Key of the active `[profile.<name>]`
+static PROFILE_KEY: RwLock<Option<String>> = RwLock::new(None);
+
+/// Where model requests go and the client that sends them
+#[derive(Clone, Default)]
+struct Endpoint {
+    client: Client,
+    api: ApiConfig,
+    /// Set when `[vertex]` is configured
+    vertex: Option<VertexConfig>,
+    /// Lifetime of context caches, when `context.cache` is on
+    cache_ttl: Option<Duration>,
+}
+
+static ENDPOINT: RwLock<Option<Endpoint>> = RwLock::new(None);
+
+struct CachedPrefix {
+    /// Resource name, or `None` when creating the cache failed and the prefix goes inline
+    name: Option<String>,
+    expires: Instant,
+}
+
+/// Requests with more attachment data than this upload it through the Files API instead of
+/// sending it inline, which keeps them under the 20 MB request limit once base64 encoded
+const INLINE_LIMIT: usize = 14_000_000;
+/// Uploaded files are deleted after 48 hours; they are reused for a little less
+const UPLOAD_LIFETIME: Duration = Duration::from_secs(47 * 60 * 60);
+/// How long to wait for an uploaded recording to be processed, in two second polls
+const UPLOAD_POLLS: usize = 90;
+
+struct Uploaded {
+    uri: String,
+    expires: Instant,
+}
+
+/// Files API uploads by a hash of their contents, so a long recording is sent once for
+/// all the questions about it
+static UPLOADS: Mutex<BTreeMap<String, Uploaded>> = Mutex::const_new(BTreeMap::new());
+
+/// Model requests sent in the last minute with their estimated prompt tokens, for the
+/// `[api]` rate limits. Held while waiting, so queued requests go out in order.
+static SENT: Mutex<VecDeque<(Instant, usize)>> = Mutex::const_new(VecDeque::new());
+
+/// Context caches by a hash of model, prefix and tools. Held across a creation so
+/// concurrent requests with the same prefix share one cache.
+static CACHES: Mutex<BTreeMap<String, CachedPrefix>> = Mutex::const_new(BTreeMap::new());
+
+/// Applies `[api]` and `[vertex]` from the config. The client honors `HTTPS_PROXY` and
+/// `NO_PROXY` unless `api.proxy` is set.
+pub fn configure(config: &Config) -> Result<()> {
+    let mut client = Client::builder();
+    if let Some(proxy) = &config.api.proxy {
+        client =
+            client
+                .proxy(reqwest::Proxy::all(proxy).map_err(|e| {
+                    color_eyre::eyre::eyre!("Invalid api.proxy `{}`: {}", proxy, e)
+                })?);
+    }
+    *ENDPOINT.write().unwrap_or_else(|e| e.into_inner()) = Some(Endpoint {
+        client: client.build()?,
+        api: config.api.clone(),
+        vertex: config.vertex.enabled().then(|| config.vertex.clone()),
+        cache_ttl: config.context.cache().then(|| config.context.cache_ttl()),
+    });
+    Ok(())
+}
+
+fn endpoint() -> Endpoint {
+    ENDPOINT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Gemini API URL of `model`; methods are appended as `:method`
+fn model_url(api: &ApiConfig, model: &str) -> String {
+    format!("{}/{}/models/{}", api.base_url(), api.version(), model)
+}
+
+/// Whether real requests can be made; without credentials responses are mocked
+pub fn has_credentials() -> bool {
+    #[cfg(feature = "mock")]
+    if crate::mock::replaying() {
+        return true;
+    }
+    endpoint().vertex.is_some() || api_key().is_some()
+}
+
+pub fn set_api_key(key: Option<String>) {
+    *STORED_KEY.write().unwrap_or_else(|e| e.into_inner()) = key;
+}
+
+/// Sets the key of the active profile, which wins over `GEMINI_API_KEY`
+pub fn set_profile_key(key: Option<String>) {
+    *PROFILE_KEY.write().unwrap_or_else(|e| e.into_inner()) = key;
+}
+
+/// The key set with [`set_profile_key`], else `GEMINI_API_KEY`, else the key set with
+/// [`set_api_key`]
+pub fn api_key() -> Option<String> {
+    PROFILE_KEY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .or_else(|| {
+            env::var("GEMINI_API_KEY")
+                .ok()
+                .filter(|k| !k.trim().is_empty())
+        })
+        .or_else(|| STORED_KEY.read().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Checks that `key` is accepted by listing models with it
+pub async fn check_api_key(key: &str) -> Result<()> {
+    let endpoint = endpoint();
+    let resp = endpoint
+        .client
+        .get(format!(
+            "{}/{}/models?pageSize=1",
+            endpoint.api.base_url(),
+            endpoint.api.version()
+        ))
+        .header("x-goog-api-key", key)
+        .send()
+        .await?;
+    if resp.status().is_success() {
+        return Ok(());
+    }
+    let status = resp.status();
+    tracing::warn!(%status, "API key check failed");
+    let body: Value = resp.json().await.unwrap_or_default();
+    let message = body["error"]["message"]
+        .as_str()
+        .unwrap_or("no details given");
+    Err(color_eyre::eyre::eyre!("{}: {}", status, message))
+}
+
+/// A service that answers model requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// The Gemini API, with an API key
+    Gemini,
+    /// Vertex AI, when `[vertex]` is configured
+    Vertex,
+}
+
+impl Provider {
+    pub const ALL: [Self; 2] = [Self::Gemini, Self::Vertex];
+
+    /// As typed in `/provider` and stored with sessions
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gemini => "gemini",
+            Self::Vertex => "vertex",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Where responses come from with `choice` (the default when `None`), for display
+pub fn provider(choice: Option<Provider>) -> &'static str {
+    #[cfg(feature = "mock")]
+    if crate::mock::replaying() {
+        return "Replay";
+    }
+    let vertex = endpoint().vertex.is_some();
+    match choice {
+        Some(Provider::Vertex) if vertex => "Vertex AI",
+        Some(Provider::Vertex) => "Vertex AI (not configured)",
+        None if vertex => "Vertex AI",
+        _ if api_key().is_some() => "Gemini API",
+        _ => "Mock",
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Usage {
     pub prompt_tokens: i32,
+    /// The part of `prompt_tokens` served from a context cache
+    pub cached_tokens: i32,
     pub response_tokens: i32,
     pub total_tokens: i32,
 }
 
+/// A `functionCall` part emitted by the model
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub id: Option<String>,
+    pub name: String,
+    /// JSON-encoded arguments
+    pub args: String,
+    /// Opaque signature Gemini 3 attaches to calls; it must be echoed back with the call
+    pub thought_signature: Option<String>,
+}
+
+/// What the safety filters made of a response: why it was stopped, if it was, and the
+/// categories rated at medium probability or above
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SafetyReport {
+    /// `finishReason` of a stopped response, or `blockReason` of a rejected prompt
+    pub blocked: Option<String>,
+    /// Category and probability as the API names them, e.g.
+    /// `("HARM_CATEGORY_DANGEROUS_CONTENT", "MEDIUM")`
+    pub ratings: Vec<(String, String)>,
+}
+
+/// The kind of failure behind an [`AiUpdate::Error`], deciding what the user is offered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The API key or credentials were refused
+    Auth,
+    /// Over quota or rate limited
+    Quota,
+    /// The request didn't reach the API, or the service was unavailable
+    Network,
+    /// The prompt was refused by the safety filters
+    Safety,
+    /// The API answered with something that couldn't be read
+    Parse,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            ErrorKind::Auth => "Authentication failed",
+            ErrorKind::Quota => "Quota exceeded",
+            ErrorKind::Network => "Network error",
+            ErrorKind::Safety => "Blocked by the safety filters",
+            ErrorKind::Parse => "Unreadable response",
+            ErrorKind::Other => "Request failed",
+        }
+    }
+}
+
+/// An [`AiUpdate::Error`] taken apart
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetails {
+    pub kind: ErrorKind,
+    /// HTTP status of a failed request
+    pub status: Option<u16>,
+    /// Status name from the API's error body, e.g. `RESOURCE_EXHAUSTED`
+    pub code: Option<String>,
+    /// The salient message: the API's own when its error body has one
+    pub message: String,
+}
+
+/// Classifies an error as sent in [`AiUpdate::Error`], e.g.
+/// `Error: API Error 429 Too Many Requests: {"error": {...}}`
+pub fn classify_error(text: &str) -> ErrorDetails {
+    let text = text.trim();
+    let text = text.strip_prefix("Error: ").unwrap_or(text);
+    let (status, body) = match text.strip_prefix("API Error ") {
+        Some(rest) => {
+            let status = rest
+                .split_whitespace()
+                .next()
+                .and_then(|code| code.parse().ok());
+            (status, rest.split_once(": ").map_or(rest, |(_, body)| body))
+        }
+        None => (None, text),
+    };
+    let error = serde_json::from_str::<Value>(body)
+        .ok()
+        .map(|json| json["error"].clone());
+    let code = error
+        .as_ref()
+        .and_then(|e| e["status"].as_str())
+        .map(str::to_string);
+    let message = error
+        .as_ref()
+        .and_then(|e| e["message"].as_str())
+        .unwrap_or(body)
+        .trim()
+        .to_string();
+
+    let lower = message.to_lowercase();
+    let kind = match (status, code.as_deref()) {
+        (Some(401 | 403), _) | (_, Some("UNAUTHENTICATED" | "PERMISSION_DENIED")) => {
+            ErrorKind::Auth
+        }
+        _ if lower.contains("api key") => ErrorKind::Auth,
+        (Some(429), _) | (_, Some("RESOURCE_EXHAUSTED")) => ErrorKind::Quota,
+        _ if lower.contains("quota") => ErrorKind::Quota,
+        _ if lower.contains("safety") || lower.contains("blocked") => ErrorKind::Safety,
+        (Some(500..=599), _) | (_, Some("UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED")) => {
+            ErrorKind::Network
+        }
+        (None, None)
+            if [
+                "error sending request",
+                "connect",
+                "timed out",
+                "dns",
+                "network",
+            ]
+            .iter()
+            .any(|s| lower.contains(s)) =>
+        {
+            ErrorKind::Network
+        }
+        _ if [
+            "decoding",
+            "eof while parsing",
+            "expected value",
+            "invalid json",
+        ]
+        .iter()
+        .any(|s| lower.contains(s)) =>
+        {
+            ErrorKind::Parse
+        }
+        _ => ErrorKind::Other,
+    };
+    ErrorDetails {
+        kind,
+        status,
+        code,
+        message,
+    }
+}
+
+/// A tool call from the previous model turn together with its output
+#[derive(Debug, Clone)]
+pub struct ToolOutcome {
+    pub call: ToolCall,
+    pub result: String,
+}
+
 pub enum AiUpdate {
+    /// The model that is answering, sent before its first chunk
+    Model(String),
+    /// The `[api]` rate limits hold the request back this long
+    RateLimited(Duration),
     Finished,
     Error(String),
+    /// The connection broke before the model finished; what arrived so far is kept
+    Interrupted(String),
     Content(String),
-    ToolCall { name: String, args: String },
+    ToolCall(ToolCall),
     Usage(Usage),
+    /// The prompt or response was blocked or flagged by the safety filters
+    Safety(SafetyReport),
+    /// Text of every candidate when several were asked for, the first being the one
+    /// already sent as `Content`
+    Alternatives(Vec<String>),
 }
 
-pub async fn stream_response(input: String, tx: UnboundedSender<AiUpdate>) {
-    if let Ok(key) = env::var("GEMINI_API_KEY") {
-        if let Err(e) = stream_gemini(&key, &input, tx.clone()).await {
+/// Everything needed for one streamed generation
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    /// Stable opening of the prompt (system instructions, repository map). It is sent
+    /// before `prompt`, from a context cache when `context.cache` is on and it is long enough.
+    pub prefix: String,
+    /// Flattened conversation history, following `prefix`
+    pub prompt: String,
+    /// Images and audio attached to the conversation, sent after the prompt in the order the
+    /// history refers to them. Too much to send inline goes through the Files API.
+    pub attachments: Vec<Attachment>,
+    /// When non-empty the request continues a tool-calling turn: the calls are replayed as
+    /// the model's turn and all results are sent back together as `functionResponse` parts
+    pub outcomes: Vec<ToolOutcome>,
+    /// Function declarations advertised alongside the built-in tools
+    pub extra_tools: Vec<Value>,
+    /// Models to try in order; the next is used when one is over quota or unavailable
+    pub models: Vec<String>,
+    /// Advertise no tools, so the model can only answer with text
+    pub without_tools: bool,
+    /// Blocking thresholds by harm category; unset ones keep the API default
+    pub safety: BTreeMap<HarmCategory, Threshold>,
+    /// Where the request goes; Vertex AI when configured, else the Gemini API, if `None`
+    pub provider: Option<Provider>,
+    /// Sampling parameters. With several candidates the request isn't streamed: the first
+    /// response arrives whole and all of them as `AiUpdate::Alternatives`.
+    pub generation: GenerationConfig,
+}
+
+impl Request {
+    /// A request whose prompt is made of `blocks`, sent in the order of their kinds and in
+    /// the given order within a kind. The system and pinned blocks form the `prefix`, so
+    /// what stays the same from turn to turn comes first, where context caches and the
+    /// provider's implicit prompt caching can reuse it.
+    pub fn from_blocks(mut blocks: Vec<ContextBlock>) -> Self {
+        blocks.sort_by_key(|block| block.kind);
+        let (prefix, prompt): (Vec<_>, Vec<_>) = blocks
+            .into_iter()
+            .partition(|block| block.kind <= BlockKind::Pinned);
+        let join = |blocks: Vec<ContextBlock>| {
+            blocks
+                .into_iter()
+                .map(|block| block.text)
+                .collect::<String>()
+        };
+        Self {
+            prefix: join(prefix),
+            prompt: join(prompt),
+            ..Default::default()
+        }
+    }
+
+    /// Hash of the prefix, the same for every request that can share its cache
+    pub fn prefix_hash(&self) -> String {
+        hex::encode(Sha256::digest(self.prefix.as_bytes()))
+    }
+}
+
+/// What a [`ContextBlock`] holds, in the order blocks are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockKind {
+    /// Instructions that only change with the config
+    System,
+    /// Material kept in view across turns, like the repository map
+    Pinned,
+    /// The conversation before the latest question
+    History,
+    /// The latest question and everything after it
+    Turn,
+}
+
+/// A part of the prompt, see [`Request::from_blocks`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextBlock {
+    pub kind: BlockKind,
+    pub text: String,
+}
+
+impl ContextBlock {
+    pub fn new(kind: BlockKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+}
+
+/// An image or audio recording sent to the model along with the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    /// `image/png`, `audio/ogg`, ...
+    pub mime_type: String,
+    pub data: Bytes,
+}
+
+impl Attachment {
+    /// `image`, `audio`, ...: the first half of the MIME type
+    pub fn kind(&self) -> &str {
+        self.mime_type.split('/').next().unwrap_or("file")
+    }
+}
+
+pub async fn stream_response(request: Request, tx: UnboundedSender<AiUpdate>) {
+    if has_credentials() {
+        if let Err(e) = stream_gemini(&request, tx.clone()).await {
             let _ = tx.send(AiUpdate::Error(format!("Error: {}", e)));
         }
     } else {
@@ -30,13 +496,17 @@ pub async fn stream_response(input: String, tx: UnboundedSender<AiUpdate>) {
         tokio::time::sleep(std::time::Duration::from_millis(500)).await;
         let _ = tx.send(AiUpdate::Content("(Mock AI): ".to_string()));
         tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-        let _ = tx.send(AiUpdate::Content(format!("I received: '{}'.\n", input)));
+        let _ = tx.send(AiUpdate::Content(format!(
+            "I received: '{}'.\n",
+            request.prompt
+        )));
         tokio::time::sleep(std::time::Duration::from_millis(200)).await;
         let _ = tx.send(AiUpdate::Content(
-            "Set GEMINI_API_KEY for real responses.".to_string(),
+            "Set GEMINI_API_KEY or run /setup for real responses.".to_string(),
         ));
         let _ = tx.send(AiUpdate::Usage(Usage {
             prompt_tokens: 10,
+            cached_tokens: 0,
             response_tokens: 20,
             total_tokens: 30,
         }));
@@ -44,180 +514,1147 @@ pub async fn stream_response(input: String, tx: UnboundedSender<AiUpdate>) {
     let _ = tx.send(AiUpdate::Finished);
 }
 
-async fn stream_gemini(api_key: &str, prompt: &str, tx: UnboundedSender<AiUpdate>) -> Result<()> {
-    let client = Client::new();
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemini-3-flash-preview:streamGenerateContent?key={}&alt=sse",
-        api_key
-    );
+/// Runs `request` to the end and returns the text, for one-shot tasks that don't stream
+pub async fn complete(request: Request) -> Result<String> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    stream_response(request, tx).await;
+    let mut text = String::new();
+    while let Ok(update) = rx.try_recv() {
+        match update {
+            AiUpdate::Content(chunk) => text.push_str(&chunk),
+            AiUpdate::Error(e) => return Err(color_eyre::eyre::eyre!(e)),
+            AiUpdate::Interrupted(reason) => {
+                return Err(color_eyre::eyre::eyre!(
+                    "The response was cut off: {}",
+                    reason
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(text)
+}
 
-    let body = json!({
-        "contents": [{
-            "parts": [{
-                "text": prompt
-            }]
-        }],
-        "tools": [{
-            "functionDeclarations": [
-                {
-                    "name": "search_google",
-                    "description": "Performs a simple google search",
-                    "parameters": {
-                        "type": "OBJECT",
-                        "properties": {
-                            "query": { "type": "STRING", "description": "The search query" }
-                        },
-                        "required": ["query"]
-                    }
+/// The conversation as `contents`: the prompt followed by the parts of its attachments, then
+/// any tool round trip
+fn build_contents(prompt: &str, attachments: &[Value], outcomes: &[ToolOutcome]) -> Value {
+    let mut parts = vec![json!({ "text": prompt })];
+    parts.extend(attachments.iter().cloned());
+    let mut contents = vec![json!({ "role": "user", "parts": parts })];
+
+    if !outcomes.is_empty() {
+        let calls: Vec<Value> = outcomes
+            .iter()
+            .map(|o| {
+                let args = serde_json::from_str::<Value>(&o.call.args).unwrap_or(Value::Null);
+                let mut call = json!({ "name": o.call.name, "args": args });
+                if let Some(id) = &o.call.id {
+                    call["id"] = json!(id);
+                }
+                let mut part = json!({ "functionCall": call });
+                if let Some(sig) = &o.call.thought_signature {
+                    part["thoughtSignature"] = json!(sig);
+                }
+                part
+            })
+            .collect();
+
+        let responses: Vec<Value> = outcomes
+            .iter()
+            .map(|o| {
+                let mut response = json!({
+                    "name": o.call.name,
+                    "response": { "result": o.result }
+                });
+                if let Some(id) = &o.call.id {
+                    response["id"] = json!(id);
+                }
+                json!({ "functionResponse": response })
+            })
+            .collect();
+
+        contents.push(json!({ "role": "model", "parts": calls }));
+        contents.push(json!({ "role": "user", "parts": responses }));
+    }
+
+    Value::Array(contents)
+}
+
+fn function_declarations(extra: &[Value]) -> Value {
+    let mut declarations = json!([
+        {
+            "name": "search_google",
+            "description": "Searches the web and returns the top results (title, URL, snippet)",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "query": { "type": "STRING", "description": "The search query" },
+                    "max_results": { "type": "INTEGER", "description": "Number of results to return (default 5, max 10)" }
                 },
-                {
-                    "name": "run_command",
-                    "description": "Executes a terminal command",
-                    "parameters": {
-                        "type": "OBJECT",
-                        "properties": {
-                            "command": { "type": "STRING", "description": "The command to run" }
-                        },
-                        "required": ["command"]
-                    }
+                "required": ["query"]
+            }
+        },
+        {
+            "name": "fetch_url",
+            "description": "Downloads a web page and returns its main content as markdown",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "url": { "type": "STRING", "description": "The http(s) URL to fetch" },
+                    "max_chars": { "type": "INTEGER", "description": "Maximum characters of page text to return (default 20000)" }
                 },
-                {
-                    "name": "create_file",
-                    "description": "Creates a new file with the given content",
-                    "parameters": {
-                        "type": "OBJECT",
-                        "properties": {
-                            "path": { "type": "STRING", "description": "File path" },
-                            "content": { "type": "STRING", "description": "File content" }
-                        },
-                        "required": ["path", "content"]
-                    }
+                "required": ["url"]
+            }
+        },
+        {
+            "name": "grep",
+            "description": "Searches project files for a regex (respects .gitignore) and returns matching lines as path:line: text",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "pattern": { "type": "STRING", "description": "Regular expression to search for" },
+                    "path": { "type": "STRING", "description": "Directory or file to search (default: current directory)" },
+                    "glob": { "type": "STRING", "description": "Comma-separated globs limiting which files are searched, e.g. \"*.rs,!target/**\"" },
+                    "case_insensitive": { "type": "BOOLEAN", "description": "Match case-insensitively" },
+                    "max_results": { "type": "INTEGER", "description": "Maximum matching lines to return (default 50)" }
                 },
-                {
-                    "name": "update_file",
-                    "description": "Updates an existing file by appending content",
-                    "parameters": {
-                        "type": "OBJECT",
-                        "properties": {
-                            "path": { "type": "STRING", "description": "File path" },
-                            "content": { "type": "STRING", "description": "Content to append" }
-                        },
-                        "required": ["path", "content"]
-                    }
+                "required": ["pattern"]
+            }
+        },
+        {
+            "name": "remember",
+            "description": "Saves a lasting fact (a user preference, a project convention) to persistent memory that survives across sessions",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "fact": { "type": "STRING", "description": "The fact to remember, phrased so it makes sense on its own" }
                 },
-                {
-                    "name": "delete_file",
-                    "description": "Deletes a file",
-                    "parameters": {
-                        "type": "OBJECT",
-                        "properties": {
-                            "path": { "type": "STRING", "description": "File path" }
-                        },
-                        "required": ["path"]
-                    }
+                "required": ["fact"]
+            }
+        },
+        {
+            "name": "recall",
+            "description": "Looks up facts previously saved with remember",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "query": { "type": "STRING", "description": "Keywords to search for; omit to list everything" }
                 }
-            ]
-        }]
+            }
+        },
+        {
+            "name": "run_command",
+            "description": "Executes a terminal command",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "command": { "type": "STRING", "description": "The command to run" }
+                },
+                "required": ["command"]
+            }
+        },
+        {
+            "name": "create_file",
+            "description": "Creates a new file with the given content",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "path": { "type": "STRING", "description": "File path" },
+                    "content": { "type": "STRING", "description": "File content" }
+                },
+                "required": ["path", "content"]
+            }
+        },
+        {
+            "name": "update_file",
+            "description": "Updates an existing file by appending content",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "path": { "type": "STRING", "description": "File path" },
+                    "content": { "type": "STRING", "description": "Content to append" }
+                },
+                "required": ["path", "content"]
+            }
+        },
+        {
+            "name": "delete_file",
+            "description": "Deletes a file",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {
+                    "path": { "type": "STRING", "description": "File path" }
+                },
+                "required": ["path"]
+            }
+        }
+    ]);
+    if let Value::Array(list) = &mut declarations {
+        list.extend(extra.iter().cloned());
+    }
+    declarations
+}
+
+/// Statuses worth retrying with the next model: quota exhausted, model missing or overloaded
+fn is_fallback_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 404 | 429 | 500 | 503)
+}
+
+/// The endpoint with `vertex` set only if requests to `provider` go to Vertex AI
+fn route(provider: Option<Provider>) -> Result<Endpoint> {
+    let mut endpoint = endpoint();
+    match provider {
+        Some(Provider::Gemini) => endpoint.vertex = None,
+        Some(Provider::Vertex) if endpoint.vertex.is_none() => {
+            return Err(color_eyre::eyre::eyre!(
+                "Vertex AI is not configured: set `project` under [vertex]"
+            ));
+        }
+        _ => {}
+    }
+    Ok(endpoint)
+}
+
+/// Request for `method` on `model` to `provider`: by default Vertex AI when configured,
+/// else the Gemini API
+async fn model_request(
+    model: &str,
+    method: &str,
+    provider: Option<Provider>,
+) -> Result<reqwest::RequestBuilder> {
+    let Endpoint {
+        client,
+        api,
+        vertex,
+        ..
+    } = route(provider)?;
+    if let Some(config) = vertex {
+        let token = vertex::access_token(&client).await?;
+        return Ok(client
+            .post(vertex::url(&config, model, method))
+            .bearer_auth(token));
+    }
+    let key = api_key().ok_or_else(|| color_eyre::eyre::eyre!("No API key"))?;
+    Ok(client
+        .post(format!("{}:{}", model_url(&api, model), method))
+        .header("x-goog-api-key", key))
+}
+
+async fn stream_request(
+    model: &str,
+    provider: Option<Provider>,
+) -> Result<reqwest::RequestBuilder> {
+    model_request(model, "streamGenerateContent?alt=sse", provider).await
+}
+
+/// What an embedding is used for; documents and queries are embedded differently
+#[derive(Debug, Clone, Copy)]
+pub enum EmbeddingTask {
+    Document,
+    Query,
+}
+
+/// Embedding vectors for `texts`, in order. Send at most [`EMBEDDING_BATCH`] at a time.
+pub async fn embed(texts: &[String], task: EmbeddingTask) -> Result<Vec<Vec<f32>>> {
+    let task = match task {
+        EmbeddingTask::Document => "RETRIEVAL_DOCUMENT",
+        EmbeddingTask::Query => "RETRIEVAL_QUERY",
+    };
+    if endpoint().vertex.is_some() {
+        // Vertex AI takes one text per request for this model
+        let mut vectors = Vec::with_capacity(texts.len());
+        for text in texts {
+            let body = json!({
+                "instances": [{ "content": text, "task_type": task }],
+                "parameters": { "outputDimensionality": EMBEDDING_DIMENSIONS }
+            });
+            let response = embedding_response("predict", &body).await?;
+            vectors.extend(vectors_at(&response["predictions"], "/embeddings/values")?);
+        }
+        return Ok(vectors);
+    }
+
+    let requests: Vec<Value> = texts
+        .iter()
+        .map(|text| {
+            json!({
+                "model": format!("models/{}", EMBEDDING_MODEL),
+                "content": { "parts": [{ "text": text }] },
+                "taskType": task,
+                "outputDimensionality": EMBEDDING_DIMENSIONS,
+            })
+        })
+        .collect();
+    let response =
+        embedding_response("batchEmbedContents", &json!({ "requests": requests })).await?;
+    vectors_at(&response["embeddings"], "/values")
+}
+
+async fn embedding_response(method: &str, body: &Value) -> Result<Value> {
+    let resp = model_request(EMBEDDING_MODEL, method, None)
+        .await?
+        .json(body)
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(color_eyre::eyre::eyre!(
+            "Embedding request failed with {}: {}",
+            status,
+            text
+        ));
+    }
+    Ok(resp.json().await?)
+}
+
+/// Images made by an image generation model, with any text it wrote alongside them
+#[derive(Debug, Clone, Default)]
+pub struct Generated {
+    pub images: Vec<Attachment>,
+    pub text: String,
+}
+
+/// Asks `model` for images matching `prompt`
+pub async fn generate_image(
+    model: &str,
+    prompt: &str,
+    provider: Option<Provider>,
+) -> Result<Generated> {
+    if !has_credentials() {
+        return Err(color_eyre::eyre::eyre!(
+            "Generating images needs an API key (/setup) or Vertex AI"
+        ));
+    }
+    let body = json!({
+        "contents": [{ "role": "user", "parts": [{ "text": prompt }] }],
+        "generationConfig": { "responseModalities": ["TEXT", "IMAGE"] }
     });
+    tracing::info!(model, "generating an image");
+    let resp = model_request(model, "generateContent", provider)
+        .await?
+        .json(&body)
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(color_eyre::eyre::eyre!("API Error {}: {}", status, text));
+    }
+    let response: Value = resp.json().await?;
+    let mut generated = Generated::default();
+    let candidate = &response["candidates"][0];
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(text) = part["text"].as_str() {
+            generated.text.push_str(text);
+        }
+        let inline = &part["inlineData"];
+        if let Some(data) = inline["data"].as_str() {
+            generated.images.push(Attachment {
+                mime_type: inline["mimeType"]
+                    .as_str()
+                    .unwrap_or("image/png")
+                    .to_string(),
+                data: STANDARD.decode(data)?.into(),
+            });
+        }
+    }
+    if generated.images.is_empty() {
+        let reason = response["promptFeedback"]["blockReason"]
+            .as_str()
+            .or(candidate["finishReason"].as_str())
+            .unwrap_or("no reason given");
+        return Err(color_eyre::eyre::eyre!(
+            "No image was generated ({}){}",
+            reason,
+            if generated.text.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", generated.text.trim())
+            }
+        ));
+    }
+    Ok(generated)
+}
+
+/// Parts carrying `attachments`: inline when they are small enough in total, else as files
+/// uploaded to the Files API, which Vertex AI doesn't have
+async fn attachment_parts(
+    attachments: &[Attachment],
+    provider: Option<Provider>,
+) -> Result<Vec<Value>> {
+    let total: usize = attachments.iter().map(|a| a.data.len()).sum();
+    if total <= INLINE_LIMIT {
+        return Ok(attachments
+            .iter()
+            .map(|a| {
+                json!({ "inlineData": { "mimeType": a.mime_type, "data": STANDARD.encode(&a.data) } })
+            })
+            .collect());
+    }
+    let endpoint = route(provider)?;
+    if endpoint.vertex.is_some() {
+        return Err(color_eyre::eyre::eyre!(
+            "Attachments over {} MB in total can't be sent to Vertex AI",
+            INLINE_LIMIT / 1_000_000
+        ));
+    }
+    let mut parts = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let uri = upload(&endpoint, attachment).await?;
+        parts.push(json!({ "fileData": { "mimeType": attachment.mime_type, "fileUri": uri } }));
+    }
+    Ok(parts)
+}
+
+/// URI of `attachment` in the Files API, uploaded unless it was recently
+async fn upload(endpoint: &Endpoint, attachment: &Attachment) -> Result<String> {
+    let hash = hex::encode(Sha256::digest(&attachment.data));
+    let now = Instant::now();
+    // Not held while uploading, which can take minutes
+    {
+        let mut uploads = UPLOADS.lock().await;
+        uploads.retain(|_, uploaded| uploaded.expires > now);
+        if let Some(uploaded) = uploads.get(&hash) {
+            return Ok(uploaded.uri.clone());
+        }
+    }
+
+    let key = api_key().ok_or_else(|| color_eyre::eyre::eyre!("No API key"))?;
+    let api = &endpoint.api;
+    tracing::info!(
+        bytes = attachment.data.len(),
+        mime_type = %attachment.mime_type,
+        "uploading an attachment"
+    );
+    // A resumable upload: the first request announces the file, the second sends it
+    let start = endpoint
+        .client
+        .post(format!("{}/upload/{}/files", api.base_url(), api.version()))
+        .header("x-goog-api-key", &key)
+        .header("X-Goog-Upload-Protocol", "resumable")
+        .header("X-Goog-Upload-Command", "start")
+        .header("X-Goog-Upload-Header-Content-Length", attachment.data.len())
+        .header("X-Goog-Upload-Header-Content-Type", &attachment.mime_type)
+        .json(&json!({ "file": { "display_name": format!("gemchat-{}", &hash[..12]) } }))
+        .send()
+        .await?;
+    let status = start.status();
+    if !status.is_success() {
+        let text = start.text().await.unwrap_or_default();
+        return Err(color_eyre::eyre::eyre!(
+            "Upload failed with {}: {}",
+            status,
+            text
+        ));
+    }
+    let url = start
+        .headers()
+        .get("x-goog-upload-url")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| color_eyre::eyre::eyre!("The upload was not given a URL"))?
+        .to_string();
+    let resp = endpoint
+        .client
+        .post(url)
+        .header("X-Goog-Upload-Offset", "0")
+        .header("X-Goog-Upload-Command", "upload, finalize")
+        .body(attachment.data.clone())
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(color_eyre::eyre::eyre!(
+            "Upload failed with {}: {}",
+            status,
+            text
+        ));
+    }
+    let mut file = resp.json::<Value>().await?["file"].take();
+
+    // Recordings are processed before they can be used
+    for _ in 0..UPLOAD_POLLS {
+        if file["state"].as_str() != Some("PROCESSING") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let name = file["name"].as_str().unwrap_or_default();
+        file = endpoint
+            .client
+            .get(format!("{}/{}/{}", api.base_url(), api.version(), name))
+            .header("x-goog-api-key", &key)
+            .send()
+            .await?
+            .json()
+            .await?;
+    }
+    match file["state"].as_str() {
+        Some("FAILED") => {
+            return Err(color_eyre::eyre::eyre!(
+                "The uploaded file could not be processed"
+            ));
+        }
+        Some("PROCESSING") => {
+            return Err(color_eyre::eyre::eyre!(
+                "The uploaded file is still being processed; try again shortly"
+            ));
+        }
+        _ => {}
+    }
+    let uri = file["uri"]
+        .as_str()
+        .ok_or_else(|| color_eyre::eyre::eyre!("Unexpected upload response"))?
+        .to_string();
+    UPLOADS.lock().await.insert(
+        hash,
+        Uploaded {
+            uri: uri.clone(),
+            expires: now + UPLOAD_LIFETIME,
+        },
+    );
+    Ok(uri)
+}
+
+/// The float array at `pointer` in each element of `list`
+fn vectors_at(list: &Value, pointer: &str) -> Result<Vec<Vec<f32>>> {
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .map(|item| {
+            item.pointer(pointer)
+                .and_then(Value::as_array)
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(Value::as_f64)
+                        .map(|v| v as f32)
+                        .collect()
+                })
+                .ok_or_else(|| color_eyre::eyre::eyre!("Unexpected embedding response"))
+        })
+        .collect()
+}
 
-    let resp = client.post(url).json(&body).send().await?;
+async fn stream_gemini(request: &Request, tx: UnboundedSender<AiUpdate>) -> Result<()> {
+    #[cfg(feature = "mock")]
+    if let Some(fixture) = crate::mock::next_fixture() {
+        let chunks = futures_util::stream::iter([Ok(bytes::Bytes::from(fixture?))]);
+        return read_stream(chunks, tx).await;
+    }
+
+    let tools = (!request.without_tools).then(|| {
+        json!([{
+            "functionDeclarations": function_declarations(&request.extra_tools)
+        }])
+    });
+    let cache_ttl = endpoint().cache_ttl;
+    let attachments = attachment_parts(&request.attachments, request.provider).await?;
 
-    if !resp.status().is_success() {
+    let default_models = [MODEL.to_string()];
+    let models = match request.models.as_slice() {
+        [] => &default_models[..],
+        models => models,
+    };
+    for (i, model) in models.iter().enumerate() {
+        let cache = match cache_ttl {
+            Some(ttl) => cached_prefix(model, request, tools.as_ref(), ttl).await,
+            None => None,
+        };
+        let body = request_body(request, &attachments, tools.as_ref(), cache.as_deref());
+        tracing::info!(
+            model,
+            prefix = &request.prefix_hash()[..12],
+            tool_results = request.outcomes.len(),
+            prompt_bytes = request.prefix.len() + request.prompt.len(),
+            cached = cache.is_some(),
+            "sending request"
+        );
+        let tokens = (request.prefix.len() + request.prompt.len()) / 4;
+        throttle(&endpoint().api, tokens, &tx).await;
+        let several = request.generation.candidates() > 1;
+        let builder = if several {
+            model_request(model, "generateContent", request.provider).await?
+        } else {
+            stream_request(model, request.provider).await?
+        };
+        let resp = builder.json(&body).send().await?;
         let status = resp.status();
+        if status.is_success() {
+            let _ = tx.send(AiUpdate::Model(model.clone()));
+            if several {
+                return read_whole(resp, tx).await;
+            }
+            #[cfg(feature = "mock")]
+            if let Some(mut file) = crate::mock::recorder()? {
+                use std::io::Write;
+                let stream = resp.bytes_stream().inspect(move |chunk| {
+                    if let Ok(chunk) = chunk {
+                        let _ = file.write_all(chunk);
+                    }
+                });
+                return read_stream(Box::pin(stream), tx).await;
+            }
+            return read_stream(resp.bytes_stream(), tx).await;
+        }
+
+        tracing::warn!(model, %status, "request failed");
         let text = resp
             .text()
             .await
             .unwrap_or_else(|_| "Could not read error body".to_string());
-        return Err(color_eyre::eyre::eyre!("API Error {}: {}", status, text));
+        if !is_fallback_status(status) || i + 1 == models.len() {
+            return Err(color_eyre::eyre::eyre!("API Error {}: {}", status, text));
+        }
     }
+    Ok(())
+}
 
-    let mut stream = resp.bytes_stream();
-    let mut buffer = String::new();
+/// Waits until a request of about `tokens` prompt tokens fits the `[api]` rate limits, then
+/// counts it as sent
+async fn throttle(api: &ApiConfig, tokens: usize, tx: &UnboundedSender<AiUpdate>) {
+    const WINDOW: Duration = Duration::from_secs(60);
 
-    // specific logging
-    use std::io::Write;
-    let mut debug_log = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("debug.log")
-        .ok();
+    if api.requests_per_minute.is_none() && api.tokens_per_minute.is_none() {
+        return;
+    }
+    let mut sent = SENT.lock().await;
+    loop {
+        let now = Instant::now();
+        while sent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW)
+        {
+            sent.pop_front();
+        }
+        // The send that has to age out of the window before this one fits, if any
+        let mut blocker = None;
+        if let Some(limit) = api.requests_per_minute
+            && sent.len() >= limit.max(1) as usize
+        {
+            blocker = Some(sent.len() - limit.max(1) as usize);
+        }
+        if let Some(limit) = api.tokens_per_minute {
+            let mut total: usize = sent.iter().map(|(_, t)| t).sum::<usize>() + tokens;
+            // A request bigger than the whole budget goes out once the window is empty
+            for (i, (_, t)) in sent.iter().enumerate() {
+                if total <= limit as usize {
+                    break;
+                }
+                total -= t;
+                blocker = blocker.max(Some(i));
+            }
+        }
+        let Some(index) = blocker else {
+            sent.push_back((now, tokens));
+            return;
+        };
+        let wait = (sent[index].0 + WINDOW).saturating_duration_since(now);
+        tracing::info!(wait_ms = wait.as_millis() as u64, "rate limited");
+        let _ = tx.send(AiUpdate::RateLimited(wait));
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// The generation request, with `attachments` as their parts. With a `cache` the prefix
+/// and tools come from it instead.
+pub fn request_body(
+    request: &Request,
+    attachments: &[Value],
+    tools: Option<&Value>,
+    cache: Option<&str>,
+) -> Value {
+    let mut body = match cache {
+        Some(cache) => json!({
+            "cachedContent": cache,
+            "contents": build_contents(&request.prompt, attachments, &request.outcomes),
+        }),
+        None => {
+            let prompt = format!("{}{}", request.prefix, request.prompt);
+            let contents = build_contents(&prompt, attachments, &request.outcomes);
+            let mut body = json!({ "contents": contents });
+            if let Some(tools) = tools {
+                body["tools"] = tools.clone();
+            }
+            body
+        }
+    };
+    let generation = &request.generation;
+    let candidates = generation.candidates();
+    let parameters = [
+        (
+            "candidateCount",
+            (candidates > 1).then(|| json!(candidates)),
+        ),
+        ("seed", generation.seed.map(|seed| json!(seed))),
+        ("temperature", generation.temperature.map(|t| json!(t))),
+        (
+            "presencePenalty",
+            generation.presence_penalty.map(|p| json!(p)),
+        ),
+        (
+            "frequencyPenalty",
+            generation.frequency_penalty.map(|p| json!(p)),
+        ),
+    ];
+    for (name, value) in parameters {
+        if let Some(value) = value {
+            body["generationConfig"][name] = value;
+        }
+    }
+    if !request.safety.is_empty() {
+        body["safetySettings"] = request
+            .safety
+            .iter()
+            .map(|(category, threshold)| {
+                json!({ "category": category.api_name(), "threshold": threshold.api_name() })
+            })
+            .collect();
+    }
+    body
+}
+
+/// Name of a context cache holding the request's prefix and `tools` for `model`, created
+/// if there is none yet. `None` when the prefix is too short to be worth caching or the
+/// cache could not be created; a failure is remembered for `ttl` rather than retried on
+/// every request.
+async fn cached_prefix(
+    model: &str,
+    request: &Request,
+    tools: Option<&Value>,
+    ttl: Duration,
+) -> Option<String> {
+    // About four bytes to a token
+    if request.prefix.len() / 4 < MIN_CACHE_TOKENS {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(model.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(format!("{:?}", request.provider).as_bytes());
+    hasher.update(b"\0");
+    hasher.update(request.prefix_hash().as_bytes());
+    hasher.update(b"\0");
+    if let Some(tools) = tools {
+        hasher.update(tools.to_string().as_bytes());
+    }
+    let key = hex::encode(hasher.finalize());
+
+    let mut caches = CACHES.lock().await;
+    let now = Instant::now();
+    caches.retain(|_, cached| cached.expires > now + CACHE_MARGIN);
+    if let Some(cached) = caches.get(&key) {
+        return cached.name.clone();
+    }
+    let name = match create_cache(model, request, tools, ttl).await {
+        Ok(name) => {
+            tracing::info!(model, %name, "created context cache");
+            Some(name)
+        }
+        Err(e) => {
+            tracing::warn!(model, error = %e, "could not create a context cache");
+            None
+        }
+    };
+    caches.insert(
+        key,
+        CachedPrefix {
+            name: name.clone(),
+            expires: now + ttl,
+        },
+    );
+    name
+}
+
+/// Creates a context cache of the request's prefix as a user turn, with `tools`, living
+/// for `ttl`
+async fn create_cache(
+    model: &str,
+    request: &Request,
+    tools: Option<&Value>,
+    ttl: Duration,
+) -> Result<String> {
+    let Endpoint {
+        client,
+        api,
+        vertex,
+        ..
+    } = route(request.provider)?;
+    let mut body = json!({
+        "contents": [{ "role": "user", "parts": [{ "text": request.prefix }] }],
+        "ttl": format!("{}s", ttl.as_secs()),
+    });
+    if let Some(tools) = tools {
+        body["tools"] = tools.clone();
+    }
+    let request = match &vertex {
+        Some(config) => {
+            body["model"] = json!(vertex::model_name(config, model));
+            let token = vertex::access_token(&client).await?;
+            client.post(vertex::cache_url(config)).bearer_auth(token)
+        }
+        None => {
+            body["model"] = json!(format!("models/{}", model));
+            let key = api_key().ok_or_else(|| color_eyre::eyre::eyre!("No API key"))?;
+            client
+                .post(format!(
+                    "{}/{}/cachedContents",
+                    api.base_url(),
+                    api.version()
+                ))
+                .header("x-goog-api-key", key)
+        }
+    };
+    let resp = request.json(&body).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(color_eyre::eyre::eyre!(
+            "Cache request failed with {}: {}",
+            status,
+            text
+        ));
+    }
+    let created: Value = resp.json().await?;
+    created["name"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| color_eyre::eyre::eyre!("Unexpected cache response"))
+}
+
+/// Forwards a whole `generateContent` response like a single streamed event, followed by
+/// the text of every candidate
+async fn read_whole(resp: reqwest::Response, tx: UnboundedSender<AiUpdate>) -> Result<()> {
+    let json: Value = resp.json().await?;
+    let mut calls = CallAssembler::default();
+    handle_event(&json, &mut calls, &tx);
+    let alternatives: Vec<String> = json["candidates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|candidate| {
+            candidate["content"]["parts"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|part| part["text"].as_str())
+                .collect()
+        })
+        .collect();
+    if alternatives.len() > 1 {
+        let _ = tx.send(AiUpdate::Alternatives(alternatives));
+    }
+    Ok(())
+}
+
+/// Forwards the events of a successful streaming response as they arrive
+async fn read_stream(
+    mut stream: impl futures_util::Stream<Item = reqwest::Result<bytes::Bytes>> + Unpin,
+    tx: UnboundedSender<AiUpdate>,
+) -> Result<()> {
+    // Raw bytes; lines are only decoded once complete, so a multi-byte character split
+    // across two network chunks is never decoded in halves
+    let mut buffer = BytesMut::new();
+    let mut parser = sse::Parser::default();
+    let mut complete = false;
+    let mut calls = CallAssembler::default();
 
     while let Some(item) = stream.next().await {
-        let chunk = item?;
-        let text = String::from_utf8_lossy(&chunk);
-
-        if let Some(log) = &mut debug_log {
-            writeln!(log, "Chunk: {:?}", text).ok();
-        }
-
-        buffer.push_str(&text);
-
-        while let Some(pos) = buffer.find('\n') {
-            let mut line = buffer[..pos].to_string();
-            // Advance buffer past the \n
-            buffer = buffer[pos + 1..].to_string();
-
-            // Trim trailing \r if present (for \r\n support)
-            if line.ends_with('\r') {
-                line.pop();
-            }
-
-            if line.starts_with("data: ") {
-                let json_str = &line[6..];
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    // Extract Content
-                    if let Some(candidates) = json.get("candidates") {
-                        if let Some(first) = candidates.get(0) {
-                            if let Some(content) = first.get("content") {
-                                if let Some(parts) = content.get("parts") {
-                                    if let Some(parts_array) = parts.as_array() {
-                                        for part in parts_array {
-                                            // 1. Check for text chunks
-                                            if let Some(text_chunk) =
-                                                part.get("text").and_then(|t| t.as_str())
-                                            {
-                                                let _ = tx.send(AiUpdate::Content(
-                                                    text_chunk.to_string(),
-                                                ));
-                                            }
-                                            // 2. Check for tool calls
-                                            if let Some(func_call) = part.get("functionCall") {
-                                                if let Some(name) =
-                                                    func_call.get("name").and_then(|n| n.as_str())
-                                                {
-                                                    let args = func_call
-                                                        .get("args")
-                                                        .unwrap_or(&serde_json::Value::Null)
-                                                        .to_string();
-                                                    let _ = tx.send(AiUpdate::ToolCall {
-                                                        name: name.to_string(),
-                                                        args,
-                                                    });
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    // Extract Usage Metadata
-                    if let Some(usage) = json.get("usageMetadata") {
-                        let prompt_tokens = usage["promptTokenCount"].as_i64().unwrap_or(0) as i32;
-                        let response_tokens =
-                            usage["candidatesTokenCount"].as_i64().unwrap_or(0) as i32;
-                        let total_tokens = usage["totalTokenCount"].as_i64().unwrap_or(0) as i32;
-
-                        let _ = tx.send(AiUpdate::Usage(Usage {
-                            prompt_tokens,
-                            response_tokens,
-                            total_tokens,
-                        }));
-                    }
-                }
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::warn!(error = %e, "stream broke");
+                let _ = tx.send(AiUpdate::Interrupted(e.to_string()));
+                return Ok(());
+            }
+        };
+        // Sizes only: chunk contents are the conversation
+        tracing::trace!(bytes = chunk.len(), "stream chunk");
+
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(pos) = memchr::memchr(b'\n', &buffer) {
+            let line = buffer.split_to(pos + 1);
+            // Drop the \n and a preceding \r (for \r\n support)
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+            if let Some(event) = parser.line(&String::from_utf8_lossy(line)) {
+                complete |= forward_event(&event, &mut calls, &tx);
             }
         }
     }
+    // A last event without the blank line after it still counts
+    if let Some(event) = parser.finish() {
+        complete |= forward_event(&event, &mut calls, &tx);
+    }
 
+    if let Some(call) = calls.finish() {
+        let _ = tx.send(AiUpdate::ToolCall(call));
+    }
+    if !complete {
+        tracing::warn!("stream ended without a finish reason");
+        let _ = tx.send(AiUpdate::Interrupted(
+            "the connection closed before the response was complete".to_string(),
+        ));
+    }
     Ok(())
 }
+
+/// Forwards one server-sent event. Returns whether the stream is over: the response is
+/// finished, or the server sent an error or `[DONE]`.
+fn forward_event(
+    event: &sse::Event,
+    calls: &mut CallAssembler,
+    tx: &UnboundedSender<AiUpdate>,
+) -> bool {
+    if event.is_done() {
+        return true;
+    }
+    if event.event.as_deref() == Some("error") {
+        tracing::warn!("error event in stream");
+        let _ = tx.send(AiUpdate::Error(format!("Error: {}", event.data)));
+        return true;
+    }
+    match serde_json::from_str::<Value>(&event.data) {
+        Ok(json) => handle_event(&json, calls, tx),
+        Err(e) => {
+            tracing::warn!(error = %e, "unparseable stream event");
+            false
+        }
+    }
+}
+
+/// Forwards the text, tool calls and usage in one streamed `GenerateContentResponse`.
+/// Returns whether it is the last one, i.e. carries a `finishReason`.
+fn handle_event(json: &Value, calls: &mut CallAssembler, tx: &UnboundedSender<AiUpdate>) -> bool {
+    if let Some(parts_array) = json
+        .get("candidates")
+        .and_then(|c| c.get(0))
+        .and_then(|first| first.get("content"))
+        .and_then(|content| content.get("parts"))
+        .and_then(|parts| parts.as_array())
+    {
+        for part in parts_array {
+            // 1. Check for text chunks
+            if let Some(text_chunk) = part.get("text").and_then(|t| t.as_str()) {
+                let _ = tx.send(AiUpdate::Content(text_chunk.to_string()));
+            }
+            // 2. Check for tool calls, possibly arriving in pieces
+            if let Some(func_call) = part.get("functionCall") {
+                for call in calls.push(func_call, part.get("thoughtSignature")) {
+                    tracing::debug!(tool = call.name.as_str(), "tool call received");
+                    let _ = tx.send(AiUpdate::ToolCall(call));
+                }
+            }
+        }
+    }
+    // Extract Usage Metadata
+    if let Some(usage) = json.get("usageMetadata") {
+        let prompt_tokens = usage["promptTokenCount"].as_i64().unwrap_or(0) as i32;
+        let cached_tokens = usage["cachedContentTokenCount"].as_i64().unwrap_or(0) as i32;
+        let response_tokens = usage["candidatesTokenCount"].as_i64().unwrap_or(0) as i32;
+        let total_tokens = usage["totalTokenCount"].as_i64().unwrap_or(0) as i32;
+
+        let _ = tx.send(AiUpdate::Usage(Usage {
+            prompt_tokens,
+            cached_tokens,
+            response_tokens,
+            total_tokens,
+        }));
+    }
+    if let Some(report) = safety_report(json) {
+        tracing::warn!(blocked = ?report.blocked, ratings = report.ratings.len(), "safety filters flagged the response");
+        let _ = tx.send(AiUpdate::Safety(report));
+    }
+    // A rejected prompt gets feedback instead of candidates, and nothing after it
+    let last = json["candidates"][0].get("finishReason").is_some()
+        || json["promptFeedback"].get("blockReason").is_some();
+    if last && let Some(call) = calls.finish() {
+        let _ = tx.send(AiUpdate::ToolCall(call));
+    }
+    last
+}
+
+/// A tool call whose arguments are still streaming in
+struct PartialCall {
+    id: Option<String>,
+    name: String,
+    args: Value,
+    thought_signature: Option<String>,
+}
+
+/// Puts tool calls back together when their arguments are streamed. Such a call starts
+/// with a `functionCall` carrying the id and name and `willContinue`; the following ones
+/// carry `partialArgs` fragments, each setting the value at a `jsonPath`, or adding to it
+/// when both are strings. The call is complete at the first `functionCall` without
+/// `willContinue`. Complete calls pass straight through.
+#[derive(Default)]
+struct CallAssembler {
+    pending: Option<PartialCall>,
+}
+
+impl CallAssembler {
+    /// Takes one `functionCall` and the `thoughtSignature` of its part, returning the calls
+    /// it completes
+    fn push(&mut self, call: &Value, signature: Option<&Value>) -> Vec<ToolCall> {
+        let mut done = Vec::new();
+        let name = call["name"].as_str();
+        let continues = call["willContinue"].as_bool() == Some(true);
+        let fragments = call["partialArgs"].as_array();
+        // A new call ends the one before it even when its closing part went missing
+        if let Some(name) = name
+            && let Some(pending) = self.pending.as_ref()
+            && (pending.name != name || fragments.is_none())
+        {
+            done.extend(self.finish());
+        }
+        let string = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+        if self.pending.is_none() {
+            if name.is_none() && fragments.is_none() {
+                return done;
+            }
+            if !continues && fragments.is_none() {
+                done.push(ToolCall {
+                    id: string(call.get("id")),
+                    name: name.unwrap_or_default().to_string(),
+                    args: call.get("args").unwrap_or(&Value::Null).to_string(),
+                    thought_signature: string(signature),
+                });
+                return done;
+            }
+            self.pending = Some(PartialCall {
+                id: None,
+                name: String::new(),
+                args: json!({}),
+                thought_signature: None,
+            });
+        }
+        let Some(pending) = self.pending.as_mut() else {
+            return done;
+        };
+        if let Some(name) = name {
+            pending.name = name.to_string();
+        }
+        if let Some(id) = string(call.get("id")) {
+            pending.id = Some(id);
+        }
+        if let Some(signature) = string(signature) {
+            pending.thought_signature = Some(signature);
+        }
+        if let Some(args) = call["args"].as_object() {
+            for (key, value) in args {
+                pending.args[key] = value.clone();
+            }
+        }
+        for fragment in fragments.into_iter().flatten() {
+            apply_fragment(&mut pending.args, fragment);
+        }
+        if !continues {
+            done.extend(self.finish());
+        }
+        done
+    }
+
+    /// The call still being assembled, as far as it got; used once the response ends
+    fn finish(&mut self) -> Option<ToolCall> {
+        let pending = self.pending.take()?;
+        if pending.name.is_empty() {
+            tracing::warn!("dropping a streamed tool call that never got a name");
+            return None;
+        }
+        Some(ToolCall {
+            id: pending.id,
+            name: pending.name,
+            args: pending.args.to_string(),
+            thought_signature: pending.thought_signature,
+        })
+    }
+}
+
+/// Applies one `partialArgs` entry to the arguments assembled so far
+fn apply_fragment(args: &mut Value, fragment: &Value) {
+    let value = if let Some(text) = fragment["stringValue"].as_str() {
+        json!(text)
+    } else if let Some(number) = fragment.get("numberValue") {
+        number.clone()
+    } else if let Some(flag) = fragment.get("boolValue") {
+        flag.clone()
+    } else if fragment.get("nullValue").is_some() {
+        Value::Null
+    } else {
+        return;
+    };
+    let path = fragment["jsonPath"].as_str().unwrap_or("$");
+    let Some(target) = json_path_mut(args, path) else {
+        tracing::warn!(path, "unsupported path in streamed tool arguments");
+        return;
+    };
+    match (target, value) {
+        (Value::String(existing), Value::String(more)) => existing.push_str(&more),
+        (target, value) => *target = value,
+    }
+}
+
+/// The value at a path like `$.files[0].name`, created as needed
+fn json_path_mut<'a>(mut value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let rest = path.strip_prefix('$')?;
+    for segment in rest.split('.').filter(|s| !s.is_empty()) {
+        let (key, indexes) = segment.split_once('[').unwrap_or((segment, ""));
+        if !key.is_empty() {
+            if !value.is_object() {
+                *value = json!({});
+            }
+            value = value.as_object_mut()?.entry(key).or_insert(Value::Null);
+        }
+        for index in indexes.split('[').filter(|i| !i.is_empty()) {
+            let index: usize = index.strip_suffix(']')?.parse().ok()?;
+            if !value.is_array() {
+                *value = json!([]);
+            }
+            let items = value.as_array_mut()?;
+            if items.len() <= index {
+                items.resize(index + 1, Value::Null);
+            }
+            value = &mut items[index];
+        }
+    }
+    Some(value)
+}
+
+/// The safety verdict in one streamed response, when something was blocked or rated at
+/// medium probability or above
+fn safety_report(json: &Value) -> Option<SafetyReport> {
+    const BLOCKING: &[&str] = &["SAFETY", "PROHIBITED_CONTENT", "BLOCKLIST", "SPII"];
+
+    let candidate = &json["candidates"][0];
+    let feedback = &json["promptFeedback"];
+    let blocked = feedback["blockReason"].as_str().or_else(|| {
+        candidate["finishReason"]
+            .as_str()
+            .filter(|reason| BLOCKING.contains(reason))
+    });
+    let ratings: Vec<(String, String)> = [candidate, feedback]
+        .into_iter()
+        .filter_map(|source| source["safetyRatings"].as_array())
+        .flatten()
+        .filter(|rating| {
+            rating["blocked"].as_bool() == Some(true)
+                || matches!(rating["probability"].as_str(), Some("MEDIUM" | "HIGH"))
+        })
+        .filter_map(|rating| {
+            Some((
+                rating["category"].as_str()?.to_string(),
+                rating["probability"].as_str()?.to_string(),
+            ))
+        })
+        .collect();
+    (blocked.is_some() || !ratings.is_empty()).then(|| SafetyReport {
+        blocked: blocked.map(str::to_string),
+        ratings,
+    })
+}
@@ -0,0 +1,189 @@
+use crate::ai::ToolCall;
+use crate::audit::Approval;
+use crate::config::{AllowRule, ApprovalConfig, ToolsConfig};
+use crate::tools;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Tools that only read or search; they never prompt
+const READ_ONLY: &[&str] = &["search_google", "fetch_url", "grep", "recall", "remember"];
+
+/// Characters that let a shell run more than the command a prefix rule was written for
+const SHELL_META: &[char] = &[';', '&', '|', '`', '$', '>', '<', '(', ')', '\n', '\r'];
+
+/// Decides whether `call` may run without asking. `None` means the user must be asked.
+pub fn check(call: &ToolCall, config: &ApprovalConfig) -> Option<Approval> {
+    if !config.enabled() || READ_ONLY.contains(&call.name.as_str()) {
+        return Some(Approval::Auto);
+    }
+    config
+        .allow
+        .iter()
+        .any(|rule| matches(rule, call))
+        .then_some(Approval::Rule)
+}
+
+fn matches(rule: &AllowRule, call: &ToolCall) -> bool {
+    if rule.tool != call.name {
+        return false;
+    }
+    let args = serde_json::from_str::<Value>(&call.args).unwrap_or_default();
+
+    if let Some(prefix) = &rule.prefix {
+        let Some(command) = args["command"].as_str() else {
+            return false;
+        };
+        if prefix.trim().is_empty() || command.contains(SHELL_META) {
+            return false;
+        }
+        // Whole words only, so `cargo test` doesn't allow `cargo testify`
+        let mut words = command.split_whitespace();
+        if !prefix.split_whitespace().all(|p| words.next() == Some(p)) {
+            return false;
+        }
+    }
+
+    if let Some(dir) = &rule.path {
+        let Some(path) = args["path"].as_str() else {
+            return false;
+        };
+        let (Some(target), Some(dir)) = (
+            absolute(&tools::resolve_path(path)),
+            absolute(Path::new(dir)),
+        ) else {
+            return false;
+        };
+        if !target.starts_with(dir) {
+            return false;
+        }
+    }
+    true
+}
+
+/// The "always allow" rule offered for `call`: the command and its subcommand for
+/// `run_command`, the containing directory for file tools and the whole tool otherwise
+pub fn suggest(call: &ToolCall) -> AllowRule {
+    let args = serde_json::from_str::<Value>(&call.args).unwrap_or_default();
+    let mut rule = AllowRule {
+        tool: call.name.clone(),
+        prefix: None,
+        path: None,
+    };
+
+    if let Some(command) = args["command"].as_str() {
+        let words: Vec<&str> = command
+            .split(SHELL_META)
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let take = match words.get(1) {
+            Some(sub)
+                if sub.chars().all(|c| c.is_alphanumeric() || c == '-')
+                    && !sub.starts_with('-') =>
+            {
+                2
+            }
+            _ => 1,
+        };
+        rule.prefix = Some(words[..take.min(words.len())].join(" "));
+    } else if let Some(path) = args["path"].as_str()
+        && let Some(target) = absolute(&tools::resolve_path(path))
+        && let Some(parent) = target.parent()
+    {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let dir = match parent.strip_prefix(&cwd) {
+            Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+            Ok(rel) => format!("{}/", rel.display()),
+            Err(_) => parent.display().to_string(),
+        };
+        rule.path = Some(dir);
+    }
+    rule
+}
+
+/// Short description for prompts, e.g. "`cargo test …` commands"
+pub fn describe(rule: &AllowRule) -> String {
+    match (&rule.prefix, &rule.path) {
+        (Some(prefix), _) => format!("`{} …` commands", prefix),
+        (None, Some(path)) => format!("{} under `{}`", rule.tool, path),
+        (None, None) => format!("every {} call", rule.tool),
+    }
+}
+
+/// What the current question has cost so far, for the `[tools]` loop guards
+#[derive(Debug, Default)]
+pub struct TurnGuard {
+    rounds: usize,
+    /// Times each call, by name and arguments, was made
+    calls: HashMap<(String, String), usize>,
+    /// Estimated spend in USD
+    pub cost: f64,
+}
+
+/// Why a tool loop is paused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tripped {
+    /// `tool` was called this many times with the same arguments
+    Repeated { tool: String, times: usize },
+    /// Rounds of tool calls made for the question
+    Rounds(usize),
+    /// Estimated spend in USD on the question
+    Cost(f64),
+}
+
+impl TurnGuard {
+    /// Starts the count again, for a new question or a loop let go on past a limit
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Counts a round of `calls` against the `[tools]` limits, returning the first one it
+    /// trips
+    pub fn check(&mut self, calls: &[ToolCall], tools: &ToolsConfig) -> Option<Tripped> {
+        self.rounds += 1;
+        let mut repeated = None;
+        for call in calls {
+            let count = self
+                .calls
+                .entry((call.name.clone(), call.args.clone()))
+                .or_default();
+            *count += 1;
+            if *count > tools.max_repeats() {
+                repeated = Some(Tripped::Repeated {
+                    tool: call.name.clone(),
+                    times: *count,
+                });
+            }
+        }
+        if repeated.is_some() {
+            repeated
+        } else if self.rounds > tools.max_rounds() {
+            Some(Tripped::Rounds(self.rounds))
+        } else if let Some(limit) = tools.max_turn_cost
+            && self.cost >= limit
+        {
+            Some(Tripped::Cost(self.cost))
+        } else {
+            None
+        }
+    }
+}
+
+/// Lexically absolute path relative to the working directory; `None` if it climbs with `..`
+fn absolute(path: &Path) -> Option<PathBuf> {
+    if path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().ok()?.join(path)
+    };
+    Some(
+        path.components()
+            .filter(|c| *c != Component::CurDir)
+            .collect(),
+    )
+}
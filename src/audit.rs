@@ -0,0 +1,92 @@
+use crate::config;
+use crate::store;
+use crate::tools::Status;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// One tool invocation, stored as a row of the database's audit table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Local>,
+    pub tool: String,
+    pub args: Value,
+    pub status: Status,
+    pub approval: Approval,
+    /// Size and SHA-256 of the full output; the output itself is not kept
+    pub output_bytes: usize,
+    pub output_sha256: String,
+}
+
+/// How the call was allowed to run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Approval {
+    /// Needs no approval (read-only tool, or approvals are off)
+    Auto,
+    /// Matched an "always allow" rule
+    Rule,
+    /// The user allowed this call
+    User,
+    /// The user declined; the tool did not run
+    Denied,
+}
+
+impl Entry {
+    pub fn new(tool: &str, args: &str, status: Status, approval: Approval, output: &str) -> Self {
+        Self {
+            timestamp: Local::now(),
+            tool: tool.to_string(),
+            // Keep malformed arguments verbatim rather than dropping them
+            args: serde_json::from_str(args).unwrap_or_else(|_| Value::String(args.into())),
+            status,
+            approval,
+            output_bytes: output.len(),
+            output_sha256: hex::encode(Sha256::digest(output.as_bytes())),
+        }
+    }
+}
+
+/// The JSON Lines log used before the audit log moved into the database
+fn legacy_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("audit.jsonl"))
+}
+
+/// Appends `entry` to the log. Rows are only ever inserted.
+pub fn record(entry: &Entry) -> io::Result<()> {
+    store::Store::open()
+        .and_then(|store| store.record_audit(entry))
+        .map_err(io::Error::other)
+}
+
+/// The last `limit` entries, oldest first
+pub fn recent(limit: usize) -> io::Result<Vec<Entry>> {
+    let store = store::Store::open().map_err(io::Error::other)?;
+    if let Some(path) = legacy_path() {
+        import_legacy(&store, &path)?;
+    }
+    store.recent_audit(limit).map_err(io::Error::other)
+}
+
+/// Copies the entries of the JSON Lines log at `path` into the audit table, skipping those
+/// already there, then renames the file so it is read only once. Lines that fail to parse
+/// are skipped.
+pub fn import_legacy(store: &store::Store, path: &Path) -> io::Result<()> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for line in BufReader::new(file).lines() {
+        let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+            continue;
+        };
+        if !store.has_audit_entry(&entry).map_err(io::Error::other)? {
+            store.record_audit(&entry).map_err(io::Error::other)?;
+        }
+    }
+    std::fs::rename(path, path.with_extension("jsonl.imported"))
+}
@@ -0,0 +1,176 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use gemchat::cipher;
+use gemchat::config::Config;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+
+const SERVICE: &str = "gemchat";
+const USER: &str = "gemini-api-key";
+/// Keyring entry of the key that encrypts stored conversations
+const STORAGE_USER: &str = "storage-key";
+
+/// Keyring entry of the API key, or of `profile`'s key
+fn entry(profile: Option<&str>) -> Result<keyring::Entry> {
+    let user = match profile {
+        Some(name) => format!("{}/{}", USER, name),
+        None => USER.to_string(),
+    };
+    keyring::Entry::new(SERVICE, &user).wrap_err("System keyring unavailable")
+}
+
+/// The storage key from the system keyring, created there on first use
+pub fn storage_key() -> Result<[u8; cipher::KEY_LEN]> {
+    let entry =
+        keyring::Entry::new(SERVICE, STORAGE_USER).wrap_err("System keyring unavailable")?;
+    match entry.get_password() {
+        Ok(encoded) => STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| eyre!("The storage key in the system keyring is malformed")),
+        Err(keyring::Error::NoEntry) => {
+            let key = cipher::random();
+            entry
+                .set_password(&STANDARD.encode(key))
+                .wrap_err("Could not write to the system keyring")?;
+            Ok(key)
+        }
+        Err(e) => Err(e).wrap_err("Could not read from the system keyring"),
+    }
+}
+
+/// The API key stored in the system keyring, if any
+pub fn load(profile: Option<&str>) -> Result<Option<String>> {
+    match entry(profile)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).wrap_err("Could not read from the system keyring"),
+    }
+}
+
+pub fn store(key: &str, profile: Option<&str>) -> Result<()> {
+    entry(profile)?
+        .set_password(key)
+        .wrap_err("Could not write to the system keyring")
+}
+
+/// Deletes the stored key; `false` if there was none
+pub fn remove(profile: Option<&str>) -> Result<bool> {
+    match entry(profile)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).wrap_err("Could not delete from the system keyring"),
+    }
+}
+
+/// Hands the keys to the client: the stored or configured one, and the active profile's
+/// own key if it has one
+pub fn use_keys(config: &Config) {
+    let load = |profile| {
+        load(profile).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "could not read the keyring");
+            None
+        })
+    };
+    let profile_key = config.active_profile.as_deref().and_then(|name| {
+        let profile = config.profile()?;
+        profile
+            .api_key_env
+            .as_ref()
+            .and_then(|var| env::var(var).ok())
+            .filter(|k| !k.trim().is_empty())
+            .or_else(|| load(Some(name)))
+            .or_else(|| profile.api_key.clone())
+    });
+    crate::ai::set_profile_key(profile_key);
+    crate::ai::set_api_key(load(None).or_else(|| config.api_key.clone()));
+}
+
+/// Names the profile, if any, at the end of a message
+fn for_profile(profile: Option<&str>) -> String {
+    profile.map_or(String::new(), |name| format!(" for profile `{}`", name))
+}
+
+/// `gemchat auth set`: reads a key without echoing it, checks it and stores it
+pub async fn set_command(profile: Option<&str>) -> Result<()> {
+    let key = read_key()?;
+    if key.is_empty() {
+        return Err(eyre!("No key entered"));
+    }
+    crate::ai::check_api_key(&key)
+        .await
+        .wrap_err("The key was rejected")?;
+    store(&key, profile)?;
+    println!(
+        "API key saved to the system keyring{}.",
+        for_profile(profile)
+    );
+    Ok(())
+}
+
+/// `gemchat auth remove`
+pub fn remove_command(profile: Option<&str>) -> Result<()> {
+    if remove(profile)? {
+        println!(
+            "API key removed from the system keyring{}.",
+            for_profile(profile)
+        );
+    } else {
+        println!(
+            "No API key was stored in the system keyring{}.",
+            for_profile(profile)
+        );
+    }
+    Ok(())
+}
+
+/// One line from stdin; typed in raw mode when stdin is a terminal so the key isn't shown
+fn read_key() -> Result<String> {
+    if !io::stdin().is_terminal() {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line.trim().to_string());
+    }
+
+    read_secret("Gemini API key: ")
+}
+
+/// A line typed after `prompt` without being shown
+pub fn read_secret(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    terminal::enable_raw_mode()?;
+    let result = read_hidden();
+    terminal::disable_raw_mode()?;
+    println!();
+    result
+}
+
+fn read_hidden() -> Result<String> {
+    let mut key = String::new();
+    loop {
+        let Event::Key(press) = event::read()? else {
+            continue;
+        };
+        if press.kind != KeyEventKind::Press {
+            continue;
+        }
+        match press.code {
+            KeyCode::Enter => return Ok(key.trim().to_string()),
+            KeyCode::Char('c') if press.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Err(eyre!("Cancelled"));
+            }
+            KeyCode::Esc => return Err(eyre!("Cancelled")),
+            KeyCode::Backspace => {
+                key.pop();
+            }
+            KeyCode::Char(c) => key.push(c),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use regex::Regex;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Command;
+use std::sync::LazyLock;
+
+/// Most recent lines of terminal output kept from a capture
+const MAX_LINES: usize = 1000;
+
+/// Bytes read from the end of a capture file, plenty for [`MAX_LINES`] lines
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Color and cursor escapes, and OSC sequences such as window titles
+static ESCAPES: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[()][0-9A-B]")
+        .expect("valid escape pattern")
+});
+
+/// Terminal output from tmux. Without a `target` it is the screen gemchat was started over,
+/// which usually ends with the output of the last command; with one, the scrollback and
+/// screen of that pane, e.g. `{last}` or `%3`.
+pub fn tmux(target: Option<&str>) -> Result<String> {
+    if std::env::var_os("TMUX").is_none() {
+        return Err(eyre!(
+            "Not inside tmux; use `/capture <file>` with saved terminal output"
+        ));
+    }
+    let mut command = Command::new("tmux");
+    command.args(["capture-pane", "-p", "-J"]);
+    match target {
+        // gemchat's own pane shows the TUI; the screen under it is kept as the alternate one
+        None => command.args(["-a"]),
+        Some(target) => command.args(["-S", &format!("-{}", MAX_LINES), "-t", target]),
+    };
+    let output = command.output().wrap_err("Could not run tmux")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "tmux capture-pane failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(clean(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Terminal output saved in a file, e.g. by `script` or `tee`. Only its end is read, so
+/// long logs don't have to fit in memory.
+pub fn file(path: &Path) -> Result<String> {
+    let (data, cut) = tail(path, MAX_FILE_BYTES)
+        .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    let text = String::from_utf8_lossy(&data);
+    // The first line read may start in the middle
+    let text = if cut {
+        text.split_once('\n').map_or("", |(_, rest)| rest)
+    } else {
+        &text
+    };
+    Ok(clean(text))
+}
+
+/// The last `max_bytes` of the file at `path`, and whether anything before them was left out
+pub fn tail(path: &Path, max_bytes: u64) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut file = File::open(path)?;
+    let start = file.metadata()?.len().saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = Vec::new();
+    file.take(max_bytes).read_to_end(&mut data)?;
+    Ok((data, start > 0))
+}
+
+/// The last 1000 lines of `text` without escapes, carriage returns or trailing blank lines
+pub fn clean(text: &str) -> String {
+    let text = ESCAPES.replace_all(text, "");
+    let lines: Vec<&str> = text
+        .lines()
+        // What a carriage return wrote over is gone from the screen
+        .map(|line| line.rsplit('\r').next().unwrap_or(line).trim_end())
+        .collect();
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |i| i + 1);
+    let start = end.saturating_sub(MAX_LINES);
+    lines[start..end].join("\n")
+}
@@ -0,0 +1,86 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::aead::{CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+
+/// PBKDF2-HMAC-SHA256 rounds for passphrases, per OWASP's current recommendation
+const PBKDF2_ROUNDS: u32 = 600_000;
+
+/// ChaCha20-Poly1305 with a random nonce per message, stored in front of the ciphertext
+#[derive(Clone)]
+pub struct Cipher {
+    key: LessSafeKey,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, key).expect("key has the AEAD's length");
+        Self {
+            key: LessSafeKey::new(key),
+        }
+    }
+
+    /// Derives the key from a passphrase; the same `salt` gives the same key
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+        let mut key = [0; KEY_LEN];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ROUNDS).expect("rounds are not zero"),
+            salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Self::new(&key)
+    }
+
+    /// `text` sealed and base64-encoded, to store in a text column
+    pub fn encrypt(&self, text: &str) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("the system random generator works");
+        let mut sealed = text.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                ring::aead::Aad::empty(),
+                &mut sealed,
+            )
+            .expect("messages are far below the AEAD's size limit");
+        let mut out = nonce.to_vec();
+        out.append(&mut sealed);
+        STANDARD.encode(out)
+    }
+
+    /// The text `encrypt` sealed; `None` for a wrong key or tampered data
+    pub fn decrypt(&self, encoded: &str) -> Option<String> {
+        let data = STANDARD.decode(encoded).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let mut sealed = sealed.to_vec();
+        let text = self
+            .key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).ok()?,
+                ring::aead::Aad::empty(),
+                &mut sealed,
+            )
+            .ok()?;
+        String::from_utf8(text.to_vec()).ok()
+    }
+}
+
+/// Random bytes for a new key or salt
+pub fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random generator works");
+    bytes
+}
@@ -0,0 +1,38 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use gemchat::ai;
+use std::io::Write;
+
+/// Puts `text` on the clipboard with an OSC 52 escape. The terminal does the copying, so
+/// it also works over SSH and, with `set-clipboard on`, inside tmux.
+pub fn copy(text: &str) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()
+}
+
+/// The image on the system clipboard, as a PNG. Terminals only paste text, so this reads the
+/// clipboard directly and works only where gemchat runs on the desktop's machine.
+pub fn image() -> Result<ai::Attachment> {
+    let mut clipboard = arboard::Clipboard::new().wrap_err("Clipboard unavailable")?;
+    let image = match clipboard.get_image() {
+        Ok(image) => image,
+        Err(arboard::Error::ContentNotAvailable) => {
+            return Err(eyre!("The clipboard holds no image"));
+        }
+        Err(e) => return Err(e).wrap_err("Could not read the clipboard"),
+    };
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.bytes)?;
+    writer.finish()?;
+    Ok(ai::Attachment {
+        mime_type: "image/png".to_string(),
+        data: data.into(),
+    })
+}
@@ -0,0 +1,52 @@
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use gemchat::{ai, config, git, redact};
+use std::io::{self, IsTerminal, Write};
+
+/// Asks the model for a commit message describing the staged changes
+pub async fn generate(config: &config::Config, redactor: &redact::Redactor) -> Result<String> {
+    let diff = git::staged_diff().await?;
+    let reply = ai::complete(ai::Request {
+        prompt: redactor.redact(&git::commit_prompt(&diff)).into_owned(),
+        models: config.models.clone(),
+        without_tools: true,
+        safety: config.safety.clone(),
+        provider: config.provider,
+        ..Default::default()
+    })
+    .await?;
+    let message = git::commit_message(&reply);
+    if message.is_empty() {
+        return Err(eyre!("The model returned an empty message"));
+    }
+    Ok(message)
+}
+
+/// `gemchat commit`: prints a message for the staged changes and, when run in a terminal,
+/// offers to commit with it as is or after editing. `yes` commits without asking.
+pub async fn command(
+    config: &config::Config,
+    redactor: &redact::Redactor,
+    yes: bool,
+) -> Result<()> {
+    let message = generate(config, redactor).await?;
+    if yes {
+        println!("{}", git::commit(&message).await?);
+        return Ok(());
+    }
+    println!("{}", message);
+    if !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    print!("\nCommit with this message? [y]es, [e]dit, [n]o: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => println!("{}", git::commit(&message).await?),
+        "e" | "edit" => git::commit_in_editor(&message).await?,
+        _ => println!("Not committed."),
+    }
+    Ok(())
+}
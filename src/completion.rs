@@ -0,0 +1,132 @@
+use ignore::WalkBuilder;
+use std::path::Path;
+
+/// Something the word at the cursor can be completed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Replaces the word
+    pub text: String,
+    /// Shown dimmed beside it, e.g. what a command does
+    pub detail: String,
+}
+
+impl Candidate {
+    pub fn new(text: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            detail: detail.into(),
+        }
+    }
+}
+
+/// What the word at the cursor is completed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A slash command, while typing the first word of the input
+    Command,
+    /// A project file mentioned as `@path`
+    File,
+    /// The argument of `/model`
+    Model,
+}
+
+/// The word being completed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub source: Source,
+    /// Character column where the word starts; the completion replaces it up to the cursor
+    pub start: usize,
+    /// What was typed of it, without a leading `@`
+    pub query: String,
+}
+
+/// The word ending at character column `col` of `line` that can be completed, if any.
+/// Commands and their arguments are only completed on the first line of the input.
+pub fn token_at(line: &str, col: usize, first_line: bool) -> Option<Token> {
+    let before: String = line.chars().take(col).collect();
+    let start = before
+        .char_indices()
+        .rfind(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &before[start..];
+    let start = before[..start].chars().count();
+
+    if first_line && start == 0 && word.starts_with('/') {
+        return Some(Token {
+            source: Source::Command,
+            start,
+            query: word.to_string(),
+        });
+    }
+    if first_line && before[..before.len() - word.len()].trim_end() == "/model" {
+        return Some(Token {
+            source: Source::Model,
+            start,
+            query: word.to_string(),
+        });
+    }
+    let query = word.strip_prefix('@')?;
+    Some(Token {
+        source: Source::File,
+        start,
+        query: query.to_string(),
+    })
+}
+
+/// How well `candidate` matches `query`, higher being better, or `None` unless the query's
+/// characters appear in it in order. Case is ignored; runs of consecutive characters and
+/// matches at the start of words score more, and shorter candidates win ties.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut last: Option<usize> = None;
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut from = 0;
+    for wanted in query.chars() {
+        let wanted = wanted.to_lowercase().next().unwrap_or(wanted);
+        let offset = chars[from..]
+            .iter()
+            .position(|c| c.to_lowercase().next() == Some(wanted))?;
+        let at = from + offset;
+        score += 1;
+        if last.is_some_and(|last| last + 1 == at) {
+            score += 5;
+        }
+        if at == 0 || chars[at - 1].is_ascii_punctuation() || chars[at - 1].is_whitespace() {
+            score += 3;
+        }
+        last = Some(at);
+        from = at + 1;
+    }
+    Some(score * 100 - chars.len() as i64)
+}
+
+/// The `candidates` matching `query`, best first and at most `limit`. Ties keep their order.
+pub fn filter(query: &str, candidates: &[Candidate], limit: usize) -> Vec<Candidate> {
+    let mut scored: Vec<(i64, &Candidate)> = candidates
+        .iter()
+        .filter_map(|c| Some((fuzzy_score(query, &c.text)?, c)))
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, c)| c.clone())
+        .collect()
+}
+
+/// Paths of the files under `root` relative to it, respecting `.gitignore`, sorted and at
+/// most `limit` of them
+pub fn project_files(root: &Path, limit: usize) -> Vec<String> {
+    let mut files: Vec<String> = WalkBuilder::new(root)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(root).ok()?;
+            Some(path.to_string_lossy().replace('\\', "/"))
+        })
+        .take(limit)
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,1080 @@
+use crate::ai::Provider;
+use crate::i18n::Locale;
+use crate::keymap;
+use crate::layout::{Edge, Side};
+use crate::theme::ColorDepth;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// User configuration, loaded from `config.toml` in the gemchat config dir
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Gemini API key, used when neither `GEMINI_API_KEY` nor the system keyring has one.
+    /// Prefer `gemchat auth set`, which keeps it out of plaintext files.
+    pub api_key: Option<String>,
+    /// Models to try in order, e.g. `["gemini-3-flash-preview", "gemini-2.5-flash-lite"]`.
+    /// A request moves on to the next when one is over quota or unavailable.
+    pub models: Vec<String>,
+    /// `gemini` or `vertex`: where requests go when both are set up. By default Vertex AI
+    /// when `[vertex]` has a project.
+    pub provider: Option<Provider>,
+    /// Instructions added to the system prompt, such as house style or what the project is
+    pub system_prompt: Option<String>,
+    pub api: ApiConfig,
+    /// Send requests to Vertex AI instead of the Gemini API
+    pub vertex: VertexConfig,
+    pub tools: ToolsConfig,
+    pub redact: RedactConfig,
+    pub theme: ThemeConfig,
+    pub keys: KeysConfig,
+    pub ui: UiConfig,
+    pub layout: LayoutConfig,
+    pub context: ContextConfig,
+    pub notifications: NotificationsConfig,
+    pub storage: StorageConfig,
+    pub generation: GenerationConfig,
+    pub images: ImagesConfig,
+    pub diagrams: DiagramsConfig,
+    pub live: LiveConfig,
+    pub share: ShareConfig,
+    pub compare: CompareConfig,
+    /// `[safety]`: how readily Gemini blocks content, by harm category, e.g.
+    /// `dangerous_content = "block_only_high"`. Unset categories keep the API default.
+    pub safety: BTreeMap<HarmCategory, Threshold>,
+    /// `[pricing]`: USD per million tokens by model, e.g.
+    /// `"gemini-2.5-pro" = { input = 1.25, output = 10.0, cached_input = 0.125 }`, for
+    /// models without a built-in price or when it changes. Keys match model names by prefix.
+    pub pricing: HashMap<String, Price>,
+    /// `[profile.<name>]`: settings for one account or project, used in place of the
+    /// top-level ones with `--profile <name>` or `/profile <name>`
+    pub profile: BTreeMap<String, Profile>,
+    /// `[template.<name>]`: starting points for new sessions, e.g. `[template."Rust reviewer"]`,
+    /// picked with `/new <name>` or from the sidebar
+    pub template: BTreeMap<String, Template>,
+    /// File the config was loaded from (or would be), where approval rules are saved
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// Name of the profile in use
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    /// The config as loaded, before a profile replaced parts of it
+    #[serde(skip)]
+    top_level: Option<Box<Config>>,
+}
+
+/// `[profile.<name>]`. Settings left out keep their top-level values.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub provider: Option<Provider>,
+    /// Gemini API key of this profile's account. Also read from the environment variable
+    /// named by `api_key_env`, or from the keyring after `gemchat --profile <name> auth set`;
+    /// either wins over `GEMINI_API_KEY`.
+    pub api_key: Option<String>,
+    pub api_key_env: Option<String>,
+    pub models: Option<Vec<String>>,
+    pub vertex: Option<VertexConfig>,
+    pub system_prompt: Option<String>,
+    /// Replaces `[tools.approval]`
+    pub approval: Option<ApprovalConfig>,
+}
+
+/// `[template.<name>]`. Sessions started from it keep its settings when opened again.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Template {
+    /// Shown beside the name in the new-session picker
+    pub description: Option<String>,
+    /// Instructions added to the system prompt after the configured ones
+    pub system_prompt: Option<String>,
+    /// Files whose contents are part of every prompt, relative to the working directory
+    pub files: Vec<PathBuf>,
+    /// Replaces `[tools.approval]`
+    pub approval: Option<ApprovalConfig>,
+    pub model: Option<String>,
+    pub provider: Option<Provider>,
+}
+
+/// `[api]`: where Gemini API requests go, for proxies and gateways such as LiteLLM
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Default `https://generativelanguage.googleapis.com`
+    pub base_url: Option<String>,
+    /// Version segment of request paths, default `v1beta`
+    pub version: Option<String>,
+    /// Proxy for model requests, e.g. `http://proxy.corp:3128`. Without it `HTTPS_PROXY`
+    /// and `NO_PROXY` apply.
+    pub proxy: Option<String>,
+    /// Model requests sent per minute at most; further ones wait their turn
+    pub requests_per_minute: Option<u32>,
+    /// Estimated prompt tokens sent per minute at most
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl ApiConfig {
+    pub fn base_url(&self) -> &str {
+        self.base_url
+            .as_deref()
+            .unwrap_or("https://generativelanguage.googleapis.com")
+            .trim_end_matches('/')
+    }
+
+    pub fn version(&self) -> &str {
+        self.version
+            .as_deref()
+            .unwrap_or("v1beta")
+            .trim_matches('/')
+    }
+}
+
+/// `[vertex]`: Vertex AI authenticated with Application Default Credentials
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VertexConfig {
+    /// Google Cloud project; Vertex AI is used when this is set
+    pub project: Option<String>,
+    /// Region such as `us-central1`, or `global` (the default)
+    pub location: Option<String>,
+}
+
+impl VertexConfig {
+    pub fn enabled(&self) -> bool {
+        self.project.is_some()
+    }
+
+    pub fn location(&self) -> &str {
+        self.location.as_deref().unwrap_or("global")
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// Shell used by `run_command`. Defaults to `cmd` on Windows and `sh` elsewhere.
+    pub shell: Option<Shell>,
+    /// Upper bound on tool calls from one model turn that run at the same time
+    pub max_parallel: Option<usize>,
+    /// Line budget for a tool result sent to the model; the full output stays viewable in the chat
+    pub max_output_lines: Option<usize>,
+    /// Byte budget for a tool result sent to the model
+    pub max_output_bytes: Option<usize>,
+    /// Rounds of tool calls for one question before asking whether to go on (default 25)
+    pub max_rounds: Option<usize>,
+    /// Times one question may make the same call, with the same arguments, before asking
+    /// whether to go on (default 3)
+    pub max_repeats: Option<usize>,
+    /// Estimated spend in USD on one question before asking whether to go on; no limit
+    /// by default
+    pub max_turn_cost: Option<f64>,
+    /// Tools declared by the user (`[[tools.custom]]`)
+    pub custom: Vec<CustomTool>,
+    /// Container backend for `run_command`
+    pub sandbox: SandboxConfig,
+    /// Confirmation before tools that change things
+    pub approval: ApprovalConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// Ask before running commands, editing files or calling custom tools (default true)
+    pub enabled: Option<bool>,
+    /// Calls matching one of these run without asking (`[[tools.approval.allow]]`)
+    pub allow: Vec<AllowRule>,
+}
+
+impl ApprovalConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+/// "Always allow" rule. With neither `prefix` nor `path` every call to `tool` is allowed.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AllowRule {
+    pub tool: String,
+    /// Leading words of a `run_command` command, e.g. `cargo test`. Commands chaining
+    /// or redirecting with shell operators never match.
+    pub prefix: Option<String>,
+    /// Directory the `path` argument must lie in, relative to the working directory
+    pub path: Option<String>,
+}
+
+/// Runs `run_command` in a throwaway container that can only see the project directory
+/// (mounted at `/workspace`) instead of on the host. File tools are not affected.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Whether sessions start sandboxed; `/sandbox on|off` switches for the current session
+    pub enabled: Option<bool>,
+    /// Container runtime, e.g. `docker` or `podman` (default `docker`)
+    pub runtime: Option<String>,
+    /// Image to run commands in; it must provide `sh` (default `debian:stable-slim`)
+    pub image: Option<String>,
+    /// Give the container network access (default false)
+    pub network: Option<bool>,
+    /// Extra arguments for `<runtime> run`, e.g. `["--memory", "1g"]`
+    pub args: Vec<String>,
+}
+
+impl SandboxConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn runtime(&self) -> &str {
+        self.runtime.as_deref().unwrap_or("docker")
+    }
+
+    pub fn image(&self) -> &str {
+        self.image.as_deref().unwrap_or("debian:stable-slim")
+    }
+
+    pub fn network(&self) -> bool {
+        self.network.unwrap_or(false)
+    }
+}
+
+/// A user-defined tool that runs a fixed program with model-supplied arguments
+/// substituted into `{placeholder}`s. No shell is involved, so a template like
+/// `["kubectl", "get", "{resource}"]` can only ever run `kubectl get`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustomTool {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments, advertised to the model as-is
+    #[serde(default = "empty_schema")]
+    pub parameters: serde_json::Value,
+    /// Program followed by its arguments. An argument referencing an optional
+    /// parameter the model left out is dropped entirely.
+    pub command: Vec<String>,
+    /// Kill the program after this many seconds (default 60)
+    pub timeout_secs: Option<u64>,
+}
+
+fn empty_schema() -> serde_json::Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// One of the built-in themes (default `dark`)
+    pub name: Option<String>,
+    /// Per-color overrides on top of it (`[theme.colors]`), e.g. `user = "#268bd2"`
+    pub colors: toml::Table,
+}
+
+impl ThemeConfig {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("dark")
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// How message times are shown next to the role (default `absolute`)
+    pub timestamps: Option<Timestamps>,
+    /// Screen-reader friendly output: no colors, spinners, sidebar or borders, and new
+    /// messages announced in the status bar (default false; also `--accessible`)
+    pub accessible: Option<bool>,
+    /// Name the session after its first exchange (default true)
+    pub auto_title: Option<bool>,
+    /// Model that writes session titles (default `gemini-2.5-flash-lite`)
+    pub title_model: Option<String>,
+    /// Messages longer than this many lines are shown folded to their start until
+    /// expanded (default 40; 0 never folds)
+    pub fold_lines: Option<usize>,
+    /// Colors the terminal shows: `truecolor`, `256`, `16` or `none` (default detected
+    /// from `NO_COLOR`, `COLORTERM` and `TERM`)
+    pub color_depth: Option<ColorDepth>,
+    /// Shown instead of the role names above messages, icons included, e.g.
+    /// `{ You = "❯ me", AI = "✦ Gemini" }`
+    pub role_labels: HashMap<String, String>,
+    /// Language of the interface, e.g. `de` (default from `LC_ALL`, `LC_MESSAGES` or
+    /// `LANG`; English where there is no translation)
+    pub locale: Option<String>,
+}
+
+impl UiConfig {
+    /// What the header of a message from `role` calls it: the configured label, or the
+    /// role's name in the current language
+    pub fn role_label<'a>(&'a self, role: &'a str) -> &'a str {
+        self.role_labels
+            .get(role)
+            .map_or_else(|| crate::i18n::tr(role), String::as_str)
+    }
+
+    pub fn timestamps(&self) -> Timestamps {
+        self.timestamps.unwrap_or(Timestamps::Absolute)
+    }
+
+    pub fn accessible(&self) -> bool {
+        self.accessible.unwrap_or(false)
+    }
+
+    pub fn auto_title(&self) -> bool {
+        self.auto_title.unwrap_or(true)
+    }
+
+    pub fn title_model(&self) -> &str {
+        self.title_model
+            .as_deref()
+            .unwrap_or("gemini-2.5-flash-lite")
+    }
+
+    pub fn fold_lines(&self) -> usize {
+        self.fold_lines.unwrap_or(40)
+    }
+
+    pub fn color_depth(&self) -> ColorDepth {
+        self.color_depth.unwrap_or_else(ColorDepth::detect)
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale
+            .as_deref()
+            .and_then(Locale::parse)
+            .unwrap_or_else(Locale::detect)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timestamps {
+    /// `14:03`
+    Absolute,
+    /// `5m ago`
+    Relative,
+    Off,
+}
+
+/// `[layout]`: where the sidebar and the input box go and how much room they take
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    /// `left` or `right` (default `left`)
+    pub sidebar: Option<Side>,
+    /// Columns of the sidebar, from 15 to 60, until it is resized in the TUI (default 25)
+    pub sidebar_width: Option<u16>,
+    /// `top` or `bottom`: which side of the messages the input box is on (default `bottom`)
+    pub input: Option<Edge>,
+    /// Rows of the input box, borders included (default 3)
+    pub input_height: Option<u16>,
+    /// Blank columns on either side of the messages, fewer in panes too narrow for them
+    /// (default 0)
+    pub padding: Option<u16>,
+}
+
+impl LayoutConfig {
+    pub fn sidebar(&self) -> Side {
+        self.sidebar.unwrap_or_default()
+    }
+
+    pub fn input(&self) -> Edge {
+        self.input.unwrap_or_default()
+    }
+
+    pub fn input_height(&self) -> u16 {
+        self.input_height.unwrap_or(3).max(3)
+    }
+
+    pub fn padding(&self) -> u16 {
+        self.padding.unwrap_or(0)
+    }
+}
+
+/// `[notifications]`: telling the user a response is ready or a tool call awaits approval.
+/// They fire while the terminal is unfocused, or when the turn took at least `after_secs`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// Desktop notification (default true)
+    pub desktop: Option<bool>,
+    /// Terminal bell (default false)
+    pub bell: Option<bool>,
+    /// Also notify in a focused terminal after turns this long (default 30; 0 always)
+    pub after_secs: Option<u64>,
+}
+
+impl NotificationsConfig {
+    pub fn desktop(&self) -> bool {
+        self.desktop.unwrap_or(true)
+    }
+
+    pub fn bell(&self) -> bool {
+        self.bell.unwrap_or(false)
+    }
+
+    pub fn after(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.after_secs.unwrap_or(30))
+    }
+}
+
+/// `[images]`: pictures made with `/imagine`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Image generation model, default `gemini-2.5-flash-image`
+    pub model: Option<String>,
+    /// Where generated images are saved, default a `gemchat` folder in the pictures directory
+    pub dir: Option<String>,
+    /// Show a generated image in the terminal when it can display graphics (default true)
+    pub preview: Option<bool>,
+}
+
+impl ImagesConfig {
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(crate::ai::IMAGE_MODEL)
+    }
+
+    pub fn dir(&self) -> Option<PathBuf> {
+        match &self.dir {
+            Some(dir) => Some(crate::tools::resolve_path(dir)),
+            None => dirs::picture_dir()
+                .map(|d| d.join("gemchat"))
+                .or_else(|| dirs::data_dir().map(|d| d.join("gemchat").join("images"))),
+        }
+    }
+
+    pub fn preview(&self) -> bool {
+        self.preview.unwrap_or(true)
+    }
+}
+
+/// `[diagrams]`: ```` ```mermaid ```` blocks rendered to images
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DiagramsConfig {
+    /// Mermaid CLI that renders them (default `mmdc`, from `npm install -g
+    /// @mermaid-js/mermaid-cli`)
+    pub mmdc: Option<String>,
+}
+
+impl DiagramsConfig {
+    pub fn mmdc(&self) -> &str {
+        self.mmdc.as_deref().unwrap_or("mmdc")
+    }
+}
+
+/// `[live]`: voice conversations with `gemchat live`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    /// Live API model, default `gemini-2.5-flash-native-audio-preview-09-2025`
+    pub model: Option<String>,
+    /// Prebuilt voice the model speaks with, e.g. `Puck` or `Kore`
+    pub voice: Option<String>,
+}
+
+impl LiveConfig {
+    pub fn model(&self) -> &str {
+        self.model
+            .as_deref()
+            .unwrap_or("gemini-2.5-flash-native-audio-preview-09-2025")
+    }
+}
+
+/// `[share]`: where `/share --gist` uploads transcripts
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShareConfig {
+    /// GitHub token allowed to create gists; `GITHUB_TOKEN` is used when unset
+    pub github_token: Option<String>,
+    /// GitHub API, default `https://api.github.com`; set it for GitHub Enterprise
+    pub api_url: Option<String>,
+}
+
+impl ShareConfig {
+    pub fn github_token(&self) -> Option<String> {
+        self.github_token
+            .clone()
+            .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+            .filter(|t| !t.is_empty())
+    }
+
+    pub fn api_url(&self) -> &str {
+        self.api_url
+            .as_deref()
+            .unwrap_or("https://api.github.com")
+            .trim_end_matches('/')
+    }
+}
+
+/// `[compare]`: who answers `/compare`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CompareConfig {
+    /// Models asked side by side, at most three, each optionally after its provider, e.g.
+    /// `["gemini-2.5-pro", "vertex:gemini-2.5-flash"]`. Defaults to the first three of
+    /// `models`.
+    pub models: Vec<String>,
+}
+
+impl CompareConfig {
+    pub const MAX_MODELS: usize = 3;
+
+    /// Each model to ask with the provider it goes to, `None` meaning the default one
+    pub fn targets(&self, models: &[String]) -> Vec<(Option<Provider>, String)> {
+        let models = if self.models.is_empty() {
+            models
+        } else {
+            &self.models
+        };
+        models
+            .iter()
+            .take(Self::MAX_MODELS)
+            .map(|entry| {
+                match entry
+                    .split_once(':')
+                    .and_then(|(provider, model)| Some((Provider::from_name(provider)?, model)))
+                {
+                    Some((provider, model)) => (Some(provider), model.to_string()),
+                    None => (None, entry.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// `[storage]`: the database of past conversations
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Encrypt message contents at rest (default false), including those already stored.
+    /// Session titles, token usage and the audit log stay readable. Once a database is
+    /// encrypted its key is always needed.
+    pub encrypt: Option<bool>,
+    /// Where the key comes from (default `keyring`)
+    pub key: Option<KeySource>,
+    /// Delete all but this many most recent sessions, at start and on `gemchat gc`.
+    /// Sessions pinned with `/pin` are always kept.
+    pub keep_sessions: Option<usize>,
+    /// Delete sessions not updated for this many days
+    pub keep_days: Option<u64>,
+}
+
+impl StorageConfig {
+    pub fn encrypt(&self) -> bool {
+        self.encrypt.unwrap_or(false)
+    }
+
+    pub fn key(&self) -> KeySource {
+        self.key.unwrap_or(KeySource::Keyring)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// A random key kept in the system keyring
+    Keyring,
+    /// Derived from a passphrase, read from `GEMCHAT_PASSPHRASE` or asked for at start
+    Passphrase,
+}
+
+/// `[generation]`: how responses are sampled
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    /// Alternative responses generated per turn, browsed with `]a`/`[a` (default 1, at
+    /// most 8). Above 1 responses arrive whole instead of streaming.
+    pub candidates: Option<u32>,
+    /// Fixed sampling seed, for repeatable responses; random per request when unset
+    pub seed: Option<i64>,
+    /// Sampling temperature; 0 picks the likeliest tokens. The model's default when unset.
+    pub temperature: Option<f64>,
+    /// Penalizes tokens that already appeared at all (-2 to 2). Not every model takes the
+    /// penalties; those that don't reject the request.
+    pub presence_penalty: Option<f64>,
+    /// Penalizes tokens by how often they already appeared (-2 to 2)
+    pub frequency_penalty: Option<f64>,
+}
+
+impl GenerationConfig {
+    pub fn candidates(&self) -> u32 {
+        self.candidates.unwrap_or(1).clamp(1, 8)
+    }
+
+    /// The parameters that were set, e.g. `seed 42, temperature 0`, to record with each
+    /// response; `None` when everything is left to the model
+    pub fn summary(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(seed) = self.seed {
+            parts.push(format!("seed {}", seed));
+        }
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("temperature {}", temperature));
+        }
+        if let Some(penalty) = self.presence_penalty {
+            parts.push(format!("presence penalty {}", penalty));
+        }
+        if let Some(penalty) = self.frequency_penalty {
+            parts.push(format!("frequency penalty {}", penalty));
+        }
+        if self.candidates() > 1 {
+            parts.push(format!("{} candidates", self.candidates()));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// A category of Gemini's safety filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HarmCategory {
+    Harassment,
+    HateSpeech,
+    SexuallyExplicit,
+    DangerousContent,
+    CivicIntegrity,
+}
+
+impl HarmCategory {
+    pub const ALL: [Self; 5] = [
+        Self::Harassment,
+        Self::HateSpeech,
+        Self::SexuallyExplicit,
+        Self::DangerousContent,
+        Self::CivicIntegrity,
+    ];
+
+    /// As written in the config and `/safety`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Harassment => "harassment",
+            Self::HateSpeech => "hate_speech",
+            Self::SexuallyExplicit => "sexually_explicit",
+            Self::DangerousContent => "dangerous_content",
+            Self::CivicIntegrity => "civic_integrity",
+        }
+    }
+
+    pub fn api_name(self) -> &'static str {
+        match self {
+            Self::Harassment => "HARM_CATEGORY_HARASSMENT",
+            Self::HateSpeech => "HARM_CATEGORY_HATE_SPEECH",
+            Self::SexuallyExplicit => "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+            Self::DangerousContent => "HARM_CATEGORY_DANGEROUS_CONTENT",
+            Self::CivicIntegrity => "HARM_CATEGORY_CIVIC_INTEGRITY",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+/// From which rated probability of harm content is blocked
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Threshold {
+    /// The filter is off and ratings are not returned
+    Off,
+    /// Nothing is blocked, but ratings are still returned
+    BlockNone,
+    BlockOnlyHigh,
+    BlockMediumAndAbove,
+    BlockLowAndAbove,
+}
+
+impl Threshold {
+    pub const ALL: [Self; 5] = [
+        Self::Off,
+        Self::BlockNone,
+        Self::BlockOnlyHigh,
+        Self::BlockMediumAndAbove,
+        Self::BlockLowAndAbove,
+    ];
+
+    /// As written in the config and `/safety`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::BlockNone => "block_none",
+            Self::BlockOnlyHigh => "block_only_high",
+            Self::BlockMediumAndAbove => "block_medium_and_above",
+            Self::BlockLowAndAbove => "block_low_and_above",
+        }
+    }
+
+    pub fn api_name(self) -> &'static str {
+        match self {
+            Self::Off => "OFF",
+            Self::BlockNone => "BLOCK_NONE",
+            Self::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
+            Self::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
+            Self::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// What a model costs, in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    /// Prompt tokens served from a context cache; the input price when unset
+    #[serde(default)]
+    pub cached_input: Option<f64>,
+}
+
+/// `[context]`: project information added to every prompt
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// Include an outline of the repository (files and top-level symbols); off by default.
+    /// `/map` builds or refreshes it on demand.
+    pub repo_map: Option<bool>,
+    /// Rough size limit of the outline in tokens (default 2000)
+    pub repo_map_tokens: Option<usize>,
+    /// Excerpts from the project index (built with `/index`) added to each question
+    /// (default 5; 0 turns retrieval off)
+    pub retrieval_chunks: Option<usize>,
+    /// Keep the stable start of the prompt (instructions, repository map) in a Gemini
+    /// context cache, so later requests pay the cached rate for it; off by default.
+    /// Caches are billed for storage while they live.
+    pub cache: Option<bool>,
+    /// How long a cache lives after it is created, in minutes (default 60, at least 5)
+    pub cache_ttl_minutes: Option<u64>,
+}
+
+impl ContextConfig {
+    pub fn repo_map(&self) -> bool {
+        self.repo_map.unwrap_or(false)
+    }
+
+    pub fn repo_map_tokens(&self) -> usize {
+        self.repo_map_tokens.unwrap_or(2000)
+    }
+
+    pub fn retrieval_chunks(&self) -> usize {
+        self.retrieval_chunks.unwrap_or(5)
+    }
+
+    pub fn cache(&self) -> bool {
+        self.cache.unwrap_or(false)
+    }
+
+    pub fn cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cache_ttl_minutes.unwrap_or(60).max(5) * 60)
+    }
+}
+
+/// Key bindings per focused pane, e.g. `quit = ["q", "ctrl+c"]` or `next_code = ["]c"]`
+/// under `[keys.normal]`. Listing a command replaces its default keys; an empty list
+/// unbinds it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KeysConfig {
+    /// The chat and the `/split` pane
+    pub normal: HashMap<keymap::Command, Vec<String>>,
+    /// The input
+    pub editing: HashMap<keymap::Command, Vec<String>>,
+    /// The sidebar's session list
+    pub sidebar: HashMap<keymap::Command, Vec<String>>,
+}
+
+/// Scrubbing of secrets from prompts, tool results and logs
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// On by default
+    pub enabled: Option<bool>,
+    /// Extra regexes to redact; a `(?P<secret>...)` group limits the replacement to that part
+    pub patterns: Vec<String>,
+    /// Environment variables whose values are redacted even if their names look harmless
+    pub env_vars: Vec<String>,
+}
+
+impl RedactConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Sh,
+    Cmd,
+    Powershell,
+    Pwsh,
+}
+
+impl Shell {
+    pub fn platform_default() -> Self {
+        if cfg!(windows) { Shell::Cmd } else { Shell::Sh }
+    }
+
+    /// Program and leading arguments; the command string is appended last
+    pub fn invocation(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Shell::Sh => ("sh", &["-c"]),
+            Shell::Cmd => ("cmd", &["/C"]),
+            Shell::Powershell => ("powershell", &["-NoProfile", "-NonInteractive", "-Command"]),
+            Shell::Pwsh => ("pwsh", &["-NoProfile", "-NonInteractive", "-Command"]),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Sh => "sh",
+            Shell::Cmd => "cmd.exe",
+            Shell::Powershell => "Windows PowerShell",
+            Shell::Pwsh => "PowerShell",
+        }
+    }
+}
+
+impl ToolsConfig {
+    pub fn shell(&self) -> Shell {
+        self.shell.unwrap_or_else(Shell::platform_default)
+    }
+
+    pub fn max_parallel(&self) -> usize {
+        self.max_parallel.unwrap_or(4).max(1)
+    }
+
+    pub fn max_output_lines(&self) -> usize {
+        self.max_output_lines.unwrap_or(400).max(2)
+    }
+
+    pub fn max_output_bytes(&self) -> usize {
+        self.max_output_bytes.unwrap_or(32 * 1024).max(256)
+    }
+
+    pub fn max_rounds(&self) -> usize {
+        self.max_rounds.unwrap_or(25).max(1)
+    }
+
+    pub fn max_repeats(&self) -> usize {
+        self.max_repeats.unwrap_or(3).max(1)
+    }
+}
+
+impl Config {
+    /// The first choice model
+    pub fn model(&self) -> &str {
+        self.models.first().map_or(crate::ai::MODEL, String::as_str)
+    }
+
+    /// Loads the config from `path`, or from the default location when `None`.
+    /// A missing file yields the default config.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(p) => p.to_path_buf(),
+            None => match default_path() {
+                Some(p) => p,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let mut config: Self = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .wrap_err_with(|| format!("Invalid config file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Could not read {}", path.display()));
+            }
+        };
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// The active profile's settings
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.get(self.active_profile.as_deref()?)
+    }
+
+    /// Switches to profile `name`, or back to the top-level settings with `None`
+    pub fn use_profile(&mut self, name: Option<&str>) -> Result<()> {
+        let profile = match name {
+            Some(name) => self.profile.get(name).cloned().ok_or_else(|| {
+                let names: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+                if names.is_empty() {
+                    eyre!(
+                        "No profile `{}`: the config file has no [profile.<name>] tables",
+                        name
+                    )
+                } else {
+                    eyre!("No profile `{}`; there are {}", name, names.join(", "))
+                }
+            })?,
+            None => Profile::default(),
+        };
+        let top = match self.top_level.take() {
+            Some(top) => top,
+            None => Box::new(self.clone()),
+        };
+        self.provider = profile.provider.or(top.provider);
+        self.models = profile.models.unwrap_or_else(|| top.models.clone());
+        self.vertex = profile.vertex.unwrap_or_else(|| top.vertex.clone());
+        self.system_prompt = profile.system_prompt.or_else(|| top.system_prompt.clone());
+        self.tools.approval = profile
+            .approval
+            .unwrap_or_else(|| top.tools.approval.clone());
+        self.top_level = Some(top);
+        self.active_profile = name.map(str::to_string);
+        Ok(())
+    }
+
+    /// Adds `rule` to the allow list, both for this session and in the config file: under the
+    /// session's `template` when it has its own `approval`, else under the active profile
+    /// when that has one, else under `[tools.approval]`
+    pub fn save_allow_rule(&mut self, rule: AllowRule, template: Option<&str>) -> Result<()> {
+        let template = template.filter(|name| {
+            self.template
+                .get(*name)
+                .is_some_and(|t| t.approval.is_some())
+        });
+        let profile = self
+            .active_profile
+            .clone()
+            .filter(|_| template.is_none())
+            .filter(|_| self.profile().is_some_and(|p| p.approval.is_some()));
+        let table = match (template, profile.as_deref()) {
+            (Some(name), _) => vec!["template", name, "approval"],
+            (None, Some(name)) => vec!["profile", name, "approval"],
+            (None, None) => vec!["tools", "approval"],
+        };
+        if let Some(path) = &self.path {
+            edit_file(path, |doc| {
+                let mut entry = toml_edit::Table::new();
+                entry["tool"] = toml_edit::value(rule.tool.as_str());
+                if let Some(prefix) = &rule.prefix {
+                    entry["prefix"] = toml_edit::value(prefix.as_str());
+                }
+                if let Some(dir) = &rule.path {
+                    entry["path"] = toml_edit::value(dir.as_str());
+                }
+                allow_list(doc, &table)?.push(entry);
+                Ok(())
+            })?;
+        }
+        if let Some(name) = template {
+            // A template's rules replace the others, so only they change
+            if let Some(approval) = self
+                .template
+                .get_mut(name)
+                .and_then(|t| t.approval.as_mut())
+            {
+                approval.allow.push(rule);
+            }
+            return Ok(());
+        }
+        match &profile {
+            Some(name) => {
+                if let Some(approval) = self.profile.get_mut(name).and_then(|p| p.approval.as_mut())
+                {
+                    approval.allow.push(rule.clone());
+                }
+            }
+            None => {
+                if let Some(top) = &mut self.top_level {
+                    top.tools.approval.allow.push(rule.clone());
+                }
+            }
+        }
+        self.tools.approval.allow.push(rule);
+        Ok(())
+    }
+
+    /// Writes `api_key` to the config file, which is made readable by the owner only
+    pub fn save_api_key(&mut self, key: &str) -> Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| eyre!("No config directory to save the key in"))?;
+        edit_file(path, |doc| {
+            doc["api_key"] = toml_edit::value(key);
+            Ok(())
+        })?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        self.api_key = Some(key.to_string());
+        Ok(())
+    }
+}
+
+/// Applies `edit` to the config file at `path`, creating it if needed. The file is edited
+/// in place so the user's comments and layout survive.
+fn edit_file(
+    path: &Path,
+    edit: impl FnOnce(&mut toml_edit::DocumentMut) -> Result<()>,
+) -> Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("Could not read {}", path.display()));
+        }
+    };
+    let mut doc: toml_edit::DocumentMut = text
+        .parse()
+        .wrap_err_with(|| format!("Invalid config file {}", path.display()))?;
+    edit(&mut doc)?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, doc.to_string())
+        .wrap_err_with(|| format!("Could not write {}", path.display()))
+}
+
+/// The `allow` list in the approval table at `path` in `doc`, created if missing
+fn allow_list<'a>(
+    doc: &'a mut toml_edit::DocumentMut,
+    path: &[&str],
+) -> Result<&'a mut toml_edit::ArrayOfTables> {
+    let mut table = doc.as_table_mut();
+    for &key in path {
+        let item = table.entry(key).or_insert_with(|| {
+            let mut t = toml_edit::Table::new();
+            t.set_implicit(true);
+            toml_edit::Item::Table(t)
+        });
+        table = item
+            .as_table_mut()
+            .ok_or_else(|| eyre!("`{}` in the config file is not a table", key))?;
+    }
+    table
+        .entry("allow")
+        .or_insert_with(|| toml_edit::Item::ArrayOfTables(toml_edit::ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| eyre!("The approval allow list in the config file is not a list of tables"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("gemchat").join("config.toml"))
+}
+
+/// Where logs go: the XDG state dir where there is one, the local data dir elsewhere
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|d| d.join("gemchat"))
+}
+
+/// Where data that can be rebuilt goes (project indexes)
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("gemchat"))
+}
+
+/// Where gemchat keeps state that outlives a session (memories, logs, ...)
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("gemchat"))
+}
@@ -0,0 +1,125 @@
+/// One line of a conversation. A fork starts with a copy of its parent's messages up to the
+/// fork point, so every branch holds its whole history.
+pub struct Branch<M> {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Messages copied from the parent
+    pub fork_at: usize,
+    /// Empty for the current branch, whose messages are checked out to the caller
+    messages: Vec<M>,
+    /// Unsent input, kept while another branch is checked out
+    draft: String,
+}
+
+impl<M> Branch<M> {
+    /// A stored branch; `messages` are its whole history
+    pub fn new(name: String, parent: Option<usize>, fork_at: usize, messages: Vec<M>) -> Self {
+        Self {
+            name,
+            parent,
+            fork_at,
+            messages,
+            draft: String::new(),
+        }
+    }
+}
+
+/// A conversation and its forks. The current branch's messages live with the caller, which
+/// hands them over on every fork or switch.
+pub struct Tree<M> {
+    branches: Vec<Branch<M>>,
+    current: usize,
+}
+
+impl<M: Clone> Tree<M> {
+    pub fn new() -> Self {
+        Self {
+            branches: vec![Branch {
+                name: "main".into(),
+                parent: None,
+                fork_at: 0,
+                messages: Vec::new(),
+                draft: String::new(),
+            }],
+            current: 0,
+        }
+    }
+
+    /// A tree of previously saved branches with `current` checked out; returns its
+    /// messages, which the caller holds from then on
+    pub fn restore(mut branches: Vec<Branch<M>>, current: usize) -> (Self, Vec<M>) {
+        if branches.is_empty() {
+            return (Self::new(), Vec::new());
+        }
+        let current = current.min(branches.len() - 1);
+        let messages = std::mem::take(&mut branches[current].messages);
+        (Self { branches, current }, messages)
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn branch(&self, index: usize) -> &Branch<M> {
+        &self.branches[index]
+    }
+
+    /// Messages in a branch that isn't checked out
+    pub fn stored_len(&self, index: usize) -> usize {
+        self.branches[index].messages.len()
+    }
+
+    /// Keeps the current branch as it is, with `draft` as its unsent input, and continues
+    /// in a new one that starts with the first `keep` of `messages`. Returns the new
+    /// branch's index.
+    pub fn fork(&mut self, messages: &mut Vec<M>, draft: String, keep: usize) -> usize {
+        let keep = keep.min(messages.len());
+        let forked = messages[..keep].to_vec();
+        let branch = &mut self.branches[self.current];
+        branch.messages = std::mem::replace(messages, forked);
+        branch.draft = draft;
+        self.branches.push(Branch {
+            name: format!("fork {}", self.branches.len()),
+            parent: Some(self.current),
+            fork_at: keep,
+            messages: Vec::new(),
+            draft: String::new(),
+        });
+        self.current = self.branches.len() - 1;
+        self.current
+    }
+
+    /// Stores `messages` and `draft` in the current branch and checks out branch `to` in
+    /// their place
+    pub fn switch(&mut self, messages: &mut Vec<M>, draft: &mut String, to: usize) {
+        if to == self.current || to >= self.branches.len() {
+            return;
+        }
+        let branch = &mut self.branches[self.current];
+        branch.messages = std::mem::take(messages);
+        branch.draft = std::mem::take(draft);
+        *messages = std::mem::take(&mut self.branches[to].messages);
+        *draft = std::mem::take(&mut self.branches[to].draft);
+        self.current = to;
+    }
+
+    /// Every branch as `(index, depth)`, each followed by its forks
+    pub fn outline(&self) -> Vec<(usize, usize)> {
+        let mut outline = Vec::with_capacity(self.branches.len());
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            outline.push((index, depth));
+            // Reversed so the oldest fork is visited first
+            for child in (0..self.branches.len()).rev() {
+                if self.branches[child].parent == Some(index) {
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+        outline
+    }
+}
@@ -0,0 +1,57 @@
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Info strings of code blocks that are mermaid diagrams
+pub const MERMAID_LANGS: &[&str] = &["mermaid", "mmd"];
+
+/// Renders the mermaid `source` to a PNG with the mermaid CLI `mmdc`. Images are kept in
+/// `dir`, such as `diagrams` in the cache dir, under a name taken from the source, so
+/// showing the same diagram again doesn't render it again.
+pub async fn render_mermaid(mmdc: &str, source: &str, dir: &Path) -> Result<PathBuf> {
+    let name = hex::encode(&Sha256::digest(source.as_bytes())[..8]);
+    let image = dir.join(format!("{}.png", name));
+    if image.exists() {
+        return Ok(image);
+    }
+    tokio::fs::create_dir_all(dir)
+        .await
+        .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    let input = dir.join(format!("{}.mmd", name));
+    tokio::fs::write(&input, source)
+        .await
+        .wrap_err_with(|| format!("Could not write {}", input.display()))?;
+
+    let output = Command::new(mmdc)
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&image)
+        // Transparent backgrounds disappear in dark viewers
+        .args(["--backgroundColor", "white", "--quiet"])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    let _ = tokio::fs::remove_file(&input).await;
+    let output = match output {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(eyre!(
+                "{} not found: install it with `npm install -g @mermaid-js/mermaid-cli` or set \
+                 `mmdc` under [diagrams]",
+                mmdc
+            ));
+        }
+        output => output.wrap_err_with(|| format!("Could not run {}", mmdc))?,
+    };
+    if !output.status.success() {
+        return Err(eyre!(
+            "{} failed: {}",
+            mmdc,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(image)
+}
@@ -0,0 +1,108 @@
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use tokio::process::Command;
+
+/// Diffs are cut to this many characters before they are sent to the model
+const MAX_DIFF_CHARS: usize = 60_000;
+
+/// Runs git in the current directory and returns its stdout
+async fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .await
+        .wrap_err("Could not run git")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The staged changes; an error when nothing is staged
+pub async fn staged_diff() -> Result<String> {
+    let diff = git(&["diff", "--cached", "--no-color", "--no-ext-diff"]).await?;
+    if diff.trim().is_empty() {
+        return Err(eyre!("Nothing is staged; `git add` the changes first"));
+    }
+    Ok(diff)
+}
+
+/// Changes against `reference` (default `HEAD`), or only the staged ones; an error when
+/// there are none
+pub async fn diff(reference: Option<&str>, staged: bool) -> Result<String> {
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff"];
+    if staged {
+        args.push("--cached");
+    }
+    // Staged changes are compared with HEAD anyway, and a new repository has no HEAD yet
+    if let Some(reference) = reference.or((!staged).then_some("HEAD")) {
+        args.push(reference);
+    }
+    args.push("--");
+    let diff = git(&args).await?;
+    if diff.trim().is_empty() {
+        return Err(eyre!("No changes to review"));
+    }
+    Ok(diff)
+}
+
+/// Commits the staged changes; returns git's one-line summary
+pub async fn commit(message: &str) -> Result<String> {
+    let output = git(&["commit", "--message", message]).await?;
+    Ok(output.lines().next().unwrap_or_default().to_string())
+}
+
+/// Commits the staged changes after letting the user edit `message` in git's editor
+pub async fn commit_in_editor(message: &str) -> Result<()> {
+    let status = Command::new("git")
+        .args(["commit", "--edit", "--message", message])
+        .status()
+        .await
+        .wrap_err("Could not run git")?;
+    if !status.success() {
+        return Err(eyre!("git commit failed"));
+    }
+    Ok(())
+}
+
+/// Prompt asking for a Conventional Commits message describing `diff`
+pub fn commit_prompt(diff: &str) -> String {
+    format!(
+        "Write a git commit message for the staged changes below, following Conventional \
+         Commits: a `type(scope): summary` subject of at most 72 characters in the imperative \
+         mood, then a blank line and a short body explaining what changed and why when the \
+         subject alone isn't enough. Reply with the message only.\n\n{}",
+        truncate_diff(diff)
+    )
+}
+
+/// The commit message in a model reply, without code fences or surrounding blank lines
+pub fn commit_message(reply: &str) -> String {
+    let reply = reply.trim();
+    let reply = match reply.strip_prefix("```") {
+        // Drop the info string line and the closing fence
+        Some(fenced) => fenced
+            .split_once('\n')
+            .map_or("", |(_, body)| body)
+            .trim_end()
+            .trim_end_matches("```"),
+        None => reply,
+    };
+    reply.trim().to_string()
+}
+
+/// `diff`, cut to a size worth sending to the model
+pub fn truncate_diff(diff: &str) -> String {
+    match diff.char_indices().nth(MAX_DIFF_CHARS) {
+        Some((cut, _)) => format!(
+            "{}\n[diff truncated: {} more characters not shown]",
+            &diff[..cut],
+            diff.len() - cut
+        ),
+        None => diff.to_string(),
+    }
+}
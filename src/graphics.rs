@@ -0,0 +1,108 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crossterm::{cursor, queue};
+use ratatui::layout::Rect;
+use std::env;
+use std::io::{self, Write};
+
+/// Escape sequences a terminal understands for showing images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// kitty, Ghostty
+    Kitty,
+    /// iTerm2, WezTerm
+    Iterm,
+}
+
+/// The terminal's graphics protocol, judged from its environment. Inside tmux the escapes
+/// don't reach the terminal, so there is none.
+pub fn detect() -> Option<Protocol> {
+    let var = |name| env::var(name).unwrap_or_default();
+    if env::var_os("TMUX").is_some() {
+        return None;
+    }
+    if env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("TERM") == "xterm-kitty"
+        || var("TERM_PROGRAM") == "ghostty"
+    {
+        Some(Protocol::Kitty)
+    } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm")
+        || var("LC_TERMINAL") == "iTerm2"
+    {
+        Some(Protocol::Iterm)
+    } else {
+        None
+    }
+}
+
+/// Draws the image in `data` over `area`, as large as fits without distorting it
+pub fn show(protocol: Protocol, data: &[u8], area: Rect) -> io::Result<()> {
+    let (columns, rows) = fit(data, area);
+    let encoded = STANDARD.encode(data);
+    let mut out = io::stdout().lock();
+    queue!(out, cursor::SavePosition, cursor::MoveTo(area.x, area.y))?;
+    match protocol {
+        Protocol::Kitty => {
+            // Sent in chunks of at most 4096 bytes; `q=2` stops the terminal from replying
+            let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = u8::from(i + 1 < chunks.len());
+                if i == 0 {
+                    write!(
+                        out,
+                        "\x1b_Gf=100,a=T,q=2,c={},r={},m={};",
+                        columns, rows, more
+                    )?;
+                } else {
+                    write!(out, "\x1b_Gm={};", more)?;
+                }
+                out.write_all(chunk)?;
+                write!(out, "\x1b\\")?;
+            }
+        }
+        Protocol::Iterm => write!(
+            out,
+            "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+            data.len(),
+            columns,
+            rows,
+            encoded
+        )?,
+    }
+    queue!(out, cursor::RestorePosition)?;
+    out.flush()
+}
+
+/// Removes images drawn with [`show`]. iTerm2 images are simply drawn over.
+pub fn clear(protocol: Protocol) -> io::Result<()> {
+    if protocol == Protocol::Kitty {
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b_Ga=d,q=2\x1b\\")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Columns and rows the image takes in `area`, taking cells to be twice as tall as wide.
+/// Images whose size can't be read from a PNG header fill the area.
+fn fit(data: &[u8], area: Rect) -> (u16, u16) {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    let size = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64)
+    };
+    let (Some(width), Some(height)) = (size(16), size(20)) else {
+        return (area.width, area.height);
+    };
+    if !data.starts_with(SIGNATURE) || width == 0 || height == 0 {
+        return (area.width, area.height);
+    }
+    let columns = area.width as u64;
+    let rows = columns * height / width / 2;
+    if rows <= area.height as u64 {
+        (area.width, rows.max(1) as u16)
+    } else {
+        let columns = area.height as u64 * 2 * width / height;
+        (columns.max(1) as u16, area.height)
+    }
+}
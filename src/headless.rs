@@ -0,0 +1,215 @@
+use crate::{
+    AFTER_TOOLS, approval_rules, execute_call, system_blocks, template_files, tool_summary,
+};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use futures_util::{StreamExt, stream};
+use gemchat::{ai, approval, audit, config, redact, repomap, tools};
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+
+pub struct Options {
+    /// Run every tool call instead of only those that need no approval
+    pub yes: bool,
+    /// Model requests allowed before giving up
+    pub max_steps: usize,
+    /// `[template.<name>]` to start from, as a new session in the TUI would
+    pub template: Option<String>,
+}
+
+/// `gemchat run`: the agent loop without the TUI. The model's text streams to stdout with
+/// a line per tool call; calls that would need approval are declined unless `yes` is set.
+pub async fn run(
+    task: &str,
+    config: &config::Config,
+    redactor: &redact::Redactor,
+    options: Options,
+) -> Result<()> {
+    let template = match options.template.as_deref() {
+        Some(name) => Some(config.template.get(name).ok_or_else(|| {
+            let names: Vec<&str> = config.template.keys().map(String::as_str).collect();
+            if names.is_empty() {
+                eyre!(
+                    "No template `{}`: add `[template.<name>]` tables to the config file",
+                    name
+                )
+            } else {
+                eyre!("No template `{}`; there are {}", name, names.join(", "))
+            }
+        })?),
+        None => None,
+    };
+    // The template's model goes first, with the configured ones as fallbacks
+    let mut models = config.models.clone();
+    if let Some(model) = template.and_then(|t| t.model.as_ref()) {
+        models.retain(|m| m != model);
+        models.insert(0, model.clone());
+    }
+    let rules = approval_rules(config, options.template.as_deref());
+    let repo_map = if config.context.repo_map() {
+        let budget = config.context.repo_map_tokens();
+        let root = std::env::current_dir()?;
+        Some(tokio::task::spawn_blocking(move || repomap::build(&root, budget)).await?)
+    } else {
+        None
+    };
+    let mut history = format!("User: {}\n\n", task);
+    let mut outcomes: Vec<ai::ToolOutcome> = Vec::new();
+
+    // The same for every step, so it can be served from a context cache
+    let system = redactor.redact_blocks(system_blocks(
+        config,
+        template,
+        &template_files(template),
+        repo_map.as_deref(),
+    ));
+    for _ in 0..options.max_steps {
+        let mut prompt = history.clone();
+        // As in the TUI, this turn's results go as functionResponse parts rather than text
+        if !outcomes.is_empty() {
+            prompt.push_str(AFTER_TOOLS);
+        }
+        for outcome in &outcomes {
+            history.push_str(&format!(
+                "Tool Result: {}({}) returned:\n{}\n\n",
+                outcome.call.name, outcome.call.args, outcome.result
+            ));
+        }
+
+        // The task opens the turn, which every step extends
+        let mut blocks = system.clone();
+        blocks.push(ai::ContextBlock::new(
+            ai::BlockKind::History,
+            "Conversation History:\n",
+        ));
+        blocks.push(ai::ContextBlock::new(
+            ai::BlockKind::Turn,
+            redactor.redact(&prompt).into_owned(),
+        ));
+        let request = ai::Request {
+            outcomes: outcomes
+                .into_iter()
+                .map(|o| ai::ToolOutcome {
+                    result: redactor.redact(&o.result).into_owned(),
+                    call: o.call,
+                })
+                .collect(),
+            extra_tools: tools::custom_declarations(&config.tools),
+            models: models.clone(),
+            without_tools: false,
+            safety: config.safety.clone(),
+            provider: template.and_then(|t| t.provider).or(config.provider),
+            generation: config::GenerationConfig {
+                candidates: None,
+                ..config.generation.clone()
+            },
+            ..ai::Request::from_blocks(blocks)
+        };
+        let (text, calls) = stream_turn(request).await?;
+        if !text.is_empty() {
+            history.push_str(&format!("AI: {}\n\n", text));
+        }
+        if calls.is_empty() {
+            return Ok(());
+        }
+        outcomes = run_tools(calls, config, rules, redactor, options.yes).await;
+    }
+    Err(eyre!(
+        "Stopped after {} requests without finishing (see --max-steps)",
+        options.max_steps
+    ))
+}
+
+/// Streams one response to stdout; returns its text and tool calls
+pub async fn stream_turn(request: ai::Request) -> Result<(String, Vec<ai::ToolCall>)> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(ai::stream_response(request, tx));
+
+    let mut stdout = io::stdout();
+    let mut text = String::new();
+    let mut calls = Vec::new();
+    while let Some(update) = rx.recv().await {
+        match update {
+            ai::AiUpdate::Content(chunk) => {
+                write!(stdout, "{}", chunk)?;
+                stdout.flush()?;
+                text.push_str(&chunk);
+            }
+            ai::AiUpdate::ToolCall(call) => calls.push(call),
+            ai::AiUpdate::Error(e) => return Err(eyre!(e)),
+            ai::AiUpdate::Interrupted(reason) => {
+                return Err(eyre!("The response was cut off: {}", reason));
+            }
+            ai::AiUpdate::Model(model) => tracing::info!(%model, "answering"),
+            ai::AiUpdate::RateLimited(wait) => {
+                eprintln!("rate limited, sending in {}s", wait.as_secs_f32().ceil());
+            }
+            ai::AiUpdate::Usage(usage) => {
+                tracing::info!(
+                    total_tokens = usage.total_tokens,
+                    cached_tokens = usage.cached_tokens,
+                    "usage"
+                )
+            }
+            ai::AiUpdate::Safety(report) => match report.blocked {
+                Some(reason) => {
+                    return Err(eyre!(
+                        "The safety filters blocked the response ({})",
+                        reason
+                    ));
+                }
+                None => tracing::warn!(ratings = ?report.ratings, "response flagged"),
+            },
+            // Only one candidate is asked for
+            ai::AiUpdate::Alternatives(_) => {}
+            ai::AiUpdate::Finished => break,
+        }
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        writeln!(stdout)?;
+    }
+    Ok((text, calls))
+}
+
+/// Runs the calls like the TUI does, checked against `rules`, with `--yes` standing in for
+/// the approval prompt
+async fn run_tools(
+    calls: Vec<ai::ToolCall>,
+    config: &config::Config,
+    rules: &config::ApprovalConfig,
+    redactor: &redact::Redactor,
+    yes: bool,
+) -> Vec<ai::ToolOutcome> {
+    let calls: Vec<_> = calls
+        .into_iter()
+        .map(|call| {
+            let decision = approval::check(&call, rules).unwrap_or(if yes {
+                audit::Approval::User
+            } else {
+                audit::Approval::Denied
+            });
+            let note = if decision == audit::Approval::Denied {
+                " (declined: needs approval; pass --yes or add an allow rule)"
+            } else {
+                ""
+            };
+            println!("» {} {}{}", call.name, tool_summary(&call.args), note);
+            (call, decision)
+        })
+        .collect();
+
+    stream::iter(calls)
+        .map(|(call, decision)| async move {
+            let (outcome, recorded) = execute_call(call, decision, &config.tools, redactor).await;
+            if let Err(e) = recorded {
+                eprintln!("Audit log: {}", e);
+            }
+            ai::ToolOutcome {
+                result: tools::truncate_output(&outcome.result, &config.tools),
+                call: outcome.call,
+            }
+        })
+        .buffered(config.tools.max_parallel())
+        .collect()
+        .await
+}
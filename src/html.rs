@@ -0,0 +1,247 @@
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements that never carry page content worth reading
+const SKIPPED: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "svg", "canvas", "iframe", "nav", "header",
+    "footer", "aside", "form", "button", "select",
+];
+
+pub struct Page {
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// Converts an HTML document into readable markdown-ish text. Navigation, scripts and
+/// other boilerplate are dropped, and `<main>`/`<article>` is preferred over the whole body.
+pub fn to_markdown(html: &str, base: Option<&Url>) -> Page {
+    let doc = Html::parse_document(html);
+
+    let title = Selector::parse("title")
+        .ok()
+        .and_then(|sel| doc.select(&sel).next())
+        .map(|t| collapse_whitespace(&t.text().collect::<String>()))
+        .filter(|t| !t.is_empty());
+
+    let root = ["main", "article", "[role=main]", "body"]
+        .iter()
+        .filter_map(|s| Selector::parse(s).ok())
+        .find_map(|sel| doc.select(&sel).next())
+        .unwrap_or_else(|| doc.root_element());
+
+    let mut writer = Writer {
+        out: String::new(),
+        base,
+        list_depth: 0,
+        in_pre: false,
+    };
+    writer.children(root);
+
+    Page {
+        title,
+        text: tidy(&writer.out),
+    }
+}
+
+struct Writer<'u> {
+    out: String,
+    base: Option<&'u Url>,
+    list_depth: usize,
+    in_pre: bool,
+}
+
+impl Writer<'_> {
+    fn children(&mut self, el: ElementRef) {
+        for child in el.children() {
+            match child.value() {
+                Node::Text(t) => self.text(t),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, el: ElementRef) {
+        let name = el.value().name();
+        if SKIPPED.contains(&name) || el.value().attr("hidden").is_some() {
+            return;
+        }
+
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                self.block_break();
+                self.out.push_str(&"#".repeat(level));
+                self.out.push(' ');
+                self.children(el);
+                self.block_break();
+            }
+            "br" => self.out.push('\n'),
+            "hr" => {
+                self.block_break();
+                self.out.push_str("---");
+                self.block_break();
+            }
+            "ul" | "ol" => {
+                // Nested lists continue their parent item instead of starting a new block
+                let nested = self.list_depth > 0;
+                self.list_depth += 1;
+                if nested {
+                    self.line_break();
+                } else {
+                    self.block_break();
+                }
+                self.children(el);
+                self.list_depth -= 1;
+                if nested {
+                    self.line_break();
+                } else {
+                    self.block_break();
+                }
+            }
+            "li" => {
+                self.line_break();
+                self.out
+                    .push_str(&"  ".repeat(self.list_depth.saturating_sub(1)));
+                self.out.push_str("- ");
+                self.children(el);
+                self.line_break();
+            }
+            "pre" => {
+                self.block_break();
+                self.out.push_str("```\n");
+                self.in_pre = true;
+                self.children(el);
+                self.in_pre = false;
+                self.line_break();
+                self.out.push_str("```");
+                self.block_break();
+            }
+            "code" if !self.in_pre => {
+                self.out.push('`');
+                self.children(el);
+                self.out.push('`');
+            }
+            "strong" | "b" => self.wrapped(el, "**"),
+            "em" | "i" => self.wrapped(el, "*"),
+            "a" => self.link(el),
+            "tr" => {
+                self.line_break();
+                self.out.push_str("| ");
+                self.children(el);
+            }
+            "td" | "th" => {
+                self.children(el);
+                self.out.push_str(" | ");
+            }
+            "p" | "div" | "section" | "article" | "main" | "blockquote" | "table" | "figure"
+            | "dl" | "dt" | "dd" => {
+                self.block_break();
+                self.children(el);
+                self.block_break();
+            }
+            _ => self.children(el),
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.in_pre {
+            self.out.push_str(text);
+            return;
+        }
+
+        let collapsed = collapse_whitespace(text);
+        if collapsed.is_empty() {
+            if !text.is_empty() && !self.out.ends_with(char::is_whitespace) {
+                self.out.push(' ');
+            }
+            return;
+        }
+        if text.starts_with(char::is_whitespace) && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+        self.out.push_str(&collapsed);
+        if text.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+    }
+
+    fn wrapped(&mut self, el: ElementRef, marker: &str) {
+        let inner = collapse_whitespace(&el.text().collect::<String>());
+        if inner.is_empty() {
+            return;
+        }
+        self.out.push_str(marker);
+        self.out.push_str(&inner);
+        self.out.push_str(marker);
+    }
+
+    fn link(&mut self, el: ElementRef) {
+        let text = collapse_whitespace(&el.text().collect::<String>());
+        let href = el
+            .value()
+            .attr("href")
+            .filter(|h| !h.starts_with('#') && !h.starts_with("javascript:"))
+            .map(|h| match self.base {
+                Some(base) => base.join(h).map(String::from).unwrap_or_else(|_| h.into()),
+                None => h.to_string(),
+            });
+
+        match href {
+            Some(href) if !text.is_empty() => {
+                self.out.push_str(&format!("[{}]({})", text, href));
+            }
+            _ => self.out.push_str(&text),
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.trim_trailing_spaces();
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn block_break(&mut self) {
+        self.trim_trailing_spaces();
+        if self.out.is_empty() {
+            return;
+        }
+        while !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let len = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(len);
+    }
+}
+
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Strips trailing whitespace and squeezes runs of blank lines down to one
+fn tidy(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank_run = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.trim().to_string()
+}
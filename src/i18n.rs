@@ -0,0 +1,787 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Languages the interface can be shown in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    English,
+    German,
+}
+
+impl Locale {
+    pub const ALL: &[Locale] = &[Locale::English, Locale::German];
+
+    /// The locale a name like `de`, `de_DE.UTF-8` or `de-AT` asks for, if it has a catalog
+    pub fn parse(name: &str) -> Option<Self> {
+        let language = name.split(['_', '-', '.', '@']).next()?;
+        match language.to_ascii_lowercase().as_str() {
+            "en" | "c" | "posix" => Some(Locale::English),
+            "de" => Some(Locale::German),
+            _ => None,
+        }
+    }
+
+    /// The locale of the environment: the first of `LC_ALL`, `LC_MESSAGES` and `LANG` that
+    /// is set, as gettext picks it, and English without a catalog for it
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// [`Self::detect`] reading variables through `var`
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(var)
+            .find(|value| !value.is_empty())
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or(Locale::English)
+    }
+
+    /// Translations of the English strings, which are their own keys
+    pub fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::English => &[],
+            Locale::German => GERMAN,
+        }
+    }
+
+    /// `english` in this locale, or as it is when the catalog lacks it
+    pub fn translate(self, english: &str) -> &str {
+        static GERMAN_INDEX: LazyLock<HashMap<&str, &str>> =
+            LazyLock::new(|| GERMAN.iter().copied().collect());
+        let index = match self {
+            Locale::English => return english,
+            Locale::German => &GERMAN_INDEX,
+        };
+        index.get(english).copied().unwrap_or(english)
+    }
+}
+
+/// The interface's locale, as an index into [`Locale::ALL`]
+static LOCALE: AtomicU8 = AtomicU8::new(0);
+
+/// Shows the interface in `locale` from now on
+pub fn set_locale(locale: Locale) {
+    let index = Locale::ALL.iter().position(|l| *l == locale).unwrap_or(0);
+    LOCALE.store(index as u8, Ordering::Relaxed);
+}
+
+pub fn locale() -> Locale {
+    Locale::ALL[LOCALE.load(Ordering::Relaxed) as usize]
+}
+
+/// `english` in the interface's locale
+pub fn tr(english: &str) -> &str {
+    locale().translate(english)
+}
+
+/// `template` with its `{}`s replaced by `args` in order, for translated strings that
+/// can't go through `format!`
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    out.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+const GERMAN: &[(&str, &str)] = &[
+    // Panes and popups
+    ("Chat", "Chat"),
+    ("Sidebar", "Seitenleiste"),
+    ("Sessions", "Sitzungen"),
+    ("Sessions:", "Sitzungen:"),
+    ("Input", "Eingabe"),
+    ("Input — 1 attachment", "Eingabe — 1 Anhang"),
+    ("Input — {} attachments", "Eingabe — {} Anhänge"),
+    (
+        "Type message... (Enter to send, Esc to quit)",
+        "Nachricht eingeben … (Enter sendet, Esc beendet)",
+    ),
+    ("Split", "Geteilt"),
+    ("Compare", "Vergleich"),
+    ("Help", "Hilfe"),
+    ("Setup", "Einrichtung"),
+    ("API key", "API-Schlüssel"),
+    ("Paste your API key", "API-Schlüssel einfügen"),
+    ("Key rejected: {}", "Schlüssel abgelehnt: {}"),
+    ("Check and save", "Prüfen und speichern"),
+    (
+        "Continue without a key (mock responses)",
+        "Ohne Schlüssel weiter (simulierte Antworten)",
+    ),
+    ("Commit message", "Commit-Nachricht"),
+    (
+        "Search all conversations",
+        "Alle Unterhaltungen durchsuchen",
+    ),
+    ("Words to find", "Gesuchte Wörter"),
+    ("Type to search", "Tippen, um zu suchen"),
+    ("Search failed: {}", "Suche fehlgeschlagen: {}"),
+    (
+        "{} matches — ↑/↓: Move, Enter: Open, Esc: Close",
+        "{} Treffer — ↑/↓: Bewegen, Enter: Öffnen, Esc: Schließen",
+    ),
+    ("Preview", "Vorschau"),
+    ("Details", "Details"),
+    ("Save as", "Speichern unter"),
+    ("Code block", "Codeblock"),
+    ("Code block ({})", "Codeblock ({})"),
+    ("Tool loop guard", "Schleifenschutz für Werkzeuge"),
+    ("Paused: {}.", "Angehalten: {}."),
+    (
+        "Allow tool call? ({} of {})",
+        "Werkzeugaufruf erlauben? ({} von {})",
+    ),
+    ("Latency trace", "Latenzverlauf"),
+    (" — {} hides", " — {} blendet aus"),
+    ("No turns yet", "Noch keine Runden"),
+    (
+        "New Session — j/k: Move, Enter: Start, Esc: Close",
+        "Neue Sitzung — j/k: Bewegen, Enter: Starten, Esc: Schließen",
+    ),
+    (
+        "Bookmarks — j/k: Move, Enter: Jump, Esc: Close",
+        "Lesezeichen — j/k: Bewegen, Enter: Springen, Esc: Schließen",
+    ),
+    (
+        "Usage — d/s/m or Tab: Group, j/k: Move, Esc: Close",
+        "Verbrauch — d/s/m oder Tab: Gruppieren, j/k: Bewegen, Esc: Schließen",
+    ),
+    (
+        "Tokens per day, last {} days",
+        "Tokens pro Tag, letzte {} Tage",
+    ),
+    // Status bar
+    ("NORMAL", "NORMAL"),
+    ("EDITING", "BEARBEITEN"),
+    ("VISUAL", "AUSWAHL"),
+    ("SESSIONS", "SITZUNGEN"),
+    ("setup", "Einrichtung"),
+    ("tool loop paused", "Werkzeugschleife angehalten"),
+    ("waiting for approval", "wartet auf Freigabe"),
+    ("committing", "Commit läuft"),
+    ("commit message", "Commit-Nachricht"),
+    ("{} indexing {}/{}", "{} indiziere {}/{}"),
+    ("{} executing {}", "{} führe {} aus"),
+    ("rate limited, sending in {}s", "Ratenlimit, sende in {}s"),
+    ("streaming {}", "empfange {}"),
+    ("waiting for first token", "warte auf erstes Token"),
+    ("connecting", "verbinde"),
+    ("{} cancels", "{} bricht ab"),
+    ("idle", "bereit"),
+    ("Normal", "Normal"),
+    ("Editing", "Bearbeiten"),
+    ("Keys ({}):", "Tasten ({}):"),
+    // Help
+    ("Normal mode", "Normalmodus"),
+    ("Editing mode", "Bearbeitungsmodus"),
+    ("Sidebar sessions", "Sitzungen in der Seitenleiste"),
+    ("Slash commands", "Slash-Befehle"),
+    ("Press any key to close", "Beliebige Taste schließt"),
+    // Actions offered in popups
+    ("Allow once", "Einmal erlauben"),
+    ("Always allow {}", "Immer erlauben: {}"),
+    ("Deny", "Ablehnen"),
+    ("Keep going", "Weitermachen"),
+    ("Stop here", "Hier aufhören"),
+    ("Commit", "Committen"),
+    ("Cancel", "Abbrechen"),
+    ("Close", "Schließen"),
+    ("Copy", "Kopieren"),
+    ("Save", "Speichern"),
+    ("Save to file", "In Datei speichern"),
+    ("Run", "Ausführen"),
+    ("Render diagram", "Diagramm zeichnen"),
+    // Errors
+    ("Could not copy: {}", "Kopieren fehlgeschlagen: {}"),
+    (
+        "Could not create {}: {}",
+        "{} konnte nicht angelegt werden: {}",
+    ),
+    (
+        "Could not list sessions: {}",
+        "Sitzungen konnten nicht geladen werden: {}",
+    ),
+    (
+        "Could not open the session: {}",
+        "Sitzung konnte nicht geöffnet werden: {}",
+    ),
+    (
+        "Could not open {}: {}",
+        "{} konnte nicht geöffnet werden: {}",
+    ),
+    (
+        "Could not pin the session: {}",
+        "Sitzung konnte nicht angeheftet werden: {}",
+    ),
+    (
+        "Could not read {}: {}",
+        "{} konnte nicht gelesen werden: {}",
+    ),
+    (
+        "Could not render the diagram: {}",
+        "Diagramm konnte nicht gezeichnet werden: {}",
+    ),
+    (
+        "Could not save layout preferences: {}",
+        "Layout-Einstellungen konnten nicht gespeichert werden: {}",
+    ),
+    (
+        "Could not save the approval rule: {}",
+        "Freigaberegel konnte nicht gespeichert werden: {}",
+    ),
+    (
+        "Could not save the session's model: {}",
+        "Modell der Sitzung konnte nicht gespeichert werden: {}",
+    ),
+    ("Could not share: {}", "Teilen fehlgeschlagen: {}"),
+    ("Could not suspend: {}", "Anhalten fehlgeschlagen: {}"),
+    (
+        "Could not write {}: {}",
+        "{} konnte nicht geschrieben werden: {}",
+    ),
+    // Key bindings
+    ("Send", "Senden"),
+    ("Undo", "Rückgängig"),
+    ("Redo", "Wiederholen"),
+    ("Paste Image", "Bild einfügen"),
+    ("Edit Mode", "Bearbeitungsmodus"),
+    ("Normal Mode", "Normalmodus"),
+    ("Scroll Up", "Nach oben"),
+    ("Scroll Down", "Nach unten"),
+    ("Bottom", "Ans Ende"),
+    ("Next Message", "Nächste Nachricht"),
+    ("Prev Message", "Vorige Nachricht"),
+    ("Next Code Block", "Nächster Codeblock"),
+    ("Prev Code Block", "Voriger Codeblock"),
+    ("Render Diagram", "Diagramm zeichnen"),
+    ("Expand/Actions", "Aufklappen/Aktionen"),
+    ("Raw/Rendered", "Roh/Formatiert"),
+    ("Clear", "Leeren"),
+    ("New Session", "Neue Sitzung"),
+    ("Visual Select", "Auswählen"),
+    ("Copy Markdown", "Markdown kopieren"),
+    ("Bookmark", "Lesezeichen"),
+    ("Next Bookmark", "Nächstes Lesezeichen"),
+    ("Bookmarks", "Lesezeichen-Liste"),
+    ("Search History", "Verlauf durchsuchen"),
+    ("Fork", "Abzweigen"),
+    ("Next Branch", "Nächster Zweig"),
+    ("Prev Branch", "Voriger Zweig"),
+    ("Continue", "Fortsetzen"),
+    ("Retry", "Erneut versuchen"),
+    ("Open Config", "Konfiguration öffnen"),
+    ("Switch Model", "Modell wechseln"),
+    ("Next Alternative", "Nächste Alternative"),
+    ("Prev Alternative", "Vorige Alternative"),
+    ("Wider Sidebar", "Seitenleiste breiter"),
+    ("Narrower Sidebar", "Seitenleiste schmaler"),
+    ("Next Pane", "Nächster Bereich"),
+    ("Prev Pane", "Voriger Bereich"),
+    ("Suspend", "Anhalten"),
+    ("Latency Trace", "Latenzverlauf"),
+    ("Quit", "Beenden"),
+    // Slash commands
+    (
+        "View and edit remembered facts",
+        "Gemerkte Fakten ansehen und bearbeiten",
+    ),
+    (
+        "Browse the tool execution log",
+        "Protokoll der Werkzeugaufrufe durchsehen",
+    ),
+    (
+        "Tokens and estimated cost by day, session or model",
+        "Tokens und geschätzte Kosten nach Tag, Sitzung oder Modell",
+    ),
+    (
+        "Run commands in a container",
+        "Befehle in einem Container ausführen",
+    ),
+    (
+        "Show or change safety filter thresholds",
+        "Schwellen der Sicherheitsfilter zeigen oder ändern",
+    ),
+    (
+        "Show or switch this session's model",
+        "Modell dieser Sitzung zeigen oder wechseln",
+    ),
+    (
+        "Show or switch this session's provider",
+        "Anbieter dieser Sitzung zeigen oder wechseln",
+    ),
+    (
+        "Attach an image or audio file to the next message",
+        "Bild oder Audiodatei an die nächste Nachricht anhängen",
+    ),
+    (
+        "Attach terminal output from tmux or a file",
+        "Terminalausgabe aus tmux oder einer Datei anhängen",
+    ),
+    (
+        "Show or switch the config profile",
+        "Konfigurationsprofil zeigen oder wechseln",
+    ),
+    ("Switch the color theme", "Farbschema wechseln"),
+    ("Enter a Gemini API key", "Gemini-API-Schlüssel eingeben"),
+    (
+        "Resume an interrupted response",
+        "Unterbrochene Antwort fortsetzen",
+    ),
+    (
+        "Start a new session, blank or from a `[template.<name>]`",
+        "Neue Sitzung beginnen, leer oder aus einem `[template.<name>]`",
+    ),
+    (
+        "Rename the session, or name it again",
+        "Sitzung umbenennen oder neu benennen lassen",
+    ),
+    (
+        "Keep this session whatever the retention policy",
+        "Sitzung unabhängig von der Aufbewahrungsregel behalten",
+    ),
+    (
+        "Pick a bookmarked message to jump to",
+        "Zu einer Nachricht mit Lesezeichen springen",
+    ),
+    (
+        "Continue in a new branch from the selected message",
+        "Ab der gewählten Nachricht in einem neuen Zweig weitermachen",
+    ),
+    (
+        "List branches or switch to one",
+        "Zweige auflisten oder zu einem wechseln",
+    ),
+    (
+        "Refresh the repository map sent with prompts",
+        "Die mitgesendete Übersicht des Repositorys erneuern",
+    ),
+    (
+        "Index project files for automatic retrieval",
+        "Projektdateien für die automatische Suche indizieren",
+    ),
+    (
+        "Write a commit message for the staged changes",
+        "Commit-Nachricht für die vorgemerkten Änderungen schreiben",
+    ),
+    (
+        "Review uncommitted or staged changes",
+        "Offene oder vorgemerkte Änderungen prüfen",
+    ),
+    (
+        "Generate an image and save it",
+        "Ein Bild erzeugen und speichern",
+    ),
+    (
+        "Copy the conversation as Markdown, or upload it as a secret gist",
+        "Unterhaltung als Markdown kopieren oder als geheimen Gist hochladen",
+    ),
+    (
+        "Answer prompts with a second model in a pane beside the chat",
+        "Mit einem zweiten Modell in einem Bereich neben dem Chat antworten",
+    ),
+    (
+        "Ask the `[compare]` models at once and show their answers side by side",
+        "Die `[compare]`-Modelle gleichzeitig fragen und ihre Antworten nebeneinander zeigen",
+    ),
+    // Notices, errors and labels
+    (
+        "{} unavailable, answering with {}",
+        "{} nicht verfügbar, {} antwortet",
+    ),
+    ("Response interrupted: {}", "Antwort unterbrochen: {}"),
+    (
+        "Error: response interrupted: {}",
+        "Fehler: Antwort unterbrochen: {}",
+    ),
+    (
+        "Response blocked by the safety filters ({}): see /safety",
+        "Antwort von den Sicherheitsfiltern blockiert ({}): siehe /safety",
+    ),
+    (
+        "Alternative responses: ]a and [a switch between them",
+        "Alternative Antworten: ]a und [a wechseln zwischen ihnen",
+    ),
+    ("Tool loop paused", "Werkzeugschleife pausiert"),
+    (
+        "{} was called {} times with the same arguments",
+        "{} wurde {}-mal mit denselben Argumenten aufgerufen",
+    ),
+    (
+        "{} rounds of tool calls for this question",
+        "{} Runden von Werkzeugaufrufen für diese Frage",
+    ),
+    (
+        "this question has cost about {} so far",
+        "diese Frage hat bisher etwa {} gekostet",
+    ),
+    ("Audit log: {}", "Audit-Protokoll: {}"),
+    (
+        "Indexed {} files in {} chunks",
+        "{} Dateien in {} Abschnitten indiziert",
+    ),
+    ("Indexing failed: {}", "Indizierung fehlgeschlagen: {}"),
+    (
+        "Repository map: {} files, ~{} tokens",
+        "Repository-Übersicht: {} Dateien, ~{} Tokens",
+    ),
+    (
+        "Image generation failed: {}",
+        "Bilderzeugung fehlgeschlagen: {}",
+    ),
+    (
+        "Shared as a secret gist: {}",
+        "Als geheimer Gist geteilt: {}",
+    ),
+    (
+        "Select a mermaid code block first",
+        "Zuerst einen Mermaid-Codeblock auswählen",
+    ),
+    ("AI responded: {}", "KI hat geantwortet: {}"),
+    ("Response ready", "Antwort fertig"),
+    ("Unknown command: `/{}`", "Unbekannter Befehl: `/{}`"),
+    ("Saved memory #{}", "Erinnerung #{} gespeichert"),
+    ("Updated memory #{}", "Erinnerung #{} aktualisiert"),
+    ("No memory #{}", "Keine Erinnerung #{}"),
+    (
+        "Usage: `/memory edit <n> <text>`",
+        "Verwendung: `/memory edit <n> <text>`",
+    ),
+    ("Forgot memory #{}", "Erinnerung #{} vergessen"),
+    (
+        "Usage: `/memory forget <n>`",
+        "Verwendung: `/memory forget <n>`",
+    ),
+    ("Cleared {} memories", "{} Erinnerungen gelöscht"),
+    (
+        "Usage: `/memory [add <text> | edit <n> <text> | forget <n> | clear]`",
+        "Verwendung: `/memory [add <text> | edit <n> <text> | forget <n> | clear]`",
+    ),
+    ("Memory store: {}", "Erinnerungsspeicher: {}"),
+    (
+        "Usage: `/attach <file>|--clipboard|--clear`",
+        "Verwendung: `/attach <file>|--clipboard|--clear`",
+    ),
+    ("Attachments removed", "Anhänge entfernt"),
+    ("Attached {} ({} KB)", "{} angehängt ({} KB)"),
+    (
+        "No terminal output to capture",
+        "Keine Terminalausgabe zum Erfassen",
+    ),
+    (
+        "Attached {} lines of terminal output",
+        "{} Zeilen Terminalausgabe angehängt",
+    ),
+    (
+        "Usage: `/sandbox [on|off]`",
+        "Verwendung: `/sandbox [on|off]`",
+    ),
+    (
+        "Sandbox **on**: commands run in `{}` via {}, network {}",
+        "Sandbox **an**: Befehle laufen in `{}` über {}, Netzwerk {}",
+    ),
+    ("enabled", "aktiviert"),
+    ("disabled", "deaktiviert"),
+    (
+        "Sandbox **off**: commands run on the host",
+        "Sandbox **aus**: Befehle laufen auf dem Host",
+    ),
+    (
+        "No template `{}`: add `[template.<name>]` tables to the config file",
+        "Keine Vorlage `{}`: `[template.<name>]`-Tabellen in der Konfigurationsdatei anlegen",
+    ),
+    (
+        "No template `{}`; there are {}",
+        "Keine Vorlage `{}`; vorhanden sind {}",
+    ),
+    ("New session from `{}`", "Neue Sitzung aus `{}`"),
+    ("Profile: {}. Defined: {}", "Profil: {}. Definiert: {}"),
+    ("Switched to profile `{}`", "Zu Profil `{}` gewechselt"),
+    ("none", "keines"),
+    (
+        "none (add `[profile.<name>]` tables to the config file)",
+        "keine (`[profile.<name>]`-Tabellen in der Konfigurationsdatei anlegen)",
+    ),
+    (
+        "Switched back to the top-level settings",
+        "Zurück zu den allgemeinen Einstellungen gewechselt",
+    ),
+    (
+        "Usage: `/provider [gemini|vertex|default]`",
+        "Verwendung: `/provider [gemini|vertex|default]`",
+    ),
+    (
+        "This session uses `{}`{} via {}{}",
+        "Diese Sitzung verwendet `{}`{} über {}{}",
+    ),
+    (" (configured)", " (konfiguriert)"),
+    (
+        "Safety thresholds for this session:\n",
+        "Sicherheitsschwellen für diese Sitzung:\n",
+    ),
+    ("API default", "API-Standard"),
+    (
+        "Themes: {}. Usage: `/theme <name>`",
+        "Farbschemata: {}. Verwendung: `/theme <name>`",
+    ),
+    ("Theme set to {}", "Farbschema auf {} gesetzt"),
+    ("Usage stats: {}", "Nutzungsstatistik: {}"),
+    ("API key saved to {}", "API-Schlüssel in {} gespeichert"),
+    (
+        "The key works but could not be saved, so it only lasts this session: {}",
+        "Der Schlüssel funktioniert, konnte aber nicht gespeichert werden und gilt daher nur für diese Sitzung: {}",
+    ),
+    (
+        "Nothing saved yet to pin",
+        "Noch nichts gespeichert, das angeheftet werden kann",
+    ),
+    (
+        "Session pinned: it is never pruned",
+        "Sitzung angeheftet: Sie wird nie aufgeräumt",
+    ),
+    ("Session unpinned", "Sitzung nicht mehr angeheftet"),
+    ("Session: {}", "Sitzung: {}"),
+    ("Nothing to name yet", "Noch nichts zu benennen"),
+    ("Bookmarked", "Lesezeichen gesetzt"),
+    ("Bookmark removed", "Lesezeichen entfernt"),
+    (
+        "No bookmarks; press m on a message to add one",
+        "Keine Lesezeichen; m auf einer Nachricht setzt eines",
+    ),
+    ("Copied {} lines", "{} Zeilen kopiert"),
+    (
+        "Only shell code blocks can be run, not `{}`",
+        "Nur Shell-Codeblöcke können ausgeführt werden, nicht `{}`",
+    ),
+    ("Saved {}", "{} gespeichert"),
+    (
+        "Only mermaid blocks are diagrams, not `{}`",
+        "Nur Mermaid-Blöcke sind Diagramme, nicht `{}`",
+    ),
+    ("Rendering diagram…", "Diagramm wird gezeichnet …"),
+    (
+        "No cache directory available for diagrams",
+        "Kein Cache-Verzeichnis für Diagramme verfügbar",
+    ),
+    (
+        "No cache directory available for the index",
+        "Kein Cache-Verzeichnis für den Index verfügbar",
+    ),
+    ("Opened {}", "{} geöffnet"),
+    ("No more code blocks", "Keine weiteren Codeblöcke"),
+    (
+        "Wait for the response to finish before forking",
+        "Vor dem Abzweigen das Ende der Antwort abwarten",
+    ),
+    ("Forked {} at message {}", "{} bei Nachricht {} abgezweigt"),
+    (
+        "Usage: `/branch <n>` with n from 1 to {}",
+        "Verwendung: `/branch <n>` mit n von 1 bis {}",
+    ),
+    ("On {}", "Auf {}"),
+    (
+        "Writing a commit message…",
+        "Commit-Nachricht wird geschrieben …",
+    ),
+    ("Already indexing", "Indizierung läuft bereits"),
+    (
+        "Indexing needs an API key; run /setup",
+        "Die Indizierung braucht einen API-Schlüssel; /setup ausführen",
+    ),
+    ("Repository map off", "Repository-Übersicht aus"),
+    ("Reviewing…", "Review läuft …"),
+    (
+        "Usage: `/imagine <description of the image>`",
+        "Verwendung: `/imagine <Beschreibung des Bildes>`",
+    ),
+    ("Generating an image…", "Bild wird erzeugt …"),
+    ("Nothing to share yet", "Noch nichts zum Teilen"),
+    (
+        "Copied the conversation as Markdown",
+        "Unterhaltung als Markdown kopiert",
+    ),
+    (
+        "Uploading the conversation…",
+        "Unterhaltung wird hochgeladen …",
+    ),
+    ("Usage: `/share [--gist]`", "Verwendung: `/share [--gist]`"),
+    ("Closed the split pane", "Geteilten Bereich geschlossen"),
+    (
+        "Usage: `/split <model>` answers the next prompts with `<model>` too, beside the chat",
+        "Verwendung: `/split <model>` beantwortet die nächsten Eingaben auch mit `<model>`, neben dem Chat",
+    ),
+    (
+        "Wait for the split pane to finish before switching to {}",
+        "Vor dem Wechsel zu {} das Ende des geteilten Bereichs abwarten",
+    ),
+    (
+        "Prompts also go to `{}` in the right pane; Tab moves the focus to it",
+        "Eingaben gehen auch an `{}` im rechten Bereich; Tab verschiebt den Fokus dorthin",
+    ),
+    ("Comparison", "Vergleich"),
+    (
+        "Usage: `/compare <prompt>`",
+        "Verwendung: `/compare <prompt>`",
+    ),
+    (
+        "Wait for the current response to finish",
+        "Das Ende der aktuellen Antwort abwarten",
+    ),
+    ("{} is still answering", "{} antwortet noch"),
+    (
+        "{} gave no answer to keep",
+        "{} hat keine Antwort zum Behalten gegeben",
+    ),
+    ("Kept the answer from {}", "Antwort von {} behalten"),
+    ("Saved image to `{}`", "Bild in `{}` gespeichert"),
+    ("Not committed", "Nicht committet"),
+    ("Committed {}", "{} committet"),
+    ("Alternative {} of {}", "Alternative {} von {}"),
+    ("No response to cancel", "Keine Antwort zum Abbrechen"),
+    ("Cancelled the response", "Antwort abgebrochen"),
+    (
+        "Suspending needs a Unix shell",
+        "Pausieren braucht eine Unix-Shell",
+    ),
+    (
+        "Suspend is not bound while editing, where ctrl+z undoes: leave it first",
+        "Pausieren ist beim Bearbeiten nicht belegt, dort macht ctrl+z rückgängig: erst verlassen",
+    ),
+    (
+        "No failed response to retry",
+        "Keine fehlgeschlagene Antwort zum Wiederholen",
+    ),
+    ("No config directory", "Kein Konfigurationsverzeichnis"),
+    (
+        "Type the model to switch to",
+        "Modell eingeben, zu dem gewechselt wird",
+    ),
+    (
+        "No interrupted response to continue",
+        "Keine unterbrochene Antwort zum Fortsetzen",
+    ),
+    ("Approval needed", "Freigabe nötig"),
+    ("Always allowing {}", "{} wird immer erlaubt"),
+    ("retry", "wiederholen"),
+    ("switch model", "Modell wechseln"),
+    ("open config", "Konfiguration öffnen"),
+    (
+        "… ({} more lines, Enter to expand)",
+        "… ({} weitere Zeilen, Enter klappt auf)",
+    ),
+    ("Nothing selected", "Nichts ausgewählt"),
+    (
+        "Select a message to show raw",
+        "Eine Nachricht zur Rohansicht auswählen",
+    ),
+    ("Session:", "Sitzung:"),
+    ("Untitled", "Ohne Titel"),
+    ("Model:", "Modell:"),
+    ("Tokens:", "Tokens:"),
+    ("Prompt: {}", "Eingabe: {}"),
+    ("Cached: {}", "Cache:  {}"),
+    ("Resp:   {}", "Antw.:  {}"),
+    ("Total:  {}", "Gesamt: {}"),
+    ("Branches:", "Zweige:"),
+    ("Turn at {}", "Runde um {}"),
+    ("Welcome to gemchat", "Willkommen bei gemchat"),
+    (
+        "No Gemini API key was found. Create one at",
+        "Kein Gemini-API-Schlüssel gefunden. Einen erstellen unter",
+    ),
+    ("Checking the key…", "Schlüssel wird geprüft …"),
+    ("Committing…", "Commit läuft …"),
+    ("Run this as a command? ", "Als Befehl ausführen? "),
+    ("Blank", "Leer"),
+    (
+        "Audit log ({} entries) — j/k: Move, g/G: Top/Bottom, Esc: Close",
+        "Audit-Protokoll ({} Einträge) — j/k: Bewegen, g/G: Anfang/Ende, Esc: Schließen",
+    ),
+    (
+        "No tool calls recorded yet",
+        "Noch keine Werkzeugaufrufe aufgezeichnet",
+    ),
+    ("Session {}", "Sitzung {}"),
+    ("No session", "Keine Sitzung"),
+    ("By {}", "Nach {}"),
+    ("Estimated cost by {}", "Geschätzte Kosten nach {}"),
+    ("day", "Tag"),
+    ("session", "Sitzung"),
+    ("model", "Modell"),
+    (
+        "{} is not an image (PNG, JPEG, WebP, HEIC) or audio file (WAV, MP3, OGG, FLAC, AAC, AIFF)",
+        "{} ist weder ein Bild (PNG, JPEG, WebP, HEIC) noch eine Audiodatei (WAV, MP3, OGG, FLAC, AAC, AIFF)",
+    ),
+    (
+        "The file is {} MB; at most {} MB can be sent",
+        "Die Datei ist {} MB groß; höchstens {} MB können gesendet werden",
+    ),
+    (
+        "Usage: `/safety [<category> <threshold> | reset]`. Categories: {}. Thresholds: {}.",
+        "Verwendung: `/safety [<category> <threshold> | reset]`. Kategorien: {}. Schwellen: {}.",
+    ),
+    ("{} running", "{} läuft"),
+    ("{} lines (Enter to expand)", "{} Zeilen (Enter klappt auf)"),
+    (
+        "Check the API key, or enter another one",
+        "API-Schlüssel prüfen oder einen anderen eingeben",
+    ),
+    (
+        "Wait a moment, or switch to another model",
+        "Einen Moment warten oder zu einem anderen Modell wechseln",
+    ),
+    (
+        "Check the connection, then retry",
+        "Verbindung prüfen und erneut versuchen",
+    ),
+    (
+        "Rephrase the prompt, or see /safety for the thresholds",
+        "Eingabe umformulieren oder unter /safety die Schwellen ansehen",
+    ),
+    (
+        "This gemchat was built without voice support: rebuild it with `--features live`",
+        "Dieses gemchat wurde ohne Sprachunterstützung gebaut: mit `--features live` neu bauen",
+    ),
+    (
+        "No data directory available",
+        "Kein Datenverzeichnis verfügbar",
+    ),
+    (
+        "No retention policy: set keep_sessions or keep_days under [storage].",
+        "Keine Aufbewahrungsregel: keep_sessions oder keep_days unter [storage] setzen.",
+    ),
+    (
+        "Deleted 1 session; reclaimed {} ({} → {}).",
+        "1 Sitzung gelöscht; {} freigegeben ({} → {}).",
+    ),
+    (
+        "Deleted {} sessions; reclaimed {} ({} → {}).",
+        "{} Sitzungen gelöscht; {} freigegeben ({} → {}).",
+    ),
+    (
+        "Passphrase for saved conversations: ",
+        "Passphrase für gespeicherte Unterhaltungen: ",
+    ),
+    (
+        "Wrong key for the saved conversations in {}",
+        "Falscher Schlüssel für die gespeicherten Unterhaltungen in {}",
+    ),
+    // Message roles and attachment kinds
+    ("You", "Du"),
+    ("AI", "KI"),
+    ("System", "System"),
+    ("Tool", "Werkzeug"),
+    ("Error", "Fehler"),
+    ("Review", "Review"),
+    ("image", "Bild"),
+    ("audio", "Audio"),
+];
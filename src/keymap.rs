@@ -0,0 +1,493 @@
+use crate::config::KeysConfig;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Something a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Send,
+    /// Undo or redo edits to the input since it was last sent
+    Undo,
+    Redo,
+    /// Attach the image on the clipboard to the next message
+    PasteImage,
+    EditMode,
+    NormalMode,
+    ScrollUp,
+    ScrollDown,
+    ScrollBottom,
+    NextMessage,
+    PrevMessage,
+    /// Jump to the next or previous code block
+    NextCode,
+    PrevCode,
+    /// Render the selected mermaid code block to an image and show it
+    RenderDiagram,
+    /// Expand or collapse the selected tool call, act on the selected code block, or open
+    /// the session selected in the sidebar
+    Toggle,
+    /// Show the selected message as the raw text received, or rendered again
+    ToggleRaw,
+    Clear,
+    /// Pick a template to start a new session from
+    NewSession,
+    /// Start or end a selection of lines, extended by moving
+    Visual,
+    /// Copy the selected lines as shown, or the Markdown of the messages they are in
+    Yank,
+    YankMarkdown,
+    /// Mark or unmark the selected message
+    Bookmark,
+    NextBookmark,
+    /// Pick a bookmarked message to jump to
+    Bookmarks,
+    /// Full-text search through every saved conversation
+    Search,
+    /// Continue the conversation in a new branch from the selected message
+    Fork,
+    NextBranch,
+    PrevBranch,
+    /// Resume a response cut off by a dropped connection
+    Continue,
+    /// Stop the response being streamed
+    Cancel,
+    /// Send the prompt of a failed response again
+    Retry,
+    /// Open the config file, or the API key setup after an authentication error
+    OpenConfig,
+    /// Move the session to the next configured model
+    SwitchModel,
+    /// Show the next or previous alternative of the last response
+    NextAlternative,
+    PrevAlternative,
+    ToggleSidebar,
+    GrowSidebar,
+    ShrinkSidebar,
+    /// Move the focus to the next or previous of the chat, the `/split` pane, the input and
+    /// the sidebar
+    FocusNext,
+    FocusPrev,
+    /// Hand the terminal back to the shell until `fg`
+    Suspend,
+    /// Show or hide the timeline of the latest turns
+    Trace,
+    /// Show every binding and slash command
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Order of the help listing
+    const ALL: &[Command] = &[
+        Command::Send,
+        Command::Undo,
+        Command::Redo,
+        Command::PasteImage,
+        Command::EditMode,
+        Command::NormalMode,
+        Command::ScrollUp,
+        Command::ScrollDown,
+        Command::ScrollBottom,
+        Command::NextMessage,
+        Command::PrevMessage,
+        Command::NextCode,
+        Command::PrevCode,
+        Command::RenderDiagram,
+        Command::Toggle,
+        Command::ToggleRaw,
+        Command::Clear,
+        Command::NewSession,
+        Command::Visual,
+        Command::Yank,
+        Command::YankMarkdown,
+        Command::Bookmark,
+        Command::NextBookmark,
+        Command::Bookmarks,
+        Command::Search,
+        Command::Fork,
+        Command::NextBranch,
+        Command::PrevBranch,
+        Command::Continue,
+        Command::Cancel,
+        Command::Retry,
+        Command::OpenConfig,
+        Command::SwitchModel,
+        Command::NextAlternative,
+        Command::PrevAlternative,
+        Command::ToggleSidebar,
+        Command::GrowSidebar,
+        Command::ShrinkSidebar,
+        Command::FocusNext,
+        Command::FocusPrev,
+        Command::Suspend,
+        Command::Trace,
+        Command::Help,
+        Command::Quit,
+    ];
+
+    /// Name of the command in the interface's language
+    pub fn label(self) -> &'static str {
+        crate::i18n::tr(match self {
+            Command::Send => "Send",
+            Command::Undo => "Undo",
+            Command::Redo => "Redo",
+            Command::PasteImage => "Paste Image",
+            Command::EditMode => "Edit Mode",
+            Command::NormalMode => "Normal Mode",
+            Command::ScrollUp => "Scroll Up",
+            Command::ScrollDown => "Scroll Down",
+            Command::ScrollBottom => "Bottom",
+            Command::NextMessage => "Next Message",
+            Command::PrevMessage => "Prev Message",
+            Command::NextCode => "Next Code Block",
+            Command::PrevCode => "Prev Code Block",
+            Command::RenderDiagram => "Render Diagram",
+            Command::Toggle => "Expand/Actions",
+            Command::ToggleRaw => "Raw/Rendered",
+            Command::Clear => "Clear",
+            Command::NewSession => "New Session",
+            Command::Visual => "Visual Select",
+            Command::Yank => "Copy",
+            Command::YankMarkdown => "Copy Markdown",
+            Command::Bookmark => "Bookmark",
+            Command::NextBookmark => "Next Bookmark",
+            Command::Bookmarks => "Bookmarks",
+            Command::Search => "Search History",
+            Command::Fork => "Fork",
+            Command::NextBranch => "Next Branch",
+            Command::PrevBranch => "Prev Branch",
+            Command::Continue => "Continue",
+            Command::Cancel => "Cancel",
+            Command::Retry => "Retry",
+            Command::OpenConfig => "Open Config",
+            Command::SwitchModel => "Switch Model",
+            Command::NextAlternative => "Next Alternative",
+            Command::PrevAlternative => "Prev Alternative",
+            Command::ToggleSidebar => "Sidebar",
+            Command::GrowSidebar => "Wider Sidebar",
+            Command::ShrinkSidebar => "Narrower Sidebar",
+            Command::FocusNext => "Next Pane",
+            Command::FocusPrev => "Prev Pane",
+            Command::Suspend => "Suspend",
+            Command::Trace => "Latency Trace",
+            Command::Help => "Help",
+            Command::Quit => "Quit",
+        })
+    }
+}
+
+/// A key plus modifiers, written like `ctrl+c`, `enter` or `G`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Chord {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<&str> = text.split('+').collect();
+        // A lone "+" is the plus key, not an empty modifier list
+        let key = match parts.pop() {
+            Some("") if text.ends_with("++") || text == "+" => "+",
+            Some(key) => key,
+            None => return Err(eyre!("empty key")),
+        };
+        for part in parts.iter().filter(|p| !p.is_empty()) {
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                other => return Err(eyre!("unknown modifier `{}`", other)),
+            };
+        }
+
+        let code = match key.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            f if f.len() > 1
+                && f.starts_with('f')
+                && f[1..].bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                KeyCode::F(f[1..].parse()?)
+            }
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(eyre!("unknown key `{}`", key)),
+                }
+            }
+        };
+        Ok(Self { code, modifiers })
+    }
+
+    fn matches(&self, key: &KeyEvent) -> bool {
+        // Shift is already part of the character for printable keys (`G`, `?`) and of
+        // back tab
+        let ignored = match key.code {
+            KeyCode::Char(_) | KeyCode::BackTab => KeyModifiers::SHIFT,
+            _ => KeyModifiers::NONE,
+        };
+        self.code == key.code && self.modifiers - ignored == key.modifiers - ignored
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("M-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Spc"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::Enter => f.write_str("Ent"),
+            KeyCode::Esc => f.write_str("Esc"),
+            KeyCode::Tab => f.write_str("Tab"),
+            KeyCode::BackTab => f.write_str("S-Tab"),
+            KeyCode::Backspace => f.write_str("BS"),
+            KeyCode::Delete => f.write_str("Del"),
+            KeyCode::Up => f.write_str("↑"),
+            KeyCode::Down => f.write_str("↓"),
+            KeyCode::Left => f.write_str("←"),
+            KeyCode::Right => f.write_str("→"),
+            KeyCode::Home => f.write_str("Home"),
+            KeyCode::End => f.write_str("End"),
+            KeyCode::PageUp => f.write_str("PgUp"),
+            KeyCode::PageDown => f.write_str("PgDn"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Chords pressed one after another, written like `]c`, or with spaces between chords
+/// that are more than one character (`ctrl+x k`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence(Vec<Chord>);
+
+impl Sequence {
+    pub fn parse(text: &str) -> Result<Self> {
+        let chords = if text.contains(char::is_whitespace) {
+            text.split_whitespace()
+                .map(Chord::parse)
+                .collect::<Result<_>>()?
+        } else {
+            match Chord::parse(text) {
+                Ok(chord) => vec![chord],
+                // `]c`: every character is a key of its own
+                Err(e) if text.contains('+') => return Err(e),
+                Err(e) => text
+                    .chars()
+                    .map(|c| Chord::parse(c.encode_utf8(&mut [0; 4])))
+                    .collect::<Result<_>>()
+                    .map_err(|_| e)?,
+            }
+        };
+        Ok(Self(chords))
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, chord) in self.0.iter().enumerate() {
+            if i > 0 && self.0.iter().any(|c| !matches!(c.code, KeyCode::Char(_))) {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}", chord)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bindings for one input mode, in help order
+pub type Bindings = Vec<(Command, Vec<Sequence>)>;
+
+/// What the keys pressed so far amount to
+pub enum Lookup {
+    Command(Command),
+    /// The keys start a longer sequence; wait for the next one
+    Pending,
+    None,
+}
+
+pub struct Keymap {
+    /// With the chat or the `/split` pane focused
+    pub normal: Bindings,
+    /// With the input focused
+    pub editing: Bindings,
+    /// With the sidebar's session list focused
+    pub sidebar: Bindings,
+}
+
+impl Keymap {
+    /// The default bindings with the ones from `[keys.normal]`, `[keys.editing]` and
+    /// `[keys.sidebar]` replacing them
+    pub fn new(config: &KeysConfig) -> Result<Self> {
+        Ok(Self {
+            normal: bindings(
+                &[
+                    (Command::EditMode, &["i"]),
+                    (Command::NormalMode, &["esc"]),
+                    (Command::ScrollUp, &["k", "up"]),
+                    (Command::ScrollDown, &["j", "down"]),
+                    (Command::ScrollBottom, &["G"]),
+                    (Command::NextMessage, &["n"]),
+                    (Command::PrevMessage, &["p"]),
+                    (Command::NextCode, &["]c"]),
+                    (Command::PrevCode, &["[c"]),
+                    (Command::RenderDiagram, &["gd"]),
+                    (Command::Toggle, &["enter"]),
+                    (Command::ToggleRaw, &["R"]),
+                    (Command::Clear, &["c"]),
+                    (Command::Visual, &["v"]),
+                    (Command::Yank, &["y"]),
+                    (Command::YankMarkdown, &["Y"]),
+                    (Command::Bookmark, &["m"]),
+                    (Command::NextBookmark, &["'"]),
+                    (Command::Bookmarks, &["\""]),
+                    (Command::Search, &["ctrl+r"]),
+                    (Command::Fork, &["f"]),
+                    (Command::NextBranch, &["]b"]),
+                    (Command::PrevBranch, &["[b"]),
+                    (Command::Continue, &["r"]),
+                    (Command::Cancel, &["ctrl+c"]),
+                    (Command::Retry, &["gr"]),
+                    (Command::OpenConfig, &["gc"]),
+                    (Command::SwitchModel, &["gm"]),
+                    (Command::NextAlternative, &["]a"]),
+                    (Command::PrevAlternative, &["[a"]),
+                    (Command::ToggleSidebar, &["b"]),
+                    (Command::GrowSidebar, &[">"]),
+                    (Command::ShrinkSidebar, &["<"]),
+                    (Command::FocusNext, &["tab", "ctrl+w"]),
+                    (Command::FocusPrev, &["backtab"]),
+                    (Command::Suspend, &["ctrl+z"]),
+                    (Command::Trace, &["ctrl+t"]),
+                    (Command::Help, &["?"]),
+                    (Command::Quit, &["q"]),
+                ],
+                &config.normal,
+            )
+            .wrap_err("Invalid [keys.normal]")?,
+            editing: bindings(
+                &[
+                    (Command::Send, &["enter"]),
+                    (Command::Undo, &["ctrl+z"]),
+                    (Command::Redo, &["ctrl+y"]),
+                    (Command::PasteImage, &["ctrl+v"]),
+                    (Command::Cancel, &["ctrl+c"]),
+                    (Command::Trace, &["ctrl+t"]),
+                    (Command::NormalMode, &["esc"]),
+                    (Command::Search, &["ctrl+r"]),
+                    (Command::FocusNext, &["tab"]),
+                    (Command::FocusPrev, &["backtab"]),
+                ],
+                &config.editing,
+            )
+            .wrap_err("Invalid [keys.editing]")?,
+            sidebar: bindings(
+                &[
+                    (Command::EditMode, &["i"]),
+                    (Command::NormalMode, &["esc"]),
+                    (Command::ScrollUp, &["k", "up"]),
+                    (Command::ScrollDown, &["j", "down"]),
+                    (Command::Toggle, &["enter"]),
+                    (Command::NewSession, &["n"]),
+                    (Command::Search, &["ctrl+r"]),
+                    (Command::FocusNext, &["tab"]),
+                    (Command::FocusPrev, &["backtab"]),
+                    (Command::ToggleSidebar, &["b"]),
+                    (Command::GrowSidebar, &[">"]),
+                    (Command::ShrinkSidebar, &["<"]),
+                    (Command::Suspend, &["ctrl+z"]),
+                    (Command::Help, &["?"]),
+                    (Command::Quit, &["q"]),
+                ],
+                &config.sidebar,
+            )
+            .wrap_err("Invalid [keys.sidebar]")?,
+        })
+    }
+}
+
+/// The first keys bound to `command`, for hints
+pub fn first_key(bindings: &Bindings, command: Command) -> Option<&Sequence> {
+    bindings
+        .iter()
+        .find(|(c, _)| *c == command)
+        .and_then(|(_, keys)| keys.first())
+}
+
+/// Looks up the keys pressed since the last command. A complete sequence wins over a
+/// longer one it starts.
+pub fn lookup(bindings: &Bindings, keys: &[KeyEvent]) -> Lookup {
+    let matches = |sequence: &Sequence| {
+        sequence.0.len() >= keys.len() && sequence.0.iter().zip(keys).all(|(c, k)| c.matches(k))
+    };
+    let mut pending = false;
+    for (command, sequences) in bindings {
+        for sequence in sequences.iter().filter(|s| matches(s)) {
+            if sequence.0.len() == keys.len() {
+                return Lookup::Command(*command);
+            }
+            pending = true;
+        }
+    }
+    if pending {
+        Lookup::Pending
+    } else {
+        Lookup::None
+    }
+}
+
+fn bindings(
+    defaults: &[(Command, &[&str])],
+    custom: &HashMap<Command, Vec<String>>,
+) -> Result<Bindings> {
+    let mut map: HashMap<Command, Vec<Sequence>> = HashMap::new();
+    for (command, keys) in defaults {
+        let sequences = keys
+            .iter()
+            .map(|k| Sequence::parse(k))
+            .collect::<Result<_>>()?;
+        map.insert(*command, sequences);
+    }
+    for (command, keys) in custom {
+        let sequences = keys
+            .iter()
+            .map(|k| Sequence::parse(k).wrap_err_with(|| format!("Bad key `{}`", k)))
+            .collect::<Result<_>>()?;
+        map.insert(*command, sequences);
+    }
+
+    Ok(Command::ALL
+        .iter()
+        .filter_map(|c| map.remove(c).map(|chords| (*c, chords)))
+        .filter(|(_, sequences)| !sequences.is_empty())
+        .collect())
+}
@@ -0,0 +1,105 @@
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use serde::Deserialize;
+
+/// Columns a message pane keeps for text at least; its padding gives way below that
+const MIN_TEXT_WIDTH: u16 = 20;
+
+/// Which side of the screen the sidebar is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    #[default]
+    Left,
+    Right,
+}
+
+/// Whether the input box is above or below the messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Top,
+    #[default]
+    Bottom,
+}
+
+/// Where the parts of the screen go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenLayout {
+    pub sidebar: Side,
+    /// Columns of the sidebar, 0 when it is hidden
+    pub sidebar_width: u16,
+    pub input: Edge,
+    /// Rows of the input box, borders included
+    pub input_height: u16,
+    /// Blank columns on either side of the messages, inside their border
+    pub padding: u16,
+}
+
+/// The areas the screen is split into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screen {
+    pub sidebar: Option<Rect>,
+    /// The chat, or whichever view replaces it
+    pub main: Rect,
+    /// One row along the bottom
+    pub status: Rect,
+}
+
+impl ScreenLayout {
+    /// Splits the whole screen `area` into the sidebar, the main view and the status bar
+    pub fn screen(&self, area: Rect) -> Screen {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .split(area);
+        let (constraints, sidebar, main) = match self.sidebar {
+            Side::Left => (
+                [Constraint::Length(self.sidebar_width), Constraint::Min(0)],
+                0,
+                1,
+            ),
+            Side::Right => (
+                [Constraint::Min(0), Constraint::Length(self.sidebar_width)],
+                1,
+                0,
+            ),
+        };
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(constraints)
+            .split(rows[0]);
+        Screen {
+            sidebar: (self.sidebar_width > 0).then_some(columns[sidebar]),
+            main: columns[main],
+            status: rows[1],
+        }
+    }
+
+    /// The padding of a message pane with `width` columns inside its borders, narrowed so
+    /// [`MIN_TEXT_WIDTH`] of them are left for text
+    pub fn padding_for(&self, width: u16) -> u16 {
+        self.padding.min(width.saturating_sub(MIN_TEXT_WIDTH) / 2)
+    }
+
+    /// Splits the chat `area` into the messages and the input box
+    pub fn chat(&self, area: Rect) -> (Rect, Rect) {
+        let input = Constraint::Length(self.input_height);
+        let messages = Constraint::Min(1);
+        match self.input {
+            Edge::Top => {
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([input, messages])
+                    .split(area);
+                (rows[1], rows[0])
+            }
+            Edge::Bottom => {
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([messages, input])
+                    .split(area);
+                (rows[0], rows[1])
+            }
+        }
+    }
+}
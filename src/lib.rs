@@ -0,0 +1,91 @@
+//! The parts of gemchat that don't need a terminal: the Gemini streaming client, the tool
+//! engine with its approval rules and audit log, configuration, persistent memory and the
+//! markdown renderer. The `gemchat` binary is a TUI on top of these.
+//!
+//! A minimal client streams one answer:
+//!
+//! ```no_run
+//! # async fn demo() {
+//! use gemchat::ai::{self, AiUpdate, Request};
+//!
+//! ai::set_api_key(Some("AIza...".to_string()));
+//! let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+//! tokio::spawn(ai::stream_response(
+//!     Request {
+//!         prompt: "User: Hello\n".to_string(),
+//!         ..Default::default()
+//!     },
+//!     tx,
+//! ));
+//! while let Some(update) = rx.recv().await {
+//!     match update {
+//!         AiUpdate::Content(text) => print!("{}", text),
+//!         AiUpdate::Finished => break,
+//!         _ => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+/// Gemini API and Vertex AI streaming client
+pub mod ai;
+/// Which tool calls may run without asking
+pub mod approval;
+/// Log of tool executions, kept in the database
+pub mod audit;
+/// Terminal output captured from tmux or a saved file
+pub mod capture;
+/// Encryption of stored conversations
+pub mod cipher;
+/// Fuzzy completion of slash commands, file mentions and model names
+pub mod completion;
+/// `config.toml` and the directories gemchat keeps its files in
+pub mod config;
+/// Mermaid diagrams rendered to images with the mermaid CLI
+pub mod diagram;
+/// Diffs and commits through the git CLI
+pub mod git;
+/// HTML to markdown conversion for `fetch_url`
+pub mod html;
+/// Translations of the interface
+pub mod i18n;
+/// Configurable key bindings
+pub mod keymap;
+/// Where the sidebar, chat and input box go on screen
+pub mod layout;
+/// Gemini Live API sessions for voice conversations
+#[cfg(feature = "live")]
+pub mod live;
+/// LaTeX math to Unicode text
+pub mod math;
+/// Facts the model chose to remember across sessions
+pub mod memory;
+/// Record and replay of model responses, for tests and demos
+#[cfg(feature = "mock")]
+pub mod mock;
+/// Estimated cost of model usage
+pub mod pricing;
+/// Embeddings index of project files for retrieval
+pub mod rag;
+/// Masking of secrets before they are sent to the model
+pub mod redact;
+/// Markdown and code highlighting to ratatui lines
+pub mod render;
+/// Outline of the project for the prompt
+pub mod repomap;
+/// Code review of a git diff
+pub mod review;
+/// Transcripts shared as GitHub gists
+pub mod share;
+/// Parsing of server-sent event streams
+pub mod sse;
+/// SQLite database of sessions, messages, token usage and the audit log
+pub mod store;
+/// Color themes
+pub mod theme;
+/// Tool declarations and their execution
+pub mod tools;
+/// Application Default Credentials and endpoints for Vertex AI
+pub mod vertex;
+/// `gemchat watch`: a prompt run again whenever matching files change
+pub mod watch;
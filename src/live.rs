@@ -0,0 +1,217 @@
+use crate::config::{ApiConfig, LiveConfig};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Rate of the 16-bit mono PCM the Live API takes
+pub const INPUT_RATE: u32 = 16_000;
+/// Rate of the 16-bit mono PCM it answers with
+pub const OUTPUT_RATE: u32 = 24_000;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Something the server sent during a session
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveEvent {
+    /// The session is ready for audio
+    Ready,
+    /// Model speech, as samples at [`OUTPUT_RATE`]
+    Audio(Vec<f32>),
+    /// More of the transcript of what the user said
+    Heard(String),
+    /// More of the transcript of what the model said
+    Said(String),
+    TurnComplete,
+    /// The user spoke over the model, which stopped; queued speech should be dropped
+    Interrupted,
+    /// The server will close the session soon
+    GoingAway,
+}
+
+/// Sending half of a session
+pub struct LiveSender(SplitSink<Socket, Message>);
+
+/// Receiving half of a session
+pub struct LiveReceiver(SplitStream<Socket>);
+
+/// Opens a session with the API key `key` and sends its setup. Wait for
+/// [`LiveEvent::Ready`] before sending audio.
+pub async fn connect(
+    api: &ApiConfig,
+    key: &str,
+    config: &LiveConfig,
+    instructions: Option<&str>,
+) -> Result<(LiveSender, LiveReceiver)> {
+    let mut request = url(api).into_client_request()?;
+    request.headers_mut().insert("x-goog-api-key", key.parse()?);
+    let (socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .wrap_err("Could not connect to the Live API")?;
+    let (sink, stream) = socket.split();
+    let mut sender = LiveSender(sink);
+    sender.send(setup_message(config, instructions)).await?;
+    Ok((sender, LiveReceiver(stream)))
+}
+
+/// WebSocket URL of the Live API under `api.base_url`
+pub fn url(api: &ApiConfig) -> String {
+    let base = api.base_url();
+    let base = match base.split_once("://") {
+        Some(("http", rest)) => format!("ws://{}", rest),
+        Some((_, rest)) => format!("wss://{}", rest),
+        None => format!("wss://{}", base),
+    };
+    format!(
+        "{}/ws/google.ai.generativelanguage.{}.GenerativeService.BidiGenerateContent",
+        base,
+        api.version()
+    )
+}
+
+/// First message of a session: spoken answers, with transcripts both ways
+pub fn setup_message(config: &LiveConfig, instructions: Option<&str>) -> Value {
+    let mut setup = json!({
+        "model": format!("models/{}", config.model()),
+        "generationConfig": { "responseModalities": ["AUDIO"] },
+        "inputAudioTranscription": {},
+        "outputAudioTranscription": {}
+    });
+    if let Some(voice) = &config.voice {
+        setup["generationConfig"]["speechConfig"] =
+            json!({ "voiceConfig": { "prebuiltVoiceConfig": { "voiceName": voice } } });
+    }
+    if let Some(instructions) = instructions {
+        setup["systemInstruction"] = json!({ "parts": [{ "text": instructions }] });
+    }
+    json!({ "setup": setup })
+}
+
+/// A chunk of microphone audio at [`INPUT_RATE`]
+pub fn audio_message(samples: &[f32]) -> Value {
+    json!({
+        "realtimeInput": {
+            "audio": {
+                "mimeType": format!("audio/pcm;rate={}", INPUT_RATE),
+                "data": STANDARD.encode(encode_pcm(samples))
+            }
+        }
+    })
+}
+
+/// The events in one server message
+pub fn parse(message: &Value) -> Vec<LiveEvent> {
+    let mut events = Vec::new();
+    if message.get("setupComplete").is_some() {
+        events.push(LiveEvent::Ready);
+    }
+    if message.get("goAway").is_some() {
+        events.push(LiveEvent::GoingAway);
+    }
+    let content = &message["serverContent"];
+    if content["interrupted"].as_bool() == Some(true) {
+        events.push(LiveEvent::Interrupted);
+    }
+    if let Some(text) = content["inputTranscription"]["text"].as_str() {
+        events.push(LiveEvent::Heard(text.to_string()));
+    }
+    if let Some(text) = content["outputTranscription"]["text"].as_str() {
+        events.push(LiveEvent::Said(text.to_string()));
+    }
+    for part in content["modelTurn"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(data) = part["inlineData"]["data"].as_str()
+            && let Ok(bytes) = STANDARD.decode(data)
+        {
+            events.push(LiveEvent::Audio(decode_pcm(&bytes)));
+        }
+    }
+    if content["turnComplete"].as_bool() == Some(true) {
+        events.push(LiveEvent::TurnComplete);
+    }
+    events
+}
+
+impl LiveSender {
+    pub async fn send(&mut self, message: Value) -> Result<()> {
+        self.0
+            .send(Message::text(message.to_string()))
+            .await
+            .wrap_err("Lost the Live API connection")
+    }
+
+    pub async fn close(&mut self) -> Result<()> {
+        self.0.close().await?;
+        Ok(())
+    }
+}
+
+impl LiveReceiver {
+    /// Events of the next server message; `None` once the session is closed
+    pub async fn next(&mut self) -> Option<Result<Vec<LiveEvent>>> {
+        loop {
+            let text = match self.0.next().await? {
+                Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                // Server messages arrive as JSON in binary frames
+                Ok(Message::Binary(bytes)) => bytes.to_vec(),
+                Ok(Message::Close(frame)) => {
+                    let reason = frame
+                        .map(|f| f.reason.to_string())
+                        .filter(|r| !r.is_empty());
+                    return reason.map(|r| Err(eyre!("The Live API closed the session: {}", r)));
+                }
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e).wrap_err("Lost the Live API connection")),
+            };
+            return Some(
+                serde_json::from_slice(&text)
+                    .map(|message| parse(&message))
+                    .wrap_err("Unexpected Live API message"),
+            );
+        }
+    }
+}
+
+/// Samples in -1..1 as 16-bit little-endian PCM
+pub fn encode_pcm(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+        .collect()
+}
+
+/// 16-bit little-endian PCM as samples in -1..1
+pub fn decode_pcm(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32)
+        .collect()
+}
+
+/// `samples` at rate `from` converted to rate `to` by linear interpolation
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    (0..len)
+        .map(|i| {
+            let at = i as f64 * step;
+            let index = at as usize;
+            let next = samples.get(index + 1).unwrap_or(&samples[index]);
+            let fraction = (at - index as f64) as f32;
+            samples[index] + (next - samples[index]) * fraction
+        })
+        .collect()
+}
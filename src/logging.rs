@@ -0,0 +1,46 @@
+use crate::config;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding a log filter, e.g. `GEMCHAT_LOG=gemchat=debug`
+pub const ENV_VAR: &str = "GEMCHAT_LOG";
+
+/// Log files kept before the oldest is deleted
+const MAX_LOG_FILES: usize = 7;
+
+/// Starts logging to daily-rotated files under `<state dir>/gemchat/logs` when a filter is
+/// given with `--log-level` or `GEMCHAT_LOG`. Logging is off otherwise. The returned guard
+/// flushes buffered lines when dropped and must be kept alive until exit.
+pub fn init(level: Option<&str>) -> Result<Option<WorkerGuard>> {
+    let filter = match level {
+        Some(level) => level.to_string(),
+        None => match std::env::var(ENV_VAR) {
+            Ok(filter) if !filter.trim().is_empty() => filter,
+            _ => return Ok(None),
+        },
+    };
+    let filter =
+        EnvFilter::try_new(&filter).wrap_err_with(|| format!("Invalid log filter `{}`", filter))?;
+
+    let dir = config::state_dir()
+        .ok_or_else(|| eyre!("No state directory available for log files"))?
+        .join("logs");
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("gemchat")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(&dir)
+        .wrap_err_with(|| format!("Could not open a log file in {}", dir.display()))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(false)
+        .init();
+    Ok(Some(guard))
+}
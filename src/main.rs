@@ -1,33 +1,284 @@
-use clap::Parser;
+use chrono::{DateTime, Local};
+use clap::{Parser, Subcommand};
 use color_eyre::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::{StreamExt, stream};
 use ratatui::{
     DefaultTerminal, Frame,
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
-};
-use syntect::{
-    easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet, util::LinesWithEndings,
+    widgets::{
+        Bar, BarChart, BarGroup, Block, Borders, Clear, List, ListItem, ListState, Padding,
+        Paragraph, Sparkline, Wrap,
+    },
 };
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::mpsc;
-use tokio::time::{self, Duration};
+use tokio::time::{self, Duration, Instant};
 use tui_textarea::TextArea;
+use unicode_width::UnicodeWidthStr;
+
+mod auth;
+mod clipboard;
+mod commit;
+mod conversation;
+mod graphics;
+mod headless;
+mod logging;
+mod notify;
+mod shell;
+mod state;
+mod tui;
+mod usage;
+#[cfg(feature = "live")]
+mod voice;
+
+use gemchat::i18n::{self, tr};
+use gemchat::layout::ScreenLayout;
+use gemchat::render::{self, owned_line};
+use gemchat::{
+    ai, approval, audit, capture, cipher, completion, config, diagram, git, keymap, memory,
+    pricing, rag, redact, repomap, review, share, store, theme, tools, watch,
+};
 
-mod ai;
-mod tools;
+/// Slash commands with their usage, for the help overlay and completion
+const SLASH_COMMANDS: &[(&str, &str)] = &[
+    (
+        "/memory [add|edit|forget|clear]",
+        "View and edit remembered facts",
+    ),
+    ("/audit", "Browse the tool execution log"),
+    (
+        "/stats",
+        "Tokens and estimated cost by day, session or model",
+    ),
+    ("/sandbox [on|off]", "Run commands in a container"),
+    (
+        "/safety [category threshold]",
+        "Show or change safety filter thresholds",
+    ),
+    (
+        "/model [name|default]",
+        "Show or switch this session's model",
+    ),
+    (
+        "/provider [gemini|vertex|default]",
+        "Show or switch this session's provider",
+    ),
+    (
+        "/attach <file>|--clipboard|--clear",
+        "Attach an image or audio file to the next message",
+    ),
+    (
+        "/capture [--pane <target>|<file>]",
+        "Attach terminal output from tmux or a file",
+    ),
+    (
+        "/profile [name|default]",
+        "Show or switch the config profile",
+    ),
+    ("/theme [name]", "Switch the color theme"),
+    ("/setup", "Enter a Gemini API key"),
+    ("/continue", "Resume an interrupted response"),
+    (
+        "/new [template]",
+        "Start a new session, blank or from a `[template.<name>]`",
+    ),
+    ("/title [text]", "Rename the session, or name it again"),
+    ("/pin", "Keep this session whatever the retention policy"),
+    ("/bookmarks", "Pick a bookmarked message to jump to"),
+    (
+        "/fork",
+        "Continue in a new branch from the selected message",
+    ),
+    ("/branch [n]", "List branches or switch to one"),
+    ("/map [off]", "Refresh the repository map sent with prompts"),
+    (
+        "/index [paths]",
+        "Index project files for automatic retrieval",
+    ),
+    ("/commit", "Write a commit message for the staged changes"),
+    (
+        "/review [ref|--staged]",
+        "Review uncommitted or staged changes",
+    ),
+    ("/imagine <prompt>", "Generate an image and save it"),
+    (
+        "/share [--gist]",
+        "Copy the conversation as Markdown, or upload it as a secret gist",
+    ),
+    (
+        "/split [model|off]",
+        "Answer prompts with a second model in a pane beside the chat",
+    ),
+    (
+        "/compare <prompt>",
+        "Ask the `[compare]` models at once and show their answers side by side",
+    ),
+];
+
+/// How long streamed text is batched before it is shown
+const CHUNK_INTERVAL: Duration = Duration::from_millis(40);
+
+/// How often the input thread checks whether the app has quit while no event arrives
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+/// Columns left of every message line: the role's bar and markers such as bookmarks
+const GUTTER_WIDTH: u16 = 3;
 
 const SPINNER_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
+/// Largest file the Files API takes
+const MAX_ATTACHMENT_BYTES: usize = 2_000_000_000;
+
+/// Bytes of each file a template pins that go into the prompt; the rest is cut off
+const MAX_PINNED_FILE_BYTES: u64 = 100_000;
+
+/// Appended to the prompt of a request that carries tool results
+const AFTER_TOOLS: &str = "System: The tools just returned data. Read it carefully and summarize the final answer to the user now. Do NOT output a function call.\n";
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Cli {}
+struct Cli {
+    /// Path to the config file (defaults to <config dir>/gemchat/config.toml)
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Run commands from the model in a container (see `[tools.sandbox]`)
+    #[arg(long)]
+    sandbox: bool,
+    /// Plain linear output for screen readers (see `ui.accessible`)
+    #[arg(long)]
+    accessible: bool,
+    /// Write logs at this level or filter (e.g. `debug`, `gemchat=trace`) to the state dir.
+    /// Also settable with GEMCHAT_LOG.
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// Use the settings of `[profile.<NAME>]` from the config file; with `auth`, manage
+    /// that profile's key
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Manage the API key in the system keyring
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Write a commit message for the staged changes and offer to commit with it
+    Commit {
+        /// Commit without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Review a git diff and print the findings by file
+    Review {
+        /// Commit or range to diff against; defaults to HEAD
+        reference: Option<String>,
+        /// Review only the staged changes
+        #[arg(long)]
+        staged: bool,
+    },
+    /// Delete sessions outside the `[storage]` retention policy and compact the database
+    Gc,
+    /// Recorded token usage
+    Usage {
+        #[command(subcommand)]
+        action: UsageAction,
+    },
+    /// Talk with the model through the Live API, with a live transcript (experimental;
+    /// needs a build with `--features live`)
+    Live,
+    /// Print shell hooks binding Alt+E to `gemchat explain` for the last command, e.g.
+    /// `eval "$(gemchat shellenv bash)"` in ~/.bashrc
+    Shellenv {
+        #[arg(value_enum)]
+        shell: shell::Shell,
+    },
+    /// Run a prompt with the changed files whenever files matching the globs change,
+    /// printing each answer
+    Watch {
+        /// Files to watch, e.g. `src/**/*.rs` or `logs/*.log`
+        #[arg(required = true)]
+        globs: Vec<String>,
+        /// What to ask each time, e.g. "Review these changes"
+        #[arg(long, short)]
+        prompt: String,
+        /// Milliseconds without changes before the prompt runs
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        debounce: u64,
+    },
+    /// Explain what running a command did, reading its output from the tmux pane
+    Explain {
+        /// The command that was run
+        #[arg(required = true)]
+        command: Vec<String>,
+        /// Its exit status
+        #[arg(long)]
+        status: Option<i32>,
+    },
+    /// Carry out a task with tools and no TUI, printing progress to stdout
+    Run {
+        /// What to do
+        #[arg(required = true)]
+        task: Vec<String>,
+        /// Run every tool call. Otherwise only calls that need no approval (read-only tools
+        /// and the allow rules of `[tools.approval]`, the profile or the template) run and the
+        /// rest are declined.
+        #[arg(long, short)]
+        yes: bool,
+        /// Give up after this many model requests
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        max_steps: usize,
+        /// Start from `[template.<name>]`: its instructions, pinned files, model and approval
+        /// rules
+        #[arg(long, value_name = "NAME")]
+        template: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UsageAction {
+    /// Print one row per model request with its tokens and estimated cost
+    Export {
+        /// First day to include, e.g. 2025-01-01
+        #[arg(long, value_name = "DATE")]
+        from: Option<chrono::NaiveDate>,
+        /// Last day to include
+        #[arg(long, value_name = "DATE")]
+        to: Option<chrono::NaiveDate>,
+        #[arg(long, value_enum, default_value_t = usage::Format::Csv)]
+        format: usage::Format,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AuthAction {
+    /// Prompt for a key, check it and store it
+    Set,
+    /// Delete the stored key
+    Remove,
+}
 
+/// The pane keys go to, cycled with Tab and Shift+Tab
 #[derive(Clone, Copy, PartialEq)]
-enum InputMode {
-    Normal,
-    Editing,
+enum Focus {
+    /// The message list, with the `[keys.normal]` bindings
+    Chat,
+    /// The `/split` pane, with the same bindings as the chat
+    Split,
+    /// The input box, with the `[keys.editing]` bindings
+    Input,
+    /// The sidebar's list of saved sessions, with the `[keys.sidebar]` bindings
+    Sidebar,
 }
 
 #[derive(Clone)]
@@ -35,584 +286,7260 @@ enum Action {
     UserInput(KeyEvent),
     SendMessage(String),
     AiResponseStart,
+    /// The model answering the current request
+    AiResponseModel(String),
+    /// The `[api]` rate limits hold the request back this long
+    RateLimited(Duration),
     AiResponseChunk(String),
     AiResponseError(String),
+    AiResponseInterrupted(String),
+    /// The safety filters blocked or flagged the response
+    AiResponseSafety(ai::SafetyReport),
+    /// Every candidate of the response, when several were generated
+    AiResponseAlternatives(Vec<String>),
     AiResponseFinish,
     UpdateUsage(ai::Usage),
-    ToolCall { name: String, args: String },
-    ToolResult { name: String, result: String },
+    ToolCall(ai::ToolCall),
+    ToolResults(Vec<ai::ToolOutcome>),
+    AuditFailed(String),
+    /// Result of checking a key entered on the setup screen
+    ApiKeyChecked(String, Result<(), String>),
+    /// A generated commit message for the staged changes
+    CommitMessage(Result<String, String>),
+    /// A title for the session written by the model
+    Titled(String),
+    /// A freshly built repository map
+    RepoMap(String),
+    /// Chunks embedded so far and in total while `/index` runs
+    IndexProgress(usize, usize),
+    Indexed(Result<Arc<rag::Index>, String>),
+    /// Output of a code block run from the chat
+    CodeRan(ai::ToolOutcome),
+    /// Findings for `/review`
+    ReviewReady(Result<review::Review, String>),
+    /// Result of `git commit`: its summary line
+    Committed(Result<String, String>),
+    /// Images generated for `/imagine`
+    Imagined(Result<ai::Generated, String>),
+    /// Image a mermaid block was rendered to
+    DiagramRendered(Result<PathBuf, String>),
+    /// A file read for `/attach`
+    Attached(Result<ai::Attachment, String>),
+    /// URL of the gist a transcript was uploaded to
+    Shared(Result<String, String>),
+    /// Updates of the `/compare` streams, by column
+    CompareChunk(usize, String),
+    CompareUsage(usize, ai::Usage),
+    CompareError(usize, String),
+    CompareFinish(usize),
+    /// Streamed text of the `/split` pane's response
+    SplitChunk(String),
+    SplitUsage(ai::Usage),
+    SplitError(String),
+    SplitFinish,
+    /// The terminal was resized
+    Resize,
+    /// SIGTSTP: suspend as Ctrl+Z does
+    Suspend,
+    /// SIGCONT: the process was continued after being stopped
+    Resumed,
+    /// The terminal gained (`true`) or lost focus
+    Focus(bool),
+    /// Text pasted in one piece (bracketed paste)
+    Paste(String),
     Tick,
-    Quit,
 }
 
+#[derive(Clone)]
 struct Message {
     role: String,
     content: String,
+    tool: Option<ToolBlock>,
+    created: DateTime<Local>,
+    /// For AI responses: time from sending the request to the first text chunk
+    first_token: Option<Duration>,
+    /// For AI responses: time from sending the request to the end of the stream
+    duration: Option<Duration>,
+    /// For AI responses: the model that wrote it
+    model: Option<String>,
+    /// Sent while a turn was in progress; kept at the bottom until that turn completes
+    queued: bool,
+    /// For AI responses: the stream broke before the model finished
+    interrupted: bool,
+    /// For AI responses: still arriving, so what is open in it is closed for now when drawn
+    streaming: bool,
+    /// For AI responses: what the safety filters blocked or flagged
+    safety: Option<ai::SafetyReport>,
+    /// For AI responses generated with several candidates: all of their texts, and which
+    /// one `content` holds
+    alternatives: Vec<String>,
+    alternative: usize,
+    /// For AI responses: the sampling parameters set when it was generated
+    generation: Option<String>,
+    /// For user messages: images and audio sent with it. They last for the session only.
+    attachments: Vec<ai::Attachment>,
+    /// Findings shown by severity; `content` holds them as text for the model
+    review: Option<review::Review>,
+    /// Marked with `m` to jump back to later
+    bookmarked: bool,
+    /// Shown as the text received instead of rendered Markdown
+    raw: bool,
+    /// Shown in full even when longer than `ui.fold_lines`
+    unfolded: bool,
+    /// For failed responses: the error taken apart, shown as a panel of what can be done
+    error: Option<ai::ErrorDetails>,
+    /// Body as last rendered; cleared whenever the content or its presentation changes
+    rendered: OnceCell<render::Document>,
+}
+
+/// A tool invocation shown as a single collapsible entry in the chat
+#[derive(Clone)]
+struct ToolBlock {
+    call: ai::ToolCall,
+    /// Full output; `None` while the tool is still queued or running
+    output: Option<String>,
+    /// Whether the model only received a head/tail excerpt of the output
+    truncated: bool,
+    expanded: bool,
+}
+
+impl Message {
+    fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            tool: None,
+            created: Local::now(),
+            first_token: None,
+            duration: None,
+            model: None,
+            queued: false,
+            interrupted: false,
+            streaming: false,
+            safety: None,
+            alternatives: Vec::new(),
+            alternative: 0,
+            generation: None,
+            attachments: Vec::new(),
+            review: None,
+            bookmarked: false,
+            raw: false,
+            unfolded: false,
+            error: None,
+            rendered: OnceCell::new(),
+        }
+    }
+
+    /// What is kept of the message in the store; tool calls are saved as text so they can
+    /// be searched
+    fn stored(&self) -> store::StoredMessage {
+        let content = match &self.tool {
+            Some(block) => format!(
+                "{} {}\n{}",
+                block.call.name,
+                block.call.args,
+                block.output.as_deref().unwrap_or_default()
+            ),
+            None => self.content.clone(),
+        };
+        store::StoredMessage {
+            role: self.role.clone(),
+            content,
+            model: self.model.clone(),
+            generation: self.generation.clone(),
+            created: self.created,
+        }
+    }
+
+    /// A message loaded from the store; tool calls come back from their text form
+    fn from_stored(stored: store::StoredMessage) -> Self {
+        let mut msg = match stored.role.as_str() {
+            "Tool" => {
+                let (header, output) = stored
+                    .content
+                    .split_once('\n')
+                    .unwrap_or((&stored.content, ""));
+                let (name, args) = header.split_once(' ').unwrap_or((header, "{}"));
+                let mut msg = Self::tool(ai::ToolCall {
+                    id: None,
+                    name: name.to_string(),
+                    args: args.to_string(),
+                    thought_signature: None,
+                });
+                if let Some(block) = &mut msg.tool {
+                    block.output = Some(output.to_string());
+                }
+                msg
+            }
+            _ => Self::new(stored.role, stored.content),
+        };
+        msg.model = stored.model;
+        msg.generation = stored.generation;
+        msg.created = stored.created;
+        msg
+    }
+
+    fn review(review: review::Review) -> Self {
+        Self {
+            review: Some(review.clone()),
+            ..Self::new("Review", review.to_string())
+        }
+    }
+
+    fn tool(call: ai::ToolCall) -> Self {
+        Self {
+            role: "Tool".into(),
+            content: String::new(),
+            tool: Some(ToolBlock {
+                call,
+                output: None,
+                truncated: false,
+                expanded: false,
+            }),
+            created: Local::now(),
+            first_token: None,
+            duration: None,
+            model: None,
+            queued: false,
+            interrupted: false,
+            streaming: false,
+            safety: None,
+            alternatives: Vec::new(),
+            alternative: 0,
+            generation: None,
+            attachments: Vec::new(),
+            review: None,
+            bookmarked: false,
+            raw: false,
+            unfolded: false,
+            error: None,
+            rendered: OnceCell::new(),
+        }
+    }
+}
+
+/// Full-screen `/audit` viewer over the tool execution log
+struct AuditView {
+    entries: Vec<audit::Entry>,
+    state: ListState,
+}
+
+/// Full-screen `/compare` view: one prompt answered by several models at once
+struct CompareView {
+    prompt: String,
+    columns: Vec<CompareColumn>,
+    /// Lines scrolled down in every column
+    scroll: u16,
+    /// The streams, aborted when the view closes
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl Drop for CompareView {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct CompareColumn {
+    model: String,
+    provider: Option<ai::Provider>,
+    text: String,
+    started: Instant,
+    first_token: Option<Duration>,
+    /// Set once the stream ends
+    duration: Option<Duration>,
+    usage: Option<ai::Usage>,
+    error: Option<String>,
+}
+
+/// Timeline of one turn for the latency trace: its phases, one after another
+struct Trace {
+    started: Instant,
+    spans: Vec<TraceSpan>,
+}
+
+struct TraceSpan {
+    label: String,
+    /// Since the start of the turn
+    start: Duration,
+    /// `None` while the phase is running
+    end: Option<Duration>,
+}
+
+impl Trace {
+    fn end(&self) -> Duration {
+        self.spans.last().map_or(Duration::ZERO, |span| {
+            span.end.unwrap_or_else(|| self.started.elapsed())
+        })
+    }
+}
+
+/// Full-screen `/stats` view of the recorded token usage
+struct StatsView {
+    rows: Vec<store::UsageRow>,
+    by: StatsGroup,
+    state: ListState,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StatsGroup {
+    Day,
+    Session,
+    Model,
+}
+
+/// Usage summed over the requests of one day, session or model
+struct UsageGroup {
+    label: String,
+    requests: usize,
+    prompt_tokens: i64,
+    total_tokens: i64,
+    /// `None` when some model has no known price
+    cost: Option<f64>,
+}
+
+/// Completions of the word at the input's cursor
+struct CompletionPopup {
+    token: completion::Token,
+    items: Vec<completion::Candidate>,
+    state: ListState,
+}
+
+/// Templates to choose one to start a new session from
+struct TemplatePicker {
+    /// Template names, after the blank session at the top of the list
+    names: Vec<String>,
+    state: ListState,
+}
+
+/// Bookmarked messages to choose one to jump to
+struct BookmarkPicker {
+    /// Indices into `App::messages`
+    messages: Vec<usize>,
+    state: ListState,
+}
+
+/// `Ctrl+R` search through every saved conversation
+struct HistorySearch<'a> {
+    input: TextArea<'a>,
+    hits: Vec<store::Hit>,
+    state: ListState,
+    /// Messages around the selected hit, and which of them is the hit
+    preview: Vec<store::StoredMessage>,
+    preview_at: usize,
+    error: Option<String>,
+}
+
+/// Copy, save or run the code block selected in the chat
+struct CodeActions<'a> {
+    block: render::CodeBlock,
+    step: CodeStep<'a>,
+    error: Option<String>,
+}
+
+enum CodeStep<'a> {
+    Menu,
+    /// Asking where to save the code
+    Save(Box<TextArea<'a>>),
+    ConfirmRun,
+}
+
+/// Info strings of code blocks that are run as shell commands
+const SHELL_LANGS: &[&str] = &["", "sh", "bash", "shell", "zsh", "console"];
+
+/// A saved `/imagine` image shown over the chat
+struct ImagePreview {
+    path: PathBuf,
+    data: bytes::Bytes,
+    /// Where the image goes in the last frame drawn, and where it was last drawn
+    area: Option<ratatui::layout::Rect>,
+    shown: Option<ratatui::layout::Rect>,
+}
+
+/// Aborts a spawned task when dropped, so aborting the task that holds it stops both
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// A second conversation shown beside the chat by `/split`, where another model answers
+/// the same prompts without tools
+struct SplitPane {
+    model: String,
+    messages: Vec<Message>,
+    list_state: ListState,
+    should_auto_scroll: bool,
+    loading: bool,
+    /// The stream of the response being written, aborted when the pane goes away
+    task: Option<tokio::task::JoinHandle<()>>,
+    /// Saved as a session of its own, created when the first answer is done
+    session: Option<i64>,
+}
+
+impl SplitPane {
+    fn new(model: String) -> Self {
+        Self {
+            model,
+            messages: Vec::new(),
+            list_state: ListState::default(),
+            should_auto_scroll: true,
+            loading: false,
+            task: None,
+            session: None,
+        }
+    }
+}
+
+impl Drop for SplitPane {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Tool calls from one model turn waiting for the user to allow or deny them
+struct ApprovalPrompt {
+    /// Each call with its decision; `None` until decided
+    calls: Vec<(ai::ToolCall, Option<audit::Approval>)>,
+}
+
+impl ApprovalPrompt {
+    /// Index of the call the user is being asked about
+    fn current(&self) -> Option<usize> {
+        self.calls
+            .iter()
+            .position(|(_, decision)| decision.is_none())
+    }
+}
+
+/// Screen asking for an API key, shown at startup when there is none
+struct Setup<'a> {
+    input: TextArea<'a>,
+    checking: bool,
+    error: Option<String>,
+}
+
+impl Setup<'_> {
+    fn new(theme: &theme::Theme) -> Self {
+        let mut input = TextArea::default();
+        input.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("API key"))
+                .style(Style::default().fg(theme.input_active)),
+        );
+        input.set_mask_char('•');
+        input.set_placeholder_text(tr("Paste your API key"));
+        input.set_cursor_line_style(Style::default());
+        Self {
+            input,
+            checking: false,
+            error: None,
+        }
+    }
+}
+
+/// Generated commit message open for editing before it is committed
+struct CommitDraft<'a> {
+    input: TextArea<'a>,
+    committing: bool,
+    error: Option<String>,
+}
+
+impl CommitDraft<'_> {
+    fn new(message: &str, theme: &theme::Theme) -> Self {
+        let mut input = TextArea::new(message.lines().map(str::to_string).collect());
+        input.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("Commit message"))
+                .style(Style::default().fg(theme.input_active)),
+        );
+        input.set_cursor_line_style(Style::default());
+        Self {
+            input,
+            committing: false,
+            error: None,
+        }
+    }
 }
 
 struct App<'a> {
     textarea: TextArea<'a>,
+    /// Name of the session, written by the model after the first exchange or set with `/title`
+    title: Option<String>,
+    /// Where the conversation is saved; `None` when the database can't be opened
+    store: Option<store::Store>,
+    /// Row of this conversation in the store, created when the first message is sent
+    session: Option<i64>,
+    /// Kept by the retention policy
+    pinned: bool,
+    /// Chosen with `/model` and `/provider` for this session, instead of the configured ones
+    session_model: Option<String>,
+    session_provider: Option<ai::Provider>,
+    /// Name of the `[template.<name>]` the session was started from
+    template: Option<String>,
+    /// The files that template pins, read when the session started
+    template_files: Vec<ai::ContextBlock>,
+    /// Images pasted and files `/attach`ed, sent with the next message
+    attachments: Vec<ai::Attachment>,
+    /// When the response being streamed was last written to the store
+    streamed_saved_at: Option<Instant>,
+    /// Messages of the current branch
     messages: Vec<Message>,
+    /// Forks of the conversation; holds every branch but the current one
+    branches: conversation::Tree<Message>,
     should_quit: bool,
     action_tx: mpsc::UnboundedSender<Action>,
     is_loading: bool,
+    /// The task streaming the in-flight response, until its end is handled or it is
+    /// cancelled
+    stream_task: Option<tokio::task::JoinHandle<()>>,
+    /// Background work started by commands and tools, aborted on quit
+    tasks: tokio::task::JoinSet<()>,
+    /// Whether a model has taken up the in-flight request
+    connected: bool,
+    /// When a request held back by the `[api]` rate limits goes out
+    rate_limited_until: Option<Instant>,
     spinner_index: usize,
-    input_mode: InputMode,
+    focus: Focus,
+    /// Keys pressed so far of a multi-key binding such as `]c`
+    pending_keys: Vec<KeyEvent>,
     list_state: ListState,
+    /// Where a visual selection started, as a list item; the selected item is its other end
+    visual_anchor: Option<usize>,
     should_auto_scroll: bool,
-    ps: SyntaxSet,
-    ts: ThemeSet,
+    /// Tool calls received during the current model turn, run once the turn finishes
+    pending_tool_calls: Vec<ai::ToolCall>,
+    highlighter: render::Highlighter,
+    /// Width messages are wrapped to; bodies are re-rendered when it changes
+    wrap_width: usize,
+    /// Colors the terminal shows; frames are drawn down to them
+    color_depth: theme::ColorDepth,
+    config: config::Config,
+    redactor: Arc<redact::Redactor>,
+    theme: theme::Theme,
+    keymap: keymap::Keymap,
+    audit_view: Option<AuditView>,
+    stats_view: Option<StatsView>,
+    compare_view: Option<CompareView>,
+    bookmark_picker: Option<BookmarkPicker>,
+    template_picker: Option<TemplatePicker>,
+    completion: Option<CompletionPopup>,
+    /// `@` mentions of the project's files, listed when first completed
+    project_files: Option<Vec<completion::Candidate>>,
+    history_search: Option<HistorySearch<'a>>,
+    code_actions: Option<CodeActions<'a>>,
+    /// Set while a code block run from the chat is executing
+    code_running: bool,
+    approval_prompt: Option<ApprovalPrompt>,
+    split: Option<SplitPane>,
+    /// Saved sessions listed in the sidebar while it has the focus, most recent first
+    sidebar_sessions: Vec<store::Session>,
+    sidebar_state: ListState,
+    turn_guard: approval::TurnGuard,
+    /// Why the tool loop is paused, while the user decides whether it goes on
+    loop_guard: Option<String>,
+    setup: Option<Setup<'a>>,
+    commit: Option<CommitDraft<'a>>,
+    /// Outline of the project included in every prompt, when enabled
+    repo_map: Option<String>,
+    /// Embedded project files that questions retrieve excerpts from
+    index: Option<Arc<rag::Index>>,
+    /// Progress of a running `/index`
+    indexing: Option<(usize, usize)>,
+    /// The next response continues the interrupted message instead of starting a new one
+    resuming: bool,
+    show_help: bool,
+    /// Whether the latency trace overlay is shown
+    show_trace: bool,
+    /// Timelines of the latest turns, oldest first
+    traces: std::collections::VecDeque<Trace>,
+    /// A generated image shown over the chat, on terminals with a graphics protocol
+    image_preview: Option<ImagePreview>,
+    graphics: Option<graphics::Protocol>,
+    /// Whether the whole screen must be repainted, e.g. to wipe an image drawn outside ratatui
+    needs_clear: bool,
+    ui_state: state::UiState,
+    /// Set while tool calls from the last turn are executing
+    tools_running: bool,
+    last_error: Option<String>,
+    notification: Option<(String, Instant)>,
+    /// When the in-flight request was sent
+    request_started: Option<Instant>,
+    /// When the user's current question was sent, tool round trips included
+    turn_started: Option<Instant>,
+    /// Whether the terminal has focus; terminals that don't report focus count as focused
+    focused: bool,
+    /// Characters received so far in the current stream and when the latest chunk arrived
+    stream_chars: usize,
+    last_chunk_at: Option<Instant>,
+    /// Whether the screen needs to be redrawn
+    dirty: bool,
+    ticks: u64,
 
     // Stats
     total_prompt_tokens: i32,
+    total_cached_tokens: i32,
     total_response_tokens: i32,
+    total_tokens: i32,
 }
 
 impl<'a> App<'a> {
-    fn new(action_tx: mpsc::UnboundedSender<Action>) -> Self {
-        let mut textarea = TextArea::default();
-        textarea.set_block(Block::default().borders(Borders::ALL).title("Input"));
-        textarea.set_placeholder_text("Type message... (Enter to send, Esc to quit)");
+    fn new(
+        action_tx: mpsc::UnboundedSender<Action>,
+        config: config::Config,
+        redactor: redact::Redactor,
+        theme: theme::Theme,
+        keymap: keymap::Keymap,
+        store: Option<store::Store>,
+    ) -> Self {
+        let mut ui_state = state::UiState::load();
+        if let Some(width) = config.layout.sidebar_width
+            && !ui_state.sidebar_resized
+        {
+            ui_state.sidebar_width =
+                width.clamp(state::MIN_SIDEBAR_WIDTH, state::MAX_SIDEBAR_WIDTH);
+        }
+        // The session shown at start is a new one
+        let textarea = input_area(&std::mem::take(&mut ui_state.draft));
+        let setup = (!ai::has_credentials()).then(|| Setup::new(&theme));
 
         Self {
             textarea,
-            messages: vec![
-                Message {
-                    role: "System".into(),
-                    content: "Welcome to the AI Chat TUI!".into(),
-                },
-                Message {
-                    role: "System".into(),
-                    content: "Set GEMINI_API_KEY env var for real AI.".into(),
-                },
-            ],
+            messages: vec![Message::new("System", "Welcome to the AI Chat TUI!")],
+            title: None,
+            store,
+            session: None,
+            pinned: false,
+            session_model: None,
+            session_provider: None,
+            template: None,
+            template_files: Vec::new(),
+            attachments: Vec::new(),
+            streamed_saved_at: None,
+            branches: conversation::Tree::new(),
             should_quit: false,
             action_tx,
             is_loading: false,
+            stream_task: None,
+            tasks: tokio::task::JoinSet::new(),
+            connected: false,
+            rate_limited_until: None,
             spinner_index: 0,
-            input_mode: InputMode::Editing,
+            focus: Focus::Input,
+            pending_keys: Vec::new(),
             list_state: ListState::default(),
+            visual_anchor: None,
             should_auto_scroll: true,
-            ps: SyntaxSet::load_defaults_newlines(),
-            ts: ThemeSet::load_defaults(),
+            pending_tool_calls: Vec::new(),
+            highlighter: render::Highlighter::new(),
+            wrap_width: 0,
+            color_depth: config.ui.color_depth(),
+            config,
+            redactor: Arc::new(redactor),
+            theme,
+            keymap,
+            audit_view: None,
+            stats_view: None,
+            compare_view: None,
+            bookmark_picker: None,
+            template_picker: None,
+            completion: None,
+            project_files: None,
+            history_search: None,
+            code_actions: None,
+            code_running: false,
+            approval_prompt: None,
+            split: None,
+            sidebar_sessions: Vec::new(),
+            sidebar_state: ListState::default(),
+            turn_guard: approval::TurnGuard::default(),
+            loop_guard: None,
+            setup,
+            commit: None,
+            repo_map: None,
+            index: config::cache_dir()
+                .zip(std::env::current_dir().ok())
+                .and_then(|(cache, root)| {
+                    rag::Index::load(&cache.join("index"), &root).unwrap_or_else(|e| {
+                        tracing::warn!(error = %e, "could not load the project index");
+                        None
+                    })
+                })
+                .map(Arc::new),
+            indexing: None,
+            resuming: false,
+            show_help: false,
+            show_trace: false,
+            traces: std::collections::VecDeque::new(),
+            image_preview: None,
+            graphics: graphics::detect(),
+            needs_clear: false,
+            ui_state,
+            tools_running: false,
+            last_error: None,
+            notification: None,
+            request_started: None,
+            turn_started: None,
+            focused: true,
+            stream_chars: 0,
+            last_chunk_at: None,
+            dirty: true,
+            ticks: 0,
             total_prompt_tokens: 0,
+            total_cached_tokens: 0,
             total_response_tokens: 0,
+            total_tokens: 0,
         }
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
+        // Ticks only matter while something on screen animates or ages
+        self.dirty |= match action {
+            Action::Tick => {
+                self.ticks += 1;
+                self.busy() && !self.config.ui.accessible()
+                    || self.notification.is_some()
+                    || self.rate_limited_until.is_some()
+                    || (self.config.ui.timestamps() == config::Timestamps::Relative
+                        && self.ticks.is_multiple_of(10))
+            }
+            _ => true,
+        };
+
         match action {
-            Action::Quit => self.should_quit = true,
             Action::Tick => {
-                if self.is_loading {
+                while let Some(result) = self.tasks.try_join_next() {
+                    if let Err(e) = result
+                        && e.is_panic()
+                    {
+                        tracing::error!(error = %e, "background task panicked");
+                    }
+                }
+                if self.busy() {
                     self.spinner_index = (self.spinner_index + 1) % SPINNER_FRAMES.len();
                 }
+                if self
+                    .rate_limited_until
+                    .is_some_and(|until| until <= Instant::now())
+                {
+                    self.rate_limited_until = None;
+                }
             }
-            Action::UserInput(key) => {
-                match self.input_mode {
-                    InputMode::Editing => {
-                        match key.code {
-                            KeyCode::Esc => {
-                                self.input_mode = InputMode::Normal;
-                            }
-                            KeyCode::Enter => {
-                                let input = self.textarea.lines().join("\n");
-                                if !input.trim().is_empty() {
-                                    self.messages.push(Message {
-                                        role: "You".into(),
-                                        content: input.clone(),
-                                    });
-                                    self.should_auto_scroll = true; // Snap to bottom on send
-                                    let _ = self.action_tx.send(Action::SendMessage(input));
-
-                                    let mut new_textarea = TextArea::default();
-                                    new_textarea.set_block(self.textarea.block().cloned().unwrap());
-                                    new_textarea.set_placeholder_text(
-                                        "Type message... (Enter to send, Esc to quit)",
-                                    );
-                                    self.textarea = new_textarea;
-                                }
-                            }
-                            _ => {
-                                self.textarea.input(key);
-                            }
-                        }
-                    }
-                    InputMode::Normal => match key.code {
-                        KeyCode::Char('q') => self.should_quit = true,
-                        KeyCode::Char('i') => self.input_mode = InputMode::Editing,
-                        KeyCode::Char('j') | KeyCode::Down => {
-                            self.scroll_down();
-                            self.should_auto_scroll = false;
-                        }
-                        KeyCode::Char('k') | KeyCode::Up => {
-                            self.scroll_up();
-                            self.should_auto_scroll = false;
-                        }
-                        KeyCode::Char('G') => {
-                            self.should_auto_scroll = true;
-                            self.scroll_to_bottom();
-                        }
-                        KeyCode::Char('c') => {
-                            self.messages.clear();
-                            self.should_auto_scroll = true;
-                        }
-                        _ => {}
-                    },
+            Action::Resize => {
+                // Some terminals leave stray cells behind when the window shrinks
+                self.needs_clear = true;
+                // Placed again once the new layout is drawn
+                if let Some(preview) = &mut self.image_preview
+                    && preview.shown.take().is_some()
+                    && let Some(protocol) = self.graphics
+                {
+                    let _ = graphics::clear(protocol);
                 }
             }
+            Action::Suspend => self.suspend(),
+            Action::Resumed => self.resumed(),
+            Action::Focus(focused) => self.focused = focused,
+            Action::Paste(text) => self.paste(&text),
+            Action::UserInput(key) if self.setup.is_some() => self.setup_key(key),
+            Action::UserInput(key) if self.commit.is_some() => self.commit_key(key),
+            Action::UserInput(key) if self.loop_guard.is_some() => self.loop_guard_key(key),
+            Action::UserInput(key) if self.approval_prompt.is_some() => self.approval_key(key),
+            Action::UserInput(key) if self.audit_view.is_some() => self.audit_key(key),
+            Action::UserInput(key) if self.stats_view.is_some() => self.stats_key(key),
+            Action::UserInput(key) if self.compare_view.is_some() => self.compare_key(key),
+            Action::UserInput(key) if self.bookmark_picker.is_some() => self.bookmark_key(key),
+            Action::UserInput(key) if self.template_picker.is_some() => self.template_key(key),
+            Action::UserInput(key) if self.history_search.is_some() => self.search_key(key),
+            Action::UserInput(key) if self.code_actions.is_some() => self.code_key(key),
+            // Any key dismisses the help overlay; the image preview also opens its file
+            Action::UserInput(_) if self.show_help => self.show_help = false,
+            Action::UserInput(key) if self.image_preview.is_some() => self.preview_key(key),
+            Action::UserInput(key) => self.key(key),
             Action::SendMessage(text) => {
-                self.is_loading = true;
-                self.spinner_index = 0;
-
-                // Build a combined prompt from conversation history so the AI has context
-                let mut full_context = String::from(
-                    "System Instructions: You are a helpful AI assistant. Answer the user's prompt based on the history below. If the history contains a 'Tool Result', DO NOT call the same tool again. Read the text provided in the Tool Result and use it to answer the user directly.\n\nConversation History:\n",
-                );
-                for msg in &self.messages {
-                    if !msg.content.is_empty() {
-                        full_context.push_str(&format!("{}: {}\n\n", msg.role, msg.content));
+                self.last_error = None;
+                let mut msg = Message::new("You", text);
+                msg.attachments = std::mem::take(&mut self.attachments);
+                // The split pane is asked the same, once it is done with its last answer
+                let split_ready = match &mut self.split {
+                    Some(pane) => {
+                        pane.messages.push(Message {
+                            queued: pane.loading,
+                            unfolded: true,
+                            ..msg.clone()
+                        });
+                        pane.should_auto_scroll = true;
+                        !pane.loading
                     }
+                    None => false,
+                };
+                if self.is_loading {
+                    msg.queued = true;
+                    self.messages.push(msg);
+                    self.scroll_to_bottom();
+                } else {
+                    self.messages.push(msg);
+                    self.request_completion();
                 }
-
-                // If this message is an automated tool result, reinforce the instruction
-                if text.starts_with("Tool") {
-                    full_context.push_str("System: The tool just returned data. Read it carefully and summarize the final answer to the user now. Do NOT output a function call.\n");
+                if split_ready {
+                    self.spawn_split_stream();
                 }
-
-                let tx = self.action_tx.clone();
-                tokio::spawn(async move {
-                    let (ai_tx, mut ai_rx) = mpsc::unbounded_channel();
-
-                    tokio::spawn(async move {
-                        ai::stream_response(full_context, ai_tx).await;
-                    });
-
-                    let _ = tx.send(Action::AiResponseStart);
-
-                    while let Some(update) = ai_rx.recv().await {
-                        match update {
-                            ai::AiUpdate::Content(s) => {
-                                let _ = tx.send(Action::AiResponseChunk(s));
-                            }
-                            ai::AiUpdate::Usage(usage) => {
-                                let _ = tx.send(Action::UpdateUsage(usage));
-                            }
-                            ai::AiUpdate::Error(e) => {
-                                let _ = tx.send(Action::AiResponseError(e));
-                            }
-                            ai::AiUpdate::ToolCall { name, args } => {
-                                let _ = tx.send(Action::ToolCall { name, args });
-                            }
-                            ai::AiUpdate::Finished => {
-                                let _ = tx.send(Action::AiResponseFinish);
-                                break;
-                            }
-                        }
-                    }
+                self.save_session();
+            }
+            Action::CompareChunk(i, chunk) => {
+                if let Some(column) = self.compare_column(i) {
+                    column
+                        .first_token
+                        .get_or_insert_with(|| column.started.elapsed());
+                    column.text.push_str(&chunk);
+                }
+            }
+            Action::CompareUsage(i, usage) => {
+                self.total_prompt_tokens += usage.prompt_tokens;
+                self.total_cached_tokens += usage.cached_tokens;
+                self.total_response_tokens += usage.response_tokens;
+                self.total_tokens += usage.total_tokens;
+                let model = self.compare_column(i).map(|column| {
+                    column.usage = Some(usage.clone());
+                    column.model.clone()
                 });
+                if let Some(model) = model
+                    && let Some(store) = &self.store
+                    && let Err(e) = store.record_usage(
+                        self.session,
+                        &model,
+                        usage.prompt_tokens.into(),
+                        usage.cached_tokens.into(),
+                        usage.response_tokens.into(),
+                        usage.total_tokens.into(),
+                    )
+                {
+                    tracing::warn!(error = %e, "could not record token usage");
+                }
+            }
+            Action::CompareError(i, err) => {
+                if let Some(column) = self.compare_column(i) {
+                    column.error = Some(err);
+                }
+            }
+            Action::CompareFinish(i) => {
+                if let Some(column) = self.compare_column(i) {
+                    column.duration = Some(column.started.elapsed());
+                }
+            }
+            Action::SplitChunk(chunk) => {
+                if let Some(pane) = &mut self.split
+                    && let Some(msg) = pane.messages.last_mut()
+                    && msg.role == "AI"
+                {
+                    msg.content.push_str(&chunk);
+                    msg.streaming = true;
+                    msg.rendered.take();
+                }
+            }
+            Action::SplitUsage(usage) => {
+                self.total_prompt_tokens += usage.prompt_tokens;
+                self.total_cached_tokens += usage.cached_tokens;
+                self.total_response_tokens += usage.response_tokens;
+                self.total_tokens += usage.total_tokens;
+                if let (Some(store), Some(pane)) = (&self.store, &self.split)
+                    && let Err(e) = store.record_usage(
+                        pane.session,
+                        &pane.model,
+                        usage.prompt_tokens.into(),
+                        usage.cached_tokens.into(),
+                        usage.response_tokens.into(),
+                        usage.total_tokens.into(),
+                    )
+                {
+                    tracing::warn!(error = %e, "could not record token usage");
+                }
+            }
+            Action::SplitError(err) => {
+                if let Some(pane) = &mut self.split {
+                    if pane
+                        .messages
+                        .last()
+                        .is_some_and(|m| m.role == "AI" && m.content.is_empty())
+                    {
+                        pane.messages.pop();
+                    }
+                    pane.messages.push(Message::new("Error", err));
+                }
+            }
+            Action::SplitFinish => {
+                if let Some(pane) = &mut self.split {
+                    pane.loading = false;
+                    pane.task = None;
+                    settle_streamed(&mut pane.messages);
+                    let mut queued = false;
+                    for msg in pane.messages.iter_mut().filter(|m| m.queued) {
+                        msg.queued = false;
+                        queued = true;
+                    }
+                    self.save_split();
+                    if queued {
+                        self.spawn_split_stream();
+                    }
+                }
             }
+            // Left over from a cancelled response
+            Action::AiResponseStart
+            | Action::AiResponseModel(_)
+            | Action::RateLimited(_)
+            | Action::AiResponseChunk(_)
+            | Action::AiResponseError(_)
+            | Action::AiResponseInterrupted(_)
+            | Action::AiResponseSafety(_)
+            | Action::AiResponseAlternatives(_)
+            | Action::AiResponseFinish
+            | Action::UpdateUsage(_)
+            | Action::ToolCall(_)
+                if self.stream_task.is_none() => {}
             Action::AiResponseStart => {
-                self.messages.push(Message {
-                    role: "AI".into(),
-                    content: String::new(),
-                });
+                // A resumed response continues in the interrupted message
+                if !std::mem::take(&mut self.resuming) {
+                    // Shown whole while it is read; Enter folds it
+                    self.push_message(Message {
+                        generation: self.config.generation.summary(),
+                        unfolded: true,
+                        ..Message::new("AI", String::new())
+                    });
+                } else if let Some(msg) = self.last_live_mut() {
+                    msg.unfolded = true;
+                    msg.rendered.take();
+                }
                 if self.should_auto_scroll {
                     self.scroll_to_bottom();
                 }
+                self.trace_phase("connect");
+            }
+            Action::RateLimited(wait) => {
+                self.trace_phase(format!("rate limited {:.1}s", wait.as_secs_f32()));
+                self.rate_limited_until = Some(Instant::now() + wait);
+            }
+            Action::AiResponseModel(model) => {
+                self.rate_limited_until = None;
+                self.connected = true;
+                self.trace_phase(format!("wait for first token · {}", model));
+                if model != self.model() {
+                    self.notify(i18n::fill(
+                        tr("{} unavailable, answering with {}"),
+                        &[&self.model(), &model],
+                    ));
+                }
+                if let Some(last_msg) = self.last_live_mut()
+                    && last_msg.role == "AI"
+                {
+                    last_msg.model = Some(model);
+                }
             }
             Action::AiResponseChunk(chunk) => {
-                if let Some(last_msg) = self.messages.last_mut() {
-                    if last_msg.role == "AI" {
-                        last_msg.content.push_str(&chunk);
+                if self.last_chunk_at.is_none() {
+                    self.trace_phase("stream");
+                }
+                let started = self.request_started;
+                if let Some(last_msg) = self.last_live_mut()
+                    && last_msg.role == "AI"
+                {
+                    if last_msg.first_token.is_none()
+                        && let Some(started) = started
+                    {
+                        last_msg.first_token = Some(started.elapsed());
                     }
+                    last_msg.content.push_str(&chunk);
+                    last_msg.streaming = true;
+                    last_msg.rendered.take();
                 }
+                self.stream_chars += chunk.chars().count();
+                self.last_chunk_at = Some(Instant::now());
+                self.save_streamed();
             }
             Action::UpdateUsage(usage) => {
                 self.total_prompt_tokens += usage.prompt_tokens;
+                self.total_cached_tokens += usage.cached_tokens;
                 self.total_response_tokens += usage.response_tokens;
+                self.total_tokens += usage.total_tokens;
+                self.turn_guard.cost += pricing::cost(
+                    &self.config.pricing,
+                    self.answering_model(),
+                    usage.prompt_tokens.into(),
+                    usage.cached_tokens.into(),
+                    usage.total_tokens.into(),
+                )
+                .unwrap_or(0.0);
+                self.record_usage(&usage);
             }
             Action::AiResponseError(err) => {
-                self.messages.push(Message {
-                    role: "Error".into(),
-                    content: err,
-                });
-                self.is_loading = false;
+                self.stream_task = None;
+                self.trace_end();
+                self.finish_timing();
+                self.push_failure(err);
+                self.pending_tool_calls.clear();
+                for msg in &mut self.messages {
+                    if let Some(block) = &mut msg.tool
+                        && block.output.is_none()
+                    {
+                        block.output = Some("Not run: the response failed".into());
+                        msg.rendered.take();
+                    }
+                }
+                self.finish_turn();
+            }
+            Action::AiResponseInterrupted(reason) => match self.last_live_mut() {
+                Some(msg) if msg.role == "AI" && !msg.content.is_empty() => {
+                    msg.interrupted = true;
+                    self.last_error = Some(i18n::fill(tr("Response interrupted: {}"), &[&reason]));
+                    self.notify(tr(
+                        "Response interrupted: press r or use /continue to resume it",
+                    ));
+                }
+                _ => self.push_error(i18n::fill(
+                    tr("Error: response interrupted: {}"),
+                    &[&reason],
+                )),
+            },
+            Action::AiResponseSafety(report) => {
+                if let Some(reason) = &report.blocked {
+                    self.notify(i18n::fill(
+                        tr("Response blocked by the safety filters ({}): see /safety"),
+                        &[&reason],
+                    ));
+                }
+                if let Some(msg) = self.last_live_mut()
+                    && msg.role == "AI"
+                {
+                    // Later events rate the whole response so far
+                    let blocked = msg.safety.take().and_then(|s| s.blocked);
+                    msg.safety = Some(ai::SafetyReport {
+                        blocked: report.blocked.or(blocked),
+                        ratings: report.ratings,
+                    });
+                }
+            }
+            Action::AiResponseAlternatives(texts) => {
+                if let Some(msg) = self.last_live_mut()
+                    && msg.role == "AI"
+                {
+                    msg.alternatives = texts;
+                    msg.alternative = 0;
+                    msg.rendered.take();
+                }
+                self.notify(tr("Alternative responses: ]a and [a switch between them"));
             }
             Action::AiResponseFinish => {
-                self.is_loading = false;
+                self.stream_task = None;
+                let tokens = self.stream_chars / 4;
+                if let Some(span) = self.current_span()
+                    && span.label == "stream"
+                {
+                    span.label = format!("stream · ~{} tokens", tokens);
+                }
+                self.trace_end();
+                self.finish_timing();
+                if self.pending_tool_calls.is_empty() {
+                    self.finish_turn();
+                } else if let Some(tripped) = self
+                    .turn_guard
+                    .check(&self.pending_tool_calls, &self.config.tools)
+                {
+                    let reason = loop_guard_reason(&tripped);
+                    self.notify_user(tr("Tool loop paused"), reason.clone());
+                    self.loop_guard = Some(reason);
+                } else {
+                    self.review_pending_tools();
+                }
             }
 
-            Action::ToolCall { name, args } => {
-                self.messages.push(Message {
-                    role: "System".into(),
-                    content: format!("Executing tool: `{}`", name),
-                });
+            Action::ToolCall(call) => {
+                self.push_message(Message::tool(call.clone()));
                 if self.should_auto_scroll {
                     self.scroll_to_bottom();
                 }
-
-                let tx = self.action_tx.clone();
-                tokio::spawn(async move {
-                    let result = tools::execute_tool(&name, &args).await;
-                    let _ = tx.send(Action::ToolResult { name, result });
-                });
+                self.pending_tool_calls.push(call);
             }
-            Action::ToolResult { name, result } => {
-                self.messages.push(Message {
-                    role: "Tool Result".into(),
-                    content: format!("**{}**\n```text\n{}\n```", name, result),
-                });
+            Action::AuditFailed(err) => {
+                self.push_error(i18n::fill(tr("Audit log: {}"), &[&err]));
+            }
+            Action::ApiKeyChecked(key, result) => self.api_key_checked(key, result),
+            Action::CommitMessage(Ok(message)) => {
+                self.commit = Some(CommitDraft::new(&message, &self.theme));
+            }
+            Action::CommitMessage(Err(e)) => self.push_error(e),
+            Action::Committed(result) => self.committed(result),
+            Action::IndexProgress(done, total) => self.indexing = Some((done, total)),
+            Action::Indexed(result) => {
+                self.indexing = None;
+                match result {
+                    Ok(index) => {
+                        self.notify(i18n::fill(
+                            tr("Indexed {} files in {} chunks"),
+                            &[&index.file_count(), &index.chunk_count()],
+                        ));
+                        self.index = Some(index);
+                    }
+                    Err(e) => self.push_error(i18n::fill(tr("Indexing failed: {}"), &[&e])),
+                }
+            }
+            // A `/title` set meanwhile wins
+            Action::Titled(title) if self.title.is_none() => self.set_title(title),
+            Action::Titled(_) => {}
+            Action::RepoMap(map) => {
+                self.notify(i18n::fill(
+                    tr("Repository map: {} files, ~{} tokens"),
+                    &[
+                        &(map.lines().filter(|l| !l.starts_with(' ')).count()),
+                        &(map.len() / 4),
+                    ],
+                ));
+                self.repo_map = Some(map);
+            }
+            Action::ReviewReady(Ok(review)) => {
+                self.push_message(Message::review(review));
+                if self.should_auto_scroll {
+                    self.scroll_to_bottom();
+                }
+            }
+            Action::ReviewReady(Err(e)) => self.push_error(e),
+            Action::Imagined(Ok(generated)) => self.save_images(generated),
+            Action::Imagined(Err(e)) => {
+                self.push_error(i18n::fill(tr("Image generation failed: {}"), &[&e]))
+            }
+            Action::Attached(attachment) => self.attach(attachment),
+            Action::DiagramRendered(Ok(path)) => self.show_diagram(path),
+            Action::DiagramRendered(Err(e)) => {
+                self.push_error(i18n::fill(tr("Could not render the diagram: {}"), &[&e]))
+            }
+            Action::Shared(Ok(url)) => {
+                // Copied too, so it can be pasted straight away
+                let _ = clipboard::copy(&url);
+                self.push_system(i18n::fill(tr("Shared as a secret gist: {}"), &[&url]));
+            }
+            Action::Shared(Err(e)) => self.push_error(i18n::fill(tr("Could not share: {}"), &[&e])),
+            Action::CodeRan(outcome) => {
+                self.code_running = false;
+                if let Some(msg) = self.messages.iter_mut().rev().find(|m| {
+                    m.tool
+                        .as_ref()
+                        .is_some_and(|b| b.output.is_none() && b.call.args == outcome.call.args)
+                }) && let Some(block) = &mut msg.tool
+                {
+                    block.output = Some(outcome.result);
+                    block.expanded = true;
+                    msg.rendered.take();
+                }
                 if self.should_auto_scroll {
                     self.scroll_to_bottom();
                 }
+            }
+            Action::ToolResults(outcomes) => {
+                self.tools_running = false;
+                self.trace_end();
+                // Build the history before the outputs are attached: they go to the model as
+                // functionResponse parts rather than as part of the transcript text
+                let context = self.build_context(true);
 
-                // Note: To make the AI aware of the result, you can uncomment the next line
-                // once your `ai` module is configured to process tool responses:
-                let _ = self.action_tx.send(Action::SendMessage(format!(
-                    "Tool {} output:\n{}",
-                    name, result
-                )));
+                let mut for_model = Vec::with_capacity(outcomes.len());
+                for outcome in outcomes {
+                    let excerpt = tools::truncate_output(&outcome.result, &self.config.tools);
+                    if let Some(msg) = self.messages.iter_mut().find(|m| {
+                        m.tool.as_ref().is_some_and(|b| {
+                            b.output.is_none()
+                                && b.call.name == outcome.call.name
+                                && b.call.args == outcome.call.args
+                        })
+                    }) && let Some(block) = &mut msg.tool
+                    {
+                        block.truncated = excerpt.len() != outcome.result.len();
+                        block.output = Some(outcome.result);
+                        msg.rendered.take();
+                    }
+                    for_model.push(ai::ToolOutcome {
+                        call: outcome.call,
+                        result: excerpt,
+                    });
+                }
+                if self.should_auto_scroll {
+                    self.scroll_to_bottom();
+                }
+
+                self.spawn_stream(context, for_model);
             }
         }
         Ok(())
     }
 
-    fn scroll_up(&mut self) {
-        let i = match self.list_state.selected() {
-            Some(i) => {
-                if i == 0 {
-                    0
-                } else {
-                    i - 1
-                }
+    /// Inserts pasted text literally into whichever input is open, so newlines in it don't
+    /// send anything
+    fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        if let Some(setup) = &mut self.setup {
+            if !setup.checking {
+                // An API key is one line
+                setup.input.insert_str(text.trim());
             }
-            None => 0,
-        };
-        self.list_state.select(Some(i));
+        } else if let Some(draft) = &mut self.commit {
+            if !draft.committing {
+                draft.input.insert_str(text);
+            }
+        } else if let Some(actions) = &mut self.code_actions {
+            if let CodeStep::Save(input) = &mut actions.step {
+                input.insert_str(text.trim());
+            }
+        } else if let Some(search) = &mut self.history_search {
+            // The query is one line
+            search.input.insert_str(text.replace('\n', " "));
+            self.run_history_search();
+        } else if self.approval_prompt.is_none()
+            && self.audit_view.is_none()
+            && self.stats_view.is_none()
+            && self.bookmark_picker.is_none()
+            && self.template_picker.is_none()
+        {
+            self.show_help = false;
+            self.pending_keys.clear();
+            self.focus = Focus::Input;
+            if text.is_empty() {
+                // Terminals paste nothing when the clipboard holds only an image
+                self.attach_command("--clipboard");
+            } else {
+                self.textarea.insert_str(text);
+                self.update_completion();
+            }
+        }
     }
 
-    fn scroll_down(&mut self) {
-        let i = match self.list_state.selected() {
-            Some(i) => {
-                if i >= self.total_list_items() - 1 {
+    /// Bindings of the focused pane
+    fn bindings(&self) -> &keymap::Bindings {
+        match self.focus {
+            Focus::Chat | Focus::Split => &self.keymap.normal,
+            Focus::Input => &self.keymap.editing,
+            Focus::Sidebar => &self.keymap.sidebar,
+        }
+    }
+
+    /// The first key bound to `command` in the focused pane, for hints
+    fn key_for(&self, command: keymap::Command) -> Option<String> {
+        keymap::first_key(self.bindings(), command).map(|key| key.to_string())
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        if self.focus != Focus::Input {
+            self.completion = None;
+        } else if self.pending_keys.is_empty() && self.completion_key(key) {
+            return;
+        }
+        self.pending_keys.push(key);
+        match keymap::lookup(self.bindings(), &self.pending_keys) {
+            keymap::Lookup::Command(command) => {
+                self.pending_keys.clear();
+                self.completion = None;
+                self.run_key_command(command);
+            }
+            keymap::Lookup::Pending => {}
+            // A key that doesn't continue the sequence starts over on its own
+            keymap::Lookup::None if self.pending_keys.len() > 1 => {
+                self.pending_keys.clear();
+                self.key(key);
+            }
+            keymap::Lookup::None => {
+                self.pending_keys.clear();
+                if self.focus == Focus::Input {
+                    self.textarea.input(key);
+                    self.update_completion();
+                }
+            }
+        }
+    }
+
+    /// Moves through, accepts or closes the completion popup; `false` for keys it doesn't
+    /// take, which go to the input
+    fn completion_key(&mut self, key: KeyEvent) -> bool {
+        let Some(popup) = &mut self.completion else {
+            return false;
+        };
+        let last = popup.items.len().saturating_sub(1);
+        let selected = popup.state.selected().unwrap_or(0);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Up => popup.state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Char('p') if ctrl => popup.state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => popup.state.select(Some((selected + 1).min(last))),
+            KeyCode::Char('n') if ctrl => popup.state.select(Some((selected + 1).min(last))),
+            KeyCode::Tab | KeyCode::Enter => self.accept_completion(),
+            KeyCode::Esc => self.completion = None,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Opens, refreshes or closes the completion popup for the word at the cursor
+    fn update_completion(&mut self) {
+        const MAX_ITEMS: usize = 8;
+
+        let (row, col) = self.textarea.cursor();
+        let line = self.textarea.lines()[row].clone();
+        let Some(token) = completion::token_at(&line, col, row == 0) else {
+            self.completion = None;
+            return;
+        };
+        let candidates = self.completion_candidates(token.source);
+        let items = completion::filter(&token.query, &candidates, MAX_ITEMS);
+        let typed: String = line.chars().take(col).skip(token.start).collect();
+        // Nothing to offer but what is already there
+        if items.iter().all(|item| item.text == typed) {
+            self.completion = None;
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.completion = Some(CompletionPopup {
+            token,
+            items,
+            state,
+        });
+    }
+
+    fn completion_candidates(&mut self, source: completion::Source) -> Vec<completion::Candidate> {
+        const MAX_FILES: usize = 5000;
+
+        match source {
+            completion::Source::Command => SLASH_COMMANDS
+                .iter()
+                .map(|(usage, description)| {
+                    let name = usage.split_whitespace().next().unwrap_or(usage);
+                    completion::Candidate::new(name, tr(description))
+                })
+                .collect(),
+            completion::Source::Model => {
+                let mut names: Vec<&str> = self.config.models.iter().map(String::as_str).collect();
+                names.push(ai::MODEL);
+                names.extend(self.session_model.as_deref());
+                let mut candidates = vec![completion::Candidate::new(
+                    "default",
+                    format!("back to {}", self.config.model()),
+                )];
+                for name in names {
+                    if !candidates.iter().any(|c| c.text == name) {
+                        candidates.push(completion::Candidate::new(name, ""));
+                    }
+                }
+                candidates
+            }
+            completion::Source::File => self
+                .project_files
+                .get_or_insert_with(|| {
+                    let root = std::env::current_dir().unwrap_or_default();
+                    completion::project_files(&root, MAX_FILES)
+                        .into_iter()
+                        .map(|path| completion::Candidate::new(format!("@{}", path), ""))
+                        .collect()
+                })
+                .clone(),
+        }
+    }
+
+    /// Replaces the word at the cursor with the selected completion
+    fn accept_completion(&mut self) {
+        let Some(popup) = self.completion.take() else {
+            return;
+        };
+        let Some(item) = popup.state.selected().and_then(|i| popup.items.get(i)) else {
+            return;
+        };
+        let (_, col) = self.textarea.cursor();
+        for _ in popup.token.start..col {
+            self.textarea.delete_char();
+        }
+        self.textarea.insert_str(&item.text);
+        self.textarea.insert_char(' ');
+        // `/model ` goes on to the model names
+        self.update_completion();
+    }
+
+    fn run_key_command(&mut self, command: keymap::Command) {
+        match command {
+            keymap::Command::Send => self.send_input(),
+            keymap::Command::Undo => {
+                self.textarea.undo();
+            }
+            keymap::Command::Redo => {
+                self.textarea.redo();
+            }
+            keymap::Command::EditMode => self.focus = Focus::Input,
+            keymap::Command::NormalMode => {
+                self.focus = Focus::Chat;
+                self.visual_anchor = None;
+            }
+            keymap::Command::Visual => self.toggle_visual(),
+            keymap::Command::Yank => self.yank(false),
+            keymap::Command::YankMarkdown => self.yank(true),
+            keymap::Command::FocusNext => self.cycle_focus(true),
+            keymap::Command::FocusPrev => self.cycle_focus(false),
+            keymap::Command::ScrollUp | keymap::Command::ScrollDown
+                if self.focus == Focus::Sidebar =>
+            {
+                self.step_sidebar_session(command == keymap::Command::ScrollDown)
+            }
+            keymap::Command::Toggle if self.focus == Focus::Sidebar => self.open_sidebar_session(),
+            keymap::Command::ScrollUp
+            | keymap::Command::ScrollDown
+            | keymap::Command::ScrollBottom
+                if self.focus == Focus::Split =>
+            {
+                self.scroll_split(command)
+            }
+            keymap::Command::ScrollUp => {
+                self.scroll_up();
+                self.should_auto_scroll = false;
+            }
+            keymap::Command::ScrollDown => {
+                self.scroll_down();
+                self.should_auto_scroll = false;
+            }
+            keymap::Command::ScrollBottom => {
+                self.should_auto_scroll = true;
+                self.scroll_to_bottom();
+            }
+            keymap::Command::NextMessage => self.step_message(true),
+            keymap::Command::PrevMessage => self.step_message(false),
+            keymap::Command::NextCode => self.step_code_block(true),
+            keymap::Command::PrevCode => self.step_code_block(false),
+            keymap::Command::RenderDiagram => match self.selected_code_block() {
+                Some(block) => self.render_diagram(block),
+                None => self.notify(tr("Select a mermaid code block first")),
+            },
+            keymap::Command::Toggle => {
+                match self.selected_code_block() {
+                    Some(block) => {
+                        self.code_actions = Some(CodeActions {
+                            block,
+                            step: CodeStep::Menu,
+                            error: None,
+                        })
+                    }
+                    None if self.toggle_selected_fold() => {}
+                    None => self.toggle_selected_tool(),
+                }
+                self.should_auto_scroll = false;
+            }
+            keymap::Command::Clear => self.new_session(None),
+            keymap::Command::NewSession => self.open_templates(),
+            keymap::Command::ToggleRaw => self.toggle_selected_raw(),
+            keymap::Command::Bookmark => self.toggle_bookmark(),
+            keymap::Command::NextBookmark => self.next_bookmark(),
+            keymap::Command::Bookmarks => self.open_bookmarks(),
+            keymap::Command::Search => self.open_history_search(),
+            keymap::Command::Fork => self.fork(),
+            keymap::Command::NextBranch => self.step_branch(true),
+            keymap::Command::PrevBranch => self.step_branch(false),
+            keymap::Command::ToggleSidebar => {
+                self.ui_state.sidebar_visible = !self.ui_state.sidebar_visible;
+                if self.focus == Focus::Sidebar {
+                    self.focus = Focus::Chat;
+                }
+                self.save_ui_state();
+            }
+            keymap::Command::GrowSidebar => {
+                self.ui_state.resize_sidebar(2);
+                self.save_ui_state();
+            }
+            keymap::Command::ShrinkSidebar => {
+                self.ui_state.resize_sidebar(-2);
+                self.save_ui_state();
+            }
+            keymap::Command::Help => self.show_help = true,
+            keymap::Command::Trace => self.show_trace = !self.show_trace,
+            keymap::Command::Continue => self.continue_response(),
+            keymap::Command::Cancel => self.cancel_response(),
+            keymap::Command::Retry => self.retry_response(),
+            keymap::Command::OpenConfig => self.open_config(),
+            keymap::Command::SwitchModel => self.switch_model(),
+            keymap::Command::NextAlternative => self.step_alternative(true),
+            keymap::Command::PasteImage => self.attach_command("--clipboard"),
+            keymap::Command::PrevAlternative => self.step_alternative(false),
+            keymap::Command::Suspend => self.suspend(),
+            keymap::Command::Quit => self.should_quit = true,
+        }
+    }
+
+    /// Moves the focus along chat, split pane, input and sidebar, skipping hidden ones
+    fn cycle_focus(&mut self, forward: bool) {
+        let sidebar = self.ui_state.sidebar_visible && !self.config.ui.accessible();
+        let order: Vec<Focus> = [Focus::Chat, Focus::Split, Focus::Input, Focus::Sidebar]
+            .into_iter()
+            .filter(|focus| match focus {
+                Focus::Split => self.split.is_some(),
+                Focus::Sidebar => sidebar,
+                _ => true,
+            })
+            .collect();
+        let current = order.iter().position(|f| *f == self.focus).unwrap_or(0);
+        let next = if forward {
+            (current + 1) % order.len()
+        } else {
+            (current + order.len() - 1) % order.len()
+        };
+        self.focus = order[next];
+        self.visual_anchor = None;
+        if self.focus == Focus::Sidebar {
+            self.load_sidebar_sessions();
+        }
+    }
+
+    /// Border of a pane: highlighted while it has the focus
+    fn focus_style(&self, pane: Focus) -> Style {
+        if self.focus == pane {
+            Style::default().fg(self.theme.accent)
+        } else {
+            Style::default()
+        }
+    }
+
+    fn load_sidebar_sessions(&mut self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        match store.sessions() {
+            Ok(sessions) => {
+                // Start on the open session, or the most recent one
+                let selected = sessions
+                    .iter()
+                    .position(|s| Some(s.id) == self.session)
+                    .unwrap_or(0);
+                self.sidebar_state.select(Some(selected));
+                self.sidebar_sessions = sessions;
+            }
+            Err(e) => self.notify(i18n::fill(tr("Could not list sessions: {}"), &[&e])),
+        }
+    }
+
+    fn step_sidebar_session(&mut self, forward: bool) {
+        let last = self.sidebar_sessions.len().saturating_sub(1);
+        let selected = self.sidebar_state.selected().unwrap_or(0);
+        self.sidebar_state.select(Some(if forward {
+            (selected + 1).min(last)
+        } else {
+            selected.saturating_sub(1)
+        }));
+    }
+
+    fn open_sidebar_session(&mut self) {
+        let Some(session) = self
+            .sidebar_state
+            .selected()
+            .and_then(|i| self.sidebar_sessions.get(i))
+        else {
+            return;
+        };
+        let id = session.id;
+        if Some(id) != self.session {
+            self.open_session(id, 0, usize::MAX);
+        }
+        self.focus = Focus::Chat;
+    }
+
+    fn save_ui_state(&mut self) {
+        // state.json is plaintext, so drafts aren't kept there when conversations are encrypted
+        let encrypted = self.config.storage.encrypt()
+            || self.store.as_ref().is_some_and(|store| store.is_unlocked());
+        let saved = if encrypted {
+            self.ui_state.without_drafts().save()
+        } else {
+            self.ui_state.save()
+        };
+        if let Err(e) = saved {
+            self.push_error(i18n::fill(
+                tr("Could not save layout preferences: {}"),
+                &[&e],
+            ));
+        }
+    }
+
+    fn send_input(&mut self) {
+        let input = self.textarea.lines().join("\n");
+        if input.trim().is_empty() && self.attachments.is_empty() {
+            return;
+        }
+        self.should_auto_scroll = true; // Snap to bottom on send
+        if let Some(command) = input.trim().strip_prefix('/') {
+            self.run_slash_command(command);
+        } else {
+            let _ = self.action_tx.send(Action::SendMessage(input));
+        }
+
+        self.textarea = input_area("");
+    }
+
+    /// Unsent text in the input box
+    fn draft(&self) -> String {
+        self.textarea.lines().join("\n")
+    }
+
+    /// Writes the current branch to the store, starting a session for it on the first message
+    /// sent
+    fn save_session(&mut self) {
+        let Some(store) = &mut self.store else {
+            return;
+        };
+        let messages: Vec<_> = self
+            .messages
+            .iter()
+            .filter(|m| !m.queued)
+            .map(Message::stored)
+            .collect();
+        if !messages.iter().any(|m| m.role == "You") {
+            return;
+        }
+        let session = match self.session {
+            Some(session) => session,
+            None => match store.create_session(self.title.as_deref()) {
+                Ok(session) => {
+                    if let Err(e) = store.set_model(
+                        session,
+                        self.session_model.as_deref(),
+                        self.session_provider.map(ai::Provider::name),
+                    ) {
+                        tracing::warn!(error = %e, "could not save the session's model");
+                    }
+                    if let Err(e) = store.set_template(session, self.template.as_deref()) {
+                        tracing::warn!(error = %e, "could not save the session's template");
+                    }
+                    *self.session.insert(session)
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "could not start a session");
+                    return;
+                }
+            },
+        };
+        let branches: Vec<_> = (0..self.branches.len())
+            .map(|i| {
+                let branch = self.branches.branch(i);
+                store::StoredBranch {
+                    name: branch.name.clone(),
+                    parent: branch.parent,
+                    fork_at: branch.fork_at,
+                }
+            })
+            .collect();
+        if let Err(e) = store
+            .save_branch(session, self.branches.current(), &messages)
+            .and_then(|()| store.save_branches(session, &branches))
+        {
+            tracing::warn!(error = %e, "could not save the session");
+        }
+        self.streamed_saved_at = Some(Instant::now());
+    }
+
+    /// Writes the response being streamed, at most once a second, so a crash loses little
+    fn save_streamed(&mut self) {
+        const EVERY: Duration = Duration::from_secs(1);
+
+        if self
+            .streamed_saved_at
+            .is_some_and(|at| at.elapsed() < EVERY)
+        {
+            return;
+        }
+        let (Some(store), Some(session)) = (&self.store, self.session) else {
+            return;
+        };
+        let Some(position) = self.messages.iter().rposition(|m| !m.queued) else {
+            return;
+        };
+        let message = self.messages[position].stored();
+        if let Err(e) = store.save_message(session, self.branches.current(), position, &message) {
+            tracing::warn!(error = %e, "could not save the response");
+        }
+        self.streamed_saved_at = Some(Instant::now());
+    }
+
+    /// The model that answered last
+    fn answering_model(&self) -> &str {
+        self.messages
+            .iter()
+            .rev()
+            .find_map(|m| m.model.as_deref())
+            .unwrap_or(self.model())
+    }
+
+    fn record_usage(&self, usage: &ai::Usage) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let model = self.answering_model();
+        if let Err(e) = store.record_usage(
+            self.session,
+            model,
+            usage.prompt_tokens.into(),
+            usage.cached_tokens.into(),
+            usage.response_tokens.into(),
+            usage.total_tokens.into(),
+        ) {
+            tracing::warn!(error = %e, "could not record token usage");
+        }
+    }
+
+    /// Keeps the unsent input for the next run
+    fn save_draft(&mut self) {
+        self.stash_draft();
+        self.save_ui_state();
+    }
+
+    /// Puts the unsent input away under the current session, for when it is shown again
+    fn stash_draft(&mut self) {
+        let draft = self.draft();
+        match self.session {
+            Some(id) if draft.is_empty() => {
+                self.ui_state.drafts.remove(&id);
+            }
+            Some(id) => {
+                self.ui_state.drafts.insert(id, draft);
+            }
+            None => self.ui_state.draft = draft,
+        }
+    }
+
+    /// Fills the input box with what was left unsent in the current session
+    fn restore_draft(&mut self) {
+        let draft = match self.session {
+            Some(id) => self.ui_state.drafts.remove(&id).unwrap_or_default(),
+            None => std::mem::take(&mut self.ui_state.draft),
+        };
+        self.textarea = input_area(&draft);
+    }
+
+    /// Throughput of the response being streamed, e.g. "42 tok/s · ~310 tokens", or how
+    /// long it has been silent when chunks stop arriving
+    fn stream_speed(&self, msg: &Message) -> String {
+        const STALL_AFTER: Duration = Duration::from_secs(5);
+        // Rough average for English text and code
+        const CHARS_PER_TOKEN: f64 = 4.0;
+
+        let (Some(started), Some(first), Some(last)) =
+            (self.request_started, msg.first_token, self.last_chunk_at)
+        else {
+            return String::new();
+        };
+        let silent = last.elapsed();
+        if silent >= STALL_AFTER {
+            return format!("no data for {}s ", silent.as_secs());
+        }
+
+        let tokens = self.stream_chars as f64 / CHARS_PER_TOKEN;
+        let streaming_for = (started + first).elapsed().as_secs_f64();
+        if streaming_for < 0.5 {
+            return format!("~{:.0} tokens ", tokens);
+        }
+        format!(
+            "{:.0} tok/s · ~{:.0} tokens ",
+            tokens / streaming_for,
+            tokens
+        )
+    }
+
+    /// Records how long the response that just ended took
+    fn finish_timing(&mut self) {
+        settle_streamed(&mut self.messages);
+        let Some(started) = self.request_started.take() else {
+            return;
+        };
+        if let Some(msg) = self.messages.iter_mut().rev().find(|m| m.role == "AI") {
+            msg.duration = Some(started.elapsed());
+        }
+    }
+
+    /// Starts the timeline of a new turn, dropping the oldest beyond the few kept
+    fn trace_turn(&mut self) {
+        const MAX_TRACES: usize = 5;
+
+        if self.traces.len() == MAX_TRACES {
+            self.traces.pop_front();
+        }
+        self.traces.push_back(Trace {
+            started: Instant::now(),
+            spans: Vec::new(),
+        });
+    }
+
+    /// The running phase of the current turn
+    fn current_span(&mut self) -> Option<&mut TraceSpan> {
+        self.traces
+            .back_mut()?
+            .spans
+            .last_mut()
+            .filter(|span| span.end.is_none())
+    }
+
+    /// Ends the running phase of the current turn, if any
+    fn trace_end(&mut self) {
+        let Some(trace) = self.traces.back_mut() else {
+            return;
+        };
+        let now = trace.started.elapsed();
+        if let Some(span) = trace.spans.last_mut()
+            && span.end.is_none()
+        {
+            span.end = Some(now);
+        }
+    }
+
+    /// Ends the running phase of the current turn and starts the next
+    fn trace_phase(&mut self, label: impl Into<String>) {
+        self.trace_end();
+        if let Some(trace) = self.traces.back_mut() {
+            let start = trace.started.elapsed();
+            trace.spans.push(TraceSpan {
+                label: label.into(),
+                start,
+                end: None,
+            });
+        }
+    }
+
+    /// Adds `msg` above any queued messages, which stay at the bottom until sent
+    fn push_message(&mut self, msg: Message) {
+        let at = self
+            .messages
+            .iter()
+            .position(|m| m.queued)
+            .unwrap_or(self.messages.len());
+        self.messages.insert(at, msg);
+    }
+
+    /// The newest message that isn't waiting in the queue
+    fn last_live_mut(&mut self) -> Option<&mut Message> {
+        self.messages.iter_mut().rev().find(|m| !m.queued)
+    }
+
+    /// Ends the current turn and sends the oldest queued message, if any
+    fn finish_turn(&mut self) {
+        self.is_loading = false;
+        self.stream_task = None;
+        self.trace_end();
+        self.save_session();
+        if let Some(msg) = self
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.role == "AI" && !m.queued)
+        {
+            let preview = msg
+                .content
+                .lines()
+                .find(|l| !l.trim().is_empty())
+                .unwrap_or_default()
+                .to_string();
+            // The status bar is where screen readers pick up what changed
+            if self.config.ui.accessible() {
+                self.notify(i18n::fill(tr("AI responded: {}"), &[&preview]));
+            }
+            self.notify_user(tr("Response ready"), preview);
+            if self.title.is_none() && self.config.ui.auto_title() {
+                self.generate_title();
+            }
+        }
+        if let Some(msg) = self.messages.iter_mut().find(|m| m.queued) {
+            msg.queued = false;
+            self.request_completion();
+        }
+    }
+
+    fn push_error(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        tracing::error!("{}", text);
+        self.last_error = Some(text.clone());
+        self.push_message(Message::new("Error", text));
+    }
+
+    /// Shows a failed response as an error panel offering a retry and fixes for its kind
+    fn push_failure(&mut self, text: String) {
+        tracing::error!("{}", text);
+        self.last_error = Some(text.clone());
+        self.push_message(Message {
+            error: Some(ai::classify_error(&text)),
+            ..Message::new("Error", text)
+        });
+    }
+
+    /// Shows `text` in the status bar for a few seconds
+    fn notify(&mut self, text: impl Into<String>) {
+        self.notification = Some((text.into(), Instant::now()));
+    }
+
+    /// A desktop notification and/or bell, when the user may not be watching: the terminal
+    /// is unfocused or the turn has been running for a while
+    fn notify_user(&mut self, summary: &str, body: String) {
+        let config = &self.config.notifications;
+        let slow = self
+            .turn_started
+            .is_some_and(|started| started.elapsed() >= config.after());
+        if !self.focused || slow {
+            notify::send(config, summary, &body);
+        }
+    }
+
+    fn push_system(&mut self, text: impl Into<String>) {
+        self.push_message(Message::new("System", text));
+        if self.should_auto_scroll {
+            self.scroll_to_bottom();
+        }
+    }
+
+    /// Handles input starting with `/` instead of sending it to the model
+    fn run_slash_command(&mut self, line: &str) {
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match name {
+            "memory" => self.memory_command(args.trim()),
+            "audit" => self.open_audit(),
+            "stats" => self.open_stats(),
+            "sandbox" => self.sandbox_command(args.trim()),
+            "safety" => self.safety_command(args.trim()),
+            "model" => self.model_command(args.trim()),
+            "provider" => self.provider_command(args.trim()),
+            "profile" => self.profile_command(args.trim()),
+            "attach" => self.attach_command(args.trim()),
+            "capture" => self.capture_command(args.trim()),
+            "theme" => self.theme_command(args.trim()),
+            "setup" => self.setup = Some(Setup::new(&self.theme)),
+            "continue" => self.continue_response(),
+            "title" => self.title_command(args.trim()),
+            "pin" => self.toggle_pin(),
+            "new" => self.new_command(args.trim()),
+            "bookmarks" => self.open_bookmarks(),
+            "fork" => self.fork(),
+            "branch" => self.branch_command(args.trim()),
+            "commit" => self.commit_command(),
+            "review" => self.review_command(args.trim()),
+            "imagine" => self.imagine_command(args.trim()),
+            "share" => self.share_command(args.trim()),
+            "split" => self.split_command(args.trim()),
+            "compare" => self.compare_command(args.trim()),
+            "map" => self.map_command(args.trim()),
+            "index" => self.index_command(args.trim()),
+            _ => self.push_system(i18n::fill(tr("Unknown command: `/{}`"), &[&name])),
+        }
+    }
+
+    /// `/memory [add <text> | edit <n> <text> | forget <n> | clear]`
+    fn memory_command(&mut self, args: &str) {
+        let (sub, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+
+        let result = match sub {
+            "" | "list" => Ok(None),
+            "add" if !rest.is_empty() => {
+                memory::add(rest).map(|n| Some(i18n::fill(tr("Saved memory #{}"), &[&n])))
+            }
+            "edit" => match rest.split_once(char::is_whitespace) {
+                Some((n, text)) => match n.parse::<usize>() {
+                    Ok(n) => memory::update(|entries| match entries.get_mut(n.wrapping_sub(1)) {
+                        Some(entry) => {
+                            entry.text = text.trim().to_string();
+                            Some(i18n::fill(tr("Updated memory #{}"), &[&n]))
+                        }
+                        None => Some(i18n::fill(tr("No memory #{}"), &[&n])),
+                    }),
+                    Err(_) => Ok(Some(tr("Usage: `/memory edit <n> <text>`").into())),
+                },
+                None => Ok(Some(tr("Usage: `/memory edit <n> <text>`").into())),
+            },
+            "forget" => match rest.parse::<usize>() {
+                Ok(n) => memory::update(|entries| {
+                    if n >= 1 && n <= entries.len() {
+                        entries.remove(n - 1);
+                        Some(i18n::fill(tr("Forgot memory #{}"), &[&n]))
+                    } else {
+                        Some(i18n::fill(tr("No memory #{}"), &[&n]))
+                    }
+                }),
+                Err(_) => Ok(Some(tr("Usage: `/memory forget <n>`").into())),
+            },
+            "clear" => memory::update(|entries| {
+                let count = entries.len();
+                entries.clear();
+                Some(i18n::fill(tr("Cleared {} memories"), &[&count]))
+            }),
+            _ => Ok(Some(
+                tr("Usage: `/memory [add <text> | edit <n> <text> | forget <n> | clear]`").into(),
+            )),
+        };
+
+        match result {
+            Ok(Some(status)) => self.push_system(status),
+            Ok(None) => self.show_memories(),
+            Err(e) => self.push_error(i18n::fill(tr("Memory store: {}"), &[&e])),
+        }
+    }
+
+    fn show_memories(&mut self) {
+        let entries = match memory::load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.push_error(i18n::fill(tr("Memory store: {}"), &[&e]));
+                return;
+            }
+        };
+
+        let mut text = format!("**Memory** ({} entries)\n", entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            text.push_str(&format!("{}. {}\n", i + 1, entry.text));
+        }
+        text.push_str("\n`/memory add <text>`, `/memory edit <n> <text>`, `/memory forget <n>`, `/memory clear`");
+        self.push_system(text);
+    }
+
+    /// `/attach <file>|--clipboard|--clear` adds an image or audio recording to the next
+    /// message
+    fn attach_command(&mut self, args: &str) {
+        match args {
+            "" => self.push_system(tr("Usage: `/attach <file>|--clipboard|--clear`")),
+            "--clear" => {
+                self.attachments.clear();
+                self.notify(tr("Attachments removed"));
+            }
+            "--clipboard" => self.attach(clipboard::image().map_err(|e| e.to_string())),
+            path => {
+                // Recordings can be large, so they are read off the UI thread
+                let path = tools::resolve_path(path);
+                let tx = self.action_tx.clone();
+                self.tasks.spawn(async move {
+                    let attachment = tokio::task::spawn_blocking(move || read_attachment(&path))
+                        .await
+                        .map_err(|e| e.to_string())
+                        .and_then(|read| read.map_err(|e| e.to_string()));
+                    let _ = tx.send(Action::Attached(attachment));
+                });
+            }
+        }
+    }
+
+    /// Adds an attachment from `/attach` to the next message
+    fn attach(&mut self, attachment: Result<ai::Attachment, String>) {
+        match attachment {
+            Ok(attachment) => {
+                let text = i18n::fill(
+                    tr("Attached {} ({} KB)"),
+                    &[
+                        &tr(attachment.kind()),
+                        &attachment.data.len().div_ceil(1000),
+                    ],
+                );
+                self.attachments.push(attachment);
+                self.notify(text);
+            }
+            Err(e) => self.notify(e),
+        }
+    }
+
+    /// `/capture [--pane <target>|<file>]` attaches terminal output to the next message, with
+    /// secrets masked
+    fn capture_command(&mut self, args: &str) {
+        let captured = match args.split_once(char::is_whitespace) {
+            _ if args.is_empty() => capture::tmux(None),
+            Some(("--pane", target)) => capture::tmux(Some(target.trim())),
+            _ if args == "--pane" => capture::tmux(Some("{last}")),
+            _ => capture::file(&tools::resolve_path(args)),
+        };
+        match captured {
+            Ok(text) if text.is_empty() => self.notify(tr("No terminal output to capture")),
+            Ok(text) => {
+                let text = self.redactor.redact(&text).into_owned();
+                self.notify(i18n::fill(
+                    tr("Attached {} lines of terminal output"),
+                    &[&text.lines().count()],
+                ));
+                self.attachments.push(ai::Attachment {
+                    mime_type: "text/plain".to_string(),
+                    data: text.into(),
+                });
+            }
+            Err(e) => self.notify(e.to_string()),
+        }
+    }
+
+    /// `/sandbox [on|off]` switches the `run_command` backend for this session
+    fn sandbox_command(&mut self, args: &str) {
+        let sandbox = &mut self.config.tools.sandbox;
+        match args {
+            "on" => sandbox.enabled = Some(true),
+            "off" => sandbox.enabled = Some(false),
+            "" => {}
+            _ => return self.push_system(tr("Usage: `/sandbox [on|off]`")),
+        }
+        let status = if sandbox.enabled() {
+            i18n::fill(
+                tr("Sandbox **on**: commands run in `{}` via {}, network {}"),
+                &[
+                    &sandbox.image(),
+                    &sandbox.runtime(),
+                    &(if sandbox.network() {
+                        tr("enabled")
+                    } else {
+                        tr("disabled")
+                    }),
+                ],
+            )
+        } else {
+            tr("Sandbox **off**: commands run on the host").to_string()
+        };
+        self.push_system(status);
+    }
+
+    /// The session's provider, or else the configured one
+    fn provider(&self) -> Option<ai::Provider> {
+        self.session_provider.or(self.config.provider)
+    }
+
+    /// The `[template.<name>]` the session was started from, while the config still has it
+    fn session_template(&self) -> Option<&config::Template> {
+        self.config.template.get(self.template.as_deref()?)
+    }
+
+    /// Starts a new session, from the `[template.<name>]` named `template` if given. The
+    /// old one stays saved.
+    fn new_session(&mut self, template: Option<String>) {
+        self.stash_draft();
+        self.messages.clear();
+        self.visual_anchor = None;
+        self.session = None;
+        self.restore_draft();
+        self.title = None;
+        self.pinned = false;
+        let chosen = template
+            .as_ref()
+            .and_then(|name| self.config.template.get(name));
+        self.session_model = chosen.and_then(|t| t.model.clone());
+        self.session_provider = chosen.and_then(|t| t.provider);
+        self.template = template;
+        self.template_files = template_files(self.session_template());
+        self.should_auto_scroll = true;
+        if let Some(pane) = &mut self.split {
+            *pane = SplitPane::new(pane.model.clone());
+        }
+    }
+
+    /// `/new [template]` starts a new session from a template, or picks one
+    fn new_command(&mut self, name: &str) {
+        if name.is_empty() {
+            return self.open_templates();
+        }
+        if !self.config.template.contains_key(name) {
+            let names: Vec<&str> = self.config.template.keys().map(String::as_str).collect();
+            return self.push_system(if names.is_empty() {
+                i18n::fill(
+                    tr("No template `{}`: add `[template.<name>]` tables to the config file"),
+                    &[&name],
+                )
+            } else {
+                i18n::fill(
+                    tr("No template `{}`; there are {}"),
+                    &[&name, &(names.join(", "))],
+                )
+            });
+        }
+        self.new_session(Some(name.to_string()));
+        self.notify(i18n::fill(tr("New session from `{}`"), &[&name]));
+    }
+
+    fn open_templates(&mut self) {
+        if self.config.template.is_empty() {
+            self.notify(tr(
+                "No templates; add `[template.<name>]` tables to the config file",
+            ));
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.template_picker = Some(TemplatePicker {
+            names: self.config.template.keys().cloned().collect(),
+            state,
+        });
+    }
+
+    fn template_key(&mut self, key: KeyEvent) {
+        let Some(picker) = &mut self.template_picker else {
+            return;
+        };
+        let last = picker.names.len();
+        let selected = picker.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.template_picker = None,
+            KeyCode::Char('j') | KeyCode::Down => {
+                picker.state.select(Some((selected + 1).min(last)))
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                picker.state.select(Some(selected.saturating_sub(1)))
+            }
+            KeyCode::Enter => {
+                let template = selected.checked_sub(1).map(|i| picker.names[i].clone());
+                self.template_picker = None;
+                self.new_session(template);
+                self.focus = Focus::Input;
+            }
+            _ => {}
+        }
+    }
+
+    /// `/profile [name|default]` switches to a `[profile.<name>]` of the config file, or back
+    /// to the top-level settings
+    fn profile_command(&mut self, name: &str) {
+        if name.is_empty() {
+            let names: Vec<&str> = self.config.profile.keys().map(String::as_str).collect();
+            let text = i18n::fill(
+                tr("Profile: {}. Defined: {}"),
+                &[
+                    &(self
+                        .config
+                        .active_profile
+                        .as_deref()
+                        .map_or(tr("none").to_string(), |p| format!("`{}`", p))),
+                    &(if names.is_empty() {
+                        tr("none (add `[profile.<name>]` tables to the config file)").to_string()
+                    } else {
+                        names.join(", ")
+                    }),
+                ],
+            );
+            return self.push_system(text);
+        }
+        let choice = (name != "default").then_some(name);
+        if let Err(e) = self
+            .config
+            .use_profile(choice)
+            .and_then(|()| ai::configure(&self.config))
+        {
+            return self.push_system(e.to_string());
+        }
+        auth::use_keys(&self.config);
+        self.push_system(match choice {
+            Some(name) => i18n::fill(tr("Switched to profile `{}`"), &[&name]),
+            None => tr("Switched back to the top-level settings").to_string(),
+        });
+        self.show_model_choice();
+    }
+
+    /// The session's model, or else the configured one
+    fn model(&self) -> &str {
+        self.session_model.as_deref().unwrap_or(self.config.model())
+    }
+
+    /// Models to try in order: the session's, then the configured ones as fallbacks
+    fn models(&self) -> Vec<String> {
+        let mut models = self.config.models.clone();
+        if let Some(model) = &self.session_model {
+            models.retain(|m| m != model);
+            models.insert(0, model.clone());
+        }
+        models
+    }
+
+    /// `/model [name|default]` switches the model of this session
+    fn model_command(&mut self, name: &str) {
+        match name {
+            "" => {}
+            "default" => self.session_model = None,
+            name => self.session_model = Some(name.to_string()),
+        }
+        if !name.is_empty() {
+            self.save_model_choice();
+        }
+        self.show_model_choice();
+    }
+
+    /// `/provider [gemini|vertex|default]` switches where this session's requests go
+    fn provider_command(&mut self, name: &str) {
+        match name {
+            "" => {}
+            "default" => self.session_provider = None,
+            name => match ai::Provider::from_name(name) {
+                Some(provider) => self.session_provider = Some(provider),
+                None => {
+                    return self.push_system(tr("Usage: `/provider [gemini|vertex|default]`"));
+                }
+            },
+        }
+        if !name.is_empty() {
+            self.save_model_choice();
+        }
+        self.show_model_choice();
+    }
+
+    fn show_model_choice(&mut self) {
+        let text = i18n::fill(
+            tr("This session uses `{}`{} via {}{}"),
+            &[
+                &self.model(),
+                &(if self.session_model.is_some() {
+                    ""
+                } else {
+                    tr(" (configured)")
+                }),
+                &ai::provider(self.provider()),
+                &(if self.session_provider.is_some() {
+                    ""
+                } else {
+                    tr(" (configured)")
+                }),
+            ],
+        );
+        self.push_system(text);
+    }
+
+    fn save_model_choice(&mut self) {
+        let (Some(store), Some(session)) = (&self.store, self.session) else {
+            return;
+        };
+        if let Err(e) = store.set_model(
+            session,
+            self.session_model.as_deref(),
+            self.session_provider.map(ai::Provider::name),
+        ) {
+            self.notify(i18n::fill(
+                tr("Could not save the session's model: {}"),
+                &[&e],
+            ));
+        }
+    }
+
+    /// `/safety [<category> <threshold>|reset]` changes a safety filter threshold for this
+    /// session
+    fn safety_command(&mut self, args: &str) {
+        let mut words = args.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("reset"), None) => {
+                self.config.safety = config::Config::load(self.config.path.as_deref())
+                    .map(|c| c.safety)
+                    .unwrap_or_default();
+            }
+            (Some(category), Some(threshold)) => {
+                match (
+                    config::HarmCategory::from_name(category),
+                    config::Threshold::from_name(threshold),
+                ) {
+                    (Some(category), Some(threshold)) => {
+                        self.config.safety.insert(category, threshold);
+                    }
+                    _ => return self.push_system(safety_usage()),
+                }
+            }
+            _ => return self.push_system(safety_usage()),
+        }
+        let mut text = String::from(tr("Safety thresholds for this session:\n"));
+        for category in config::HarmCategory::ALL {
+            text.push_str(&format!(
+                "\n- `{}`: {}",
+                category.name(),
+                self.config
+                    .safety
+                    .get(&category)
+                    .map_or(tr("API default"), |t| t.name())
+            ));
+        }
+        text.push_str(
+            "\n\n`/safety <category> <threshold>` changes one, `/safety reset` restores the config",
+        );
+        self.push_system(text);
+    }
+
+    /// `/theme [name]` switches the color theme for this session
+    fn theme_command(&mut self, name: &str) {
+        if name.is_empty() {
+            let list = theme::BUILTIN
+                .iter()
+                .map(|t| format!("`{}`", t))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return self.push_system(i18n::fill(
+                tr("Themes: {}. Usage: `/theme <name>`"),
+                &[&list],
+            ));
+        }
+        match theme::Theme::resolve(name, &self.config.theme.colors) {
+            Ok(theme) => {
+                self.theme = theme;
+                for msg in &mut self.messages {
+                    msg.rendered.take();
+                }
+                self.config.theme.name = Some(name.to_string());
+                self.notify(i18n::fill(tr("Theme set to {}"), &[&name]));
+            }
+            Err(e) => self.push_system(e.to_string()),
+        }
+    }
+
+    fn open_audit(&mut self) {
+        const MAX_ENTRIES: usize = 1000;
+
+        match audit::recent(MAX_ENTRIES) {
+            Ok(entries) => {
+                let mut state = ListState::default();
+                state.select(entries.len().checked_sub(1));
+                self.audit_view = Some(AuditView { entries, state });
+            }
+            Err(e) => self.push_error(i18n::fill(tr("Audit log: {}"), &[&e])),
+        }
+    }
+
+    fn audit_key(&mut self, key: KeyEvent) {
+        let Some(view) = &mut self.audit_view else {
+            return;
+        };
+        let last = view.entries.len().saturating_sub(1);
+        let selected = view.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.audit_view = None,
+            KeyCode::Char('j') | KeyCode::Down => view.state.select(Some((selected + 1).min(last))),
+            KeyCode::Char('k') | KeyCode::Up => view.state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Char('g') => view.state.select(Some(0)),
+            KeyCode::Char('G') => view.state.select(Some(last)),
+            _ => {}
+        }
+    }
+
+    fn open_stats(&mut self) {
+        let Some(store) = &self.store else {
+            self.notify(tr(
+                "Usage stats need the database, which could not be opened",
+            ));
+            return;
+        };
+        match store.usage(None, None) {
+            Ok(rows) => {
+                let mut state = ListState::default();
+                state.select(Some(0));
+                self.stats_view = Some(StatsView {
+                    rows,
+                    by: StatsGroup::Day,
+                    state,
+                });
+            }
+            Err(e) => self.push_error(i18n::fill(tr("Usage stats: {}"), &[&e])),
+        }
+    }
+
+    fn stats_key(&mut self, key: KeyEvent) {
+        let Some(view) = &mut self.stats_view else {
+            return;
+        };
+        let selected = view.state.selected().unwrap_or(0);
+        let by = match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.stats_view = None;
+                return;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                // Clamped to the groups when drawn
+                view.state.select(Some(selected + 1));
+                return;
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                view.state.select(Some(selected.saturating_sub(1)));
+                return;
+            }
+            KeyCode::Char('d') => StatsGroup::Day,
+            KeyCode::Char('s') => StatsGroup::Session,
+            KeyCode::Char('m') => StatsGroup::Model,
+            KeyCode::Tab => match view.by {
+                StatsGroup::Day => StatsGroup::Session,
+                StatsGroup::Session => StatsGroup::Model,
+                StatsGroup::Model => StatsGroup::Day,
+            },
+            _ => return,
+        };
+        view.by = by;
+        view.state.select(Some(0));
+    }
+
+    /// Handles a key on the setup screen: Enter checks the typed API key, Esc closes it
+    fn setup_key(&mut self, key: KeyEvent) {
+        let Some(setup) = &mut self.setup else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.setup = None;
+                if !ai::has_credentials() {
+                    self.push_system(tr(
+                        "No API key: responses are mocked. Run /setup or set GEMINI_API_KEY.",
+                    ));
+                }
+            }
+            KeyCode::Enter if !setup.checking => {
+                let api_key = setup.input.lines().concat().trim().to_string();
+                if api_key.is_empty() {
+                    return;
+                }
+                setup.checking = true;
+                setup.error = None;
+                let tx = self.action_tx.clone();
+                self.tasks.spawn(async move {
+                    let result = ai::check_api_key(&api_key).await.map_err(|e| e.to_string());
+                    let _ = tx.send(Action::ApiKeyChecked(api_key, result));
+                });
+            }
+            _ if !setup.checking => {
+                setup.input.input(key);
+            }
+            _ => {}
+        }
+    }
+
+    fn api_key_checked(&mut self, key: String, result: Result<(), String>) {
+        // Closed while the check was running
+        let Some(setup) = &mut self.setup else {
+            return;
+        };
+        setup.checking = false;
+        if let Err(e) = result {
+            setup.error = Some(e);
+            return;
+        }
+        self.setup = None;
+        ai::set_api_key(Some(key.clone()));
+        // The config file is the fallback for systems without a keyring service
+        let saved = match auth::store(&key, None) {
+            Ok(()) => Ok("the system keyring".to_string()),
+            Err(e) => {
+                tracing::warn!(error = %e, "keyring unavailable, saving the key to the config file");
+                self.config.save_api_key(&key).map(|()| {
+                    self.config
+                        .path
+                        .as_ref()
+                        .map_or("the config file".to_string(), |p| p.display().to_string())
+                })
+            }
+        };
+        match saved {
+            Ok(place) => self.notify(i18n::fill(tr("API key saved to {}"), &[&place])),
+            Err(e) => self.push_error(i18n::fill(
+                tr("The key works but could not be saved, so it only lasts this session: {}"),
+                &[&e],
+            )),
+        }
+    }
+
+    /// `/title [text]`: renames the session, or asks the model for a title again
+    fn title_command(&mut self, args: &str) {
+        if args.is_empty() {
+            self.title = None;
+            self.generate_title();
+        } else {
+            self.set_title(args.to_string());
+        }
+    }
+
+    /// `/pin`: exempts the session from pruning, or makes it subject to it again
+    fn toggle_pin(&mut self) {
+        let (Some(store), Some(session)) = (&self.store, self.session) else {
+            self.notify(tr("Nothing saved yet to pin"));
+            return;
+        };
+        match store.set_pinned(session, !self.pinned) {
+            Ok(()) => {
+                self.pinned = !self.pinned;
+                self.notify(if self.pinned {
+                    tr("Session pinned: it is never pruned")
+                } else {
+                    tr("Session unpinned")
+                });
+            }
+            Err(e) => self.notify(i18n::fill(tr("Could not pin the session: {}"), &[&e])),
+        }
+    }
+
+    fn set_title(&mut self, title: String) {
+        if let (Some(store), Some(session)) = (&self.store, self.session)
+            && let Err(e) = store.set_title(session, &title)
+        {
+            tracing::warn!(error = %e, "could not save the session title");
+        }
+        let _ = crossterm::execute!(
+            std::io::stdout(),
+            crossterm::terminal::SetTitle(format!("{} — gemchat", title))
+        );
+        self.notify(i18n::fill(tr("Session: {}"), &[&title]));
+        self.title = Some(title);
+    }
+
+    /// Asks the title model to name the session after its first question and answer
+    fn generate_title(&mut self) {
+        let question = self.messages.iter().find(|m| m.role == "You");
+        let answer = self.messages.iter().find(|m| m.role == "AI");
+        let (Some(question), Some(answer)) = (question, answer) else {
+            self.notify(tr("Nothing to name yet"));
+            return;
+        };
+        // Mocked replies would make poor titles
+        if !ai::has_credentials() {
+            return;
+        }
+        let request = ai::Request {
+            prompt: self
+                .redactor
+                .redact(&title_prompt(&question.content, &answer.content))
+                .into_owned(),
+            models: vec![self.config.ui.title_model().to_string()],
+            without_tools: true,
+            ..Default::default()
+        };
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            match ai::complete(request).await {
+                Ok(reply) => {
+                    if let Some(title) = clean_title(&reply) {
+                        let _ = tx.send(Action::Titled(title));
+                    }
+                }
+                Err(e) => tracing::warn!(error = %e, "could not title the session"),
+            }
+        });
+    }
+
+    fn toggle_bookmark(&mut self) {
+        let Some(i) = self.selected_message() else {
+            return;
+        };
+        let bookmarked = !self.messages[i].bookmarked;
+        self.messages[i].bookmarked = bookmarked;
+        self.notify(if bookmarked {
+            tr("Bookmarked")
+        } else {
+            tr("Bookmark removed")
+        });
+    }
+
+    /// Jumps to the first bookmark below the selection, wrapping around to the top
+    fn next_bookmark(&mut self) {
+        let marked: Vec<usize> = (0..self.messages.len())
+            .filter(|&i| self.messages[i].bookmarked)
+            .collect();
+        let Some(&first) = marked.first() else {
+            self.notify(tr("No bookmarks; press m on a message to add one"));
+            return;
+        };
+        let after = self.selected_message();
+        let next = marked
+            .iter()
+            .copied()
+            .find(|&i| after.is_none_or(|after| i > after))
+            .unwrap_or(first);
+        self.jump_to_message(next);
+    }
+
+    fn open_bookmarks(&mut self) {
+        let messages: Vec<usize> = (0..self.messages.len())
+            .filter(|&i| self.messages[i].bookmarked)
+            .collect();
+        if messages.is_empty() {
+            self.notify(tr("No bookmarks; press m on a message to add one"));
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.bookmark_picker = Some(BookmarkPicker { messages, state });
+    }
+
+    fn bookmark_key(&mut self, key: KeyEvent) {
+        let Some(picker) = &mut self.bookmark_picker else {
+            return;
+        };
+        let last = picker.messages.len().saturating_sub(1);
+        let selected = picker.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.bookmark_picker = None,
+            KeyCode::Char('j') | KeyCode::Down => {
+                picker.state.select(Some((selected + 1).min(last)))
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                picker.state.select(Some(selected.saturating_sub(1)))
+            }
+            KeyCode::Enter => {
+                let message = picker.messages[selected];
+                self.bookmark_picker = None;
+                self.jump_to_message(message);
+            }
+            _ => {}
+        }
+    }
+
+    fn open_history_search(&mut self) {
+        if self.store.is_none() {
+            self.notify(tr(
+                "History search needs the database, which could not be opened",
+            ));
+            return;
+        }
+        let mut input = TextArea::default();
+        input.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("Search all conversations"))
+                .style(Style::default().fg(self.theme.input_active)),
+        );
+        input.set_placeholder_text(tr("Words to find"));
+        input.set_cursor_line_style(Style::default());
+        self.history_search = Some(HistorySearch {
+            input,
+            hits: Vec::new(),
+            state: ListState::default(),
+            preview: Vec::new(),
+            preview_at: 0,
+            error: None,
+        });
+    }
+
+    fn search_key(&mut self, key: KeyEvent) {
+        let Some(search) = &mut self.history_search else {
+            return;
+        };
+        let last = search.hits.len().saturating_sub(1);
+        let selected = search.state.selected().unwrap_or(0);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => self.history_search = None,
+            KeyCode::Enter => {
+                if let Some(hit) = search.hits.get(selected).cloned() {
+                    self.history_search = None;
+                    self.open_session(hit.session, hit.branch, hit.position);
+                }
+            }
+            // Pressing Ctrl+R again steps to the next match, as in a shell
+            KeyCode::Down | KeyCode::Char('n' | 'r') if ctrl || key.code == KeyCode::Down => {
+                search.state.select(Some((selected + 1).min(last)));
+                self.preview_hit();
+            }
+            KeyCode::Up | KeyCode::Char('p') if ctrl || key.code == KeyCode::Up => {
+                search.state.select(Some(selected.saturating_sub(1)));
+                self.preview_hit();
+            }
+            _ => {
+                if search.input.input(key) {
+                    self.run_history_search();
+                }
+            }
+        }
+    }
+
+    fn run_history_search(&mut self) {
+        const LIMIT: usize = 50;
+
+        let (Some(search), Some(store)) = (&mut self.history_search, &self.store) else {
+            return;
+        };
+        let text = search.input.lines().join(" ");
+        match store.search(&text, LIMIT) {
+            Ok(hits) => {
+                search.hits = hits;
+                search.error = None;
+            }
+            Err(e) => {
+                search.hits.clear();
+                search.error = Some(e.to_string());
+            }
+        }
+        search.state.select((!search.hits.is_empty()).then_some(0));
+        self.preview_hit();
+    }
+
+    /// Loads the messages around the selected hit
+    fn preview_hit(&mut self) {
+        const AROUND: usize = 2;
+
+        let (Some(search), Some(store)) = (&mut self.history_search, &self.store) else {
+            return;
+        };
+        search.preview.clear();
+        let Some(hit) = search.state.selected().and_then(|i| search.hits.get(i)) else {
+            return;
+        };
+        match store.messages(hit.session, hit.branch) {
+            Ok(messages) => {
+                let start = hit.position.saturating_sub(AROUND);
+                let end = (hit.position + AROUND + 1).min(messages.len());
+                search.preview = messages.get(start..end).unwrap_or_default().to_vec();
+                search.preview_at = hit.position - start;
+            }
+            Err(e) => search.error = Some(e.to_string()),
+        }
+    }
+
+    /// Replaces the conversation with a saved session, checked out on `branch` with
+    /// `position` selected. The current one is saved first.
+    fn open_session(&mut self, id: i64, branch: usize, position: usize) {
+        if self.is_loading || self.tools_running || self.approval_prompt.is_some() {
+            self.notify(tr(
+                "Wait for the response to finish before opening another session",
+            ));
+            return;
+        }
+        self.save_session();
+        let Some(store) = &self.store else {
+            return;
+        };
+        let mut pinned = false;
+        let mut chosen = (None, None);
+        let mut template = None;
+        let loaded = store.session(id).and_then(|session| {
+            let mut infos = store.branches(id)?;
+            // Sessions saved before branches were recorded
+            if infos.is_empty() {
+                infos = (0..=branch)
+                    .map(|i| store::StoredBranch {
+                        name: if i == 0 {
+                            "main".into()
+                        } else {
+                            format!("fork {}", i)
+                        },
+                        parent: (i > 0).then_some(0),
+                        fork_at: 0,
+                    })
+                    .collect();
+            }
+            let mut branches = Vec::with_capacity(infos.len());
+            for (index, info) in infos.into_iter().enumerate() {
+                let messages = store
+                    .messages(id, index)?
+                    .into_iter()
+                    .map(Message::from_stored)
+                    .collect();
+                branches.push(conversation::Branch::new(
+                    info.name,
+                    info.parent,
+                    info.fork_at,
+                    messages,
+                ));
+            }
+            pinned = session.as_ref().is_some_and(|s| s.pinned);
+            if let Some(session) = &session {
+                template = session.template.clone();
+                chosen = (
+                    session.model.clone(),
+                    session
+                        .provider
+                        .as_deref()
+                        .and_then(ai::Provider::from_name),
+                );
+            }
+            Ok((session.and_then(|s| s.title), branches))
+        });
+        let (title, branches) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.notify(i18n::fill(tr("Could not open the session: {}"), &[&e]));
+                return;
+            }
+        };
+        self.stash_draft();
+        let (tree, messages) = conversation::Tree::restore(branches, branch);
+        self.branches = tree;
+        self.messages = messages;
+        self.session = Some(id);
+        self.restore_draft();
+        self.title = None;
+        self.pinned = pinned;
+        (self.session_model, self.session_provider) = chosen;
+        self.template = template;
+        self.template_files = template_files(self.session_template());
+        self.resuming = false;
+        self.bookmark_picker = None;
+        self.code_actions = None;
+        self.show_branch();
+        if let Some(title) = title {
+            self.set_title(title);
+        }
+        if !self.messages.is_empty() {
+            self.jump_to_message(position.min(self.messages.len() - 1));
+        }
+    }
+
+    /// The code block under the selection, if any
+    fn selected_code_block(&self) -> Option<render::CodeBlock> {
+        let i = self.selected_message()?;
+        let msg = &self.messages[i];
+        if msg.tool.is_some() || msg.review.is_some() || msg.raw {
+            return None;
+        }
+        let row = self
+            .list_state
+            .selected()?
+            .checked_sub(self.message_start(i) + 1)?;
+        let index = self
+            .message_code_blocks(msg)
+            .iter()
+            .position(|lines| lines.contains(&row))?;
+        render::code_blocks(&msg.content).into_iter().nth(index)
+    }
+
+    fn code_key(&mut self, key: KeyEvent) {
+        let Some(actions) = &mut self.code_actions else {
+            return;
+        };
+        match &mut actions.step {
+            CodeStep::Menu => match key.code {
+                KeyCode::Char('c') => {
+                    let lines = actions.block.code.lines().count();
+                    match clipboard::copy(&actions.block.code) {
+                        Ok(()) => {
+                            self.code_actions = None;
+                            self.notify(i18n::fill(tr("Copied {} lines"), &[&lines]));
+                        }
+                        Err(e) => actions.error = Some(i18n::fill(tr("Could not copy: {}"), &[&e])),
+                    }
+                }
+                KeyCode::Char('s') => {
+                    let name = format!(
+                        "snippet.{}",
+                        self.highlighter
+                            .extension(&actions.block.lang)
+                            .unwrap_or("txt")
+                    );
+                    let mut input = TextArea::new(vec![name]);
+                    input.move_cursor(tui_textarea::CursorMove::End);
+                    input.set_cursor_line_style(Style::default());
+                    input.set_block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(tr("Save as"))
+                            .style(Style::default().fg(self.theme.input_active)),
+                    );
+                    actions.step = CodeStep::Save(Box::new(input));
+                    actions.error = None;
+                }
+                KeyCode::Char('d') => {
+                    let block = actions.block.clone();
+                    if diagram::MERMAID_LANGS.contains(&block.lang.as_str()) {
+                        self.code_actions = None;
+                    }
+                    self.render_diagram(block);
+                }
+                KeyCode::Char('r') if SHELL_LANGS.contains(&actions.block.lang.as_str()) => {
+                    actions.step = CodeStep::ConfirmRun;
+                    actions.error = None;
+                }
+                KeyCode::Char('r') => {
+                    actions.error = Some(i18n::fill(
+                        tr("Only shell code blocks can be run, not `{}`"),
+                        &[&actions.block.lang],
+                    ));
+                }
+                KeyCode::Esc | KeyCode::Char('q') => self.code_actions = None,
+                _ => {}
+            },
+            CodeStep::Save(input) => match key.code {
+                KeyCode::Esc => self.code_actions = None,
+                KeyCode::Enter => {
+                    let raw = input.lines().concat();
+                    let raw = raw.trim();
+                    if raw.is_empty() {
+                        return;
+                    }
+                    let path = tools::resolve_path(raw);
+                    let saved = if path.exists() {
+                        Err(format!("{} already exists", path.display()))
+                    } else {
+                        std::fs::write(&path, &actions.block.code).map_err(|e| e.to_string())
+                    };
+                    match saved {
+                        Ok(()) => {
+                            self.code_actions = None;
+                            self.notify(i18n::fill(tr("Saved {}"), &[&path.display()]));
+                        }
+                        Err(e) => actions.error = Some(e),
+                    }
+                }
+                _ => {
+                    input.input(key);
+                }
+            },
+            CodeStep::ConfirmRun => match key.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    let code = actions.block.code.clone();
+                    self.code_actions = None;
+                    self.run_code(code);
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.code_actions = None,
+                _ => {}
+            },
+        }
+    }
+
+    /// Renders a mermaid `block` with `mmdc` in the background and shows the image once it
+    /// is ready
+    fn render_diagram(&mut self, block: render::CodeBlock) {
+        if !diagram::MERMAID_LANGS.contains(&block.lang.as_str()) {
+            let error = i18n::fill(
+                tr("Only mermaid blocks are diagrams, not `{}`"),
+                &[&block.lang],
+            );
+            match &mut self.code_actions {
+                Some(actions) => actions.error = Some(error),
+                None => self.notify(error),
+            }
+            return;
+        }
+        self.notify(tr("Rendering diagram…"));
+        let Some(dir) = config::cache_dir().map(|dir| dir.join("diagrams")) else {
+            return self.notify(tr("No cache directory available for diagrams"));
+        };
+        let mmdc = self.config.diagrams.mmdc().to_string();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let rendered = diagram::render_mermaid(&mmdc, &block.code, &dir)
+                .await
+                .map_err(|e| e.to_string());
+            let _ = tx.send(Action::DiagramRendered(rendered));
+        });
+    }
+
+    /// Shows a rendered diagram over the chat where the terminal can draw images, and in
+    /// the desktop's image viewer elsewhere
+    fn show_diagram(&mut self, path: PathBuf) {
+        if self.graphics.is_some() && !self.config.ui.accessible() {
+            match std::fs::read(&path) {
+                Ok(data) => {
+                    self.image_preview = Some(ImagePreview {
+                        path,
+                        data: data.into(),
+                        area: None,
+                        shown: None,
+                    })
+                }
+                Err(e) => self.push_error(i18n::fill(
+                    tr("Could not read {}: {}"),
+                    &[&path.display(), &e],
+                )),
+            }
+            return;
+        }
+        match open_in_desktop(&path) {
+            Ok(()) => self.notify(i18n::fill(tr("Opened {}"), &[&path.display()])),
+            Err(e) => self.push_error(i18n::fill(
+                tr("Could not open {}: {}"),
+                &[&path.display(), &e],
+            )),
+        }
+    }
+
+    /// Runs `code` through `run_command` as if the model had called it, so it is sandboxed
+    /// and audited like any other command and its output becomes part of the conversation
+    fn run_code(&mut self, code: String) {
+        let command = code
+            .lines()
+            .map(|line| line.strip_prefix("$ ").unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let call = ai::ToolCall {
+            id: None,
+            name: "run_command".into(),
+            args: serde_json::json!({ "command": command }).to_string(),
+            thought_signature: None,
+        };
+        self.push_message(Message::tool(call.clone()));
+        self.should_auto_scroll = true;
+        self.scroll_to_bottom();
+        self.code_running = true;
+
+        let tools_config = self.config.tools.clone();
+        let redactor = self.redactor.clone();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let (outcome, recorded) =
+                execute_call(call, audit::Approval::User, &tools_config, &redactor).await;
+            if let Err(e) = recorded {
+                let _ = tx.send(Action::AuditFailed(e.to_string()));
+            }
+            let _ = tx.send(Action::CodeRan(outcome));
+        });
+    }
+
+    /// Whether anything shown with a spinner is under way
+    fn busy(&self) -> bool {
+        self.is_loading
+            || self.indexing.is_some()
+            || self.code_running
+            || self.split.as_ref().is_some_and(|pane| pane.loading)
+            || self
+                .compare_view
+                .as_ref()
+                .is_some_and(|view| view.columns.iter().any(|column| column.duration.is_none()))
+    }
+
+    /// Current spinner frame; a fixed ellipsis in accessible mode
+    fn spinner(&self) -> &'static str {
+        if self.config.ui.accessible() {
+            "…"
+        } else {
+            SPINNER_FRAMES[self.spinner_index]
+        }
+    }
+
+    /// Box borders, or none in accessible mode where screen readers would read them out
+    fn borders(&self) -> Borders {
+        if self.config.ui.accessible() {
+            Borders::NONE
+        } else {
+            Borders::ALL
+        }
+    }
+
+    /// List row of message `i`'s header
+    fn message_start(&self, i: usize) -> usize {
+        self.messages[..i]
+            .iter()
+            .map(|m| self.message_height(m))
+            .sum()
+    }
+
+    /// Selects the header of message `i` and stops following new output
+    fn jump_to_message(&mut self, i: usize) {
+        self.should_auto_scroll = false;
+        self.list_state.select(Some(self.message_start(i)));
+    }
+
+    /// Moves to the next message's header, or back to the current one's header (then the
+    /// previous one's) when going up
+    fn step_message(&mut self, forward: bool) {
+        let Some(i) = self.selected_message() else {
+            return;
+        };
+        let at_header = self.list_state.selected() == Some(self.message_start(i));
+        let to = match (forward, at_header) {
+            (true, _) => (i + 1).min(self.messages.len() - 1),
+            (false, true) => i.saturating_sub(1),
+            (false, false) => i,
+        };
+        self.jump_to_message(to);
+    }
+
+    /// Moves to the opening fence of the next or previous code block in any message
+    fn step_code_block(&mut self, forward: bool) {
+        let selected = self.list_state.selected().unwrap_or(0);
+        let mut start = 0;
+        let mut fences = Vec::new();
+        for msg in &self.messages {
+            if msg.tool.is_none() && msg.review.is_none() && !msg.raw {
+                // Past the header
+                let body = start + 1;
+                fences.extend(
+                    self.message_code_blocks(msg)
+                        .iter()
+                        .map(|lines| body + lines.start),
+                );
+            }
+            start += self.message_height(msg);
+        }
+        let target = if forward {
+            fences.into_iter().find(|&row| row > selected)
+        } else {
+            fences.into_iter().rev().find(|&row| row < selected)
+        };
+        match target {
+            Some(row) => {
+                self.should_auto_scroll = false;
+                self.list_state.select(Some(row));
+            }
+            None => self.notify(tr("No more code blocks")),
+        }
+    }
+
+    /// `/fork`: keeps the conversation as it is and continues in a new branch from the
+    /// selected message. Forking at a question of yours puts it back in the input to edit.
+    fn fork(&mut self) {
+        if self.is_loading || self.tools_running || self.approval_prompt.is_some() {
+            self.notify(tr("Wait for the response to finish before forking"));
+            return;
+        }
+        let selected = if self.should_auto_scroll {
+            None
+        } else {
+            self.selected_message()
+        };
+        let draft = self.draft();
+        let keep = match selected {
+            Some(i) if self.messages[i].role == "You" => {
+                self.textarea = input_area(&self.messages[i].content);
+                self.focus = Focus::Input;
+                i
+            }
+            Some(i) => i + 1,
+            None => self.messages.len(),
+        };
+        let branch = self.branches.fork(&mut self.messages, draft, keep);
+        self.resuming = false;
+        self.save_session();
+        self.show_branch();
+        self.notify(i18n::fill(
+            tr("Forked {} at message {}"),
+            &[&self.branches.branch(branch).name, &keep],
+        ));
+    }
+
+    /// `/branch [n]`: lists the branches, or switches to the `n`th
+    fn branch_command(&mut self, args: &str) {
+        if args.is_empty() {
+            let mut text = String::from("**Branches**\n");
+            for (index, depth) in self.branches.outline() {
+                let branch = self.branches.branch(index);
+                text.push_str(&format!(
+                    "{}{}. {}",
+                    "  ".repeat(depth),
+                    index + 1,
+                    branch.name
+                ));
+                if let Some(parent) = branch.parent {
+                    text.push_str(&format!(
+                        " (from {} at message {})",
+                        self.branches.branch(parent).name,
+                        branch.fork_at
+                    ));
+                }
+                if index == self.branches.current() {
+                    text.push_str(" ← current");
+                }
+                text.push('\n');
+            }
+            text.push_str("\n`/branch <n>` switches; `/fork` starts a new one");
+            return self.push_system(text);
+        }
+        match args.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.branches.len() => self.switch_branch(n - 1),
+            _ => self.push_system(i18n::fill(
+                tr("Usage: `/branch <n>` with n from 1 to {}"),
+                &[&self.branches.len()],
+            )),
+        }
+    }
+
+    /// Moves to the next or previous branch in outline order
+    fn step_branch(&mut self, forward: bool) {
+        let outline = self.branches.outline();
+        let at = outline
+            .iter()
+            .position(|(index, _)| *index == self.branches.current())
+            .unwrap_or(0);
+        let to = if forward {
+            (at + 1) % outline.len()
+        } else {
+            (at + outline.len() - 1) % outline.len()
+        };
+        self.switch_branch(outline[to].0);
+    }
+
+    fn switch_branch(&mut self, to: usize) {
+        if to == self.branches.current() {
+            return;
+        }
+        if self.is_loading || self.tools_running || self.approval_prompt.is_some() {
+            self.notify(tr(
+                "Wait for the response to finish before switching branches",
+            ));
+            return;
+        }
+        self.save_session();
+        let mut draft = self.draft();
+        self.branches.switch(&mut self.messages, &mut draft, to);
+        self.textarea = input_area(&draft);
+        self.resuming = false;
+        self.show_branch();
+        self.notify(i18n::fill(tr("On {}"), &[&self.branches.branch(to).name]));
+    }
+
+    /// Redraws the checked-out branch from its end
+    fn show_branch(&mut self) {
+        // Stored branches may have been rendered at another width or theme
+        for msg in &mut self.messages {
+            msg.rendered.take();
+        }
+        self.should_auto_scroll = true;
+        self.list_state.select(None);
+        self.scroll_to_bottom();
+    }
+
+    /// `/commit`: writes a message for the staged changes and opens it for editing
+    fn commit_command(&mut self) {
+        self.notify(tr("Writing a commit message…"));
+        let config = self.config.clone();
+        let redactor = self.redactor.clone();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let result = commit::generate(&config, &redactor)
+                .await
+                .map_err(|e| e.to_string());
+            let _ = tx.send(Action::CommitMessage(result));
+        });
+    }
+
+    /// `/index [paths]`: embeds the files under `paths` (default: the whole project) so
+    /// questions can retrieve the relevant parts
+    fn index_command(&mut self, args: &str) {
+        if self.indexing.is_some() {
+            self.notify(tr("Already indexing"));
+            return;
+        }
+        if !ai::has_credentials() {
+            self.push_error(tr("Indexing needs an API key; run /setup"));
+            return;
+        }
+        let Some(dir) = config::cache_dir().map(|dir| dir.join("index")) else {
+            return self.push_error(tr("No cache directory available for the index"));
+        };
+        let paths: Vec<PathBuf> = match args {
+            "" => vec![PathBuf::from(".")],
+            args => args.split_whitespace().map(PathBuf::from).collect(),
+        };
+        self.indexing = Some((0, 0));
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let root = std::env::current_dir().unwrap_or_default();
+            let progress_tx = tx.clone();
+            let result = rag::Index::update(&dir, &root, &paths, |done, total| {
+                let _ = progress_tx.send(Action::IndexProgress(done, total));
+            })
+            .await
+            .map(Arc::new)
+            .map_err(|e| e.to_string());
+            let _ = tx.send(Action::Indexed(result));
+        });
+    }
+
+    /// `/map [off]`: builds the repository map again, or stops sending it
+    fn map_command(&mut self, args: &str) {
+        if args == "off" {
+            self.repo_map = None;
+            self.notify(tr("Repository map off"));
+            return;
+        }
+        let budget = self.config.context.repo_map_tokens();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn_blocking(move || {
+            let root = std::env::current_dir().unwrap_or_default();
+            let _ = tx.send(Action::RepoMap(repomap::build(&root, budget)));
+        });
+    }
+
+    /// `/review [ref|--staged]`: reviews the diff and adds the findings to the chat, where
+    /// the model can be asked about them
+    fn review_command(&mut self, args: &str) {
+        let (reference, staged) = match args {
+            "" => (None, false),
+            "--staged" => (None, true),
+            reference => (Some(reference.to_string()), false),
+        };
+        self.notify(tr("Reviewing…"));
+        let config = self.config.clone();
+        let redactor = self.redactor.clone();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let result = review::generate(reference.as_deref(), staged, &config, &redactor)
+                .await
+                .map_err(|e| e.to_string());
+            let _ = tx.send(Action::ReviewReady(result));
+        });
+    }
+
+    /// `/imagine <prompt>`: generates images with the `[images]` model
+    fn imagine_command(&mut self, prompt: &str) {
+        if prompt.is_empty() {
+            return self.push_system(tr("Usage: `/imagine <description of the image>`"));
+        }
+        self.notify(tr("Generating an image…"));
+        let model = self.config.images.model().to_string();
+        let prompt = self.redactor.redact(prompt).into_owned();
+        let provider = self.provider();
+        let tx = self.action_tx.clone();
+        self.tasks.spawn(async move {
+            let result = ai::generate_image(&model, &prompt, provider)
+                .await
+                .map_err(|e| e.to_string());
+            let _ = tx.send(Action::Imagined(result));
+        });
+    }
+
+    /// `/share [--gist]`: the conversation as Markdown, with secrets masked, on the clipboard
+    /// or in a secret gist
+    fn share_command(&mut self, args: &str) {
+        if !self.messages.iter().any(|m| m.role == "You") {
+            return self.notify(tr("Nothing to share yet"));
+        }
+        let text = self.transcript();
+        let text = self.redactor.redact(&text).into_owned();
+        match args {
+            "" => match clipboard::copy(&text) {
+                Ok(()) => self.notify(tr("Copied the conversation as Markdown")),
+                Err(e) => self.push_error(i18n::fill(tr("Could not copy: {}"), &[&e])),
+            },
+            "--gist" => {
+                self.notify(tr("Uploading the conversation…"));
+                let config = self.config.share.clone();
+                let title = self
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| "gemchat conversation".into());
+                let filename = format!("gemchat-{}.md", Local::now().format("%Y%m%d-%H%M%S"));
+                let tx = self.action_tx.clone();
+                self.tasks.spawn(async move {
+                    let result = share::gist(&config, &filename, &title, &text)
+                        .await
+                        .map_err(|e| e.to_string());
+                    let _ = tx.send(Action::Shared(result));
+                });
+            }
+            _ => self.push_system(tr("Usage: `/share [--gist]`")),
+        }
+    }
+
+    /// `/split [model|off]` opens a pane beside the chat where another model answers the
+    /// prompts sent from now on
+    fn split_command(&mut self, args: &str) {
+        match args {
+            "off" => {
+                self.split = None;
+                if self.focus == Focus::Split {
+                    self.focus = Focus::Chat;
+                }
+                self.notify(tr("Closed the split pane"));
+            }
+            "" if self.split.is_none() => self.push_system(tr("Usage: `/split <model>` answers the next prompts with `<model>` too, beside the chat")),
+            "" => {}
+            model if self.split.as_ref().is_some_and(|pane| pane.loading) => self.notify(i18n::fill(tr("Wait for the split pane to finish before switching to {}"), &[&model])),
+            model => {
+                // A different model starts a conversation of its own
+                self.split = Some(SplitPane::new(model.to_string()));
+            }
+        }
+        if let Some(pane) = &self.split {
+            let text = i18n::fill(
+                tr("Prompts also go to `{}` in the right pane; Tab moves the focus to it"),
+                &[&pane.model],
+            );
+            self.notify(text);
+        }
+    }
+
+    /// Sends the split pane's conversation to its model, streaming the answer into the pane
+    fn spawn_split_stream(&mut self) {
+        let mut blocks = system_blocks(
+            &self.config,
+            self.session_template(),
+            &self.template_files,
+            self.repo_map.as_deref(),
+        );
+        let Some(pane) = &mut self.split else {
+            return;
+        };
+        blocks.extend(history_blocks(&pane.messages, &self.config.tools));
+        let request = ai::Request {
+            attachments: history_attachments(&pane.messages),
+            models: vec![pane.model.clone()],
+            without_tools: true,
+            safety: self.config.safety.clone(),
+            provider: self.session_provider.or(self.config.provider),
+            generation: self.config.generation.clone(),
+            ..ai::Request::from_blocks(self.redactor.redact_blocks(blocks))
+        };
+        pane.messages.push(Message {
+            model: Some(pane.model.clone()),
+            unfolded: true,
+            generation: self.config.generation.summary(),
+            ..Message::new("AI", String::new())
+        });
+        pane.loading = true;
+        pane.should_auto_scroll = true;
+
+        let tx = self.action_tx.clone();
+        pane.task = Some(tokio::spawn(async move {
+            let (ai_tx, mut ai_rx) = mpsc::unbounded_channel();
+            let _stream = AbortOnDrop(tokio::spawn(async move {
+                ai::stream_response(request, ai_tx).await;
+            }));
+            while let Some(update) = ai_rx.recv().await {
+                let action = match update {
+                    ai::AiUpdate::Content(text) => Action::SplitChunk(text),
+                    ai::AiUpdate::Usage(usage) => Action::SplitUsage(usage),
+                    ai::AiUpdate::Error(e) | ai::AiUpdate::Interrupted(e) => Action::SplitError(e),
+                    ai::AiUpdate::Finished => break,
+                    _ => continue,
+                };
+                let _ = tx.send(action);
+            }
+            let _ = tx.send(Action::SplitFinish);
+        }));
+    }
+
+    /// Writes the split pane's conversation to the store as a session of its own
+    fn save_split(&mut self) {
+        let (Some(store), Some(pane)) = (&mut self.store, &mut self.split) else {
+            return;
+        };
+        let messages: Vec<_> = pane
+            .messages
+            .iter()
+            .filter(|m| !m.queued)
+            .map(Message::stored)
+            .collect();
+        let session = match pane.session {
+            Some(session) => session,
+            None => {
+                let title = format!(
+                    "{} ({})",
+                    self.title.as_deref().unwrap_or(tr("Comparison")),
+                    pane.model
+                );
+                match store.create_session(Some(&title)) {
+                    Ok(session) => {
+                        if let Err(e) = store.set_model(
+                            session,
+                            Some(&pane.model),
+                            self.session_provider.map(ai::Provider::name),
+                        ) {
+                            tracing::warn!(error = %e, "could not save the session's model");
+                        }
+                        *pane.session.insert(session)
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "could not start a session");
+                        return;
+                    }
+                }
+            }
+        };
+        if let Err(e) = store.save_branch(session, 0, &messages) {
+            tracing::warn!(error = %e, "could not save the split pane");
+        }
+    }
+
+    /// Scrolls the split pane like the chat
+    fn scroll_split(&mut self, command: keymap::Command) {
+        let Some(pane) = &self.split else {
+            return;
+        };
+        let count: usize = pane.messages.iter().map(|m| self.message_height(m)).sum();
+        let last = count.saturating_sub(1);
+        let Some(pane) = &mut self.split else {
+            return;
+        };
+        let selected = pane.list_state.selected().unwrap_or(last);
+        let selected = match command {
+            keymap::Command::ScrollUp => selected.saturating_sub(1),
+            keymap::Command::ScrollDown => (selected + 1).min(last),
+            _ => last,
+        };
+        pane.list_state.select(Some(selected));
+        pane.should_auto_scroll = command == keymap::Command::ScrollBottom;
+    }
+
+    /// `/compare <prompt>` asks every `[compare]` model at once, following the conversation,
+    /// and shows the answers side by side
+    fn compare_command(&mut self, prompt: &str) {
+        if prompt.is_empty() {
+            return self.push_system(tr("Usage: `/compare <prompt>`"));
+        }
+        let targets = self.config.compare.targets(&self.config.models);
+        if targets.len() < 2 {
+            return self.push_system(tr(
+                "`/compare` needs two or three models; list them under `models` in `[compare]`",
+            ));
+        }
+        let mut blocks = system_blocks(
+            &self.config,
+            self.session_template(),
+            &self.template_files,
+            self.repo_map.as_deref(),
+        );
+        blocks.extend(history_blocks(&self.messages, &self.config.tools));
+        // The question isn't in the conversation, so everything before it is history
+        if let Some(turn) = blocks.pop() {
+            blocks.push(ai::ContextBlock::new(ai::BlockKind::History, turn.text));
+        }
+        blocks.push(ai::ContextBlock::new(
+            ai::BlockKind::Turn,
+            format!("You: {}\n", prompt),
+        ));
+        let request = ai::Request {
+            attachments: history_attachments(&self.messages),
+            without_tools: true,
+            safety: self.config.safety.clone(),
+            generation: self.config.generation.clone(),
+            ..ai::Request::from_blocks(self.redactor.redact_blocks(blocks))
+        };
+
+        let mut columns = Vec::new();
+        let mut tasks = Vec::new();
+        for (i, (provider, model)) in targets.into_iter().enumerate() {
+            let request = ai::Request {
+                models: vec![model.clone()],
+                provider: provider.or(self.provider()),
+                ..request.clone()
+            };
+            let tx = self.action_tx.clone();
+            // Each stream tags its updates with its column, so they can share the pipeline
+            tasks.push(tokio::spawn(async move {
+                let (ai_tx, mut ai_rx) = mpsc::unbounded_channel();
+                let _stream = AbortOnDrop(tokio::spawn(async move {
+                    ai::stream_response(request, ai_tx).await;
+                }));
+                while let Some(update) = ai_rx.recv().await {
+                    let action = match update {
+                        ai::AiUpdate::Content(text) => Action::CompareChunk(i, text),
+                        ai::AiUpdate::Usage(usage) => Action::CompareUsage(i, usage),
+                        ai::AiUpdate::Error(e) | ai::AiUpdate::Interrupted(e) => {
+                            Action::CompareError(i, e)
+                        }
+                        ai::AiUpdate::Finished => break,
+                        _ => continue,
+                    };
+                    let _ = tx.send(action);
+                }
+                let _ = tx.send(Action::CompareFinish(i));
+            }));
+            columns.push(CompareColumn {
+                model,
+                provider,
+                text: String::new(),
+                started: Instant::now(),
+                first_token: None,
+                duration: None,
+                usage: None,
+                error: None,
+            });
+        }
+        self.compare_view = Some(CompareView {
+            prompt: prompt.to_string(),
+            columns,
+            scroll: 0,
+            tasks,
+        });
+    }
+
+    fn compare_column(&mut self, i: usize) -> Option<&mut CompareColumn> {
+        self.compare_view.as_mut()?.columns.get_mut(i)
+    }
+
+    fn compare_key(&mut self, key: KeyEvent) {
+        let Some(view) = &mut self.compare_view else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.compare_view = None,
+            KeyCode::Char('j') | KeyCode::Down => view.scroll = view.scroll.saturating_add(1),
+            KeyCode::Char('k') | KeyCode::Up => view.scroll = view.scroll.saturating_sub(1),
+            KeyCode::Char('g') => view.scroll = 0,
+            KeyCode::Char(c @ '1'..='9') => self.keep_compared(c as usize - '1' as usize),
+            _ => {}
+        }
+    }
+
+    /// Adds the compared prompt and the answer in column `i` to the conversation
+    fn keep_compared(&mut self, i: usize) {
+        if self.is_loading {
+            return self.notify(tr("Wait for the current response to finish"));
+        }
+        let Some(view) = &self.compare_view else {
+            return;
+        };
+        let Some(column) = view.columns.get(i) else {
+            return;
+        };
+        if column.duration.is_none() {
+            return self.notify(i18n::fill(tr("{} is still answering"), &[&column.model]));
+        }
+        if column.text.is_empty() {
+            return self.notify(i18n::fill(
+                tr("{} gave no answer to keep"),
+                &[&column.model],
+            ));
+        }
+        let prompt = Message::new("You", view.prompt.clone());
+        let answer = Message {
+            model: Some(column.model.clone()),
+            first_token: column.first_token,
+            duration: column.duration,
+            ..Message::new("AI", column.text.clone())
+        };
+        let kept = i18n::fill(tr("Kept the answer from {}"), &[&column.model]);
+        self.compare_view = None;
+        self.messages.push(prompt);
+        self.messages.push(answer);
+        self.should_auto_scroll = true;
+        self.save_session();
+        self.notify(kept);
+    }
+
+    fn draw_compare(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        let Some(view) = &self.compare_view else {
+            return;
+        };
+        let [prompt_area, columns_area] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(4), Constraint::Min(3)])
+            .areas(area);
+        let dim = Style::default().fg(self.theme.dim);
+        let prompt = Paragraph::new(vec![
+            Line::from(view.prompt.clone()),
+            Line::from(Span::styled(
+                format!(
+                    "1-{}: keep that answer  j/k: scroll  Esc: close",
+                    view.columns.len()
+                ),
+                dim,
+            )),
+        ])
+        .block(
+            Block::default()
+                .borders(self.borders())
+                .title(tr("Compare"))
+                .style(Style::default().fg(self.theme.text)),
+        );
+        frame.render_widget(prompt, prompt_area);
+
+        let areas = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![
+                Constraint::Ratio(1, view.columns.len() as u32);
+                view.columns.len()
+            ])
+            .split(columns_area);
+        for (i, (column, &area)) in view.columns.iter().zip(areas.iter()).enumerate() {
+            let width = area.width.saturating_sub(2) as usize;
+            let mut lines = match &column.error {
+                Some(e) => vec![Line::from(Span::styled(
+                    e.clone(),
+                    Style::default().fg(self.theme.error),
+                ))],
+                None => Vec::new(),
+            };
+            lines.extend(render::markdown(
+                &column.text,
+                width,
+                &self.highlighter,
+                &self.theme,
+            ));
+
+            let mut stats = Vec::new();
+            match column.duration {
+                None => stats.push(format!(
+                    "{} {:.1}s",
+                    self.spinner(),
+                    column.started.elapsed().as_secs_f64()
+                )),
+                Some(total) => {
+                    if let Some(first) = column.first_token {
+                        stats.push(format!("first token {:.1}s", first.as_secs_f64()));
+                    }
+                    stats.push(format!("total {:.1}s", total.as_secs_f64()));
+                }
+            }
+            if let Some(usage) = &column.usage {
+                stats.push(format!(
+                    "{} tokens",
+                    format_tokens(usage.response_tokens.into())
+                ));
+                stats.push(format_cost(pricing::cost(
+                    &self.config.pricing,
+                    &column.model,
+                    usage.prompt_tokens.into(),
+                    usage.cached_tokens.into(),
+                    usage.total_tokens.into(),
+                )));
+            }
+            let title = match column.provider {
+                Some(provider) => format!("{} · {} ({})", i + 1, column.model, provider.name()),
+                None => format!("{} · {}", i + 1, column.model),
+            };
+            let block = Block::default()
+                .borders(self.borders())
+                .title(title)
+                .title_bottom(Line::from(Span::styled(stats.join(" · "), dim)))
+                .style(Style::default().fg(self.theme.text));
+            frame.render_widget(
+                Paragraph::new(lines).block(block).scroll((view.scroll, 0)),
+                area,
+            );
+        }
+    }
+
+    /// The conversation as Markdown, tool calls folded into `<details>`
+    fn transcript(&self) -> String {
+        let mut text = format!(
+            "# {}\n\n",
+            self.title.as_deref().unwrap_or("gemchat conversation")
+        );
+        for msg in self.messages.iter().filter(|m| !m.queued) {
+            if let Some(block) = &msg.tool {
+                text.push_str(&format!(
+                    "<details><summary>⚙ {} {}</summary>\n\n```json\n{}\n```\n\n```\n{}\n```\n</details>\n\n",
+                    block.call.name,
+                    tool_summary(&block.call.args),
+                    block.call.args,
+                    block.output.as_deref().unwrap_or("(not run)").trim_end()
+                ));
+                continue;
+            }
+            if msg.role == "System" || msg.content.is_empty() {
+                continue;
+            }
+            let by = match &msg.model {
+                Some(model) => format!("{} ({})", msg.role, model),
+                None => msg.role.clone(),
+            };
+            text.push_str(&format!(
+                "**{}** · {}\n\n{}\n\n",
+                by,
+                msg.created.format("%Y-%m-%d %H:%M"),
+                msg.content.trim_end()
+            ));
+        }
+        text
+    }
+
+    /// Writes generated images to `images.dir`, lists them in the chat and previews the first
+    fn save_images(&mut self, generated: ai::Generated) {
+        let Some(dir) = self.config.images.dir() else {
+            return self.push_error(tr(
+                "No directory to save images in; set `dir` under [images]",
+            ));
+        };
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            return self.push_error(i18n::fill(
+                tr("Could not create {}: {}"),
+                &[&dir.display(), &e],
+            ));
+        }
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        let mut lines = Vec::new();
+        let mut first = None;
+        for (i, image) in generated.images.into_iter().enumerate() {
+            let extension = image.mime_type.strip_prefix("image/").unwrap_or("png");
+            let path = dir.join(format!("imagine-{}-{}.{}", stamp, i + 1, extension));
+            if let Err(e) = std::fs::write(&path, &image.data) {
+                return self.push_error(i18n::fill(
+                    tr("Could not write {}: {}"),
+                    &[&path.display(), &e],
+                ));
+            }
+            lines.push(i18n::fill(tr("Saved image to `{}`"), &[&path.display()]));
+            first.get_or_insert((path, image.data));
+        }
+        if !generated.text.trim().is_empty() {
+            lines.push(generated.text.trim().to_string());
+        }
+        self.push_system(lines.join("\n\n"));
+        if self.should_auto_scroll {
+            self.scroll_to_bottom();
+        }
+        if let Some((path, data)) = first
+            && self.graphics.is_some()
+            && self.config.images.preview()
+            && !self.config.ui.accessible()
+        {
+            self.image_preview = Some(ImagePreview {
+                path,
+                data,
+                area: None,
+                shown: None,
+            });
+        }
+    }
+
+    /// `o` opens the previewed image in the desktop's viewer; any key closes the preview
+    fn preview_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('o')
+            && let Some(preview) = &self.image_preview
+        {
+            let path = preview.path.clone();
+            if let Err(e) = open_in_desktop(&path) {
+                self.push_error(i18n::fill(
+                    tr("Could not open {}: {}"),
+                    &[&path.display(), &e],
+                ));
+            }
+        }
+        self.close_preview();
+    }
+
+    fn close_preview(&mut self) {
+        self.image_preview = None;
+        if let Some(protocol) = self.graphics
+            && let Err(e) = graphics::clear(protocol)
+        {
+            tracing::warn!(error = %e, "could not remove the image preview");
+        }
+        self.needs_clear = true;
+    }
+
+    /// Draws the previewed image where the last frame left room for it, once per placement
+    fn show_graphics(&mut self) {
+        let (Some(preview), Some(protocol)) = (&mut self.image_preview, self.graphics) else {
+            return;
+        };
+        let Some(area) = preview.area.filter(|area| preview.shown != Some(*area)) else {
+            return;
+        };
+        if preview.shown.is_some() {
+            let _ = graphics::clear(protocol);
+        }
+        preview.shown = Some(area);
+        if let Err(e) = graphics::show(protocol, &preview.data, area) {
+            tracing::warn!(error = %e, "could not draw the image preview");
+        }
+    }
+
+    fn commit_key(&mut self, key: KeyEvent) {
+        let Some(draft) = &mut self.commit else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.commit = None;
+                self.notify(tr("Not committed"));
+            }
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                if draft.committing {
+                    return;
+                }
+                let message = draft.input.lines().join("\n").trim().to_string();
+                if message.is_empty() {
+                    return;
+                }
+                draft.committing = true;
+                draft.error = None;
+                let tx = self.action_tx.clone();
+                self.tasks.spawn(async move {
+                    let result = git::commit(&message).await.map_err(|e| e.to_string());
+                    let _ = tx.send(Action::Committed(result));
+                });
+            }
+            _ if !draft.committing => {
+                draft.input.input(key);
+            }
+            _ => {}
+        }
+    }
+
+    fn committed(&mut self, result: Result<String, String>) {
+        let Some(draft) = &mut self.commit else {
+            return;
+        };
+        draft.committing = false;
+        match result {
+            Ok(summary) => {
+                self.commit = None;
+                self.push_system(i18n::fill(tr("Committed {}"), &[&summary]));
+            }
+            Err(e) => draft.error = Some(e),
+        }
+    }
+
+    /// The conversation as prompt blocks so the AI has context. The system blocks are added
+    /// when the request is built, as its cacheable prefix.
+    fn build_context(&self, after_tools: bool) -> Vec<ai::ContextBlock> {
+        let mut blocks = history_blocks(&self.messages, &self.config.tools);
+
+        // If this request carries tool results, reinforce the instruction
+        if after_tools {
+            blocks.push(ai::ContextBlock::new(ai::BlockKind::Turn, AFTER_TOOLS));
+        }
+        blocks
+    }
+
+    /// Replaces the last response with its next or previous alternative, which is then what
+    /// the history holds
+    fn step_alternative(&mut self, forward: bool) {
+        if self.is_loading {
+            self.notify(tr("Wait for the current response to finish"));
+            return;
+        }
+        let Some(msg) = self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.role == "AI" && !m.queued)
+            .filter(|m| m.alternatives.len() > 1)
+        else {
+            self.notify(tr(
+                "The last response has no alternatives (see generation.candidates)",
+            ));
+            return;
+        };
+        let count = msg.alternatives.len();
+        msg.alternative = if forward {
+            (msg.alternative + 1) % count
+        } else {
+            (msg.alternative + count - 1) % count
+        };
+        msg.content = msg.alternatives[msg.alternative].clone();
+        msg.rendered.take();
+        let shown = i18n::fill(
+            tr("Alternative {} of {}"),
+            &[&(msg.alternative + 1), &count],
+        );
+        self.notify(shown);
+        self.save_session();
+    }
+
+    /// Stops the response being streamed, keeping what arrived as an interrupted message
+    /// that can be continued
+    fn cancel_response(&mut self) {
+        if !self.abort_response() {
+            return self.notify(tr("No response to cancel"));
+        }
+        self.notify(tr("Cancelled the response"));
+        self.finish_turn();
+    }
+
+    /// Stops the stream of the in-flight response, if there is one, marking its partial
+    /// text as interrupted and its pending tools as not run
+    fn abort_response(&mut self) -> bool {
+        let Some(task) = self.stream_task.take() else {
+            return false;
+        };
+        task.abort();
+        self.finish_timing();
+        self.rate_limited_until = None;
+        self.resuming = false;
+        self.pending_tool_calls.clear();
+        for msg in &mut self.messages {
+            if let Some(block) = &mut msg.tool
+                && block.output.is_none()
+            {
+                block.output = Some("Not run: the response was cancelled".into());
+                msg.rendered.take();
+            }
+        }
+        if let Some(i) = self.messages.iter().rposition(|m| !m.queued)
+            && self.messages[i].role == "AI"
+        {
+            if self.messages[i].content.is_empty() {
+                self.messages.remove(i);
+            } else {
+                self.messages[i].interrupted = true;
+                self.messages[i].rendered.take();
+            }
+        }
+        true
+    }
+
+    /// Hands the terminal back to the shell and stops until continued, e.g. with `fg`
+    fn suspend(&mut self) {
+        #[cfg(unix)]
+        match tui::suspend() {
+            Ok(()) => self.repaint(),
+            Err(e) => self.push_error(i18n::fill(tr("Could not suspend: {}"), &[&e])),
+        }
+        #[cfg(not(unix))]
+        self.notify(tr("Suspending needs a Unix shell"));
+    }
+
+    /// Turns the terminal modes on again after the process was continued and redraws
+    fn resumed(&mut self) {
+        if let Err(e) = tui::resume() {
+            tracing::warn!(error = %e, "could not restore the terminal modes");
+        }
+        self.repaint();
+    }
+
+    /// Draws everything again, since the shell or another program used the terminal meanwhile
+    fn repaint(&mut self) {
+        self.needs_clear = true;
+        if let Some(preview) = &mut self.image_preview {
+            preview.shown = None;
+        }
+    }
+
+    /// Stops everything still running and saves what quitting would otherwise lose: the
+    /// partial response, the split pane's conversation, the draft and the session
+    fn shutdown(&mut self) {
+        self.abort_response();
+        self.tasks.abort_all();
+        self.save_split();
+        self.split = None;
+        self.compare_view = None;
+        self.save_draft();
+        self.save_session();
+    }
+
+    /// The failed response at the end of the chat
+    fn failure(&self) -> Option<&ai::ErrorDetails> {
+        self.messages.iter().rfind(|m| !m.queued)?.error.as_ref()
+    }
+
+    /// Sends the prompt of a failed response again, dropping the error and whatever the
+    /// turn produced before it
+    fn retry_response(&mut self) {
+        if self.is_loading {
+            return self.notify(tr("Wait for the current response to finish"));
+        }
+        if self.failure().is_none() {
+            return self.notify(tr("No failed response to retry"));
+        }
+        let Some(end) = self.messages.iter().rposition(|m| !m.queued) else {
+            return;
+        };
+        let start = self.messages[..end]
+            .iter()
+            .rposition(|m| m.role == "You")
+            .map_or(end, |i| i + 1);
+        self.messages.drain(start..=end);
+        self.last_error = None;
+        self.request_completion();
+    }
+
+    /// Opens the API key setup after an authentication error, else the config file with
+    /// the desktop's default application
+    fn open_config(&mut self) {
+        if self
+            .failure()
+            .is_some_and(|e| e.kind == ai::ErrorKind::Auth)
+        {
+            self.setup = Some(Setup::new(&self.theme));
+            return;
+        }
+        let Some(path) = self.config.path.clone() else {
+            return self.notify(tr("No config directory"));
+        };
+        let opened = (|| {
+            if !path.exists() {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&path, "")?;
+            }
+            open_in_desktop(&path)
+        })();
+        match opened {
+            Ok(()) => self.notify(i18n::fill(tr("Opened {}"), &[&path.display()])),
+            Err(e) => self.push_error(i18n::fill(
+                tr("Could not open {}: {}"),
+                &[&path.display(), &e],
+            )),
+        }
+    }
+
+    /// Moves the session to the configured model after the current one, wrapping around,
+    /// or starts a `/model` command when only one is configured
+    fn switch_model(&mut self) {
+        let models = &self.config.models;
+        if models.len() < 2 {
+            self.textarea = input_area("/model ");
+            self.focus = Focus::Input;
+            return self.notify(tr("Type the model to switch to"));
+        }
+        let next = models
+            .iter()
+            .position(|m| m == self.model())
+            .map_or(0, |i| (i + 1) % models.len());
+        let next = models[next].clone();
+        self.model_command(&next);
+    }
+
+    /// Asks the model to pick up an interrupted response where it stopped
+    fn continue_response(&mut self) {
+        if self.is_loading {
+            self.notify(tr("Wait for the current response to finish"));
+            return;
+        }
+        let Some(msg) = self
+            .last_live_mut()
+            .filter(|m| m.role == "AI" && m.interrupted)
+        else {
+            self.notify(tr("No interrupted response to continue"));
+            return;
+        };
+        msg.interrupted = false;
+        self.last_error = None;
+        self.resuming = true;
+        let mut context = self.build_context(false);
+        context.push(ai::ContextBlock::new(ai::BlockKind::Turn, "System: Your last reply was cut off by a dropped connection. Continue it from exactly where it stopped, without repeating anything or adding a preamble.\n"));
+        self.spawn_stream(context, Vec::new());
+    }
+
+    fn request_completion(&mut self) {
+        self.turn_started = Some(Instant::now());
+        self.trace_turn();
+        self.turn_guard.reset();
+        let context = self.build_context(false);
+        self.spawn_stream(context, Vec::new());
+    }
+
+    fn spawn_stream(&mut self, context: Vec<ai::ContextBlock>, outcomes: Vec<ai::ToolOutcome>) {
+        self.trace_phase("build request");
+        self.is_loading = true;
+        self.connected = false;
+        self.request_started = Some(Instant::now());
+        self.stream_chars = 0;
+        self.last_chunk_at = None;
+        self.spinner_index = 0;
+
+        // Nothing that looks like a credential leaves the machine
+        let mut blocks = system_blocks(
+            &self.config,
+            self.session_template(),
+            &self.template_files,
+            self.repo_map.as_deref(),
+        );
+        blocks.extend(context);
+        let request = ai::Request {
+            attachments: history_attachments(&self.messages),
+            outcomes: outcomes
+                .into_iter()
+                .map(|o| ai::ToolOutcome {
+                    result: self.redactor.redact(&o.result).into_owned(),
+                    call: o.call,
+                })
+                .collect(),
+            extra_tools: tools::custom_declarations(&self.config.tools),
+            models: self.models(),
+            without_tools: false,
+            safety: self.config.safety.clone(),
+            provider: self.provider(),
+            generation: self.config.generation.clone(),
+            ..ai::Request::from_blocks(self.redactor.redact_blocks(blocks))
+        };
+        // A new question (not a tool follow-up) gets excerpts from the project index
+        let chunks = self.config.context.retrieval_chunks();
+        let retrieval = self
+            .index
+            .clone()
+            .filter(|_| request.outcomes.is_empty() && chunks > 0)
+            .zip(
+                self.messages
+                    .iter()
+                    .rfind(|m| m.role == "You" && !m.queued)
+                    .map(|m| m.content.clone()),
+            );
+        let redactor = self.redactor.clone();
+        let tx = self.action_tx.clone();
+        self.stream_task = Some(tokio::spawn(async move {
+            let mut request = request;
+            if let Some((index, question)) = retrieval {
+                match index.search(&question, chunks).await {
+                    Ok(found) if !found.is_empty() => {
+                        tracing::info!(chunks = found.len(), "retrieved project excerpts");
+                        request
+                            .prompt
+                            .push_str(&redactor.redact(&rag::excerpts(&found)));
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = %e, "retrieval failed"),
+                }
+            }
+            let (ai_tx, mut ai_rx) = mpsc::unbounded_channel();
+
+            let _stream = AbortOnDrop(tokio::spawn(async move {
+                ai::stream_response(request, ai_tx).await;
+            }));
+
+            let _ = tx.send(Action::AiResponseStart);
+
+            // Text arriving within one frame interval is forwarded as a single chunk so
+            // fast streams don't cost a redraw per token
+            let mut text = String::new();
+            let mut flush_at: Option<Instant> = None;
+            loop {
+                let update = match flush_at {
+                    Some(deadline) => tokio::select! {
+                        update = ai_rx.recv() => update,
+                        _ = time::sleep_until(deadline) => {
+                            let _ = tx.send(Action::AiResponseChunk(std::mem::take(&mut text)));
+                            flush_at = None;
+                            continue;
+                        }
+                    },
+                    None => ai_rx.recv().await,
+                };
+                let Some(update) = update else {
+                    if !text.is_empty() {
+                        let _ = tx.send(Action::AiResponseChunk(text));
+                    }
+                    // Every stream ends with an error or its end, unless it panicked
+                    let _ = tx.send(Action::AiResponseError(
+                        "Error: the response stream ended unexpectedly".to_string(),
+                    ));
+                    break;
+                };
+                // Anything else is ordered after the text received before it
+                if !matches!(update, ai::AiUpdate::Content(_)) && !text.is_empty() {
+                    let _ = tx.send(Action::AiResponseChunk(std::mem::take(&mut text)));
+                    flush_at = None;
+                }
+                match update {
+                    ai::AiUpdate::Content(s) => {
+                        text.push_str(&s);
+                        flush_at.get_or_insert_with(|| Instant::now() + CHUNK_INTERVAL);
+                    }
+                    ai::AiUpdate::Model(model) => {
+                        let _ = tx.send(Action::AiResponseModel(model));
+                    }
+                    ai::AiUpdate::RateLimited(wait) => {
+                        let _ = tx.send(Action::RateLimited(wait));
+                    }
+                    ai::AiUpdate::Usage(usage) => {
+                        let _ = tx.send(Action::UpdateUsage(usage));
+                    }
+                    // An error ends the response, and the turn with it
+                    ai::AiUpdate::Error(e) => {
+                        let _ = tx.send(Action::AiResponseError(e));
+                        break;
+                    }
+                    ai::AiUpdate::Interrupted(reason) => {
+                        let _ = tx.send(Action::AiResponseInterrupted(reason));
+                    }
+                    ai::AiUpdate::Safety(report) => {
+                        let _ = tx.send(Action::AiResponseSafety(report));
+                    }
+                    ai::AiUpdate::Alternatives(texts) => {
+                        let _ = tx.send(Action::AiResponseAlternatives(texts));
+                    }
+                    ai::AiUpdate::ToolCall(call) => {
+                        let _ = tx.send(Action::ToolCall(call));
+                    }
+                    ai::AiUpdate::Finished => {
+                        let _ = tx.send(Action::AiResponseFinish);
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+
+    /// Goes on with the paused tool loop, with fresh limits, or stops it
+    fn loop_guard_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                self.loop_guard = None;
+                self.turn_guard.reset();
+                self.review_pending_tools();
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                self.loop_guard = None;
+                self.pending_tool_calls.clear();
+                for msg in &mut self.messages {
+                    if let Some(block) = &mut msg.tool
+                        && block.output.is_none()
+                    {
+                        block.output = Some("Not run: the tool loop was stopped".into());
+                        msg.rendered.take();
+                    }
+                }
+                self.finish_turn();
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks about the pending calls that need approval; the rest are decided right away
+    fn review_pending_tools(&mut self) {
+        let calls = std::mem::take(&mut self.pending_tool_calls)
+            .into_iter()
+            .map(|call| {
+                let decision = approval::check(
+                    &call,
+                    approval_rules(&self.config, self.template.as_deref()),
+                );
+                (call, decision)
+            })
+            .collect();
+        self.approval_prompt = Some(ApprovalPrompt { calls });
+        self.run_approved_tools();
+        if let Some(prompt) = &self.approval_prompt
+            && let Some(index) = prompt.current()
+        {
+            let call = &prompt.calls[index].0;
+            let body = format!("{} {}", call.name, tool_summary(&call.args));
+            self.trace_phase("await approval");
+            self.notify_user(tr("Approval needed"), body);
+        }
+    }
+
+    fn approval_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = &mut self.approval_prompt else {
+            return;
+        };
+        let Some(index) = prompt.current() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                prompt.calls[index].1 = Some(audit::Approval::User);
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                prompt.calls[index].1 = Some(audit::Approval::Denied);
+            }
+            KeyCode::Char('a') => {
+                let rule = approval::suggest(&prompt.calls[index].0);
+                let description = approval::describe(&rule);
+                let saved = self.config.save_allow_rule(rule, self.template.as_deref());
+                prompt.calls[index].1 = Some(audit::Approval::Rule);
+                // The new rule may cover other calls from the same turn
+                let rules = approval_rules(&self.config, self.template.as_deref());
+                for (call, decision) in &mut prompt.calls {
+                    if decision.is_none() {
+                        *decision = approval::check(call, rules);
+                    }
+                }
+                match saved {
+                    Ok(()) => self.notify(i18n::fill(tr("Always allowing {}"), &[&description])),
+                    Err(e) => self.push_error(i18n::fill(
+                        tr("Could not save the approval rule: {}"),
+                        &[&e],
+                    )),
+                }
+            }
+            _ => {}
+        }
+        self.run_approved_tools();
+    }
+
+    /// Starts the tools once every call in the prompt has a decision
+    fn run_approved_tools(&mut self) {
+        let Some(prompt) = self.approval_prompt.take_if(|p| p.current().is_none()) else {
+            return;
+        };
+        let calls = prompt
+            .calls
+            .into_iter()
+            .map(|(call, decision)| (call, decision.unwrap_or(audit::Approval::Denied)))
+            .collect();
+        self.run_tools(calls);
+    }
+
+    /// Runs every tool call from the finished turn concurrently, bounded by
+    /// `tools.max_parallel`, and reports all outcomes in call order
+    fn run_tools(&mut self, calls: Vec<(ai::ToolCall, audit::Approval)>) {
+        self.tools_running = true;
+        let names: Vec<&str> = calls.iter().map(|(call, _)| call.name.as_str()).collect();
+        self.trace_phase(format!("tools · {}", names.join(", ")));
+        let tools_config = self.config.tools.clone();
+        let tx = self.action_tx.clone();
+        let redactor = self.redactor.clone();
+
+        self.tasks.spawn(async move {
+            let limit = tools_config.max_parallel();
+            let outcomes = stream::iter(calls)
+                .map(|(call, decision)| {
+                    let tools_config = &tools_config;
+                    let redactor = &redactor;
+                    let tx = tx.clone();
+                    async move {
+                        let (outcome, recorded) =
+                            execute_call(call, decision, tools_config, redactor).await;
+                        if let Err(e) = recorded {
+                            let _ = tx.send(Action::AuditFailed(e.to_string()));
+                        }
+                        outcome
+                    }
+                })
+                .buffered(limit)
+                .collect::<Vec<_>>()
+                .await;
+            let _ = tx.send(Action::ToolResults(outcomes));
+        });
+    }
+
+    fn scroll_up(&mut self) {
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    0
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+    }
+
+    fn scroll_down(&mut self) {
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i >= self.total_list_items() - 1 {
                     i
                 } else {
                     i + 1
                 }
             }
-            None => 0,
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+    }
+
+    /// Wraps the chat to `width` again, keeping the selection, the visual selection and
+    /// the scroll position on the lines they were on
+    fn reflow(&mut self, width: usize) {
+        // Every list item moves, so each position is held as a place in its message
+        let positions = |state: &ListState, messages: &[Message]| {
+            (
+                state
+                    .selected()
+                    .and_then(|item| self.reflow_anchor(messages, item)),
+                self.reflow_anchor(messages, state.offset()),
+            )
+        };
+        // Nothing was drawn yet at width 0
+        let known = self.wrap_width > 0;
+        let main = known.then(|| positions(&self.list_state, &self.messages));
+        let visual = self
+            .visual_anchor
+            .and_then(|item| self.reflow_anchor(&self.messages, item));
+        let split = self
+            .split
+            .as_ref()
+            .filter(|_| known)
+            .map(|pane| positions(&pane.list_state, &pane.messages));
+
+        self.wrap_width = width;
+        let split_messages = self.split.iter_mut().flat_map(|pane| &mut pane.messages);
+        for msg in self.messages.iter_mut().chain(split_messages) {
+            msg.rendered.take();
+        }
+
+        if let Some((selected, top)) = main {
+            let selected = selected.map(|anchor| self.reflowed_item(&self.messages, anchor));
+            let top = top.map_or(0, |anchor| self.reflowed_item(&self.messages, anchor));
+            self.list_state.select(selected);
+            *self.list_state.offset_mut() = top;
+        }
+        if known {
+            self.visual_anchor = visual.map(|anchor| self.reflowed_item(&self.messages, anchor));
+        }
+        if let Some((selected, top)) = split
+            && let Some(pane) = &self.split
+        {
+            let selected = selected.map(|anchor| self.reflowed_item(&pane.messages, anchor));
+            let top = top.map_or(0, |anchor| self.reflowed_item(&pane.messages, anchor));
+            if let Some(pane) = &mut self.split {
+                pane.list_state.select(selected);
+                *pane.list_state.offset_mut() = top;
+            }
+        }
+    }
+
+    /// Where list item `item` of a chat pane is: its message and how far into the
+    /// message's items, as a fraction
+    fn reflow_anchor(&self, messages: &[Message], item: usize) -> Option<(usize, f64)> {
+        let mut start = 0;
+        for (i, msg) in messages.iter().enumerate() {
+            let height = self.message_height(msg);
+            if item < start + height {
+                return Some((i, (item - start) as f64 / height as f64));
+            }
+            start += height;
+        }
+        None
+    }
+
+    /// The list item at `anchor` from [`Self::reflow_anchor`] with the current wrapping
+    fn reflowed_item(&self, messages: &[Message], (message, fraction): (usize, f64)) -> usize {
+        let start: usize = messages[..message]
+            .iter()
+            .map(|msg| self.message_height(msg))
+            .sum();
+        let height = self.message_height(&messages[message]);
+        start + ((fraction * height as f64) as usize).min(height - 1)
+    }
+
+    fn scroll_to_bottom(&mut self) {
+        let count = self.total_list_items();
+        if count > 0 {
+            self.list_state.select(Some(count - 1));
+        }
+    }
+
+    fn total_list_items(&self) -> usize {
+        self.messages.iter().map(|m| self.message_height(m)).sum()
+    }
+
+    /// Rendered list items for one message: header, content lines and spacer
+    fn message_height(&self, msg: &Message) -> usize {
+        1 + self.message_body(msg).len() + 1
+    }
+
+    /// Rendered body of a message, cached on the message so that neither drawing nor
+    /// scrolling re-parses markdown that hasn't changed
+    fn message_body<'m>(&self, msg: &'m Message) -> &'m [Line<'static>] {
+        &self.rendered(msg).lines
+    }
+
+    /// Line ranges of the code blocks in [`Self::message_body`], fences included
+    fn message_code_blocks<'m>(&self, msg: &'m Message) -> &'m [std::ops::Range<usize>] {
+        &self.rendered(msg).code_blocks
+    }
+
+    fn rendered<'m>(&self, msg: &'m Message) -> &'m render::Document {
+        msg.rendered.get_or_init(|| {
+            let document = |lines: Vec<Line<'static>>| render::Document {
+                lines,
+                code_blocks: Vec::new(),
+            };
+            if let Some(error) = &msg.error {
+                let keys: Vec<String> = [
+                    (keymap::Command::Retry, tr("retry")),
+                    (keymap::Command::SwitchModel, tr("switch model")),
+                    (keymap::Command::OpenConfig, tr("open config")),
+                ]
+                .into_iter()
+                .filter_map(|(command, label)| {
+                    keymap::first_key(&self.keymap.normal, command)
+                        .map(|key| format!("{} {}", key, label))
+                })
+                .collect();
+                return document(
+                    error_body(error, &keys, &self.theme)
+                        .into_iter()
+                        .flat_map(|line| render::wrap(line, self.wrap_width))
+                        .collect(),
+                );
+            }
+            let mut rendered = match (&msg.tool, &msg.review) {
+                // Collapsed tool calls are a line; expanded, long output folds too
+                (Some(block), _) => document(
+                    tool_body(block, &self.theme)
+                        .into_iter()
+                        .flat_map(|line| render::wrap(owned_line(line), self.wrap_width))
+                        .collect(),
+                ),
+                (None, _) if msg.raw => document(
+                    msg.content
+                        .lines()
+                        .flat_map(|line| {
+                            render::wrap(Line::from(line.to_string()), self.wrap_width)
+                        })
+                        .collect(),
+                ),
+                (None, Some(review)) => document(
+                    review_body(review, &self.theme)
+                        .into_iter()
+                        .flat_map(|line| render::wrap(line, self.wrap_width))
+                        .collect(),
+                ),
+                (None, None) => render::document(
+                    &msg.content,
+                    msg.streaming,
+                    self.wrap_width,
+                    &self.highlighter,
+                    &self.theme,
+                ),
+            };
+            let limit = self.config.ui.fold_lines();
+            let lines = &mut rendered.lines;
+            if !msg.unfolded && limit > 0 && lines.len() > limit {
+                let hidden = lines.len() - limit;
+                lines.truncate(limit);
+                rendered.code_blocks.retain(|block| block.start < limit);
+                lines.push(Line::from(Span::styled(
+                    i18n::fill(tr("… ({} more lines, Enter to expand)"), &[&hidden]),
+                    Style::default().fg(self.theme.dim),
+                )));
+            }
+            rendered
+        })
+    }
+
+    /// Index of the message that owns the currently selected list item
+    fn selected_message(&self) -> Option<usize> {
+        self.message_at(self.list_state.selected()?)
+    }
+
+    /// Index of the message that owns list item `item`
+    fn message_at(&self, item: usize) -> Option<usize> {
+        let mut start = 0;
+        for (i, msg) in self.messages.iter().enumerate() {
+            start += self.message_height(msg);
+            if item < start {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Starts a visual selection at the selected line, or drops the one in progress
+    fn toggle_visual(&mut self) {
+        if self.visual_anchor.take().is_some() {
+            return;
+        }
+        if self.list_state.selected().is_none() {
+            self.scroll_to_bottom();
+        }
+        self.visual_anchor = self.list_state.selected();
+        self.should_auto_scroll = false;
+    }
+
+    /// List items from the visual selection's start to the selected one, or only the
+    /// selected one
+    fn selected_items(&self) -> Option<std::ops::RangeInclusive<usize>> {
+        let cursor = self.list_state.selected()?;
+        let anchor = self.visual_anchor.unwrap_or(cursor);
+        Some(anchor.min(cursor)..=anchor.max(cursor))
+    }
+
+    /// Copies the selected lines as they are drawn, or with `markdown` the text of every
+    /// message they touch
+    fn yank(&mut self, markdown: bool) {
+        let Some(items) = self.selected_items().filter(|_| !self.messages.is_empty()) else {
+            return self.notify(tr("Nothing selected"));
+        };
+        let text = if markdown {
+            let last = self.messages.len().saturating_sub(1);
+            let first = self.message_at(*items.start()).unwrap_or(last);
+            let end = self.message_at(*items.end()).unwrap_or(last);
+            match &self.messages[first..=end] {
+                [msg] => msg.stored().content,
+                messages => messages
+                    .iter()
+                    .map(|msg| format!("{}: {}", msg.role, msg.stored().content))
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            }
+        } else {
+            let lines = self.rendered_lines();
+            let end = (*items.end()).min(lines.len().saturating_sub(1));
+            lines
+                .get(*items.start()..=end)
+                .unwrap_or_default()
+                .join("\n")
+                .trim_end()
+                .to_string()
+        };
+        self.visual_anchor = None;
+        match clipboard::copy(&text) {
+            Ok(()) => self.notify(i18n::fill(tr("Copied {} lines"), &[&text.lines().count()])),
+            Err(e) => self.push_error(i18n::fill(tr("Could not copy: {}"), &[&e])),
+        }
+    }
+
+    /// Every list item of the chat as plain text, without the gutter
+    fn rendered_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for msg in &self.messages {
+            lines.push(match &msg.tool {
+                Some(block) => tool_header(block, "", &self.theme).to_string(),
+                None => format!("{}:", self.config.ui.role_label(&msg.role)),
+            });
+            lines.extend(self.message_body(msg).iter().map(Line::to_string));
+            lines.push(String::new());
+        }
+        lines
+    }
+
+    /// Switches the selected message between rendered Markdown and the text received
+    fn toggle_selected_raw(&mut self) {
+        let Some(i) = self
+            .selected_message()
+            .filter(|&i| self.messages[i].tool.is_none())
+        else {
+            return self.notify(tr("Select a message to show raw"));
+        };
+        let msg = &mut self.messages[i];
+        msg.raw = !msg.raw;
+        msg.rendered.take();
+        // Keep the selection on the header since the body's height changes
+        self.list_state.select(Some(self.message_start(i)));
+        self.should_auto_scroll = false;
+    }
+
+    /// Whether the body of `msg` is cut at `ui.fold_lines`
+    fn is_folded(&self, msg: &Message) -> bool {
+        let limit = self.config.ui.fold_lines();
+        !msg.unfolded && limit > 0 && self.message_body(msg).len() > limit
+    }
+
+    /// Folds or unfolds the selected message, keeping the selection on its header;
+    /// `false` for tool calls, which expand on their own
+    fn toggle_selected_fold(&mut self) -> bool {
+        let Some(i) = self
+            .selected_message()
+            .filter(|&i| self.messages[i].tool.is_none())
+        else {
+            return false;
+        };
+        let msg = &mut self.messages[i];
+        msg.unfolded = !msg.unfolded;
+        msg.rendered.take();
+        self.list_state.select(Some(self.message_start(i)));
+        true
+    }
+
+    /// Expands the selected tool call, then unfolds its output if that is folded, then
+    /// collapses it again
+    fn toggle_selected_tool(&mut self) {
+        if let Some(i) = self.selected_message()
+            && self.messages[i].tool.is_some()
+        {
+            let folded = self.is_folded(&self.messages[i]);
+            let msg = &mut self.messages[i];
+            let Some(block) = msg.tool.as_mut() else {
+                return;
+            };
+            if !block.expanded {
+                block.expanded = true;
+            } else if folded {
+                msg.unfolded = true;
+            } else {
+                block.expanded = false;
+                msg.unfolded = false;
+            }
+            msg.rendered.take();
+            // Keep the selection on the tool header so collapsing doesn't jump away
+            self.list_state.select(Some(self.message_start(i)));
+        }
+    }
+
+    /// Where the sidebar, the chat and its input go, from `[layout]` and the sidebar as
+    /// resized and toggled in the TUI
+    fn screen_layout(&self) -> ScreenLayout {
+        let layout = &self.config.layout;
+        let sidebar_width = if self.ui_state.sidebar_visible && !self.config.ui.accessible() {
+            self.ui_state.sidebar_width
+        } else {
+            0
+        };
+        ScreenLayout {
+            sidebar: layout.sidebar(),
+            sidebar_width,
+            input: layout.input(),
+            input_height: layout.input_height(),
+            padding: layout.padding(),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let screen = self.screen_layout().screen(frame.area());
+        self.draw_status_bar(frame, screen.status);
+        if let Some(area) = screen.sidebar {
+            self.draw_sidebar(frame, area);
+        }
+        let main_area = screen.main;
+        if let Some(setup) = &self.setup {
+            draw_setup(setup, frame, main_area, &self.theme);
+        } else if let Some(view) = &mut self.audit_view {
+            draw_audit(view, frame, main_area, &self.theme);
+        } else if let Some(view) = &mut self.stats_view {
+            draw_stats(view, &self.config.pricing, frame, main_area, &self.theme);
+        } else if self.compare_view.is_some() {
+            self.draw_compare(frame, main_area);
+        } else {
+            self.draw_main_chat(frame, main_area);
+        }
+        if let Some(draft) = &self.commit {
+            draw_commit(draft, frame, main_area, &self.theme);
+        }
+        if let Some(actions) = &self.code_actions {
+            draw_code_actions(actions, frame, main_area, &self.theme);
+        }
+        if let Some(picker) = &mut self.bookmark_picker {
+            draw_bookmarks(picker, &self.messages, frame, main_area, &self.theme);
+        }
+        if let Some(picker) = &mut self.template_picker {
+            draw_templates(picker, &self.config.template, frame, main_area, &self.theme);
+        }
+        if let Some(search) = &mut self.history_search {
+            draw_history_search(search, frame, main_area, &self.theme);
+        }
+        if let Some(prompt) = &self.approval_prompt {
+            draw_approval(prompt, frame, main_area, &self.theme);
+        }
+        if let Some(reason) = &self.loop_guard {
+            draw_loop_guard(reason, frame, main_area, &self.theme);
+        }
+        if let Some(preview) = &mut self.image_preview {
+            draw_image_preview(preview, frame, main_area, &self.theme);
+        }
+        if self.show_trace {
+            self.draw_trace(frame, main_area);
+        }
+        if self.show_help {
+            self.draw_help(frame);
+        }
+    }
+
+    /// Mode, model, provider, activity, last error and the current notification
+    fn draw_status_bar(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        const NOTIFICATION_TIME: Duration = Duration::from_secs(4);
+        const MAX_ERROR_WIDTH: usize = 60;
+
+        if self
+            .notification
+            .as_ref()
+            .is_some_and(|(_, at)| at.elapsed() > NOTIFICATION_TIME)
+        {
+            self.notification = None;
+        }
+
+        let (mode, mode_color) = match self.focus {
+            Focus::Input => (tr("EDITING"), self.theme.input_active),
+            Focus::Chat if self.visual_anchor.is_some() => (tr("VISUAL"), self.theme.accent),
+            Focus::Chat | Focus::Split => (tr("NORMAL"), self.theme.sidebar),
+            Focus::Sidebar => (tr("SESSIONS"), self.theme.accent),
+        };
+        let mode = format!(" {} ", mode);
+        let separator = Span::styled(" │ ", Style::default().fg(self.theme.dim));
+
+        let activity = if self.setup.is_some() {
+            tr("setup").to_string()
+        } else if self.loop_guard.is_some() {
+            tr("tool loop paused").to_string()
+        } else if self.approval_prompt.is_some() {
+            tr("waiting for approval").to_string()
+        } else if self.commit.as_ref().is_some_and(|d| d.committing) {
+            tr("committing").to_string()
+        } else if self.commit.is_some() {
+            tr("commit message").to_string()
+        } else if let Some((done, total)) = self.indexing {
+            i18n::fill(tr("{} indexing {}/{}"), &[&self.spinner(), &done, &total])
+        } else if self.tools_running {
+            let running: Vec<&str> = self
+                .messages
+                .iter()
+                .filter_map(|m| m.tool.as_ref())
+                .filter(|block| block.output.is_none())
+                .map(|block| block.call.name.as_str())
+                .collect();
+            i18n::fill(
+                tr("{} executing {}"),
+                &[&self.spinner(), &running.join(", ")],
+            )
+        } else if let Some(until) = self.rate_limited_until
+            && self.is_loading
+        {
+            let wait = until.saturating_duration_since(Instant::now());
+            i18n::fill(
+                tr("rate limited, sending in {}s"),
+                &[&wait.as_secs_f32().ceil()],
+            )
+        } else if self.is_loading {
+            let phase = match self.messages.iter().rfind(|m| !m.queued) {
+                Some(msg) if self.last_chunk_at.is_some() && msg.role == "AI" => {
+                    i18n::fill(tr("streaming {}"), &[&self.stream_speed(msg).trim_end()])
+                }
+                _ if self.connected => tr("waiting for first token").to_string(),
+                _ => tr("connecting").to_string(),
+            };
+            let mut activity = format!("{} {}", self.spinner(), phase.trim_end());
+            if let Some(started) = self.turn_started {
+                activity.push_str(&format!(" · {}s", started.elapsed().as_secs()));
+            }
+            if let Some(key) = self.key_for(keymap::Command::Cancel) {
+                activity.push_str(&format!(" · {}", i18n::fill(tr("{} cancels"), &[&key])));
+            }
+            activity
+        } else {
+            tr("idle").to_string()
         };
-        self.list_state.select(Some(i));
+
+        let mut spans = vec![
+            Span::styled(
+                mode,
+                Style::default()
+                    .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+                    .fg(mode_color),
+            ),
+            Span::raw(" "),
+            Span::raw(self.model().to_string()),
+            separator.clone(),
+            Span::raw(ai::provider(self.provider())),
+            separator.clone(),
+            Span::styled(activity, Style::default().fg(self.theme.accent)),
+        ];
+        if let Some(error) = &self.last_error {
+            let first_line = error.lines().next().unwrap_or_default();
+            let error = render::truncate(first_line, MAX_ERROR_WIDTH);
+            spans.push(separator.clone());
+            spans.push(Span::styled(
+                format!("✗ {}", error),
+                Style::default().fg(self.theme.error),
+            ));
+        }
+        if let Some((text, _)) = &self.notification {
+            spans.push(separator);
+            spans.push(Span::styled(
+                text.clone(),
+                Style::default().fg(self.theme.success),
+            ));
+        }
+        frame.render_widget(
+            Paragraph::new(Line::from(spans)).style(Style::default().fg(self.theme.text)),
+            area,
+        );
+    }
+
+    fn draw_sidebar(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        let sidebar_block = Block::default()
+            .borders(Borders::ALL)
+            .title(tr("Sidebar"))
+            .style(Style::default().fg(self.theme.sidebar))
+            .border_style(self.focus_style(Focus::Sidebar));
+
+        let inner_area = sidebar_block.inner(area);
+        frame.render_widget(sidebar_block, area);
+
+        // Saved sessions to pick from while the sidebar has the focus
+        let sessions_height = if self.focus == Focus::Sidebar {
+            (self.sidebar_sessions.len() as u16 + 2).min(inner_area.height / 2)
+        } else {
+            0
+        };
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(12), // Session and stats
+                Constraint::Length(self.branches_height()),
+                Constraint::Length(sessions_height),
+                Constraint::Min(0), // Keybindings
+            ])
+            .split(inner_area);
+
+        // Stats
+        let stats_text = vec![
+            Line::from(Span::styled(
+                tr("Session:"),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(format!(
+                "{}{}{}",
+                self.title.as_deref().unwrap_or(tr("Untitled")),
+                self.template
+                    .as_ref()
+                    .map_or(String::new(), |name| format!(" · {}", name)),
+                if self.pinned { " (pinned)" } else { "" }
+            )),
+            Line::from(""),
+            Line::from(Span::styled(
+                tr("Model:"),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(match &self.config.active_profile {
+                Some(profile) => format!("{} ({})", self.model(), profile),
+                None => self.model().to_string(),
+            }),
+            Line::from(""),
+            Line::from(Span::styled(
+                tr("Tokens:"),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(i18n::fill(tr("Prompt: {}"), &[&self.total_prompt_tokens])),
+            Line::from(i18n::fill(tr("Cached: {}"), &[&self.total_cached_tokens])),
+            Line::from(i18n::fill(tr("Resp:   {}"), &[&self.total_response_tokens])),
+            Line::from(i18n::fill(tr("Total:  {}"), &[&self.total_tokens])),
+        ];
+        frame.render_widget(Paragraph::new(stats_text), layout[0]);
+        self.draw_branches(frame, layout[1]);
+        if sessions_height > 0 {
+            let items: Vec<ListItem> = self
+                .sidebar_sessions
+                .iter()
+                .map(|session| {
+                    ListItem::new(format!(
+                        "{}{}",
+                        if Some(session.id) == self.session {
+                            "● "
+                        } else {
+                            "  "
+                        },
+                        session.title.as_deref().unwrap_or(tr("Untitled"))
+                    ))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().title(Span::styled(
+                    tr("Sessions:"),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(list, layout[2], &mut self.sidebar_state);
+        }
+
+        // Keybindings for the current mode, generated from the keymap
+        let (mode, bindings) = match self.focus {
+            Focus::Input => (tr("Editing"), &self.keymap.editing),
+            Focus::Chat | Focus::Split => (tr("Normal"), &self.keymap.normal),
+            Focus::Sidebar => (tr("Sessions"), &self.keymap.sidebar),
+        };
+        let keys: Vec<String> = bindings
+            .iter()
+            .map(|(_, chords)| {
+                chords
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect();
+        let width = keys.iter().map(|k| k.width()).max().unwrap_or(0);
+        let mut help_text = vec![Line::from(Span::styled(
+            i18n::fill(tr("Keys ({}):"), &[&mode]),
+            Style::default().add_modifier(Modifier::BOLD),
+        ))];
+        for ((command, _), keys) in bindings.iter().zip(&keys) {
+            help_text.push(Line::from(format!(
+                "{} {}",
+                render::pad(keys, width),
+                command.label()
+            )));
+        }
+        frame.render_widget(Paragraph::new(help_text), layout[3]);
+    }
+
+    /// Rows of the sidebar's branch tree; none until the conversation is forked
+    fn branches_height(&self) -> u16 {
+        match self.branches.len() {
+            1 => 0,
+            n => n as u16 + 2,
+        }
+    }
+
+    fn draw_branches(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        if self.branches.len() == 1 {
+            return;
+        }
+        let mut lines = vec![Line::from(Span::styled(
+            tr("Branches:"),
+            Style::default().add_modifier(Modifier::BOLD),
+        ))];
+        for (index, depth) in self.branches.outline() {
+            let current = index == self.branches.current();
+            let count = if current {
+                self.messages.len()
+            } else {
+                self.branches.stored_len(index)
+            };
+            let text = format!(
+                "{}{}{}. {} ({})",
+                if current { "▸ " } else { "  " },
+                "  ".repeat(depth),
+                index + 1,
+                self.branches.branch(index).name,
+                count
+            );
+            lines.push(Line::from(if current {
+                Span::styled(
+                    text,
+                    Style::default()
+                        .fg(self.theme.accent)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else {
+                Span::raw(text)
+            }));
+        }
+        frame.render_widget(Paragraph::new(lines), area);
+    }
+
+    /// The latency trace: each phase of the latest turns with its start, duration and a
+    /// bar placed on the turn's timeline
+    fn draw_trace(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        const LABEL_WIDTH: usize = 36;
+
+        let dim = Style::default().fg(self.theme.dim);
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let width = area.width.saturating_sub(4).min(100);
+        let bar_width = (width as usize).saturating_sub(LABEL_WIDTH + 18).max(10);
+        let mut lines = Vec::new();
+        for trace in self.traces.iter().rev() {
+            if !lines.is_empty() {
+                lines.push(Line::from(""));
+            }
+            let total = trace.end();
+            let at = Local::now()
+                - chrono::Duration::from_std(trace.started.elapsed()).unwrap_or_default();
+            lines.push(Line::from(vec![
+                Span::styled(
+                    i18n::fill(tr("Turn at {}"), &[&(at.format("%H:%M:%S"))]),
+                    bold,
+                ),
+                Span::styled(format!("  {:.2}s", total.as_secs_f64()), dim),
+            ]));
+            let scale = bar_width as f64 / total.as_secs_f64().max(0.001);
+            for span in &trace.spans {
+                let end = span.end.unwrap_or(total);
+                let offset = (span.start.as_secs_f64() * scale) as usize;
+                let length = (((end - span.start).as_secs_f64() * scale) as usize).max(1);
+                let running = if span.end.is_none() { "…" } else { " " };
+                lines.push(Line::from(vec![
+                    Span::styled(format!("  +{:>6.2}s ", span.start.as_secs_f64()), dim),
+                    Span::raw(render::pad(
+                        &render::truncate(&span.label, LABEL_WIDTH),
+                        LABEL_WIDTH,
+                    )),
+                    Span::raw(format!(
+                        "{:>7.2}s{} ",
+                        (end - span.start).as_secs_f64(),
+                        running
+                    )),
+                    Span::raw(" ".repeat(offset.min(bar_width - 1))),
+                    Span::styled(
+                        "█".repeat(length.min(bar_width - offset.min(bar_width - 1))),
+                        Style::default().fg(self.theme.accent),
+                    ),
+                ]));
+            }
+        }
+        if lines.is_empty() {
+            lines.push(Line::from(Span::styled(tr("No turns yet"), dim)));
+        }
+
+        let height = (lines.len() as u16 + 2).min(area.height);
+        let popup = ratatui::layout::Rect {
+            x: area.x + area.width.saturating_sub(width) / 2,
+            y: area.y,
+            width,
+            height,
+        };
+        let close = self
+            .key_for(keymap::Command::Trace)
+            .map_or(String::new(), |key| i18n::fill(tr(" — {} hides"), &[&key]));
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("{}{}", tr("Latency trace"), close))
+                    .style(Style::default().fg(self.theme.text)),
+            ),
+            popup,
+        );
+    }
+
+    /// Centered cheat sheet of every key binding and slash command
+    fn draw_help(&self, frame: &mut Frame) {
+        let heading = Style::default()
+            .add_modifier(Modifier::BOLD)
+            .fg(self.theme.accent);
+        let key_style = Style::default().add_modifier(Modifier::BOLD);
+
+        // Ctrl+Z undoes while editing by default, so suspending needs leaving that mode
+        let editing_note = keymap::first_key(&self.keymap.editing, keymap::Command::Suspend)
+            .is_none()
+            .then(|| tr("Suspend is not bound while editing, where ctrl+z undoes: leave it first"));
+        let mut lines = Vec::new();
+        for (mode, bindings, note) in [
+            (tr("Normal mode"), &self.keymap.normal, None),
+            (tr("Editing mode"), &self.keymap.editing, editing_note),
+            (tr("Sidebar sessions"), &self.keymap.sidebar, None),
+        ] {
+            lines.push(Line::from(Span::styled(mode, heading)));
+            for (command, chords) in bindings {
+                let keys = chords
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                lines.push(Line::from(vec![
+                    Span::styled(format!("  {}", render::pad(&keys, 16)), key_style),
+                    Span::raw(command.label()),
+                ]));
+            }
+            if let Some(note) = note {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}", note),
+                    Style::default().fg(self.theme.dim),
+                )));
+            }
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(Span::styled(tr("Slash commands"), heading)));
+        for (usage, description) in SLASH_COMMANDS {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<34}", usage), key_style),
+                Span::raw(tr(description)),
+            ]));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            tr("Press any key to close"),
+            Style::default().fg(self.theme.dim),
+        )));
+
+        let area = frame.area();
+        let width = 72.min(area.width);
+        let height = (lines.len() as u16 + 2).min(area.height);
+        let popup = ratatui::layout::Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(tr("Help"))
+                    .style(Style::default().fg(self.theme.text)),
+            ),
+            popup,
+        );
+    }
+
+    fn draw_main_chat(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        let layout = self.screen_layout();
+        let (messages_area, input_box) = layout.chat(area);
+
+        // The chat, and the split pane beside it
+        let panes = match self.split {
+            Some(_) => Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(messages_area),
+            None => [messages_area].into(),
+        };
+
+        // Inside the list's borders, its padding and the gutter
+        let borders = if self.borders() == Borders::NONE {
+            0
+        } else {
+            2
+        };
+        let pane_width = panes.iter().map(|pane| pane.width).min().unwrap_or(0);
+        let padding = layout.padding_for(pane_width.saturating_sub(borders + GUTTER_WIDTH));
+        // 0 would mean no wrapping at all
+        let width = pane_width
+            .saturating_sub(borders + 2 * padding + GUTTER_WIDTH)
+            .max(1) as usize;
+        if width != self.wrap_width {
+            self.reflow(width);
+        }
+
+        let mut list_items = self.chat_items(&self.messages, self.model());
+        if self.visual_anchor.is_some()
+            && let Some(items) = self.selected_items()
+        {
+            let selection = Style::default().add_modifier(Modifier::REVERSED);
+            list_items = list_items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    if items.contains(&i) {
+                        item.style(selection)
+                    } else {
+                        item
+                    }
+                })
+                .collect();
+        }
+
+        if self.should_auto_scroll && !list_items.is_empty() {
+            self.list_state.select(Some(list_items.len() - 1));
+        }
+
+        let title = match self.split {
+            Some(_) => format!("{} — {}", tr("Chat"), self.model()),
+            None => tr("Chat").to_string(),
+        };
+        let block = Block::default()
+            .borders(self.borders())
+            .title(title)
+            .border_style(self.focus_style(Focus::Chat))
+            .padding(Padding::horizontal(padding));
+
+        let messages_list = List::new(list_items)
+            .block(block)
+            .style(Style::default().fg(self.theme.text))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(messages_list, panes[0], &mut self.list_state);
+        if let Some(&area) = panes.get(1) {
+            self.draw_split(frame, area, padding);
+        }
+
+        let input_block_style = if self.focus == Focus::Input {
+            Style::default().fg(self.theme.input_active)
+        } else {
+            Style::default().fg(self.theme.input_inactive)
+        };
+
+        let title = match self.attachments.len() {
+            0 => tr("Input").to_string(),
+            1 => tr("Input — 1 attachment").to_string(),
+            n => i18n::fill(tr("Input — {} attachments"), &[&n]),
+        };
+        self.textarea.set_block(
+            Block::default()
+                .borders(self.borders())
+                .title(title)
+                .style(input_block_style),
+        );
+        frame.render_widget(&self.textarea, input_box);
+        self.draw_completion(frame, input_box);
+    }
+
+    /// The completion popup at the start of the word being completed in the input `area`:
+    /// above the cursor when there is room, else below it
+    fn draw_completion(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        if self.focus != Focus::Input {
+            return;
+        }
+        let Some(popup) = &mut self.completion else {
+            return;
+        };
+        let (row, _) = self.textarea.cursor();
+        let before: String = self.textarea.lines()[row]
+            .chars()
+            .take(popup.token.start)
+            .collect();
+        let screen = frame.area();
+        let width = popup
+            .items
+            .iter()
+            .map(|item| item.text.width() + item.detail.width() + 2)
+            .max()
+            .unwrap_or(0)
+            .min(60) as u16
+            + 2;
+        let width = width.min(screen.width);
+        let height = (popup.items.len() as u16 + 2).min(screen.height);
+        let cursor_y = (area.y + 1 + row as u16).min(area.bottom().saturating_sub(2));
+        let y = if cursor_y >= screen.y + height {
+            cursor_y - height
+        } else {
+            (cursor_y + 1).min(screen.bottom().saturating_sub(height))
+        };
+        let x = (area.x + 1 + before.width() as u16).min(screen.right().saturating_sub(width));
+        let rect = ratatui::layout::Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        let dim = Style::default().fg(self.theme.dim);
+        let items: Vec<ListItem> = popup
+            .items
+            .iter()
+            .map(|item| {
+                let mut spans = vec![Span::raw(item.text.clone())];
+                if !item.detail.is_empty() {
+                    spans.push(Span::styled(format!("  {}", item.detail), dim));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        frame.render_widget(Clear, rect);
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .style(Style::default().fg(self.theme.accent)),
+            )
+            .style(Style::default().fg(self.theme.text))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, rect, &mut popup.state);
+    }
+
+    fn draw_split(&mut self, frame: &mut Frame, area: ratatui::layout::Rect, padding: u16) {
+        let Some(pane) = &self.split else {
+            return;
+        };
+        let list_items = self.chat_items(&pane.messages, &pane.model);
+
+        let block = Block::default()
+            .borders(self.borders())
+            .title(if pane.loading {
+                format!("{} — {} {}", tr("Split"), pane.model, self.spinner())
+            } else {
+                format!("{} — {}", tr("Split"), pane.model)
+            })
+            .border_style(self.focus_style(Focus::Split))
+            .padding(Padding::horizontal(padding));
+        let count = list_items.len();
+        let list = List::new(list_items)
+            .block(block)
+            .style(Style::default().fg(self.theme.text))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        let Some(pane) = &mut self.split else {
+            return;
+        };
+        if pane.should_auto_scroll && count > 0 {
+            pane.list_state.select(Some(count - 1));
+        }
+        frame.render_stateful_widget(list, area, &mut pane.list_state);
+    }
+
+    /// List items for a chat pane: each message's header, body and a spacer. `model` is
+    /// the one responses aren't labelled with.
+    fn chat_items(&self, messages: &[Message], model: &str) -> Vec<ListItem<'static>> {
+        let mut list_items = Vec::new();
+        for msg in messages {
+            let content_lines = self.message_body(msg);
+            let (color, shade) = match (&msg.tool, msg.role.as_str()) {
+                (Some(_), _) => (self.theme.dim, self.theme.tool_bg),
+                (None, "You") => (self.theme.user, self.theme.user_bg),
+                (None, "AI") => (self.theme.ai, self.theme.ai_bg),
+                (None, "Error") => (self.theme.error, Color::Reset),
+                (None, "Review") => (self.theme.accent, Color::Reset),
+                _ => (self.theme.system, Color::Reset),
+            };
+            let shade = Style::default().bg(shade);
+            // A bar in the role's color down the message, so long transcripts can be skimmed
+            let bar = if self.config.ui.accessible() {
+                " "
+            } else {
+                "▌"
+            };
+            let bar = Span::styled(bar, Style::default().fg(color));
+            let mark = if msg.bookmarked { "★ " } else { "  " };
+            let mark = Span::styled(mark, Style::default().fg(self.theme.accent));
+            let blank = Span::raw("  ");
+
+            let header = match &msg.tool {
+                Some(block) => tool_header(block, self.spinner(), &self.theme),
+                None => {
+                    let mut role_spans = vec![Span::styled(
+                        format!("{}: ", self.config.ui.role_label(&msg.role)),
+                        Style::default().add_modifier(Modifier::BOLD).fg(color),
+                    )];
+                    if let Some(meta) = message_meta(msg, self.config.ui.timestamps(), model) {
+                        role_spans.push(Span::styled(meta, Style::default().fg(self.theme.dim)));
+                    }
+                    Line::from(role_spans)
+                }
+            };
+            list_items.push(ListItem::new(render::decorate(
+                header,
+                vec![bar.clone(), mark],
+                shade,
+            )));
+            for line in content_lines {
+                list_items.push(ListItem::new(render::decorate(
+                    line.clone(),
+                    vec![bar.clone(), blank.clone()],
+                    shade,
+                )));
+            }
+            list_items.push(ListItem::new(Line::from(""))); // Spacer
+        }
+        list_items
+    }
+}
+
+/// A frame for the image preview; the image itself is drawn with the terminal's graphics
+/// protocol after the frame
+fn draw_image_preview(
+    preview: &mut ImagePreview,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let popup = ratatui::layout::Rect {
+        x: area.x + area.width / 10,
+        y: area.y + area.height / 10,
+        width: area.width - area.width / 5,
+        height: area.height - area.height / 5,
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(
+            "{} — o opens, any other key closes",
+            preview.path.display()
+        ))
+        .style(Style::default().fg(theme.text));
+    preview.area = Some(block.inner(popup)).filter(|inner| !inner.is_empty());
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+}
+
+fn draw_approval(
+    prompt: &ApprovalPrompt,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    const MAX_ARG_LINES: usize = 12;
+
+    let Some(index) = prompt.current() else {
+        return;
+    };
+    let call = &prompt.calls[index].0;
+    let dim = Style::default().fg(theme.dim);
+    let key = Style::default()
+        .add_modifier(Modifier::BOLD)
+        .fg(theme.accent);
+
+    let mut lines = vec![Line::from(Span::styled(
+        format!("⚙ {}", call.name),
+        Style::default().add_modifier(Modifier::BOLD),
+    ))];
+    let args = serde_json::from_str::<serde_json::Value>(&call.args)
+        .ok()
+        .and_then(|v| serde_json::to_string_pretty(&v).ok())
+        .unwrap_or_else(|| call.args.clone());
+    let arg_lines: Vec<&str> = args.lines().collect();
+    for line in arg_lines.iter().take(MAX_ARG_LINES) {
+        lines.push(Line::from(Span::styled(format!("│ {}", line), dim)));
+    }
+    if arg_lines.len() > MAX_ARG_LINES {
+        lines.push(Line::from(Span::styled(
+            format!("│ … {} more lines", arg_lines.len() - MAX_ARG_LINES),
+            dim,
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+        Span::styled("y", key),
+        Span::raw(format!(": {}  ", tr("Allow once"))),
+        Span::styled("a", key),
+        Span::raw(format!(
+            ": {}  ",
+            i18n::fill(
+                tr("Always allow {}"),
+                &[&approval::describe(&approval::suggest(call))]
+            )
+        )),
+        Span::styled("n", key),
+        Span::raw(format!(": {}", tr("Deny"))),
+    ]));
+
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width / 4),
+        y: area.y + area.height.saturating_sub(height + 3),
+        width: area.width.saturating_sub(4),
+        height,
+    };
+    let title = i18n::fill(
+        tr("Allow tool call? ({} of {})"),
+        &[&(index + 1), &prompt.calls.len()],
+    );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .style(Style::default().fg(theme.accent)),
+        ),
+        popup,
+    );
+}
+
+fn draw_loop_guard(
+    reason: &str,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let key = Style::default()
+        .add_modifier(Modifier::BOLD)
+        .fg(theme.accent);
+    let lines = vec![
+        Line::from(i18n::fill(tr("Paused: {}."), &[&reason])),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("y", key),
+            Span::raw(format!(": {}  ", tr("Keep going"))),
+            Span::styled("n", key),
+            Span::raw(format!(": {}", tr("Stop here"))),
+        ]),
+    ];
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width / 4),
+        y: area.y + area.height.saturating_sub(height + 3),
+        width: area.width.saturating_sub(4),
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("Tool loop guard"))
+                .style(Style::default().fg(theme.accent)),
+        ),
+        popup,
+    );
+}
+
+fn draw_setup(setup: &Setup, frame: &mut Frame, area: ratatui::layout::Rect, theme: &theme::Theme) {
+    let key = Style::default()
+        .add_modifier(Modifier::BOLD)
+        .fg(theme.accent);
+
+    let lines = vec![
+        Line::from(Span::styled(
+            tr("Welcome to gemchat"),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+        Line::from(tr("No Gemini API key was found. Create one at")),
+        Line::from(Span::styled(
+            "https://aistudio.google.com/apikey",
+            Style::default().fg(theme.link),
+        )),
+        Line::from("and paste it below. It is checked, then saved to the system keyring."),
+        Line::from(""),
+    ];
+    let status = if setup.checking {
+        Line::from(Span::styled(
+            tr("Checking the key…"),
+            Style::default().fg(theme.accent),
+        ))
+    } else if let Some(error) = &setup.error {
+        Line::from(Span::styled(
+            format!("✗ {}", i18n::fill(tr("Key rejected: {}"), &[error])),
+            Style::default().fg(theme.error),
+        ))
+    } else {
+        Line::from(vec![
+            Span::styled("Enter", key),
+            Span::raw(format!(": {}  ", tr("Check and save"))),
+            Span::styled("Esc", key),
+            Span::raw(format!(
+                ": {}",
+                tr("Continue without a key (mock responses)")
+            )),
+        ])
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(tr("Setup"))
+        .style(Style::default().fg(theme.text));
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints(vec![
+            Constraint::Length(lines.len() as u16),
+            Constraint::Length(3),
+            Constraint::Min(0),
+        ])
+        .split(inner);
+
+    frame.render_widget(Paragraph::new(lines), layout[0]);
+    frame.render_widget(&setup.input, layout[1]);
+    frame.render_widget(Paragraph::new(status).wrap(Wrap { trim: false }), layout[2]);
+}
+
+fn draw_commit(
+    draft: &CommitDraft,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let key = Style::default()
+        .add_modifier(Modifier::BOLD)
+        .fg(theme.accent);
+    let status = if draft.committing {
+        Line::from(Span::styled(
+            tr("Committing…"),
+            Style::default().fg(theme.accent),
+        ))
+    } else if let Some(error) = &draft.error {
+        Line::from(Span::styled(
+            format!("✗ {}", error),
+            Style::default().fg(theme.error),
+        ))
+    } else {
+        Line::from(vec![
+            Span::styled("Ctrl+S", key),
+            Span::raw(format!(": {}  ", tr("Commit"))),
+            Span::styled("Esc", key),
+            Span::raw(format!(": {}", tr("Cancel"))),
+        ])
+    };
+
+    let area = area.inner(ratatui::layout::Margin::new(2, 1));
+    frame.render_widget(Clear, area);
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Min(3), Constraint::Length(1)])
+        .split(area);
+    frame.render_widget(&draft.input, layout[0]);
+    frame.render_widget(Paragraph::new(status).wrap(Wrap { trim: false }), layout[1]);
+}
+
+fn draw_code_actions(
+    actions: &CodeActions,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    const MAX_CODE_LINES: usize = 12;
+
+    let key = Style::default()
+        .add_modifier(Modifier::BOLD)
+        .fg(theme.accent);
+    let dim = Style::default().fg(theme.dim);
+    let code: Vec<&str> = actions.block.code.lines().collect();
+    let mut lines: Vec<Line> = code
+        .iter()
+        .take(MAX_CODE_LINES)
+        .map(|line| Line::from(Span::styled(format!("│ {}", line), dim)))
+        .collect();
+    if code.len() > MAX_CODE_LINES {
+        lines.push(Line::from(Span::styled(
+            format!("│ … {} more lines", code.len() - MAX_CODE_LINES),
+            dim,
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(match &actions.step {
+        CodeStep::Menu if diagram::MERMAID_LANGS.contains(&actions.block.lang.as_str()) => {
+            Line::from(vec![
+                Span::styled("d", key),
+                Span::raw(format!(": {}  ", tr("Render diagram"))),
+                Span::styled("c", key),
+                Span::raw(format!(": {}  ", tr("Copy"))),
+                Span::styled("s", key),
+                Span::raw(format!(": {}  ", tr("Save to file"))),
+                Span::styled("Esc", key),
+                Span::raw(format!(": {}", tr("Close"))),
+            ])
+        }
+        CodeStep::Menu => Line::from(vec![
+            Span::styled("c", key),
+            Span::raw(format!(": {}  ", tr("Copy"))),
+            Span::styled("s", key),
+            Span::raw(format!(": {}  ", tr("Save to file"))),
+            Span::styled("r", key),
+            Span::raw(format!(": {}  ", tr("Run"))),
+            Span::styled("Esc", key),
+            Span::raw(format!(": {}", tr("Close"))),
+        ]),
+        CodeStep::Save(_) => Line::from(vec![
+            Span::styled("Enter", key),
+            Span::raw(format!(": {}  ", tr("Save"))),
+            Span::styled("Esc", key),
+            Span::raw(format!(": {}", tr("Cancel"))),
+        ]),
+        CodeStep::ConfirmRun => Line::from(vec![
+            Span::raw(tr("Run this as a command? ")),
+            Span::styled("y", key),
+            Span::raw(format!(": {}  ", tr("Run"))),
+            Span::styled("n", key),
+            Span::raw(format!(": {}", tr("Cancel"))),
+        ]),
+    });
+    if let Some(error) = &actions.error {
+        lines.push(Line::from(Span::styled(
+            format!("✗ {}", error),
+            Style::default().fg(theme.error),
+        )));
+    }
+
+    let input_height = if matches!(actions.step, CodeStep::Save(_)) {
+        3
+    } else {
+        0
+    };
+    let height = (lines.len() as u16 + 2 + input_height).min(area.height);
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width),
+        y: area.y + (area.height - height) / 2,
+        width: area.width.saturating_sub(4),
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    let title = match actions.block.lang.as_str() {
+        "" => tr("Code block").to_string(),
+        lang => i18n::fill(tr("Code block ({})"), &[&lang]),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .style(Style::default().fg(theme.accent));
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Min(0), Constraint::Length(input_height)])
+        .split(inner);
+    frame.render_widget(
+        Paragraph::new(lines).style(Style::default().fg(theme.text)),
+        layout[0],
+    );
+    if let CodeStep::Save(input) = &actions.step {
+        frame.render_widget(&**input, layout[1]);
     }
+}
 
-    fn scroll_to_bottom(&mut self) {
-        let count = self.total_list_items();
-        if count > 0 {
-            self.list_state.select(Some(count - 1));
+fn draw_history_search(
+    search: &mut HistorySearch,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width),
+        y: area.y + 1.min(area.height),
+        width: area.width.saturating_sub(4),
+        height: area.height.saturating_sub(2),
+    };
+    frame.render_widget(Clear, popup);
+    let [input_area, hits_area, preview_area] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Percentage(40),
+        ])
+        .areas(popup);
+    frame.render_widget(&search.input, input_area);
+
+    let dim = Style::default().fg(theme.dim);
+    let items: Vec<ListItem> = search
+        .hits
+        .iter()
+        .map(|hit| {
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{} ", hit.created.format("%Y-%m-%d")), dim),
+                Span::styled(
+                    format!("{} · ", hit.title.as_deref().unwrap_or(tr("Untitled"))),
+                    Style::default().fg(theme.accent),
+                ),
+                Span::styled(
+                    format!("{}: ", hit.role),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(hit.snippet.replace('\n', " ")),
+            ]))
+        })
+        .collect();
+    let title = match &search.error {
+        Some(error) => i18n::fill(tr("Search failed: {}"), &[error]),
+        None if search.input.is_empty() => tr("Type to search").to_string(),
+        None => i18n::fill(
+            tr("{} matches — ↑/↓: Move, Enter: Open, Esc: Close"),
+            &[&search.hits.len()],
+        ),
+    };
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .style(Style::default().fg(theme.accent)),
+        )
+        .style(Style::default().fg(theme.text))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, hits_area, &mut search.state);
+
+    let mut lines = Vec::new();
+    for (i, msg) in search.preview.iter().enumerate() {
+        let style = if i == search.preview_at {
+            Style::default().fg(theme.text)
+        } else {
+            dim
+        };
+        lines.push(Line::from(Span::styled(
+            format!("{}:", msg.role),
+            style.add_modifier(Modifier::BOLD),
+        )));
+        // The context around the match, not the whole of long answers
+        for line in msg
+            .content
+            .lines()
+            .take(if i == search.preview_at { 12 } else { 3 })
+        {
+            lines.push(Line::from(Span::styled(line.to_string(), style)));
         }
+        lines.push(Line::from(""));
     }
+    let preview = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(tr("Preview"))
+            .style(Style::default().fg(theme.accent)),
+    );
+    frame.render_widget(preview, preview_area);
+}
 
-    fn total_list_items(&self) -> usize {
-        let mut count = 0;
-        for msg in &self.messages {
-            count += 1; // Header
-            count += parse_markdown(&msg.content, &self.ps, &self.ts).len(); // Content lines
-            count += 1; // Spacer
+fn draw_templates(
+    picker: &mut TemplatePicker,
+    templates: &std::collections::BTreeMap<String, config::Template>,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let dim = Style::default().fg(theme.dim);
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mut items = vec![ListItem::new(Line::from(vec![
+        Span::styled(tr("Blank"), bold),
+        Span::styled("  the configured settings", dim),
+    ]))];
+    items.extend(picker.names.iter().map(|name| {
+        let template = &templates[name];
+        let mut details: Vec<String> = template.description.iter().cloned().collect();
+        if let Some(model) = &template.model {
+            details.push(model.clone());
+        }
+        if !template.files.is_empty() {
+            details.push(format!("{} pinned files", template.files.len()));
+        }
+        ListItem::new(Line::from(vec![
+            Span::styled(name.clone(), bold),
+            Span::styled(format!("  {}", details.join(" · ")), dim),
+        ]))
+    }));
+
+    let height = (items.len() as u16 + 2).min(area.height);
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width),
+        y: area.y + (area.height - height) / 2,
+        width: area.width.saturating_sub(4),
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("New Session — j/k: Move, Enter: Start, Esc: Close"))
+                .style(Style::default().fg(theme.accent)),
+        )
+        .style(Style::default().fg(theme.text))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, popup, &mut picker.state);
+}
+
+fn draw_bookmarks(
+    picker: &mut BookmarkPicker,
+    messages: &[Message],
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let items: Vec<ListItem> = picker
+        .messages
+        .iter()
+        .map(|&i| {
+            let msg = &messages[i];
+            let preview = match &msg.tool {
+                Some(block) => format!("{} {}", block.call.name, tool_summary(&block.call.args)),
+                None => msg
+                    .content
+                    .lines()
+                    .find(|l| !l.trim().is_empty())
+                    .unwrap_or("")
+                    .to_string(),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("#{:<4} ", i + 1), Style::default().fg(theme.dim)),
+                Span::styled(
+                    format!("{}: ", msg.role),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(preview),
+            ]))
+        })
+        .collect();
+
+    let height = (items.len() as u16 + 2).min(area.height);
+    let popup = ratatui::layout::Rect {
+        x: area.x + 2.min(area.width),
+        y: area.y + (area.height - height) / 2,
+        width: area.width.saturating_sub(4),
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("Bookmarks — j/k: Move, Enter: Jump, Esc: Close"))
+                .style(Style::default().fg(theme.accent)),
+        )
+        .style(Style::default().fg(theme.text))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, popup, &mut picker.state);
+}
+
+fn draw_audit(
+    view: &mut AuditView,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Min(3), Constraint::Length(10)])
+        .split(area);
+
+    let dim = Style::default().fg(theme.dim);
+    let items: Vec<ListItem> = view
+        .entries
+        .iter()
+        .map(|entry| {
+            let (status, color) = match entry.status {
+                tools::Status::Ok | tools::Status::Exit(0) => ("ok".to_string(), theme.success),
+                tools::Status::Exit(code) => (format!("exit {}", code), theme.error),
+                tools::Status::Signal => ("killed".to_string(), theme.error),
+                tools::Status::Error => ("error".to_string(), theme.error),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    entry.timestamp.format("%Y-%m-%d %H:%M:%S ").to_string(),
+                    dim,
+                ),
+                Span::styled(
+                    format!("{:<14}", entry.tool),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(format!("{:<8}", status), Style::default().fg(color)),
+                Span::raw(tool_summary(&entry.args.to_string())),
+            ]))
+        })
+        .collect();
+
+    let title = i18n::fill(
+        tr("Audit log ({} entries) — j/k: Move, g/G: Top/Bottom, Esc: Close"),
+        &[&view.entries.len()],
+    );
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, layout[0], &mut view.state);
+
+    let details = match view.state.selected().and_then(|i| view.entries.get(i)) {
+        Some(entry) => {
+            let mut lines = vec![
+                Line::from(format!(
+                    "{} — {:?}, approval: {:?}",
+                    entry.timestamp.to_rfc3339(),
+                    entry.status,
+                    entry.approval
+                )),
+                Line::from(Span::styled(
+                    format!(
+                        "output: {} bytes, sha256 {}",
+                        entry.output_bytes, entry.output_sha256
+                    ),
+                    dim,
+                )),
+            ];
+            let args = serde_json::to_string_pretty(&entry.args).unwrap_or_default();
+            lines.extend(args.lines().map(|l| Line::from(l.to_string())));
+            lines
         }
-        count
+        None => vec![Line::from(Span::styled(
+            tr("No tool calls recorded yet"),
+            dim,
+        ))],
+    };
+    frame.render_widget(
+        Paragraph::new(details).block(Block::default().borders(Borders::ALL).title(tr("Details"))),
+        layout[1],
+    );
+}
+
+/// `rows` summed by day (oldest first), session (latest first) or model (costliest first)
+fn usage_groups(
+    rows: &[store::UsageRow],
+    by: StatsGroup,
+    pricing: &HashMap<String, config::Price>,
+) -> Vec<UsageGroup> {
+    let mut groups: Vec<UsageGroup> = Vec::new();
+    for row in rows {
+        let label = match by {
+            StatsGroup::Day => row.timestamp.format("%Y-%m-%d").to_string(),
+            StatsGroup::Session => match (&row.title, row.session) {
+                (Some(title), _) => title.clone(),
+                (None, Some(id)) => i18n::fill(tr("Session {}"), &[&id]),
+                (None, None) => tr("No session").to_string(),
+            },
+            StatsGroup::Model => row.model.clone(),
+        };
+        let cost = pricing::cost(
+            pricing,
+            &row.model,
+            row.prompt_tokens,
+            row.cached_tokens,
+            row.total_tokens,
+        );
+        // Rows come oldest first, so a group is most likely near the end
+        let index = match groups.iter().rposition(|g| g.label == label) {
+            Some(index) => index,
+            None => {
+                groups.push(UsageGroup {
+                    label,
+                    requests: 0,
+                    prompt_tokens: 0,
+                    total_tokens: 0,
+                    cost: Some(0.0),
+                });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index];
+        group.requests += 1;
+        group.prompt_tokens += row.prompt_tokens;
+        group.total_tokens += row.total_tokens;
+        group.cost = group.cost.zip(cost).map(|(a, b)| a + b);
+    }
+    match by {
+        StatsGroup::Day => {}
+        StatsGroup::Session => groups.reverse(),
+        StatsGroup::Model => groups.sort_by(|a, b| {
+            let cost = |g: &UsageGroup| g.cost.unwrap_or(f64::MAX);
+            cost(b).total_cmp(&cost(a))
+        }),
     }
+    groups
+}
 
-    fn draw(&mut self, frame: &mut Frame) {
-        // Main Layout: Left Sidebar (25 chars) | Right Main (Min 0)
-        let main_layout = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Length(25), Constraint::Min(0)])
-            .split(frame.area());
+/// USD with enough decimals to tell small amounts apart
+/// What the paused tool loop prompt says tripped
+fn loop_guard_reason(tripped: &approval::Tripped) -> String {
+    match tripped {
+        approval::Tripped::Repeated { tool, times } => i18n::fill(
+            tr("{} was called {} times with the same arguments"),
+            &[tool, times],
+        ),
+        approval::Tripped::Rounds(rounds) => {
+            i18n::fill(tr("{} rounds of tool calls for this question"), &[rounds])
+        }
+        approval::Tripped::Cost(cost) => i18n::fill(
+            tr("this question has cost about {} so far"),
+            &[&format_cost(Some(*cost))],
+        ),
+    }
+}
 
-        // Sidebar
-        let sidebar_area = main_layout[0];
-        let main_area = main_layout[1];
+fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(cost) if cost < 0.01 => format!("${:.4}", cost),
+        Some(cost) => format!("${:.2}", cost),
+        None => "unknown".to_string(),
+    }
+}
 
-        self.draw_sidebar(frame, sidebar_area);
-        self.draw_main_chat(frame, main_area);
+/// A token count shortened for narrow columns, e.g. "12.3k"
+fn format_tokens(tokens: i64) -> String {
+    match tokens {
+        n if n >= 1_000_000 => format!("{:.1}M", n as f64 / 1_000_000.0),
+        n if n >= 1_000 => format!("{:.1}k", n as f64 / 1_000.0),
+        n => n.to_string(),
     }
+}
 
-    fn draw_sidebar(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
-        let sidebar_block = Block::default()
-            .borders(Borders::ALL)
-            .title("Sidebar")
-            .style(Style::default().fg(Color::Cyan));
+fn draw_stats(
+    view: &mut StatsView,
+    pricing: &HashMap<String, config::Price>,
+    frame: &mut Frame,
+    area: ratatui::layout::Rect,
+    theme: &theme::Theme,
+) {
+    const DAYS: i64 = 30;
+    const LABEL_WIDTH: usize = 28;
 
-        let inner_area = sidebar_block.inner(area);
-        frame.render_widget(sidebar_block, area);
+    let [summary_area, spark_area, body_area] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(6),
+            Constraint::Min(5),
+        ])
+        .areas(area);
+    let [list_area, chart_area] = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+        .areas(body_area);
 
-        let layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![
-                Constraint::Length(10), // Stats
-                Constraint::Min(0),     // Keybindings
-            ])
-            .split(inner_area);
+    let dim = Style::default().fg(theme.dim);
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let prompt_tokens: i64 = view.rows.iter().map(|r| r.prompt_tokens).sum();
+    let cached_tokens: i64 = view.rows.iter().map(|r| r.cached_tokens).sum();
+    let total_tokens: i64 = view.rows.iter().map(|r| r.total_tokens).sum();
+    let cost = view.rows.iter().try_fold(0.0, |sum, r| {
+        pricing::cost(
+            pricing,
+            &r.model,
+            r.prompt_tokens,
+            r.cached_tokens,
+            r.total_tokens,
+        )
+        .map(|c| sum + c)
+    });
+    let mut summary = vec![
+        Span::styled(format!("{} requests", view.rows.len()), bold),
+        Span::raw(format!(
+            "  ·  {} prompt, {} output tokens  ·  ",
+            format_tokens(prompt_tokens),
+            format_tokens(total_tokens - prompt_tokens)
+        )),
+        Span::styled(
+            format!("{} estimated", format_cost(cost)),
+            bold.fg(theme.accent),
+        ),
+    ];
+    if cached_tokens > 0 {
+        let saved = view.rows.iter().try_fold(0.0, |sum, r| {
+            pricing::cache_savings(pricing, &r.model, r.cached_tokens).map(|s| sum + s)
+        });
+        summary.push(Span::styled(
+            format!(
+                "  ·  {} from cache, {} saved",
+                format_tokens(cached_tokens),
+                format_cost(saved)
+            ),
+            dim,
+        ));
+    }
+    let summary = Line::from(summary);
+    frame.render_widget(
+        Paragraph::new(summary).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(tr("Usage — d/s/m or Tab: Group, j/k: Move, Esc: Close")),
+        ),
+        summary_area,
+    );
 
-        // Stats
-        let stats_text = vec![
-            Line::from(Span::styled(
-                "Model:",
-                Style::default().add_modifier(Modifier::BOLD),
-            )),
-            Line::from("Gemini 3 Flash"),
-            Line::from(""),
-            Line::from(Span::styled(
-                "Tokens:",
-                Style::default().add_modifier(Modifier::BOLD),
-            )),
-            Line::from(format!("Prompt: {}", self.total_prompt_tokens)),
-            Line::from(format!("Resp:   {}", self.total_response_tokens)),
-            Line::from(format!(
-                "Total:  {}",
-                self.total_prompt_tokens + self.total_response_tokens
-            )),
-        ];
-        frame.render_widget(Paragraph::new(stats_text), layout[0]);
+    // Tokens per day over the last month, empty days included
+    let today = Local::now().date_naive();
+    let mut daily = vec![0u64; DAYS as usize];
+    for row in &view.rows {
+        let age = (today - row.timestamp.date_naive()).num_days();
+        if (0..DAYS).contains(&age) {
+            daily[(DAYS - 1 - age) as usize] += row.total_tokens.max(0) as u64;
+        }
+    }
+    frame.render_widget(
+        Sparkline::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(i18n::fill(tr("Tokens per day, last {} days"), &[&DAYS])),
+            )
+            .data(&daily)
+            .style(Style::default().fg(theme.accent)),
+        spark_area,
+    );
 
-        // Keybindings
-        let help_text = vec![
-            Line::from(Span::styled(
-                "Keys:",
-                Style::default().add_modifier(Modifier::BOLD),
-            )),
-            Line::from("Esc: Normal Mode"),
-            Line::from("i:   Edit Mode"),
-            Line::from("Ent: Send"),
-            Line::from("j/k: Scroll"),
-            Line::from("G:   Bottom"),
-            Line::from("c:   Clear"),
-            Line::from("q:   Quit"),
-        ];
-        frame.render_widget(Paragraph::new(help_text), layout[1]);
+    let groups = usage_groups(&view.rows, view.by, pricing);
+    let by = match view.by {
+        StatsGroup::Day => tr("day"),
+        StatsGroup::Session => tr("session"),
+        StatsGroup::Model => tr("model"),
+    };
+    let items: Vec<ListItem> = groups
+        .iter()
+        .map(|g| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!(
+                        "{} ",
+                        render::pad(&render::truncate(&g.label, LABEL_WIDTH), LABEL_WIDTH)
+                    ),
+                    bold,
+                ),
+                Span::styled(format!("{:>5} req ", g.requests), dim),
+                Span::raw(format!(
+                    "{:>7} in {:>7} out ",
+                    format_tokens(g.prompt_tokens),
+                    format_tokens(g.total_tokens - g.prompt_tokens)
+                )),
+                Span::styled(format_cost(g.cost), Style::default().fg(theme.accent)),
+            ]))
+        })
+        .collect();
+    if let Some(selected) = view.state.selected() {
+        view.state
+            .select(Some(selected.min(groups.len().saturating_sub(1))));
     }
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(i18n::fill(tr("By {}"), &[&by])),
+        )
+        .style(Style::default().fg(theme.text))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, list_area, &mut view.state);
 
-    fn draw_main_chat(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
-        let layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![
-                Constraint::Min(1),    // Messages area
-                Constraint::Length(3), // Input area
-            ])
-            .split(area);
+    // The costliest groups, in hundredths of a cent so cheap ones still get a bar
+    let mut costly: Vec<(&str, f64)> = groups
+        .iter()
+        .filter_map(|g| Some((g.label.as_str(), g.cost?)))
+        .collect();
+    costly.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let bars: Vec<Bar> = costly
+        .iter()
+        .take(chart_area.height.saturating_sub(2) as usize / 2)
+        .map(|&(label, cost)| {
+            Bar::default()
+                .label(Line::from(render::truncate(label, 16)))
+                .value((cost * 10_000.0).round() as u64)
+                .text_value(format_cost(Some(cost)))
+        })
+        .collect();
+    frame.render_widget(
+        BarChart::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(i18n::fill(tr("Estimated cost by {}"), &[&by])),
+            )
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(1)
+            .bar_style(Style::default().fg(theme.accent))
+            .value_style(Style::default().fg(theme.text))
+            .data(BarGroup::default().bars(&bars)),
+        chart_area,
+    );
+}
 
-        let mut list_items = Vec::new();
-        for (i, msg) in self.messages.iter().enumerate() {
-            let content_lines = parse_markdown(&msg.content, &self.ps, &self.ts);
+/// The message input holding `text`, with undo history for as long as it lives
+fn input_area(text: &str) -> TextArea<'static> {
+    const UNDO_STEPS: usize = 1000;
 
-            let mut role_spans = vec![Span::styled(
-                format!("{}: ", msg.role),
-                Style::default()
-                    .add_modifier(Modifier::BOLD)
-                    .fg(match msg.role.as_str() {
-                        "You" => Color::Blue,
-                        "AI" => Color::Green,
-                        "Error" => Color::Red,
-                        _ => Color::Yellow,
-                    }),
-            )];
+    let mut textarea = TextArea::new(text.lines().map(str::to_string).collect());
+    textarea.set_block(Block::default().borders(Borders::ALL).title(tr("Input")));
+    textarea.set_placeholder_text(tr("Type message... (Enter to send, Esc to quit)"));
+    textarea.set_max_histories(UNDO_STEPS);
+    textarea.move_cursor(tui_textarea::CursorMove::Bottom);
+    textarea.move_cursor(tui_textarea::CursorMove::End);
+    textarea
+}
 
-            if self.is_loading && i == self.messages.len() - 1 && msg.role == "AI" {
-                role_spans.push(Span::styled(
-                    format!(" {} ", SPINNER_FRAMES[self.spinner_index]),
-                    Style::default().fg(Color::Yellow),
+/// Flattens messages into the history block and the turn block, which starts at the latest
+/// question, attachments noted where they were sent
+fn history_blocks(messages: &[Message], config: &config::ToolsConfig) -> Vec<ai::ContextBlock> {
+    let turn_start = messages
+        .iter()
+        .rposition(|m| m.role == "You" && !m.queued)
+        .unwrap_or(messages.len());
+    let mut earlier = "Conversation History:\n".to_string();
+    let mut turn = String::new();
+    // Numbered in the order `history_attachments` sends them
+    let mut attached = 0;
+    for (i, msg) in messages.iter().enumerate() {
+        let text = if i < turn_start {
+            &mut earlier
+        } else {
+            &mut turn
+        };
+        if let Some(block) = &msg.tool {
+            if let Some(output) = &block.output {
+                text.push_str(&format!(
+                    "Tool Result: {}({}) returned:\n{}\n\n",
+                    block.call.name,
+                    block.call.args,
+                    tools::truncate_output(output, config)
                 ));
             }
-
-            let header = Line::from(role_spans);
-            list_items.push(ListItem::new(header));
-
-            for line in content_lines {
-                list_items.push(ListItem::new(line));
+        } else if (!msg.content.is_empty() || !msg.attachments.is_empty()) && !msg.queued {
+            text.push_str(&format!("{}: {}\n", msg.role, msg.content));
+            for attachment in &msg.attachments {
+                attached += 1;
+                text.push_str(&format!("[Attached {} {}]\n", attachment.kind(), attached));
             }
-            list_items.push(ListItem::new(Line::from(""))); // Spacer
+            text.push('\n');
         }
+    }
+    vec![
+        ai::ContextBlock::new(ai::BlockKind::History, earlier),
+        ai::ContextBlock::new(ai::BlockKind::Turn, turn),
+    ]
+}
 
-        if self.should_auto_scroll {
-            if !list_items.is_empty() {
-                self.list_state.select(Some(list_items.len() - 1));
-            }
-        }
+/// Marks the responses that were streaming as complete, to be drawn as they are from now on:
+/// folded, like other long messages, once they have been read as they arrived
+fn settle_streamed(messages: &mut [Message]) {
+    for msg in messages.iter_mut().filter(|m| m.streaming) {
+        msg.streaming = false;
+        msg.unfolded = false;
+        msg.rendered.take();
+    }
+}
 
-        let title = match self.input_mode {
-            InputMode::Editing => "Chat (Editing)",
-            InputMode::Normal => "Chat (Normal)",
-        };
+/// Attachments of the messages in the history, in order
+fn history_attachments(messages: &[Message]) -> Vec<ai::Attachment> {
+    messages
+        .iter()
+        .filter(|m| m.tool.is_none() && !m.queued)
+        .flat_map(|m| m.attachments.iter().cloned())
+        .collect()
+}
 
-        let messages_list = List::new(list_items)
-            .block(Block::default().borders(Borders::ALL).title(title))
-            .style(Style::default().fg(Color::White))
-            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+/// Asks for a short title for a conversation that starts with `question` and `answer`
+fn title_prompt(question: &str, answer: &str) -> String {
+    const MAX_CHARS: usize = 2000;
 
-        frame.render_stateful_widget(messages_list, layout[0], &mut self.list_state);
+    let cut = |text: &str| text.chars().take(MAX_CHARS).collect::<String>();
+    format!(
+        "Write a title of 4 to 6 words for the conversation below. Reply with the title only: no quotes, no markdown, no trailing period.\n\nUser: {}\n\nAI: {}\n",
+        cut(question),
+        cut(answer)
+    )
+}
 
-        let input_block_style = match self.input_mode {
-            InputMode::Editing => Style::default().fg(Color::Yellow),
-            InputMode::Normal => Style::default().fg(Color::DarkGray),
-        };
+/// The title in a model reply, without the quotes and markup models add anyway
+fn clean_title(reply: &str) -> Option<String> {
+    const MAX_CHARS: usize = 80;
 
-        let mut textarea = self.textarea.clone();
-        textarea.set_block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Input")
-                .style(input_block_style),
-        );
+    let line = reply.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.trim_start_matches(['#', '*', ' ']);
+    let title = line
+        .strip_prefix("Title:")
+        .unwrap_or(line)
+        .trim_matches(|c: char| matches!(c, '"' | '\'' | '*' | '`' | '.' | ' '));
+    (!title.is_empty()).then(|| title.chars().take(MAX_CHARS).collect())
+}
 
-        frame.render_widget(&textarea, layout[1]);
+/// An image or audio file for `/attach`, typed by its extension. Files too large to send
+/// are refused before they are read.
+fn read_attachment(path: &Path) -> Result<ai::Attachment> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let mime_type = match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("heic") => "image/heic",
+        Some("heif") => "image/heif",
+        Some("wav") => "audio/wav",
+        Some("mp3") => "audio/mp3",
+        Some("ogg" | "oga" | "opus") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        Some("aac") => "audio/aac",
+        Some("aif" | "aiff") => "audio/aiff",
+        _ => {
+            return Err(color_eyre::eyre::eyre!(i18n::fill(
+                tr(
+                    "{} is not an image (PNG, JPEG, WebP, HEIC) or audio file (WAV, MP3, OGG, FLAC, AAC, AIFF)"
+                ),
+                &[&path.display()]
+            )));
+        }
+    };
+    let read_error = |e| {
+        color_eyre::eyre::eyre!(i18n::fill(
+            tr("Could not read {}: {}"),
+            &[&path.display(), &e]
+        ))
+    };
+    let size = std::fs::metadata(path).map_err(read_error)?.len();
+    if size > MAX_ATTACHMENT_BYTES as u64 {
+        return Err(color_eyre::eyre::eyre!(i18n::fill(
+            tr("The file is {} MB; at most {} MB can be sent"),
+            &[&(size / 1_000_000), &(MAX_ATTACHMENT_BYTES / 1_000_000)]
+        )));
     }
+    let data = std::fs::read(path).map_err(read_error)?;
+    Ok(ai::Attachment {
+        mime_type: mime_type.to_string(),
+        data: data.into(),
+    })
 }
 
-// Markdown Parser with Syntax Highlighting
-fn parse_markdown<'a>(text: &'a str, ps: &SyntaxSet, ts: &ThemeSet) -> Vec<Line<'a>> {
-    let mut lines = Vec::new();
-    let mut in_code_block = false;
-    let mut current_lang = String::new();
-    let mut code_block_content = String::new();
-
-    for line in text.lines() {
-        if line.trim().starts_with("```") {
-            if in_code_block {
-                // End of code block
-                in_code_block = false;
-
-                // Highlight accumulated code
-                let syntax = ps
-                    .find_syntax_by_token(&current_lang)
-                    .unwrap_or_else(|| ps.find_syntax_plain_text());
-
-                // Use a dark theme for better contrast on terminals usually
-                let theme = &ts.themes["base16-ocean.dark"];
-                let mut h = HighlightLines::new(syntax, theme);
-
-                for code_line in LinesWithEndings::from(&code_block_content) {
-                    let ranges: Vec<(syntect::highlighting::Style, &str)> =
-                        h.highlight_line(code_line, ps).unwrap_or_default();
-                    let spans: Vec<Span> = ranges
-                        .into_iter()
-                        .map(|(style, content)| {
-                            Span::styled(content.to_string(), translate_style(style))
-                        })
-                        .collect();
-                    lines.push(Line::from(spans));
-                }
+/// The blocks opening every prompt: the instructions, then the files pinned by the session's
+/// `template` and the repository map
+fn system_blocks(
+    config: &config::Config,
+    template: Option<&config::Template>,
+    template_files: &[ai::ContextBlock],
+    repo_map: Option<&str>,
+) -> Vec<ai::ContextBlock> {
+    let mut prompt = format!(
+        "System Instructions: You are a helpful AI assistant. Answer the user's prompt based on the history below. If the history contains a 'Tool Result', DO NOT call the same tool again. Read the text provided in the Tool Result and use it to answer the user directly. Commands passed to run_command are executed by {}. You have a persistent memory: use `remember` to save lasting user preferences or project facts, and `recall` to look them up when they could matter.\n\n",
+        command_environment(&config.tools),
+    );
+    if let Some(instructions) = &config.system_prompt {
+        prompt.push_str(instructions.trim());
+        prompt.push_str("\n\n");
+    }
+    if let Some(instructions) = template.and_then(|t| t.system_prompt.as_ref()) {
+        prompt.push_str(instructions.trim());
+        prompt.push_str("\n\n");
+    }
+    let mut blocks = vec![ai::ContextBlock::new(ai::BlockKind::System, prompt)];
+    blocks.extend_from_slice(template_files);
+    if let Some(map) = repo_map {
+        blocks.push(ai::ContextBlock::new(
+            ai::BlockKind::Pinned,
+            format!(
+                "Repository Map (files in the working directory and their top-level symbols):\n{}\n",
+                map
+            ),
+        ));
+    }
+    blocks
+}
 
-                // Add closing fence (optional, maybe dim it)
-                lines.push(Line::from(Span::styled(
-                    "```",
-                    Style::default().fg(Color::DarkGray),
-                )));
+/// The session template's approval rules, which replace the configured ones
+fn approval_rules<'a>(
+    config: &'a config::Config,
+    template: Option<&str>,
+) -> &'a config::ApprovalConfig {
+    template
+        .and_then(|name| config.template.get(name))
+        .and_then(|template| template.approval.as_ref())
+        .unwrap_or(&config.tools.approval)
+}
+
+/// The files `template` pins, as prompt blocks. Each is read up to `MAX_PINNED_FILE_BYTES`.
+fn template_files(template: Option<&config::Template>) -> Vec<ai::ContextBlock> {
+    template
+        .map_or(&[][..], |t| &t.files)
+        .iter()
+        .map(|path| {
+            let text = read_pinned(path).unwrap_or_else(|e| format!("(could not be read: {})", e));
+            ai::ContextBlock::new(
+                ai::BlockKind::Pinned,
+                format!(
+                    "Pinned File {}:\n```\n{}\n```\n\n",
+                    path.display(),
+                    text.trim_end()
+                ),
+            )
+        })
+        .collect()
+}
+
+fn read_pinned(path: &Path) -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?
+        .take(MAX_PINNED_FILE_BYTES + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 <= MAX_PINNED_FILE_BYTES {
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    bytes.truncate(MAX_PINNED_FILE_BYTES as usize);
+    Ok(format!(
+        "{}\n… (cut off after {} KB)",
+        String::from_utf8_lossy(&bytes),
+        MAX_PINNED_FILE_BYTES / 1000
+    ))
+}
 
-                code_block_content.clear();
+/// Where `run_command` runs, for the system prompt
+fn command_environment(tools: &config::ToolsConfig) -> String {
+    let sandbox = &tools.sandbox;
+    if sandbox.enabled() {
+        format!(
+            "sh inside a `{}` container with the project directory mounted at /workspace{}",
+            sandbox.image(),
+            if sandbox.network() {
+                ""
             } else {
-                // Start of code block
-                in_code_block = true;
-                current_lang = line.trim().trim_start_matches("```").to_string();
-                lines.push(Line::from(Span::styled(
-                    line,
-                    Style::default().fg(Color::DarkGray),
-                )));
+                " and no network access"
             }
-        } else if in_code_block {
-            code_block_content.push_str(line);
-            code_block_content.push('\n');
+        )
+    } else {
+        format!("{} on {}", tools.shell().name(), std::env::consts::OS)
+    }
+}
+
+/// `gemchat review`: prints the findings grouped by file, colored by severity
+async fn review_command(
+    reference: Option<&str>,
+    staged: bool,
+    config: &config::Config,
+    redactor: &redact::Redactor,
+) -> Result<()> {
+    use crossterm::style::{Color, Stylize};
+    use std::io::IsTerminal;
+
+    let review = review::generate(reference, staged, config, redactor).await?;
+    let color = std::io::stdout().is_terminal();
+    for file in &review.files {
+        if color {
+            println!("{}", file.path.as_str().bold());
         } else {
-            let parts = parse_inline_styles(line);
-            lines.push(Line::from(parts));
+            println!("{}", file.path);
+        }
+        for finding in &file.findings {
+            let label = format!("{:<6}", finding.severity.label());
+            let label = match (color, finding.severity) {
+                (false, _) => label,
+                (true, review::Severity::High) => label.with(Color::Red).bold().to_string(),
+                (true, review::Severity::Medium) => label.with(Color::Yellow).bold().to_string(),
+                (true, review::Severity::Low) => label.with(Color::DarkGrey).bold().to_string(),
+            };
+            let line = finding.line.map_or(String::new(), |n| format!(":{}", n));
+            println!("  {} {:<6} {}", label, line, finding.message);
+        }
+    }
+    if !review.summary.is_empty() {
+        if !review.is_empty() {
+            println!();
         }
+        println!("{}", review.summary);
     }
+    Ok(())
+}
 
-    // Handle unclosed code blocks (during streaming)
-    if in_code_block && !code_block_content.is_empty() {
-        let syntax = ps
-            .find_syntax_by_token(&current_lang)
-            .unwrap_or_else(|| ps.find_syntax_plain_text());
-        let theme = &ts.themes["base16-ocean.dark"];
-        let mut h = HighlightLines::new(syntax, theme);
+/// Runs `call` unless it was declined and records it in the audit log. The outcome is
+/// returned even if recording fails.
+async fn execute_call(
+    call: ai::ToolCall,
+    decision: audit::Approval,
+    tools_config: &config::ToolsConfig,
+    redactor: &redact::Redactor,
+) -> (ai::ToolOutcome, std::io::Result<()>) {
+    tracing::info!(tool = %call.name, ?decision, "running tool");
+    let execution = if decision == audit::Approval::Denied {
+        tools::Execution::error("Error: the user declined to run this tool call".into())
+    } else {
+        tools::execute_tool(&call.name, &call.args, tools_config).await
+    };
+    let entry = audit::Entry::new(
+        &call.name,
+        &redactor.redact(&call.args),
+        execution.status,
+        decision,
+        &execution.output,
+    );
+    let recorded = audit::record(&entry);
+    let outcome = ai::ToolOutcome {
+        call,
+        result: execution.output,
+    };
+    (outcome, recorded)
+}
 
-        for code_line in LinesWithEndings::from(&code_block_content) {
-            let ranges: Vec<(syntect::highlighting::Style, &str)> =
-                h.highlight_line(code_line, ps).unwrap_or_default();
-            let spans: Vec<Span> = ranges
-                .into_iter()
-                .map(|(style, content)| Span::styled(content.to_string(), translate_style(style)))
-                .collect();
-            lines.push(Line::from(spans));
+/// E.g. `[blocked: SAFETY, dangerous content HIGH]` or `[flagged: harassment MEDIUM]`
+fn safety_label(report: &ai::SafetyReport) -> String {
+    let mut parts: Vec<String> = report
+        .ratings
+        .iter()
+        .map(|(category, probability)| {
+            let category = category
+                .strip_prefix("HARM_CATEGORY_")
+                .unwrap_or(category)
+                .to_lowercase()
+                .replace('_', " ");
+            format!("{} {}", category, probability)
+        })
+        .collect();
+    match &report.blocked {
+        Some(reason) => {
+            parts.insert(0, reason.clone());
+            format!("[blocked: {}]", parts.join(", "))
+        }
+        None => format!("[flagged: {}]", parts.join(", ")),
+    }
+}
+
+fn safety_usage() -> String {
+    let names = |names: Vec<&str>| {
+        names
+            .into_iter()
+            .map(|n| format!("`{}`", n))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    i18n::fill(
+        tr("Usage: `/safety [<category> <threshold> | reset]`. Categories: {}. Thresholds: {}."),
+        &[
+            &(names(config::HarmCategory::ALL.map(|c| c.name()).to_vec())),
+            &(names(config::Threshold::ALL.map(|t| t.name()).to_vec())),
+        ],
+    )
+}
+
+/// Dimmed header suffix: timestamp, timing and, when a fallback answered instead of
+/// `model`, the model used
+fn message_meta(msg: &Message, timestamps: config::Timestamps, model: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    if msg.queued {
+        parts.push("queued".to_string());
+    }
+    if msg.interrupted {
+        parts.push("[interrupted]".to_string());
+    }
+    if msg.raw {
+        parts.push("raw".to_string());
+    }
+    if let Some(safety) = &msg.safety {
+        parts.push(safety_label(safety));
+    }
+    if msg.alternatives.len() > 1 {
+        parts.push(format!(
+            "alternative {}/{}",
+            msg.alternative + 1,
+            msg.alternatives.len()
+        ));
+    }
+    if let Some(used) = msg.model.as_deref().filter(|used| *used != model) {
+        parts.push(used.to_string());
+    }
+    if let Some(generation) = &msg.generation {
+        parts.push(generation.clone());
+    }
+    match msg.attachments.len() {
+        0 => {}
+        1 => parts.push("1 attachment".to_string()),
+        n => parts.push(format!("{} attachments", n)),
+    }
+    match timestamps {
+        config::Timestamps::Absolute => {
+            let format = if msg.created.date_naive() == Local::now().date_naive() {
+                "%H:%M"
+            } else {
+                "%Y-%m-%d %H:%M"
+            };
+            parts.push(msg.created.format(format).to_string());
         }
+        config::Timestamps::Relative => parts.push(relative_time(msg.created)),
+        config::Timestamps::Off => {}
+    }
+    if let Some(first) = msg.first_token {
+        parts.push(format!("first token {:.1}s", first.as_secs_f64()));
+    }
+    if let Some(total) = msg.duration {
+        parts.push(format!("total {:.1}s", total.as_secs_f64()));
+    }
+    (!parts.is_empty()).then(|| parts.join(" · "))
+}
+
+fn relative_time(time: DateTime<Local>) -> String {
+    let secs = (Local::now() - time).num_seconds().max(0);
+    match secs {
+        0..10 => "just now".to_string(),
+        10..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => time.format("%Y-%m-%d").to_string(),
+    }
+}
+
+fn tool_header(block: &ToolBlock, spinner: &str, theme: &theme::Theme) -> Line<'static> {
+    let mut spans = vec![
+        Span::styled(
+            format!("⚙ {}", block.call.name),
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(theme.accent),
+        ),
+        Span::raw(format!(": {}", tool_summary(&block.call.args))),
+    ];
+
+    let status = match &block.output {
+        None => format!(" {}", i18n::fill(tr("{} running"), &[&spinner])),
+        Some(_) if block.expanded => " ▾".to_string(),
+        Some(output) => format!(
+            " ▸ {}",
+            i18n::fill(tr("{} lines (Enter to expand)"), &[&output.lines().count()])
+        ),
+    };
+    spans.push(Span::styled(status, Style::default().fg(theme.dim)));
+    if block.truncated {
+        spans.push(Span::styled(
+            " (truncated for model)",
+            Style::default().fg(theme.dim),
+        ));
+    }
+    Line::from(spans)
+}
+
+/// Short one-line description of a call: the first string argument, e.g. the command
+fn tool_summary(args: &str) -> String {
+    const MAX_WIDTH: usize = 60;
+
+    let value = serde_json::from_str::<serde_json::Value>(args).unwrap_or_default();
+    let summary = value
+        .as_object()
+        .and_then(|obj| obj.values().find_map(|v| v.as_str()))
+        .unwrap_or(args);
+    let summary = summary.lines().next().unwrap_or_default();
+
+    render::truncate(summary, MAX_WIDTH)
+}
+
+fn tool_body<'m>(block: &'m ToolBlock, theme: &theme::Theme) -> Vec<Line<'m>> {
+    if !block.expanded {
+        return Vec::new();
     }
 
+    let dim = Style::default().fg(theme.dim);
+    let mut lines = vec![Line::from(Span::styled("  args:", dim))];
+
+    let args = serde_json::from_str::<serde_json::Value>(&block.call.args)
+        .ok()
+        .and_then(|v| serde_json::to_string_pretty(&v).ok())
+        .unwrap_or_else(|| block.call.args.clone());
+    for line in args.lines() {
+        lines.push(Line::from(Span::styled(format!("  │ {}", line), dim)));
+    }
+
+    if let Some(output) = &block.output {
+        lines.push(Line::from(Span::styled("  output:", dim)));
+        for line in output.lines() {
+            lines.push(Line::from(vec![
+                Span::styled("  │ ", dim),
+                style_output_line(&block.call.name, line, theme),
+            ]));
+        }
+    }
     lines
 }
 
-fn translate_style(style: syntect::highlighting::Style) -> Style {
-    Style::default().fg(Color::Rgb(
-        style.foreground.r,
-        style.foreground.g,
-        style.foreground.b,
-    ))
+/// A failed response: its kind and status, the API's message, what usually fixes it and
+/// the `keys` that help
+fn error_body(
+    error: &ai::ErrorDetails,
+    keys: &[String],
+    theme: &theme::Theme,
+) -> Vec<Line<'static>> {
+    let bar = || Span::styled("│ ", Style::default().fg(theme.error));
+    let mut title = error.kind.label().to_string();
+    if let Some(status) = error.status {
+        title.push_str(&format!(" · {}", status));
+    }
+    if let Some(code) = &error.code {
+        title.push_str(&format!(" {}", code));
+    }
+    let mut lines = vec![Line::from(vec![
+        bar(),
+        Span::styled(
+            title,
+            Style::default()
+                .fg(theme.error)
+                .add_modifier(Modifier::BOLD),
+        ),
+    ])];
+    for line in error.message.lines() {
+        lines.push(Line::from(vec![bar(), Span::raw(line.to_string())]));
+    }
+    let advice = match error.kind {
+        ai::ErrorKind::Auth => Some(tr("Check the API key, or enter another one")),
+        ai::ErrorKind::Quota => Some(tr("Wait a moment, or switch to another model")),
+        ai::ErrorKind::Network => Some(tr("Check the connection, then retry")),
+        ai::ErrorKind::Safety => Some(tr("Rephrase the prompt, or see /safety for the thresholds")),
+        ai::ErrorKind::Parse => Some(tr(
+            "The API sent something unexpected; retrying usually helps",
+        )),
+        ai::ErrorKind::Other => None,
+    };
+    if let Some(advice) = advice {
+        lines.push(Line::from(vec![
+            bar(),
+            Span::styled(advice, Style::default().fg(theme.dim)),
+        ]));
+    }
+    if !keys.is_empty() {
+        lines.push(Line::from(vec![
+            bar(),
+            Span::styled(keys.join(" · "), Style::default().fg(theme.accent)),
+        ]));
+    }
+    lines
 }
 
-fn parse_inline_styles(line: &str) -> Vec<Span<'_>> {
-    let mut spans = Vec::new();
-    let mut current_text = String::new();
-    let mut chars = line.chars().peekable();
-    let mut is_bold = false;
+/// Opens `path` with the desktop's default application, detached from the TUI
+fn open_in_desktop(path: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command
+        .arg(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(drop)
+}
 
-    while let Some(c) = chars.next() {
-        if c == '*' && chars.peek() == Some(&'*') {
-            chars.next(); // consume second *
-            if !current_text.is_empty() {
-                spans.push(if is_bold {
-                    Span::styled(
-                        current_text.clone(),
-                        Style::default().add_modifier(Modifier::BOLD),
-                    )
-                } else {
-                    Span::raw(current_text.clone())
-                });
-                current_text.clear();
-            }
-            is_bold = !is_bold;
-        } else {
-            current_text.push(c);
+fn review_body(review: &review::Review, theme: &theme::Theme) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for file in &review.files {
+        lines.push(Line::from(Span::styled(
+            file.path.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        for finding in &file.findings {
+            let color = match finding.severity {
+                review::Severity::High => theme.error,
+                review::Severity::Medium => theme.accent,
+                review::Severity::Low => theme.dim,
+            };
+            let line = finding.line.map_or(String::new(), |n| format!(":{}", n));
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("  {:<6} ", finding.severity.label()),
+                    Style::default().fg(color).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(format!("{:<6} ", line), Style::default().fg(theme.dim)),
+                Span::raw(finding.message.clone()),
+            ]));
         }
     }
-    if !current_text.is_empty() {
-        spans.push(if is_bold {
-            Span::styled(current_text, Style::default().add_modifier(Modifier::BOLD))
-        } else {
-            Span::raw(current_text)
-        });
+    if !review.summary.is_empty() {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(review.summary.clone()));
+    }
+    lines
+}
+
+fn style_output_line<'m>(tool: &str, line: &'m str, theme: &theme::Theme) -> Span<'m> {
+    if tool != "search_google" {
+        return Span::raw(line);
+    }
+
+    // Search results are "N. Title" followed by an indented URL and snippet
+    let trimmed = line.trim_start();
+    let is_title = trimmed
+        .split_once(". ")
+        .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    if is_title {
+        Span::styled(line, Style::default().add_modifier(Modifier::BOLD))
+    } else if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+        Span::styled(
+            line,
+            Style::default()
+                .fg(theme.link)
+                .add_modifier(Modifier::UNDERLINED),
+        )
+    } else {
+        Span::raw(line)
     }
-    spans
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
+    tui::install_panic_hook();
     dotenvy::dotenv().ok();
 
-    let _cli = Cli::parse();
+    let cli = Cli::parse();
+    let _log_guard = logging::init(cli.log_level.as_deref())?;
+    let mut config = config::Config::load(cli.config.as_deref())?;
+    if let Some(name) = &cli.profile
+        && !matches!(cli.command, Some(CliCommand::Auth { .. }))
+    {
+        config.use_profile(Some(name))?;
+    }
+    i18n::set_locale(config.ui.locale());
+    ai::configure(&config)?;
+    #[cfg(feature = "mock")]
+    gemchat::mock::configure_from_env();
+    match cli.command {
+        Some(CliCommand::Auth {
+            action: AuthAction::Set,
+        }) => return auth::set_command(cli.profile.as_deref()).await,
+        Some(CliCommand::Auth {
+            action: AuthAction::Remove,
+        }) => return auth::remove_command(cli.profile.as_deref()),
+        Some(CliCommand::Gc) => return gc_command(&config.storage),
+        Some(CliCommand::Usage {
+            action: UsageAction::Export { from, to, format },
+        }) => return usage::export(&config, from, to, format),
+        Some(CliCommand::Shellenv { shell }) => {
+            print!("{}", shell::env(shell));
+            return Ok(());
+        }
+        Some(
+            CliCommand::Commit { .. }
+            | CliCommand::Review { .. }
+            | CliCommand::Run { .. }
+            | CliCommand::Explain { .. }
+            | CliCommand::Watch { .. }
+            | CliCommand::Live,
+        )
+        | None => {}
+    }
+    auth::use_keys(&config);
+    if cli.sandbox {
+        config.tools.sandbox.enabled = Some(true);
+    }
+    if cli.accessible {
+        config.ui.accessible = Some(true);
+    }
+    let redactor = redact::Redactor::new(&config.redact)?;
+    match cli.command {
+        Some(CliCommand::Run {
+            task,
+            yes,
+            max_steps,
+            template,
+        }) => {
+            let options = headless::Options {
+                yes,
+                max_steps,
+                template,
+            };
+            // Unlocks the database, so the audit log of the run's tool calls is encrypted too
+            open_store(&config.storage)?;
+            return headless::run(&task.join(" "), &config, &redactor, options).await;
+        }
+        Some(CliCommand::Commit { yes }) => return commit::command(&config, &redactor, yes).await,
+        Some(CliCommand::Watch {
+            globs,
+            prompt,
+            debounce,
+        }) => {
+            let options = watch::Options {
+                patterns: globs,
+                prompt,
+                debounce: Duration::from_millis(debounce),
+            };
+            return watch::run(options, &config, &redactor, headless::stream_turn).await;
+        }
+        Some(CliCommand::Explain { command, status }) => {
+            return shell::explain(&command.join(" "), status, &config, &redactor).await;
+        }
+        Some(CliCommand::Review { reference, staged }) => {
+            return review_command(reference.as_deref(), staged, &config, &redactor).await;
+        }
+        _ => {}
+    }
+    let theme = if config.ui.accessible() {
+        theme::Theme::builtin("plain").expect("plain is a built-in theme")
+    } else {
+        theme::Theme::resolve(config.theme.name(), &config.theme.colors)?
+    };
+    if matches!(cli.command, Some(CliCommand::Live)) {
+        #[cfg(feature = "live")]
+        return voice::run(&config, &theme).await;
+        #[cfg(not(feature = "live"))]
+        return Err(color_eyre::eyre::eyre!(tr(
+            "This gemchat was built without voice support: rebuild it with `--features live`"
+        )));
+    }
+    let keymap = keymap::Keymap::new(&config.keys)?;
+
+    // Before the TUI starts, since it may ask for a passphrase
+    let store = open_store(&config.storage)?;
 
-    let terminal = ratatui::init();
-    let result = run(terminal).await;
-    ratatui::restore();
-    result
+    let (terminal, _tui) = tui::enter()?;
+    run(terminal, config, redactor, theme, keymap, store).await
+}
+
+/// `gemchat gc`: applies the retention policy and reports the space it freed
+fn gc_command(config: &config::StorageConfig) -> Result<()> {
+    let path =
+        store::path().ok_or_else(|| color_eyre::eyre::eyre!(tr("No data directory available")))?;
+    // The write-ahead log holds pages not yet copied into the database
+    let size = || {
+        ["", "-wal"]
+            .iter()
+            .filter_map(|suffix| {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                std::fs::metadata(file).ok()
+            })
+            .map(|m| m.len())
+            .sum::<u64>()
+    };
+    let before = size();
+    let store = store::Store::open()?;
+    if config.keep_sessions.is_none() && config.keep_days.is_none() {
+        println!(
+            "{}",
+            tr("No retention policy: set keep_sessions or keep_days under [storage].")
+        );
+    }
+    let pruned = store.prune(config.keep_sessions, config.keep_days)?;
+    store.vacuum()?;
+    drop(store);
+    let after = size();
+    let reclaimed = human_bytes(before.saturating_sub(after));
+    let (before, after) = (human_bytes(before), human_bytes(after));
+    let summary = if pruned == 1 {
+        i18n::fill(
+            tr("Deleted 1 session; reclaimed {} ({} → {})."),
+            &[&reclaimed, &before, &after],
+        )
+    } else {
+        i18n::fill(
+            tr("Deleted {} sessions; reclaimed {} ({} → {})."),
+            &[&pruned, &reclaimed, &before, &after],
+        )
+    };
+    println!("{}", summary);
+    Ok(())
+}
+
+/// `bytes` in the largest unit that keeps it above 1, e.g. "3.2 MiB"
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The conversation database, unlocked when it is or should be encrypted. `None` when it
+/// can't be opened; conversations then aren't saved.
+fn open_store(config: &config::StorageConfig) -> Result<Option<store::Store>> {
+    let mut store = match store::Store::open() {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::warn!(error = %e, "could not open the database");
+            return Ok(None);
+        }
+    };
+    match store.prune(config.keep_sessions, config.keep_days) {
+        Ok(0) => {}
+        Ok(pruned) => tracing::info!(pruned, "pruned old sessions"),
+        Err(e) => tracing::warn!(error = %e, "could not prune old sessions"),
+    }
+    if !config.encrypt() && !store.is_encrypted()? {
+        return Ok(Some(store));
+    }
+    let cipher = match config.key() {
+        config::KeySource::Keyring => cipher::Cipher::new(&auth::storage_key()?),
+        config::KeySource::Passphrase => {
+            let passphrase = match std::env::var("GEMCHAT_PASSPHRASE") {
+                Ok(passphrase) => passphrase,
+                Err(_) => auth::read_secret(tr("Passphrase for saved conversations: "))?,
+            };
+            cipher::Cipher::from_passphrase(&passphrase, &store.salt()?)
+        }
+    };
+    if !store.unlock(cipher)? {
+        return Err(color_eyre::eyre::eyre!(i18n::fill(
+            tr("Wrong key for the saved conversations in {}"),
+            &[&store::path().unwrap_or_default().display()]
+        )));
+    }
+    Ok(Some(store))
 }
 
-async fn run(mut terminal: DefaultTerminal) -> Result<()> {
+async fn run(
+    mut terminal: DefaultTerminal,
+    config: config::Config,
+    redactor: redact::Redactor,
+    theme: theme::Theme,
+    keymap: keymap::Keymap,
+    store: Option<store::Store>,
+) -> Result<()> {
     let (tx, mut rx) = mpsc::unbounded_channel();
-    let mut app = App::new(tx.clone());
+    let mut app = App::new(tx.clone(), config, redactor, theme, keymap, store);
+    if app.config.context.repo_map() {
+        app.map_command("");
+    }
 
     // Tick task
     let tick_tx = tx.clone();
-    tokio::spawn(async move {
+    let tick = tokio::spawn(async move {
         let mut interval = time::interval(Duration::from_millis(100));
         loop {
             interval.tick().await;
@@ -622,29 +7549,82 @@ async fn run(mut terminal: DefaultTerminal) -> Result<()> {
         }
     });
 
+    // Polled rather than blocking on a read, so the thread sees the quit instead of holding
+    // up the runtime's shutdown until the next key press
+    let reading = Arc::new(AtomicBool::new(true));
     let input_tx = tx.clone();
-    tokio::task::spawn_blocking(move || {
-        loop {
-            if let Ok(Event::Key(key)) = event::read() {
-                if key.kind == KeyEventKind::Press {
-                    if input_tx.send(Action::UserInput(key)).is_err() {
-                        break;
+    let input = tokio::task::spawn_blocking({
+        let reading = reading.clone();
+        move || {
+            while reading.load(Ordering::Relaxed) {
+                match event::poll(INPUT_POLL) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => break,
+                }
+                let action = match event::read() {
+                    Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                        Action::UserInput(key)
                     }
+                    Ok(Event::Resize(..)) => Action::Resize,
+                    Ok(Event::FocusGained) => Action::Focus(true),
+                    Ok(Event::FocusLost) => Action::Focus(false),
+                    Ok(Event::Paste(text)) => Action::Paste(text),
+                    _ => continue,
+                };
+                if input_tx.send(action).is_err() {
+                    break;
                 }
             }
         }
     });
 
+    #[cfg(unix)]
+    let signals = tokio::spawn(tui::job_control(
+        tx.clone(),
+        Action::Suspend,
+        Action::Resumed,
+    ));
+
+    let result = event_loop(&mut app, &mut terminal, &mut rx).await;
+    app.shutdown();
+    tick.abort();
+    #[cfg(unix)]
+    signals.abort();
+    reading.store(false, Ordering::Relaxed);
+    let _ = input.await;
+    result
+}
+
+/// Draws and applies actions until the app quits
+async fn event_loop(
+    app: &mut App<'_>,
+    terminal: &mut DefaultTerminal,
+    rx: &mut mpsc::UnboundedReceiver<Action>,
+) -> Result<()> {
     loop {
-        terminal.draw(|frame| app.draw(frame))?;
+        if app.dirty {
+            if std::mem::take(&mut app.needs_clear) {
+                terminal.clear()?;
+            }
+            terminal.draw(|frame| {
+                app.draw(frame);
+                app.color_depth.adapt_buffer(frame.buffer_mut());
+            })?;
+            app.show_graphics();
+            app.dirty = false;
+        }
 
         if let Some(action) = rx.recv().await {
             app.update(action)?;
         }
+        // Apply whatever else queued up meanwhile before drawing again
+        while let Ok(action) = rx.try_recv() {
+            app.update(action)?;
+        }
 
         if app.should_quit {
-            break;
+            return Ok(());
         }
     }
-    Ok(())
 }
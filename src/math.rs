@@ -0,0 +1,548 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A stretch of a line of markdown: text, or math between dollar signs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    /// `raw` is the math with its `$` or `$$` delimiters, `tex` what is between them
+    Math {
+        raw: &'a str,
+        tex: &'a str,
+    },
+}
+
+/// `line` split into text and `$…$` or `$$…$$` math. Dollars in inline code, escaped ones
+/// and ones that read like prices or shell variables stay text.
+pub fn pieces(line: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let bytes = line.as_bytes();
+    let mut text_start = 0;
+    let mut in_code = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => {
+                in_code = !in_code;
+                i += 1;
+            }
+            b'$' if !in_code => {
+                let delim = if line[i..].starts_with("$$") {
+                    "$$"
+                } else {
+                    "$"
+                };
+                let open = i + delim.len();
+                match closing(&line[open..], delim) {
+                    Some(len) => {
+                        if text_start < i {
+                            pieces.push(Piece::Text(&line[text_start..i]));
+                        }
+                        let end = open + len + delim.len();
+                        pieces.push(Piece::Math {
+                            raw: &line[i..end],
+                            tex: &line[open..open + len],
+                        });
+                        i = end;
+                        text_start = end;
+                    }
+                    None => i = open,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    if text_start < line.len() {
+        pieces.push(Piece::Text(&line[text_start..]));
+    }
+    pieces
+}
+
+/// Length of the math at the start of `rest` that `delim` closes. Inline math can't start
+/// or end with a space, nor close before a digit, and has to look like math, so `$5 and
+/// $10` or `$HOME/$USER` don't turn into it.
+fn closing(rest: &str, delim: &str) -> Option<usize> {
+    let inline = delim == "$";
+    if rest.is_empty() || (inline && rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    let mut from = rest.chars().next().map_or(1, char::len_utf8);
+    while let Some(at) = rest.get(from..)?.find(delim) {
+        let end = from + at;
+        let before = rest[..end].chars().next_back();
+        let after = rest[end + delim.len()..].chars().next();
+        let closes = before != Some('\\')
+            && !(inline
+                && (before.is_some_and(char::is_whitespace)
+                    || after.is_some_and(|c| c.is_ascii_digit())));
+        if closes {
+            let tex = &rest[..end];
+            let math = !inline
+                || tex.chars().count() <= 3
+                || tex.contains(['\\', '^', '_', '{', '=', '+', '<', '>']);
+            return math.then_some(end);
+        }
+        from = end + 1;
+    }
+    None
+}
+
+/// A best-effort Unicode rendering of the LaTeX math `tex`: Greek letters and symbols,
+/// superscripts and subscripts, fractions and roots. `None` for anything it doesn't know,
+/// which is better shown as it is.
+pub fn to_unicode(tex: &str) -> Option<String> {
+    let mut parser = Parser {
+        chars: tex.chars().peekable(),
+    };
+    let text = parser.expr(false)?;
+    Some(
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Everything up to the end, or up to and including the `}` closing a group
+    fn expr(&mut self, group: bool) -> Option<String> {
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.chars.next() else {
+                // An unclosed group
+                return (!group).then_some(out);
+            };
+            match c {
+                '}' if group => return Some(out),
+                '}' => return None,
+                '{' => out.push_str(&self.expr(true)?),
+                '^' | '_' => {
+                    let arg = self.atom()?;
+                    out.push_str(&script(&arg, c == '^'));
+                }
+                '\\' => out.push_str(&self.command()?),
+                '&' => out.push(' '),
+                '\'' => out.push('′'),
+                c if c.is_whitespace() => {
+                    if !out.ends_with([' ', '\n']) {
+                        out.push(' ');
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// The argument of a command or script: a group, a command or a single character
+    fn atom(&mut self) -> Option<String> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match self.chars.next()? {
+            '{' => self.expr(true),
+            '\\' => self.command(),
+            c => Some(c.to_string()),
+        }
+    }
+
+    /// The command after a `\`, with its arguments
+    fn command(&mut self) -> Option<String> {
+        let mut name = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+            name.push(c);
+        }
+        if name.is_empty() {
+            let c = self.chars.next()?;
+            return Some(match c {
+                '\\' => "\n".to_string(),
+                ',' | ':' | ';' | ' ' => " ".to_string(),
+                '!' => String::new(),
+                c => c.to_string(),
+            });
+        }
+        if let Some(symbol) = symbol(&name) {
+            return Some(symbol.to_string());
+        }
+        Some(match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.atom()?;
+                let denominator = self.atom()?;
+                fraction(&numerator, &denominator)
+            }
+            "sqrt" => {
+                let index = match self.chars.next_if_eq(&'[') {
+                    Some(_) => {
+                        let index: String = self.chars.by_ref().take_while(|&c| c != ']').collect();
+                        to_unicode(&index)?
+                    }
+                    None => String::new(),
+                };
+                let radicand = group(&self.atom()?);
+                match index.as_str() {
+                    "" | "2" => format!("√{radicand}"),
+                    "3" => format!("∛{radicand}"),
+                    "4" => format!("∜{radicand}"),
+                    index => format!("{}√{radicand}", script(index, true)),
+                }
+            }
+            "binom" => {
+                let n = self.atom()?;
+                let k = self.atom()?;
+                format!("({n} choose {k})")
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mathrm" | "mathit" | "mathbf" | "mathsf"
+            | "mathtt" | "mathcal" | "boldsymbol" | "operatorname" => self.atom()?,
+            "mathbb" => self.atom()?.chars().map(double_struck).collect(),
+            "hat" | "widehat" => accent(&self.atom()?, '\u{302}'),
+            "bar" | "overline" => accent(&self.atom()?, '\u{305}'),
+            "tilde" | "widetilde" => accent(&self.atom()?, '\u{303}'),
+            "dot" => accent(&self.atom()?, '\u{307}'),
+            "ddot" => accent(&self.atom()?, '\u{308}'),
+            "vec" => accent(&self.atom()?, '\u{20d7}'),
+            "left" | "right" => {
+                // `.` is the empty delimiter
+                self.chars.next_if_eq(&'.');
+                String::new()
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr"
+            | "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            // Environments like `aligned` or `cases` keep their rows and columns
+            "begin" | "end" => {
+                self.atom()?;
+                "\n".to_string()
+            }
+            "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan"
+            | "sinh" | "cosh" | "tanh" | "log" | "ln" | "lg" | "exp" | "lim" | "max" | "min"
+            | "sup" | "inf" | "det" | "dim" | "ker" | "deg" | "gcd" | "arg" | "Pr" | "mod" => name,
+            _ => return None,
+        })
+    }
+}
+
+/// `arg` raised or lowered with Unicode's superscript or subscript characters, or behind
+/// a `^` or `_` where one is missing
+fn script(arg: &str, sup: bool) -> String {
+    let mapped: Option<String> = arg
+        .chars()
+        .map(|c| if sup { superscript(c) } else { subscript(c) })
+        .collect();
+    match mapped {
+        Some(mapped) if !mapped.is_empty() => mapped,
+        _ => format!("{}{}", if sup { '^' } else { '_' }, group(arg)),
+    }
+}
+
+/// `a/b`, or one character like `½` where there is one
+fn fraction(numerator: &str, denominator: &str) -> String {
+    let vulgar = match (numerator, denominator) {
+        ("1", "2") => "½",
+        ("1", "3") => "⅓",
+        ("2", "3") => "⅔",
+        ("1", "4") => "¼",
+        ("3", "4") => "¾",
+        ("1", "5") => "⅕",
+        ("2", "5") => "⅖",
+        ("3", "5") => "⅗",
+        ("4", "5") => "⅘",
+        ("1", "6") => "⅙",
+        ("5", "6") => "⅚",
+        ("1", "7") => "⅐",
+        ("1", "8") => "⅛",
+        ("3", "8") => "⅜",
+        ("5", "8") => "⅝",
+        ("7", "8") => "⅞",
+        ("1", "9") => "⅑",
+        ("1", "10") => "⅒",
+        _ => "",
+    };
+    if !vulgar.is_empty() {
+        return vulgar.to_string();
+    }
+    if numerator.chars().all(|c| c.is_ascii_digit())
+        && denominator.chars().all(|c| c.is_ascii_digit())
+    {
+        return format!("{}⁄{}", script(numerator, true), script(denominator, false));
+    }
+    format!("{}/{}", group(numerator), group(denominator))
+}
+
+/// `text` in parentheses unless it is a single term
+fn group(text: &str) -> String {
+    if text.chars().count() <= 1 || text.chars().all(char::is_alphanumeric) {
+        text.to_string()
+    } else {
+        format!("({text})")
+    }
+}
+
+/// `text` with the combining `mark` over each character
+fn accent(text: &str, mark: char) -> String {
+    text.chars().flat_map(|c| [c, mark]).collect()
+}
+
+fn superscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '+' => '⁺',
+        '-' | '−' => '⁻',
+        '=' => '⁼',
+        '(' => '⁽',
+        ')' => '⁾',
+        'a' => 'ᵃ',
+        'b' => 'ᵇ',
+        'c' => 'ᶜ',
+        'd' => 'ᵈ',
+        'e' => 'ᵉ',
+        'f' => 'ᶠ',
+        'g' => 'ᵍ',
+        'h' => 'ʰ',
+        'i' => 'ⁱ',
+        'j' => 'ʲ',
+        'k' => 'ᵏ',
+        'l' => 'ˡ',
+        'm' => 'ᵐ',
+        'n' => 'ⁿ',
+        'o' => 'ᵒ',
+        'p' => 'ᵖ',
+        'r' => 'ʳ',
+        's' => 'ˢ',
+        't' => 'ᵗ',
+        'u' => 'ᵘ',
+        'v' => 'ᵛ',
+        'w' => 'ʷ',
+        'x' => 'ˣ',
+        'y' => 'ʸ',
+        'z' => 'ᶻ',
+        'A' => 'ᴬ',
+        'B' => 'ᴮ',
+        'D' => 'ᴰ',
+        'E' => 'ᴱ',
+        'G' => 'ᴳ',
+        'H' => 'ᴴ',
+        'I' => 'ᴵ',
+        'J' => 'ᴶ',
+        'K' => 'ᴷ',
+        'L' => 'ᴸ',
+        'M' => 'ᴹ',
+        'N' => 'ᴺ',
+        'O' => 'ᴼ',
+        'P' => 'ᴾ',
+        'R' => 'ᴿ',
+        'T' => 'ᵀ',
+        'U' => 'ᵁ',
+        'V' => 'ⱽ',
+        'W' => 'ᵂ',
+        'α' => 'ᵅ',
+        'β' => 'ᵝ',
+        'γ' => 'ᵞ',
+        'δ' => 'ᵟ',
+        'ε' => 'ᵋ',
+        'θ' => 'ᶿ',
+        'φ' => 'ᵠ',
+        'χ' => 'ᵡ',
+        '′' | '*' | '∗' => c,
+        _ => return None,
+    })
+}
+
+fn subscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '₀',
+        '1' => '₁',
+        '2' => '₂',
+        '3' => '₃',
+        '4' => '₄',
+        '5' => '₅',
+        '6' => '₆',
+        '7' => '₇',
+        '8' => '₈',
+        '9' => '₉',
+        '+' => '₊',
+        '-' | '−' => '₋',
+        '=' => '₌',
+        '(' => '₍',
+        ')' => '₎',
+        'a' => 'ₐ',
+        'e' => 'ₑ',
+        'h' => 'ₕ',
+        'i' => 'ᵢ',
+        'j' => 'ⱼ',
+        'k' => 'ₖ',
+        'l' => 'ₗ',
+        'm' => 'ₘ',
+        'n' => 'ₙ',
+        'o' => 'ₒ',
+        'p' => 'ₚ',
+        'r' => 'ᵣ',
+        's' => 'ₛ',
+        't' => 'ₜ',
+        'u' => 'ᵤ',
+        'v' => 'ᵥ',
+        'x' => 'ₓ',
+        'β' => 'ᵦ',
+        'γ' => 'ᵧ',
+        'ρ' => 'ᵨ',
+        'φ' => 'ᵩ',
+        'χ' => 'ᵪ',
+        _ => return None,
+    })
+}
+
+fn double_struck(c: char) -> char {
+    match c {
+        'C' => 'ℂ',
+        'H' => 'ℍ',
+        'N' => 'ℕ',
+        'P' => 'ℙ',
+        'Q' => 'ℚ',
+        'R' => 'ℝ',
+        'Z' => 'ℤ',
+        c => c,
+    }
+}
+
+/// What a command without arguments stands for
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "times" => "×",
+        "cdot" => "·",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "•",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "cong" => "≅",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "propto" => "∝",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "emptyset" | "varnothing" => "∅",
+        "forall" => "∀",
+        "exists" => "∃",
+        "nexists" => "∄",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "to" | "rightarrow" => "→",
+        "gets" | "leftarrow" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "implies" => "⟹",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "angle" => "∠",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "mid" => "∣",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "lvert" | "rvert" | "vert" => "|",
+        "lVert" | "rVert" | "Vert" => "‖",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        "aleph" => "ℵ",
+        "prime" => "′",
+        "degree" => "°",
+        "therefore" => "∴",
+        "because" => "∵",
+        "quad" => "  ",
+        "qquad" => "    ",
+        _ => return None,
+    })
+}
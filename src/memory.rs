@@ -0,0 +1,101 @@
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Serializes read-modify-write cycles, since tool calls can run concurrently
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// A fact the model (or the user) asked to keep across sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Memory {
+    pub text: String,
+    /// Unix timestamp in seconds
+    pub created: u64,
+}
+
+pub fn path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("memory.json"))
+}
+
+pub fn load() -> io::Result<Vec<Memory>> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    read()
+}
+
+/// Applies `f` to the stored memories and writes the result back
+pub fn update<R>(f: impl FnOnce(&mut Vec<Memory>) -> R) -> io::Result<R> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = read()?;
+    let result = f(&mut entries);
+    write(&entries)?;
+    Ok(result)
+}
+
+/// Stores `text` unless an identical memory exists. Returns its 1-based position.
+pub fn add(text: &str) -> io::Result<usize> {
+    let text = text.trim().to_string();
+    update(|entries| {
+        if let Some(i) = entries.iter().position(|m| m.text == text) {
+            return i + 1;
+        }
+        entries.push(Memory {
+            text,
+            created: now(),
+        });
+        entries.len()
+    })
+}
+
+/// Memories sharing words with `query`, best matches first, with their 1-based positions.
+/// An empty query returns everything.
+pub fn search(query: &str) -> io::Result<Vec<(usize, Memory)>> {
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() > 1)
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut scored: Vec<(usize, usize, Memory)> = load()?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, m)| {
+            let text = m.text.to_lowercase();
+            let score = words.iter().filter(|w| text.contains(w.as_str())).count();
+            (words.is_empty() || score > 0).then_some((score, i + 1, m))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    Ok(scored.into_iter().map(|(_, i, m)| (i, m)).collect())
+}
+
+fn read() -> io::Result<Vec<Memory>> {
+    let Some(path) = path() else {
+        return Ok(Vec::new());
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write(entries: &[Memory]) -> io::Result<()> {
+    let path = path().ok_or_else(|| io::Error::other("no data directory available"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write then rename so a crash never leaves a half-written store behind
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(entries)?)?;
+    std::fs::rename(tmp, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,70 @@
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory of fixtures to answer from instead of the network
+pub const REPLAY_VAR: &str = "GEMCHAT_REPLAY";
+/// Directory live responses are copied to
+pub const RECORD_VAR: &str = "GEMCHAT_RECORD";
+
+static REPLAY_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+static RECORD_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+static NEXT_REPLAY: AtomicUsize = AtomicUsize::new(0);
+static NEXT_RECORD: AtomicUsize = AtomicUsize::new(0);
+
+/// Replays fixtures from `dir`: each request is answered with the raw SSE body in the next
+/// `000.sse`, `001.sse`, ... The numbering restarts at zero.
+pub fn set_replay_dir(dir: Option<PathBuf>) {
+    *REPLAY_DIR.write().unwrap_or_else(|e| e.into_inner()) = dir;
+    NEXT_REPLAY.store(0, Ordering::SeqCst);
+}
+
+/// Copies each live response body to the next numbered file in `dir`, in the format
+/// [`set_replay_dir`] reads back
+pub fn set_record_dir(dir: Option<PathBuf>) {
+    *RECORD_DIR.write().unwrap_or_else(|e| e.into_inner()) = dir;
+    NEXT_RECORD.store(0, Ordering::SeqCst);
+}
+
+/// Replays or records as `GEMCHAT_REPLAY` and `GEMCHAT_RECORD` say
+pub fn configure_from_env() {
+    set_replay_dir(std::env::var_os(REPLAY_VAR).map(PathBuf::from));
+    set_record_dir(std::env::var_os(RECORD_VAR).map(PathBuf::from));
+}
+
+pub fn replaying() -> bool {
+    REPLAY_DIR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+}
+
+/// The next fixture's bytes; `None` when not replaying
+pub fn next_fixture() -> Option<Result<Vec<u8>>> {
+    let dir = REPLAY_DIR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()?;
+    let path = fixture_path(&dir, NEXT_REPLAY.fetch_add(1, Ordering::SeqCst));
+    Some(std::fs::read(&path).wrap_err_with(|| format!("No recorded response {}", path.display())))
+}
+
+/// The file the next live response is copied to; `None` when not recording
+pub fn recorder() -> Result<Option<File>> {
+    let Some(dir) = RECORD_DIR.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    let path = fixture_path(&dir, NEXT_RECORD.fetch_add(1, Ordering::SeqCst));
+    let file =
+        File::create(&path).wrap_err_with(|| format!("Could not create {}", path.display()))?;
+    Ok(Some(file))
+}
+
+fn fixture_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:03}.sse", index))
+}
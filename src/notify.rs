@@ -0,0 +1,29 @@
+use gemchat::config::NotificationsConfig;
+use std::io::Write;
+
+/// Tells the user about `summary` with a desktop notification and/or the terminal bell,
+/// as configured. Failures are only logged: a missing notification daemon shouldn't
+/// interrupt the chat.
+pub fn send(config: &NotificationsConfig, summary: &str, body: &str) {
+    if config.bell() {
+        let mut stdout = std::io::stdout();
+        if let Err(e) = stdout.write_all(b"\x07").and_then(|()| stdout.flush()) {
+            tracing::warn!(error = %e, "could not ring the terminal bell");
+        }
+    }
+    if config.desktop() {
+        let summary = summary.to_string();
+        let body = body.to_string();
+        // Talking to the notification daemon can block
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = notify_rust::Notification::new()
+                .appname("gemchat")
+                .summary(&summary)
+                .body(&body)
+                .show()
+            {
+                tracing::warn!(error = %e, "could not show a desktop notification");
+            }
+        });
+    }
+}
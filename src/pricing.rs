@@ -0,0 +1,111 @@
+use crate::config::Price;
+use std::collections::HashMap;
+
+/// List prices in USD per million tokens for prompts up to 200k tokens, by model prefix
+const BUILTIN: &[(&str, Price)] = &[
+    (
+        "gemini-3-pro",
+        Price {
+            input: 2.0,
+            output: 12.0,
+            cached_input: Some(0.2),
+        },
+    ),
+    (
+        "gemini-3-flash",
+        Price {
+            input: 0.5,
+            output: 3.0,
+            cached_input: Some(0.05),
+        },
+    ),
+    (
+        "gemini-2.5-pro",
+        Price {
+            input: 1.25,
+            output: 10.0,
+            cached_input: Some(0.125),
+        },
+    ),
+    (
+        "gemini-2.5-flash-lite",
+        Price {
+            input: 0.1,
+            output: 0.4,
+            cached_input: Some(0.01),
+        },
+    ),
+    (
+        "gemini-2.5-flash",
+        Price {
+            input: 0.3,
+            output: 2.5,
+            cached_input: Some(0.03),
+        },
+    ),
+    (
+        "gemini-2.0-flash-lite",
+        Price {
+            input: 0.075,
+            output: 0.3,
+            cached_input: None,
+        },
+    ),
+    (
+        "gemini-2.0-flash",
+        Price {
+            input: 0.1,
+            output: 0.4,
+            cached_input: Some(0.025),
+        },
+    ),
+];
+
+/// The price of `model`: the longest matching prefix in `overrides`, then in the
+/// built-in table
+pub fn price(overrides: &HashMap<String, Price>, model: &str) -> Option<Price> {
+    longest_prefix(model, overrides.iter().map(|(k, v)| (k.as_str(), *v)))
+        .or_else(|| longest_prefix(model, BUILTIN.iter().copied()))
+}
+
+fn longest_prefix<'a>(
+    model: &str,
+    prices: impl Iterator<Item = (&'a str, Price)>,
+) -> Option<Price> {
+    prices
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| price)
+}
+
+/// Estimated cost in USD. The `cached_tokens` of the prompt are billed at the cached rate,
+/// and everything beyond the prompt as output, which includes thinking tokens. Cache
+/// storage is not included.
+pub fn cost(
+    overrides: &HashMap<String, Price>,
+    model: &str,
+    prompt_tokens: i64,
+    cached_tokens: i64,
+    total_tokens: i64,
+) -> Option<f64> {
+    let price = price(overrides, model)?;
+    let cached = cached_tokens.clamp(0, prompt_tokens.max(0));
+    let output = (total_tokens - prompt_tokens).max(0);
+    Some(
+        ((prompt_tokens - cached) as f64 * price.input
+            + cached as f64 * price.cached_input.unwrap_or(price.input)
+            + output as f64 * price.output)
+            / 1_000_000.0,
+    )
+}
+
+/// What serving `cached_tokens` from a cache saved in USD over sending them uncached
+pub fn cache_savings(
+    overrides: &HashMap<String, Price>,
+    model: &str,
+    cached_tokens: i64,
+) -> Option<f64> {
+    let price = price(overrides, model)?;
+    let rate = price.input - price.cached_input.unwrap_or(price.input);
+    Some(cached_tokens.max(0) as f64 * rate / 1_000_000.0)
+}
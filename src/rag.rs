@@ -0,0 +1,251 @@
+use crate::ai::{self, EmbeddingTask};
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Lines per chunk, and how many of them each chunk repeats from the one before
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 5;
+/// Larger files are usually generated or data, not worth embedding
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+/// Chunks less similar than this to the question aren't worth the prompt space
+const MIN_SCORE: f32 = 0.5;
+
+/// A run of lines from a project file with its embedding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Relative to the project root
+    pub path: String,
+    /// 1-based and inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    vector: Vec<f32>,
+}
+
+/// Embedded chunks of a project's files, kept in one file per project in an index directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    model: String,
+    /// SHA-256 of each indexed file, so unchanged files aren't embedded again
+    files: HashMap<String, String>,
+    chunks: Vec<Chunk>,
+}
+
+impl Index {
+    fn path(dir: &Path, root: &Path) -> PathBuf {
+        let id = hex::encode(Sha256::digest(root.to_string_lossy().as_bytes()));
+        dir.join(format!("{}.json", &id[..16]))
+    }
+
+    /// The index of the project at `root` saved in `dir`, if there is one
+    pub fn load(dir: &Path, root: &Path) -> Result<Option<Self>> {
+        let path = Self::path(dir, root);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", path.display())),
+        };
+        let index: Self = serde_json::from_str(&text)
+            .wrap_err_with(|| format!("Could not parse {}", path.display()))?;
+        // Vectors from another model can't be compared with new queries
+        Ok((index.model == ai::EMBEDDING_MODEL).then_some(index))
+    }
+
+    fn save(&self, dir: &Path, root: &Path) -> Result<()> {
+        let path = Self::path(dir, root);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, serde_json::to_string(self)?)
+            .wrap_err_with(|| format!("Could not write {}", path.display()))
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Indexes the files under `paths` (relative to `root`, respecting `.gitignore`) and
+    /// saves the result in `dir`. Only files that changed since the last run are embedded
+    /// again; `progress` is told how many of the chunks to embed are done.
+    pub async fn update(
+        dir: &Path,
+        root: &Path,
+        paths: &[PathBuf],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Self> {
+        let mut index = Self::load(dir, root).ok().flatten().unwrap_or_default();
+        index.model = ai::EMBEDDING_MODEL.to_string();
+
+        let prefixes: Vec<String> = paths.iter().map(|p| prefix(root, p)).collect();
+        let covered = |path: &str| {
+            prefixes.iter().any(|prefix| {
+                prefix.is_empty()
+                    || path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        };
+
+        let walk_root = root.to_path_buf();
+        let walk_paths = paths.to_vec();
+        let files =
+            tokio::task::spawn_blocking(move || read_files(&walk_root, &walk_paths)).await?;
+
+        let mut hashes = HashMap::new();
+        let mut fresh = Vec::new();
+        for (path, text) in files {
+            let hash = hex::encode(Sha256::digest(text.as_bytes()));
+            if index.files.get(&path) != Some(&hash) {
+                fresh.extend(chunk(&path, &text));
+            }
+            hashes.insert(path, hash);
+        }
+        // Chunks of files under `paths` that changed or are gone are replaced
+        index
+            .chunks
+            .retain(|c| !covered(&c.path) || index.files.get(&c.path) == hashes.get(&c.path));
+        index.files.retain(|path, _| !covered(path));
+
+        let total = fresh.len();
+        progress(0, total);
+        for (done, batch) in fresh.chunks_mut(ai::EMBEDDING_BATCH).enumerate() {
+            // The path tells the model what the lines are part of
+            let texts: Vec<String> = batch
+                .iter()
+                .map(|c| format!("{}\n{}", c.path, c.text))
+                .collect();
+            let vectors = ai::embed(&texts, EmbeddingTask::Document).await?;
+            if vectors.len() != batch.len() {
+                return Err(eyre!(
+                    "Expected {} embeddings, got {}",
+                    batch.len(),
+                    vectors.len()
+                ));
+            }
+            for (chunk, vector) in batch.iter_mut().zip(vectors) {
+                chunk.vector = vector;
+            }
+            progress((done * ai::EMBEDDING_BATCH + batch.len()).min(total), total);
+        }
+        index.chunks.extend(fresh);
+        index.files.extend(hashes);
+        index.save(dir, root)?;
+        Ok(index)
+    }
+
+    /// Up to `k` chunks most similar to `query`, best first
+    pub async fn search(&self, query: &str, k: usize) -> Result<Vec<&Chunk>> {
+        if self.chunks.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let query = ai::embed(&[query.to_string()], EmbeddingTask::Query)
+            .await?
+            .pop()
+            .ok_or_else(|| eyre!("No embedding returned for the query"))?;
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .chunks
+            .iter()
+            .map(|c| (cosine(&query, &c.vector), c))
+            .filter(|(score, _)| *score >= MIN_SCORE)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().take(k).map(|(_, c)| c).collect())
+    }
+}
+
+/// `chunks` as a prompt section
+pub fn excerpts(chunks: &[&Chunk]) -> String {
+    let mut text = String::from(
+        "Project Excerpts (retrieved automatically; they may or may not be relevant):\n",
+    );
+    for c in chunks {
+        text.push_str(&format!(
+            "{}:{}-{}\n```\n{}\n```\n",
+            c.path, c.start_line, c.end_line, c.text
+        ));
+    }
+    text
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// `path` relative to `root` as stored in the index; empty for the root itself
+fn prefix(root: &Path, path: &Path) -> String {
+    let path = root.join(path);
+    path.strip_prefix(root)
+        .unwrap_or(&path)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Text files under `paths` with their paths relative to `root`
+fn read_files(root: &Path, paths: &[PathBuf]) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    for path in paths {
+        for entry in WalkBuilder::new(root.join(path)).build().flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file())
+                || entry.metadata().map_or(true, |m| m.len() > MAX_FILE_BYTES)
+            {
+                continue;
+            }
+            // Binary and non-UTF-8 files are skipped
+            let Ok(text) = std::fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            if text.contains('\0') || text.trim().is_empty() {
+                continue;
+            }
+            files.push((relative(root, entry.path()), text));
+        }
+    }
+    files.sort();
+    files.dedup_by(|a, b| a.0 == b.0);
+    files
+}
+
+fn chunk(path: &str, text: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let body = lines[start..end].join("\n");
+        if !body.trim().is_empty() {
+            chunks.push(Chunk {
+                path: path.to_string(),
+                start_line: start + 1,
+                end_line: end,
+                text: body,
+                vector: Vec::new(),
+            });
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+    chunks
+}
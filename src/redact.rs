@@ -0,0 +1,123 @@
+use crate::ai::ContextBlock;
+use crate::config::RedactConfig;
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Well-known credential formats. When a pattern has a `secret` group only that part is
+/// replaced, so `password = hunter22` keeps the variable name for context.
+const BUILTIN_PATTERNS: &[&str] = &[
+    // Google API keys
+    r"AIza[0-9A-Za-z_\-]{35}",
+    // AWS access key ids
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    // GitHub tokens
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}",
+    r"\bgithub_pat_[A-Za-z0-9_]{22,}",
+    // OpenAI/Anthropic style keys
+    r"\bsk-[A-Za-z0-9_\-]{20,}",
+    // Slack tokens
+    r"\bxox[abposr]-[A-Za-z0-9\-]{10,}",
+    // PEM private keys
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+    // Authorization headers
+    r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9._~+/\-]{20,}=*)",
+    // Environment-style assignments such as `AWS_SECRET_ACCESS_KEY=...`
+    r#"\b[A-Z0-9_]*(?:SECRET|TOKEN|PASSWORD|PASSWD|API_?KEY|PRIVATE_?KEY|CREDENTIALS?)[A-Z0-9_]*\s*[:=]\s*["']?(?P<secret>[^\s"',;]{6,})"#,
+    // Quoted values in config files and JSON such as `"api_key": "..."`
+    r#"(?i)\b[a-z0-9_\-]*(?:secret|token|password|passwd|api_?key|private_?key)[a-z0-9_\-]*["']?\s*[:=]\s*["'](?P<secret>[^"'\s]{6,})["']"#,
+];
+
+/// Environment variables whose names look like this have their values redacted
+const SECRET_ENV_NAME: &str = r"(?i)(KEY|SECRET|TOKEN|PASSWORD|PASSWD|CREDENTIAL)";
+
+/// Short values would match ordinary text far too often
+const MIN_ENV_VALUE_LEN: usize = 8;
+
+/// Replaces secrets in text before it leaves the machine or is written to a log
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    /// Literal values of secret-looking environment variables, longest first
+    values: Vec<String>,
+}
+
+impl Redactor {
+    /// Compiles the built-in and user patterns and snapshots the environment
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        if !config.enabled() {
+            return Ok(Self {
+                patterns: Vec::new(),
+                values: Vec::new(),
+            });
+        }
+
+        let mut patterns = Vec::new();
+        for pattern in BUILTIN_PATTERNS {
+            patterns.push(Regex::new(pattern).expect("built-in redaction pattern is valid"));
+        }
+        for pattern in &config.patterns {
+            patterns.push(
+                Regex::new(pattern)
+                    .wrap_err_with(|| format!("Invalid redaction pattern `{}`", pattern))?,
+            );
+        }
+
+        let secret_name = Regex::new(SECRET_ENV_NAME).expect("env name pattern is valid");
+        let mut values: Vec<String> = std::env::vars()
+            .filter(|(name, _)| secret_name.is_match(name) || config.env_vars.contains(name))
+            .map(|(_, value)| value)
+            .filter(|value| value.trim().len() >= MIN_ENV_VALUE_LEN)
+            .collect();
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+        values.dedup();
+
+        Ok(Self { patterns, values })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+
+        for value in &self.values {
+            if text.contains(value.as_str()) {
+                text = Cow::Owned(text.replace(value.as_str(), REDACTED));
+            }
+        }
+
+        for pattern in &self.patterns {
+            if !pattern.is_match(&text) {
+                continue;
+            }
+            let replaced =
+                pattern.replace_all(&text, |caps: &Captures| match caps.name("secret") {
+                    // Counts like `max_tokens: 100000` are not secrets
+                    Some(secret) if secret.as_str().bytes().all(|b| b.is_ascii_digit()) => {
+                        caps[0].to_string()
+                    }
+                    Some(secret) => {
+                        let whole = caps.get(0).expect("group 0 always matches");
+                        let start = secret.start() - whole.start();
+                        let end = secret.end() - whole.start();
+                        let whole = whole.as_str();
+                        format!("{}{}{}", &whole[..start], REDACTED, &whole[end..])
+                    }
+                    None => REDACTED.to_string(),
+                });
+            text = Cow::Owned(replaced.into_owned());
+        }
+        text
+    }
+
+    /// Redacts the text of every block of a prompt
+    pub fn redact_blocks(&self, blocks: Vec<ContextBlock>) -> Vec<ContextBlock> {
+        blocks
+            .into_iter()
+            .map(|block| ContextBlock {
+                text: self.redact(&block.text).into_owned(),
+                ..block
+            })
+            .collect()
+    }
+}
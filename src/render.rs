@@ -0,0 +1,821 @@
+use crate::math;
+use crate::theme::Theme;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use std::ops::Range;
+use syntect::{
+    easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet, util::LinesWithEndings,
+};
+use unicode_width::UnicodeWidthStr;
+
+/// Syntax definitions and color themes for code blocks. Loading them takes a while, so
+/// build one and keep it.
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    themes: ThemeSet,
+}
+
+impl Highlighter {
+    pub fn new() -> Self {
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            themes: ThemeSet::load_defaults(),
+        }
+    }
+}
+
+impl Highlighter {
+    /// Usual file extension for code in `lang`, e.g. `rs` for `rust`
+    pub fn extension(&self, lang: &str) -> Option<&str> {
+        self.syntaxes
+            .find_syntax_by_token(lang)
+            .and_then(|syntax| syntax.file_extensions.first())
+            .map(String::as_str)
+    }
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rendered markdown and where its code blocks are
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub lines: Vec<Line<'static>>,
+    /// Line ranges of the code blocks, fences included, in the order [`code_blocks`]
+    /// returns them
+    pub code_blocks: Vec<Range<usize>>,
+}
+
+/// `text` as styled lines at most `width` columns wide; 0 disables wrapping
+pub fn markdown(
+    text: &str,
+    width: usize,
+    highlighter: &Highlighter,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    document(text, false, width, highlighter, theme).lines
+}
+
+/// [`markdown`] of a response still arriving: a code block left open is closed for now with
+/// a fence marked as streaming, so it looks like the block it will become
+pub fn streaming_markdown(
+    text: &str,
+    width: usize,
+    highlighter: &Highlighter,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    document(text, true, width, highlighter, theme).lines
+}
+
+/// [`markdown`], or [`streaming_markdown`] while `streaming`, with its code blocks located
+/// in the wrapped lines
+pub fn document(
+    text: &str,
+    streaming: bool,
+    width: usize,
+    highlighter: &Highlighter,
+    theme: &Theme,
+) -> Document {
+    let parsed = parse_markdown(
+        text,
+        streaming,
+        &highlighter.syntaxes,
+        &highlighter.themes,
+        theme,
+    );
+    // Fences are found before wrapping: a wrapped line loses what was in front of it
+    let blocks = code_block_lines(&parsed);
+    let mut starts = Vec::with_capacity(parsed.len() + 1);
+    let mut lines = Vec::new();
+    for line in parsed {
+        starts.push(lines.len());
+        lines.extend(wrap(owned_line(line), width));
+    }
+    starts.push(lines.len());
+    Document {
+        lines,
+        code_blocks: blocks
+            .into_iter()
+            .map(|block| starts[block.start]..starts[block.end])
+            .collect(),
+    }
+}
+
+/// `line` behind the `gutter` spans, with `shade` under its own styles: spans keep what they
+/// set and take the rest, such as a background, from `shade`
+pub fn decorate(
+    mut line: Line<'static>,
+    gutter: Vec<Span<'static>>,
+    shade: Style,
+) -> Line<'static> {
+    line.spans.splice(0..0, gutter);
+    line.style = shade.patch(line.style);
+    line
+}
+
+/// Splits `line` into lines at most `width` columns wide, breaking after whitespace where
+/// it can and inside words longer than a line. Trailing whitespace may overhang.
+pub fn wrap(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line];
+    }
+    let mut lines = Vec::new();
+    let mut current: Vec<Span<'static>> = Vec::new();
+    let mut used = 0;
+
+    for span in &line.spans {
+        for word in span.content.split_inclusive(char::is_whitespace) {
+            let mut word = word;
+            let visible = word.trim_end().width();
+            if used > 0 && visible > 0 && used + visible > width {
+                lines.push(std::mem::take(&mut current));
+                used = 0;
+            }
+            while word.trim_end().width() > width.saturating_sub(used) {
+                let (head, tail) = split_at_width(word, width - used);
+                push_text(&mut current, head, span.style);
+                lines.push(std::mem::take(&mut current));
+                used = 0;
+                word = tail;
+            }
+            push_text(&mut current, word, span.style);
+            used += word.width();
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+        .into_iter()
+        .map(|spans| Line::from(spans).style(line.style))
+        .collect()
+}
+
+/// `text` cut to at most `width` columns, ending in `…` when it was cut
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let (head, _) = split_at_width(text, width.saturating_sub(1));
+    // A wide character that doesn't fit is left out rather than overhanging
+    let head = if head.width() > width.saturating_sub(1) {
+        ""
+    } else {
+        head
+    };
+    format!("{}…", head)
+}
+
+/// `text` followed by spaces up to `width` columns; `format!("{:<w$}")` counts characters,
+/// which misaligns wide ones
+pub fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+/// The longest prefix of `text` at most `width` wide, and the rest. The prefix holds at
+/// least one character, so a character wider than the line still makes progress.
+fn split_at_width(text: &str, width: usize) -> (&str, &str) {
+    let mut used = 0;
+    for (i, c) in text.char_indices() {
+        used += unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        if used > width {
+            return text.split_at(if i == 0 { c.len_utf8() } else { i });
+        }
+    }
+    (text, "")
+}
+
+/// Appends `text`, merged into the last span when the style matches
+fn push_text(spans: &mut Vec<Span<'static>>, text: &str, style: Style) {
+    if text.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.content.to_mut().push_str(text),
+        _ => spans.push(Span::styled(text.to_string(), style)),
+    }
+}
+
+/// Styled lines for `text`: fenced code blocks are highlighted with the theme's syntect
+/// theme (diffs in added/removed colors) and `**bold**` spans are emboldened. Unclosed
+/// fences are highlighted as far as they go, and closed tentatively when `streaming`.
+fn parse_markdown<'a>(
+    text: &'a str,
+    streaming: bool,
+    ps: &SyntaxSet,
+    ts: &ThemeSet,
+    theme: &Theme,
+) -> Vec<Line<'a>> {
+    let code_theme = ts
+        .themes
+        .get(&theme.code)
+        .unwrap_or(&ts.themes["base16-ocean.dark"]);
+    let dim = Style::default().fg(theme.dim);
+    let mut lines = Vec::new();
+    let mut fences = Fences::default();
+    // The opening fence of the block in progress, containers included
+    let mut fence: Option<&str> = None;
+    let mut current_lang = "";
+    let mut code_block_content = String::new();
+    // What each code line had before it: blockquote markers and indentation
+    let mut prefixes: Vec<&str> = Vec::new();
+
+    let mut table: Vec<&str> = Vec::new();
+    // Lines of a `$$` display math block in progress
+    let mut math: Option<Vec<&str>> = None;
+
+    for line in text.lines() {
+        let kind = match fences.line(line) {
+            FenceLine::Outside => {
+                // The block's container ended before its closing fence
+                fence = None;
+                lines.extend(code_lines(
+                    current_lang,
+                    &std::mem::take(&mut code_block_content),
+                    &std::mem::take(&mut prefixes),
+                    ps,
+                    code_theme,
+                    theme,
+                ));
+                fences.line(line)
+            }
+            kind => kind,
+        };
+        if kind == FenceLine::Text && math.is_none() && line.trim_start().starts_with('|') {
+            table.push(line);
+            continue;
+        }
+        if !table.is_empty() {
+            lines.extend(table_lines(&std::mem::take(&mut table), theme));
+        }
+        // Display math doesn't span blank lines or fences: a `$$` cut off by one was text
+        if math.is_some()
+            && (line.trim().is_empty() || !matches!(kind, FenceLine::Text | FenceLine::Outside))
+        {
+            for line in math.take().into_iter().flatten() {
+                lines.push(Line::from(parse_inline_styles(line, theme)));
+            }
+        }
+        match kind {
+            FenceLine::Open {
+                fence: opening,
+                info,
+            } => {
+                fence = Some(opening);
+                current_lang = info.split_whitespace().next().unwrap_or_default();
+                lines.push(Line::from(Span::styled(line, dim)));
+            }
+            FenceLine::Code { prefix, code } => {
+                code_block_content.push_str(code);
+                code_block_content.push('\n');
+                prefixes.push(prefix);
+            }
+            FenceLine::Close => {
+                fence = None;
+                lines.extend(code_lines(
+                    current_lang,
+                    &std::mem::take(&mut code_block_content),
+                    &std::mem::take(&mut prefixes),
+                    ps,
+                    code_theme,
+                    theme,
+                ));
+                lines.push(Line::from(Span::styled(line, dim)));
+            }
+            FenceLine::Text | FenceLine::Outside => {
+                if let Some(block) = &mut math {
+                    block.push(line);
+                    if line.trim_end().ends_with("$$") {
+                        lines.extend(math_lines(block, theme));
+                        math = None;
+                    }
+                } else if let Some(rest) = line.trim().strip_prefix("$$")
+                    && !rest.contains("$$")
+                {
+                    math = Some(vec![line]);
+                } else {
+                    let parts = parse_inline_styles(line, theme);
+                    lines.push(Line::from(parts));
+                }
+            }
+        }
+    }
+
+    lines.extend(table_lines(&table, theme));
+    // Unclosed math, still arriving or never closed, is shown as written
+    lines.extend(
+        math.into_iter()
+            .flatten()
+            .map(|line| Line::from(Span::styled(line, dim))),
+    );
+
+    // Handle unclosed code blocks (during streaming)
+    if fence.is_some() && !code_block_content.is_empty() {
+        lines.extend(code_lines(
+            current_lang,
+            &code_block_content,
+            &prefixes,
+            ps,
+            code_theme,
+            theme,
+        ));
+    }
+    if let Some(fence) = fence
+        && streaming
+    {
+        // The opening fence under its blockquote markers, with a list item's marker blanked
+        let (container, run) = fence.split_at(fence.trim_end_matches(['`', '~']).len());
+        let container: String = container
+            .chars()
+            .map(|c| if c == '>' { '>' } else { ' ' })
+            .collect();
+        lines.push(Line::from(vec![
+            Span::styled(container + run, dim),
+            Span::styled(" streaming…", dim.add_modifier(Modifier::ITALIC)),
+        ]));
+    }
+
+    lines
+}
+
+/// Lines of a `$$` display math block, indented, or dimmed as written where it can't be
+/// rendered
+fn math_lines<'a>(block: &[&'a str], theme: &Theme) -> Vec<Line<'a>> {
+    let tex = block.join("\n");
+    let rendered = tex
+        .trim()
+        .strip_prefix("$$")
+        .and_then(|tex| tex.strip_suffix("$$"))
+        .and_then(math::to_unicode);
+    match rendered {
+        Some(math) => math
+            .lines()
+            .map(|line| Line::from(format!("  {line}")))
+            .collect(),
+        None => block
+            .iter()
+            .map(|line| Line::from(Span::styled(*line, Style::default().fg(theme.dim))))
+            .collect(),
+    }
+}
+
+/// Highlighted lines of a code block, each behind the dimmed `prefixes` of its source line
+fn code_lines<'a>(
+    lang: &str,
+    code: &str,
+    prefixes: &[&'a str],
+    ps: &SyntaxSet,
+    code_theme: &syntect::highlighting::Theme,
+    theme: &Theme,
+) -> Vec<Line<'a>> {
+    let dim = Style::default().fg(theme.dim);
+    highlight_code(lang, code, ps, code_theme, theme)
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut line: Line<'a> = line;
+            if let Some(prefix) = prefixes.get(i).filter(|prefix| !prefix.is_empty()) {
+                line.spans.insert(0, Span::styled(*prefix, dim));
+            }
+            line
+        })
+        .collect()
+}
+
+/// What a line of markdown is to the fenced code blocks around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FenceLine<'a> {
+    /// Not part of a code block
+    Text,
+    /// Opens a block: `fence` is the line up to the end of its backticks or tildes
+    Open { fence: &'a str, info: &'a str },
+    /// Code of the open block, behind the `prefix` of its containers and indentation
+    Code { prefix: &'a str, code: &'a str },
+    /// Closes the open block
+    Close,
+    /// Outside the open block's blockquote or list item, which ends the block unclosed.
+    /// Asking again tells what the line itself is.
+    Outside,
+}
+
+/// Follows fenced code blocks line by line the CommonMark way: a fence is three or more
+/// backticks or tildes indented up to three spaces, in blockquotes and list items too, and
+/// only a fence of the same character at least as long and without an info string
+/// closes it
+#[derive(Debug, Default)]
+struct Fences {
+    /// Content columns of the list items lines may continue, innermost last
+    items: Vec<usize>,
+    open: Option<Fence>,
+}
+
+#[derive(Debug)]
+struct Fence {
+    /// Blockquote markers in front of it
+    quotes: usize,
+    /// Content column of the list item it is in, 0 outside lists
+    column: usize,
+    /// Spaces before it, taken off its code's lines as well
+    indent: usize,
+    marker: char,
+    len: usize,
+}
+
+impl Fences {
+    fn line<'a>(&mut self, line: &'a str) -> FenceLine<'a> {
+        if let Some(fence) = &self.open {
+            let (quotes, rest) = strip_quotes(line, fence.quotes);
+            let blank = rest.trim().is_empty();
+            if quotes < fence.quotes || (!blank && spaces(rest) < fence.column) {
+                self.open = None;
+                return FenceLine::Outside;
+            }
+            let rest = &rest[spaces(rest).min(fence.column)..];
+            if let Some((marker, len, after)) = fence_run(rest)
+                && marker == fence.marker
+                && len >= fence.len
+                && after.trim().is_empty()
+            {
+                self.open = None;
+                return FenceLine::Close;
+            }
+            let code = &rest[spaces(rest).min(fence.indent)..];
+            return FenceLine::Code {
+                prefix: &line[..line.len() - code.len()],
+                code,
+            };
+        }
+
+        let (quotes, rest) = strip_quotes(line, usize::MAX);
+        if rest.trim().is_empty() {
+            return FenceLine::Text;
+        }
+        let leading = spaces(rest);
+        self.items.retain(|&column| column <= leading);
+        if let Some(column) = list_marker(rest) {
+            self.items.push(column);
+        }
+        let column = self.items.last().copied().unwrap_or(0);
+        let rest = &rest[column.min(rest.len())..];
+        let Some((marker, len, info)) = fence_run(rest) else {
+            return FenceLine::Text;
+        };
+        // Backticks in the info string make it inline code instead
+        if marker == '`' && info.contains('`') {
+            return FenceLine::Text;
+        }
+        self.open = Some(Fence {
+            quotes,
+            column,
+            indent: spaces(rest),
+            marker,
+            len,
+        });
+        FenceLine::Open {
+            fence: &line[..line.len() - info.len()],
+            info: info.trim(),
+        }
+    }
+}
+
+/// Leading spaces of `line`
+fn spaces(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// `line` without up to `max` blockquote markers, and how many it had
+fn strip_quotes(mut line: &str, max: usize) -> (usize, &str) {
+    let mut quotes = 0;
+    while quotes < max
+        && spaces(line) <= 3
+        && let Some(rest) = line.trim_start_matches(' ').strip_prefix('>')
+    {
+        line = rest.strip_prefix(' ').unwrap_or(rest);
+        quotes += 1;
+    }
+    (quotes, line)
+}
+
+/// Content column of the list item `line` starts, e.g. 2 for `- item` and 4 for `10. item`
+fn list_marker(line: &str) -> Option<usize> {
+    let body = line.trim_start_matches(' ');
+    let digits = body.len() - body.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match digits {
+        0 if body.starts_with(['-', '*', '+']) => 1,
+        1..=9 if body[digits..].starts_with(['.', ')']) => digits + 1,
+        _ => return None,
+    };
+    // Past four spaces the rest is indented code, which starts a space after the marker
+    let gap = match spaces(&body[marker..]) {
+        0 => return None,
+        gap @ 1..=4 => gap,
+        _ => 1,
+    };
+    Some(line.len() - body.len() + marker + gap)
+}
+
+/// The fence `line` starts with after up to three spaces: its character, its length and
+/// the rest of the line
+fn fence_run(line: &str) -> Option<(char, usize, &str)> {
+    if spaces(line) > 3 {
+        return None;
+    }
+    let body = line.trim_start_matches(' ');
+    let marker = body.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let rest = body.trim_start_matches(marker);
+    let len = body.len() - rest.len();
+    (len >= 3).then_some((marker, len, rest))
+}
+
+/// A fenced code block in markdown source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The first word of the info string after the opening fence, e.g. `rust`
+    pub lang: String,
+    pub code: String,
+}
+
+/// Code blocks of `text` in order, fenced the way `markdown` renders them
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut fences = Fences::default();
+    let mut open: Option<CodeBlock> = None;
+    for line in text.lines() {
+        let kind = match fences.line(line) {
+            FenceLine::Outside => {
+                blocks.extend(open.take());
+                fences.line(line)
+            }
+            kind => kind,
+        };
+        match kind {
+            FenceLine::Open { info, .. } => {
+                open = Some(CodeBlock {
+                    lang: info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    code: String::new(),
+                })
+            }
+            FenceLine::Code { code, .. } => {
+                if let Some(block) = &mut open {
+                    block.code.push_str(code);
+                    block.code.push('\n');
+                }
+            }
+            FenceLine::Close => blocks.extend(open.take()),
+            FenceLine::Text | FenceLine::Outside => {}
+        }
+    }
+    // Unclosed fences (mid-stream) run to the end
+    blocks.extend(open);
+    blocks
+}
+
+/// Line ranges of the code blocks in `parse_markdown` output, fences included
+fn code_block_lines(lines: &[Line]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut fences = Fences::default();
+    let mut start = None;
+    for (i, line) in lines.iter().enumerate() {
+        // Code lines are drawn behind what their source had in front, so they follow the
+        // same fences
+        let text = line.to_string();
+        let text = text.trim_end_matches('\n');
+        let kind = match fences.line(text) {
+            FenceLine::Outside => {
+                ranges.extend(start.take().map(|open| open..i));
+                fences.line(text)
+            }
+            kind => kind,
+        };
+        match kind {
+            FenceLine::Open { .. } => start = Some(i),
+            FenceLine::Close => ranges.extend(start.take().map(|open| open..i + 1)),
+            _ => {}
+        }
+    }
+    ranges.extend(start.map(|open| open..lines.len()));
+    ranges
+}
+
+/// Rows of a markdown table with their columns padded to the widest cell. Rows without a
+/// `|---|` separator under the first are not a table and pass through as text.
+fn table_lines<'a>(rows: &[&'a str], theme: &Theme) -> Vec<Line<'a>> {
+    let cells: Vec<Vec<&str>> = rows
+        .iter()
+        .map(|row| {
+            let row = row.trim();
+            let row = row.strip_prefix('|').unwrap_or(row);
+            let row = row.strip_suffix('|').unwrap_or(row);
+            row.split('|').map(str::trim).collect()
+        })
+        .collect();
+    let is_separator = |cells: &[&str]| {
+        cells
+            .iter()
+            .all(|c| !c.is_empty() && c.chars().all(|ch| matches!(ch, '-' | ':')))
+    };
+    if cells.len() < 2 || !is_separator(&cells[1]) {
+        return rows
+            .iter()
+            .map(|row| Line::from(parse_inline_styles(row, theme)))
+            .collect();
+    }
+
+    let styled: Vec<Vec<Vec<Span>>> = cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| parse_inline_styles(cell, theme))
+                .collect()
+        })
+        .collect();
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in styled
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .map(|(_, r)| r)
+    {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.iter().map(Span::width).sum());
+        }
+    }
+
+    let border = Style::default().fg(theme.dim);
+    styled
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            if i == 1 {
+                let rule: Vec<String> = widths.iter().map(|w| "-".repeat(w + 2)).collect();
+                return Line::from(Span::styled(format!("|{}|", rule.join("|")), border));
+            }
+            let mut spans = vec![Span::styled("| ", border)];
+            let mut row = row.into_iter();
+            for (column, width) in widths.iter().enumerate() {
+                if column > 0 {
+                    spans.push(Span::styled(" | ", border));
+                }
+                let cell = row.next().unwrap_or_default();
+                let used: usize = cell.iter().map(Span::width).sum();
+                spans.extend(cell);
+                if used < *width {
+                    spans.push(Span::raw(" ".repeat(width - used)));
+                }
+            }
+            spans.push(Span::styled(" |", border));
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Lines of a code block: diffs get added/removed colors, everything else syntect's
+/// highlighting for `lang`
+fn highlight_code(
+    lang: &str,
+    code: &str,
+    ps: &SyntaxSet,
+    code_theme: &syntect::highlighting::Theme,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    if matches!(lang, "diff" | "patch") {
+        return code.lines().map(|line| diff_line(line, theme)).collect();
+    }
+    if theme.code.is_empty() {
+        return code
+            .lines()
+            .map(|line| Line::from(line.to_string()))
+            .collect();
+    }
+    let syntax = ps
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| ps.find_syntax_plain_text());
+    let mut h = HighlightLines::new(syntax, code_theme);
+    LinesWithEndings::from(code)
+        .map(|code_line| {
+            let ranges: Vec<(syntect::highlighting::Style, &str)> =
+                h.highlight_line(code_line, ps).unwrap_or_default();
+            Line::from(
+                ranges
+                    .into_iter()
+                    .map(|(style, content)| {
+                        Span::styled(content.to_string(), translate_style(style))
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect()
+}
+
+/// One line of a unified diff, with its `+`/`-` marker set apart as a gutter
+fn diff_line(line: &str, theme: &Theme) -> Line<'static> {
+    let header = Style::default().fg(theme.dim).add_modifier(Modifier::BOLD);
+    if line.starts_with("+++ ")
+        || line.starts_with("--- ")
+        || line.starts_with("diff ")
+        || line.starts_with("index ")
+    {
+        return Line::from(Span::styled(line.to_string(), header));
+    }
+    if line.starts_with("@@") {
+        return Line::from(Span::styled(
+            line.to_string(),
+            Style::default().fg(theme.accent),
+        ));
+    }
+    let (marker, rest, style) = match line.split_at_checked(1) {
+        Some(("+", rest)) => ("+", rest, Style::default().fg(theme.success)),
+        Some(("-", rest)) => ("-", rest, Style::default().fg(theme.error)),
+        Some((" ", rest)) => (" ", rest, Style::default()),
+        _ => ("", line, Style::default()),
+    };
+    Line::from(vec![
+        Span::styled(format!("{} ", marker), style.add_modifier(Modifier::BOLD)),
+        Span::styled(rest.to_string(), style),
+    ])
+}
+
+/// Copies borrowed span text so the line can be cached
+pub fn owned_line(line: Line<'_>) -> Line<'static> {
+    Line {
+        spans: line
+            .spans
+            .into_iter()
+            .map(|span| Span::styled(span.content.into_owned(), span.style))
+            .collect(),
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+fn translate_style(style: syntect::highlighting::Style) -> Style {
+    Style::default().fg(Color::Rgb(
+        style.foreground.r,
+        style.foreground.g,
+        style.foreground.b,
+    ))
+}
+
+/// Spans of a line of text: `**bold**` is emboldened and `$math$` turned into Unicode, or
+/// dimmed as written where it can't be
+fn parse_inline_styles<'a>(line: &'a str, theme: &Theme) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut is_bold = false;
+
+    for piece in math::pieces(line) {
+        let text = match piece {
+            math::Piece::Text(text) => text,
+            math::Piece::Math { raw, tex } => {
+                let style = if is_bold {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                };
+                spans.push(match math::to_unicode(tex) {
+                    Some(math) => Span::styled(math.replace('\n', " "), style),
+                    None => Span::styled(raw, style.fg(theme.dim)),
+                });
+                continue;
+            }
+        };
+        let mut current_text = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '*' && chars.peek() == Some(&'*') {
+                chars.next(); // consume second *
+                if !current_text.is_empty() {
+                    spans.push(if is_bold {
+                        Span::styled(
+                            current_text.clone(),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        )
+                    } else {
+                        Span::raw(current_text.clone())
+                    });
+                    current_text.clear();
+                }
+                is_bold = !is_bold;
+            } else {
+                current_text.push(c);
+            }
+        }
+        if !current_text.is_empty() {
+            spans.push(if is_bold {
+                Span::styled(current_text, Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                Span::raw(current_text)
+            });
+        }
+    }
+    spans
+}
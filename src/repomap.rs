@@ -0,0 +1,92 @@
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// A token is about this many characters of code, for budgeting
+const CHARS_PER_TOKEN: usize = 4;
+/// Files larger than this are listed without symbols
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+const MAX_SYMBOL_CHARS: usize = 100;
+
+/// Unindented declarations in common languages, so nested items don't crowd the outline
+static SYMBOL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^(?:",
+        // Rust
+        r"(?:pub(?:\([^)]*\))?\s+)?(?:async\s+|const\s+|unsafe\s+)*(?:fn|struct|enum|trait|type|mod|macro_rules!)\s+\w+",
+        r"|impl\b[^{;]*",
+        // Python
+        r"|(?:async\s+)?def\s+\w+|class\s+\w+",
+        // JavaScript and TypeScript
+        r"|(?:export\s+(?:default\s+)?)?(?:async\s+)?(?:function\*?|class|interface|type|enum)\s+\w+",
+        // Go
+        r"|func\s+(?:\([^)]*\)\s*)?\w+|type\s+\w+",
+        r")"
+    ))
+    .expect("symbol pattern is valid")
+});
+
+/// Outline of the files under `root` (respecting `.gitignore`) with their top-level symbols,
+/// cut to roughly `budget` tokens. Symbols are dropped before files are.
+pub fn build(root: &Path, budget: usize) -> String {
+    let budget = budget * CHARS_PER_TOKEN;
+    let mut files: Vec<_> = WalkBuilder::new(root)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort();
+
+    let mut unlisted: usize = files
+        .iter()
+        .map(|path| relative(root, path).len() + 1)
+        .sum();
+    let mut map = String::new();
+    for (i, path) in files.iter().enumerate() {
+        let name = relative(root, path);
+        if map.len() + name.len() + 1 > budget {
+            map.push_str(&format!("… {} more files\n", files.len() - i));
+            break;
+        }
+        map.push_str(&name);
+        map.push('\n');
+        unlisted -= name.len() + 1;
+        // Symbols only while every file can still be listed after them
+        for symbol in symbols(path) {
+            if map.len() + symbol.len() + 3 + unlisted > budget {
+                break;
+            }
+            map.push_str(&format!("  {}\n", symbol));
+        }
+    }
+    map
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+fn symbols(path: &Path) -> Vec<String> {
+    if std::fs::metadata(path).map_or(true, |m| m.len() > MAX_FILE_BYTES) {
+        return Vec::new();
+    }
+    // Binary and non-UTF-8 files have no symbols worth listing
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| SYMBOL.find(line))
+        .map(|m| {
+            let symbol = m.as_str().trim_end();
+            match symbol.char_indices().nth(MAX_SYMBOL_CHARS) {
+                Some((cut, _)) => format!("{}…", &symbol[..cut]),
+                None => symbol.to_string(),
+            }
+        })
+        .collect()
+}
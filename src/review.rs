@@ -0,0 +1,164 @@
+use crate::config::Config;
+use crate::redact::Redactor;
+use crate::{ai, git};
+use color_eyre::Result;
+use regex::Regex;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// `[severity] path:line: description`, optionally as a list item; the line is optional
+static FINDING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^\s*(?:[-*]\s+)?\[(high|medium|low)\]\s+`?([^\s:`]+)`?(?::(\d+))?`?:\s*(.*)$")
+        .expect("finding pattern is valid")
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    High,
+    Medium,
+    Low,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::High => "high",
+            Severity::Medium => "medium",
+            Severity::Low => "low",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Line in the new version of the file
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileFindings {
+    pub path: String,
+    /// Most severe first
+    pub findings: Vec<Finding>,
+}
+
+/// Findings grouped by file, in the order the model first mentioned each file
+#[derive(Debug, Clone, Default)]
+pub struct Review {
+    pub files: Vec<FileFindings>,
+    /// The model's overall assessment
+    pub summary: String,
+}
+
+impl Review {
+    /// Reads the line format [`prompt`] asks for. Lines that don't start a finding continue
+    /// the one above, up to a blank line; other text is ignored.
+    pub fn parse(reply: &str) -> Self {
+        let mut review = Review::default();
+        let mut last: Option<(usize, usize)> = None;
+        for line in reply.lines() {
+            let text = line.trim();
+            if let Some(summary) = text.strip_prefix("Summary:") {
+                review.summary = summary.trim().to_string();
+                last = None;
+            } else if let Some(captures) = FINDING.captures(line) {
+                let severity = match captures[1].to_lowercase().as_str() {
+                    "high" => Severity::High,
+                    "medium" => Severity::Medium,
+                    _ => Severity::Low,
+                };
+                let path = &captures[2];
+                let file = match review.files.iter().position(|f| f.path == path) {
+                    Some(i) => i,
+                    None => {
+                        review.files.push(FileFindings {
+                            path: path.to_string(),
+                            findings: Vec::new(),
+                        });
+                        review.files.len() - 1
+                    }
+                };
+                review.files[file].findings.push(Finding {
+                    severity,
+                    line: captures.get(3).and_then(|m| m.as_str().parse().ok()),
+                    message: captures[4].trim().to_string(),
+                });
+                last = Some((file, review.files[file].findings.len() - 1));
+            } else if text.is_empty() {
+                last = None;
+            } else if let Some((file, finding)) = last {
+                let message = &mut review.files[file].findings[finding].message;
+                message.push(' ');
+                message.push_str(text);
+            }
+        }
+        for file in &mut review.files {
+            file.findings.sort_by_key(|f| (f.severity, f.line));
+        }
+        review
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// The line format [`Review::parse`] reads, so the review can go back into a prompt
+impl fmt::Display for Review {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            for finding in &file.findings {
+                write!(f, "[{}] {}", finding.severity.label(), file.path)?;
+                if let Some(line) = finding.line {
+                    write!(f, ":{}", line)?;
+                }
+                writeln!(f, ": {}", finding.message)?;
+            }
+        }
+        if !self.summary.is_empty() {
+            writeln!(f, "Summary: {}", self.summary)?;
+        }
+        Ok(())
+    }
+}
+
+/// Review-focused prompt for `diff`
+pub fn prompt(diff: &str) -> String {
+    format!(
+        "You are a meticulous code reviewer. Review the diff below for bugs, security problems, \
+         performance issues and hard-to-follow code. Skip style nits a formatter would fix. \
+         Report each finding on its own line in exactly this form:\n\
+         [severity] path:line: description\n\
+         where severity is high, medium or low, path is the file as named in the diff and line \
+         is a line number in the new version of the file (leave out `:line` when no single line \
+         applies). After the findings write one line starting with `Summary:` giving your \
+         overall assessment. If nothing is worth reporting, reply with only the summary line.\n\n{}",
+        git::truncate_diff(diff)
+    )
+}
+
+/// Reviews the changes against `reference` (default `HEAD`), or only the staged ones
+pub async fn generate(
+    reference: Option<&str>,
+    staged: bool,
+    config: &Config,
+    redactor: &Redactor,
+) -> Result<Review> {
+    let diff = git::diff(reference, staged).await?;
+    let reply = ai::complete(ai::Request {
+        prompt: redactor.redact(&prompt(&diff)).into_owned(),
+        models: config.models.clone(),
+        without_tools: true,
+        safety: config.safety.clone(),
+        provider: config.provider,
+        ..Default::default()
+    })
+    .await?;
+    let mut review = Review::parse(&reply);
+    if review.is_empty() && review.summary.is_empty() {
+        review.summary = reply.trim().to_string();
+    }
+    Ok(review)
+}
@@ -0,0 +1,40 @@
+use crate::config::ShareConfig;
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use serde_json::{Value, json};
+
+/// Uploads `content` as a secret gist holding one file, `filename`, and returns its URL
+pub async fn gist(
+    config: &ShareConfig,
+    filename: &str,
+    description: &str,
+    content: &str,
+) -> Result<String> {
+    let token = config.github_token().ok_or_else(|| {
+        eyre!("No GitHub token: set `github_token` under [share] or GITHUB_TOKEN")
+    })?;
+    let body = json!({
+        "description": description,
+        "public": false,
+        "files": { filename: { "content": content } }
+    });
+    let response = reqwest::Client::new()
+        .post(format!("{}/gists", config.api_url()))
+        .bearer_auth(token)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .header(reqwest::header::USER_AGENT, "gemchat")
+        .json(&body)
+        .send()
+        .await
+        .wrap_err("Could not reach GitHub")?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(eyre!("GitHub error {}: {}", status, text));
+    }
+    let created: Value = response.json().await?;
+    created["html_url"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("GitHub did not return the gist's URL"))
+}
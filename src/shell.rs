@@ -0,0 +1,101 @@
+use crate::{capture, headless};
+use color_eyre::Result;
+use gemchat::{ai, config, redact};
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+const BASH: &str = r#"# gemchat: Alt+E explains the last command and its output
+__gemchat_status=0
+__gemchat_record_status() { __gemchat_status=$?; }
+PROMPT_COMMAND="__gemchat_record_status${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+__gemchat_explain() {
+    local last
+    last=$(fc -ln -1 | sed 's/^[[:space:]]*//')
+    [ -n "$last" ] && gemchat explain --status "$__gemchat_status" -- "$last"
+}
+bind -x '"\ee": __gemchat_explain'
+"#;
+
+const ZSH: &str = r#"# gemchat: Alt+E explains the last command and its output
+typeset -g __gemchat_status=0
+__gemchat_record_status() { __gemchat_status=$? }
+precmd_functions=(__gemchat_record_status $precmd_functions)
+__gemchat_explain() {
+    local last=$(fc -ln -1)
+    [[ -n $last ]] || return
+    zle -I
+    gemchat explain --status $__gemchat_status -- "$last" </dev/tty
+}
+zle -N __gemchat_explain
+bindkey '^[e' __gemchat_explain
+"#;
+
+const FISH: &str = r#"# gemchat: Alt+E explains the last command and its output
+set -g __gemchat_status 0
+function __gemchat_record_status --on-event fish_postexec
+    set -g __gemchat_status $status
+end
+function __gemchat_explain
+    set -l last $history[1]
+    test -n "$last"; or return
+    echo
+    gemchat explain --status $__gemchat_status -- $last
+    commandline -f repaint
+end
+bind \ee __gemchat_explain
+"#;
+
+/// `gemchat shellenv <shell>`: hooks to `eval` from the shell's startup file
+pub fn env(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+/// `gemchat explain`: streams an explanation of what running `command` did. Inside tmux its
+/// output is read from the pane; elsewhere only the command and its status are sent.
+pub async fn explain(
+    command: &str,
+    status: Option<i32>,
+    config: &config::Config,
+    redactor: &redact::Redactor,
+) -> Result<()> {
+    let mut prompt = format!(
+        "I ran this command in my terminal:\n\n```\n{}\n```\n\n",
+        command
+    );
+    if let Some(status) = status {
+        prompt.push_str(&format!("It exited with status {}.\n\n", status));
+    }
+    if let Ok(pane) = std::env::var("TMUX_PANE") {
+        match capture::tmux(Some(&pane)) {
+            Ok(output) if !output.is_empty() => prompt.push_str(&format!(
+                "My terminal, ending with its output:\n\n```\n{}\n```\n\n",
+                output
+            )),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "could not capture the pane"),
+        }
+    }
+    prompt.push_str(
+        "Explain briefly what happened. If it failed, say why and how to fix it. Answer in plain text for a terminal, without markdown headings.",
+    );
+
+    let request = ai::Request {
+        prompt: format!("User: {}\n", redactor.redact(&prompt)),
+        models: config.models.clone(),
+        without_tools: true,
+        safety: config.safety.clone(),
+        provider: config.provider,
+        ..Default::default()
+    };
+    headless::stream_turn(request).await?;
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+/// Payload of a stream that ends with a sentinel rather than by closing
+pub const DONE: &str = "[DONE]";
+
+/// One dispatched server-sent event
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    /// `event:` field; `None` for the default `message` type
+    pub event: Option<String>,
+    /// The `data:` lines of the event joined with newlines
+    pub data: String,
+}
+
+impl Event {
+    /// Whether this is the `[DONE]` sentinel some providers end their streams with
+    pub fn is_done(&self) -> bool {
+        self.data.trim() == DONE
+    }
+}
+
+/// Turns the lines of a `text/event-stream` into events, following the WHATWG rules:
+/// fields until a blank line make an event, `data:` lines are joined, lines starting with
+/// `:` are comments, and one space after the colon is dropped.
+#[derive(Debug, Default)]
+pub struct Parser {
+    event: Option<String>,
+    data: Option<String>,
+    /// `id:` of the latest event that had one
+    pub last_id: Option<String>,
+    /// Reconnection delay asked for with `retry:`
+    pub retry: Option<Duration>,
+}
+
+impl Parser {
+    /// Takes one line without its line ending, returning the event it completes
+    pub fn line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "event" => self.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// The event left unterminated when the stream ends
+    pub fn finish(&mut self) -> Option<Event> {
+        self.dispatch()
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+        // An event without data is dropped along with its type
+        let data = self.data.take()?;
+        Some(Event {
+            event: event.filter(|e| e != "message"),
+            data,
+        })
+    }
+}
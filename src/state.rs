@@ -0,0 +1,77 @@
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+pub const MIN_SIDEBAR_WIDTH: u16 = 15;
+pub const MAX_SIDEBAR_WIDTH: u16 = 60;
+
+/// Layout preferences changed from inside the TUI and the unsent input of each session,
+/// remembered between runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiState {
+    pub sidebar_visible: bool,
+    pub sidebar_width: u16,
+    /// Whether the sidebar was resized in the TUI, which then wins over `[layout]`
+    pub sidebar_resized: bool,
+    /// What was in the input box of a session not saved yet
+    pub draft: String,
+    /// Unsent input of saved sessions, by session id
+    pub drafts: BTreeMap<i64, String>,
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            sidebar_visible: true,
+            sidebar_width: 25,
+            sidebar_resized: false,
+            draft: String::new(),
+            drafts: BTreeMap::new(),
+        }
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("state.json"))
+}
+
+impl UiState {
+    /// The saved state, or the default when there is none or it can't be read
+    pub fn load() -> Self {
+        path()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::other("no data directory available"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// This state without unsent input, for when conversations are stored encrypted
+    pub fn without_drafts(&self) -> Self {
+        Self {
+            draft: String::new(),
+            drafts: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn resize_sidebar(&mut self, delta: i16) {
+        self.sidebar_width = self
+            .sidebar_width
+            .saturating_add_signed(delta)
+            .clamp(MIN_SIDEBAR_WIDTH, MAX_SIDEBAR_WIDTH);
+        self.sidebar_resized = true;
+        self.sidebar_visible = true;
+    }
+}
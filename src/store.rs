@@ -0,0 +1,886 @@
+use crate::audit;
+use crate::cipher::{self, Cipher};
+use crate::config;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Shown in place of encrypted contents when the store is locked
+pub const LOCKED: &str = "[encrypted]";
+
+/// Key of the store unlocked in this process, which the stores opened after it use too,
+/// such as the one each tool call writes the audit log through
+static KEY: OnceLock<Cipher> = OnceLock::new();
+
+/// Tables, indexes and the full-text index over message contents, kept in sync by triggers
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    title TEXT,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    session INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    branch INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    model TEXT,
+    created TEXT NOT NULL,
+    UNIQUE (session, branch, position)
+);
+CREATE TABLE IF NOT EXISTS branches (
+    session INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    branch INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    parent INTEGER,
+    fork_at INTEGER NOT NULL,
+    PRIMARY KEY (session, branch)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content, content = 'messages', content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_au AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TABLE IF NOT EXISTS usage (
+    id INTEGER PRIMARY KEY,
+    session INTEGER REFERENCES sessions(id) ON DELETE SET NULL,
+    timestamp TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    response_tokens INTEGER NOT NULL,
+    total_tokens INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    tool TEXT NOT NULL,
+    args TEXT NOT NULL,
+    status TEXT NOT NULL,
+    approval TEXT NOT NULL,
+    output_bytes INTEGER NOT NULL,
+    output_sha256 TEXT NOT NULL
+);
+";
+
+/// Schema changes since `SCHEMA`, applied in order; `user_version` counts those applied
+const MIGRATIONS: &[&str] = &[
+    // Encrypted contents, which the full-text index must not see, and the key check
+    "
+    ALTER TABLE messages ADD COLUMN encrypted INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    DROP TRIGGER messages_ai;
+    DROP TRIGGER messages_ad;
+    DROP TRIGGER messages_au;
+    CREATE TRIGGER messages_ai AFTER INSERT ON messages WHEN NOT new.encrypted BEGIN
+        INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+    END;
+    CREATE TRIGGER messages_ad AFTER DELETE ON messages WHEN NOT old.encrypted BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    END;
+    CREATE TRIGGER messages_au AFTER UPDATE OF content, encrypted ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+            SELECT 'delete', old.id, old.content WHERE NOT old.encrypted;
+        INSERT INTO messages_fts (rowid, content)
+            SELECT new.id, new.content WHERE NOT new.encrypted;
+    END;
+    ",
+    // Sessions kept whatever the retention policy
+    "ALTER TABLE sessions ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+    // Prompt tokens served from a context cache
+    "ALTER TABLE usage ADD COLUMN cached_tokens INTEGER NOT NULL DEFAULT 0;",
+    // Sampling parameters a response was generated with
+    "ALTER TABLE messages ADD COLUMN generation TEXT;",
+    // Model and provider chosen for a session instead of the configured ones
+    "
+    ALTER TABLE sessions ADD COLUMN model TEXT;
+    ALTER TABLE sessions ADD COLUMN provider TEXT;
+    ",
+    // Template a session was started from
+    "ALTER TABLE sessions ADD COLUMN template TEXT;",
+    // Encrypted session titles and tool arguments
+    "
+    ALTER TABLE sessions ADD COLUMN title_encrypted INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE audit ADD COLUMN encrypted INTEGER NOT NULL DEFAULT 0;
+    ",
+];
+
+/// A message as stored; the TUI keeps more per message than is worth saving
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredMessage {
+    pub role: String,
+    pub content: String,
+    pub model: Option<String>,
+    /// For responses: the sampling parameters set, see `GenerationConfig::summary`
+    pub generation: Option<String>,
+    pub created: DateTime<Local>,
+}
+
+/// Where a branch of a session forks off; see `conversation::Tree`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredBranch {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub fork_at: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: i64,
+    pub title: Option<String>,
+    pub created: DateTime<Local>,
+    pub updated: DateTime<Local>,
+    /// Never pruned
+    pub pinned: bool,
+    /// Set with `/model` and `/provider`; the configured ones are used when unset
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    /// Name of the `[template.<name>]` the session was started from
+    pub template: Option<String>,
+}
+
+/// A message matching a search, with the matched words in `snippet` between `[` and `]`
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub session: i64,
+    pub title: Option<String>,
+    pub branch: usize,
+    pub position: usize,
+    pub role: String,
+    pub snippet: String,
+    pub created: DateTime<Local>,
+}
+
+/// One model request's token counts
+#[derive(Debug, Clone)]
+pub struct UsageRow {
+    pub timestamp: DateTime<Local>,
+    pub session: Option<i64>,
+    /// Title of the session, if it has one and still exists
+    pub title: Option<String>,
+    pub model: String,
+    pub prompt_tokens: i64,
+    /// The part of `prompt_tokens` served from a context cache
+    pub cached_tokens: i64,
+    pub response_tokens: i64,
+    pub total_tokens: i64,
+}
+
+/// Tokens spent, summed over the rows it covers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageTotals {
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub cached_tokens: i64,
+    pub response_tokens: i64,
+    pub total_tokens: i64,
+}
+
+pub fn path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("gemchat.db"))
+}
+
+/// Sessions, their messages, token usage and the tool audit log in one SQLite database
+pub struct Store {
+    conn: Connection,
+    /// Set once unlocked; new and changed messages, titles and audit entries are then
+    /// stored encrypted
+    cipher: Option<Cipher>,
+}
+
+impl Store {
+    /// Opens the database in the data directory, creating it when needed. Once a store was
+    /// unlocked, it is opened with the same key.
+    pub fn open() -> Result<Self> {
+        let path = path().ok_or_else(|| {
+            rusqlite::Error::InvalidPath(PathBuf::from("no data directory available"))
+        })?;
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let mut store = Self::open_at(&path)?;
+        store.cipher = KEY.get().cloned();
+        Ok(store)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        // Tool calls write to the audit log from other threads while the TUI saves messages
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i as i64 + 1)?;
+            tx.commit()?;
+        }
+        Ok(Self { conn, cipher: None })
+    }
+
+    /// Salt for deriving a key from a passphrase, created with the first one
+    pub fn salt(&self) -> Result<Vec<u8>> {
+        if let Some(salt) = self.meta("salt")? {
+            return Ok(STANDARD.decode(salt).unwrap_or_default());
+        }
+        let salt = cipher::random::<{ cipher::SALT_LEN }>();
+        self.set_meta("salt", &STANDARD.encode(salt))?;
+        Ok(salt.to_vec())
+    }
+
+    /// Encrypts from now on with `cipher`, and encrypts what was stored in plaintext.
+    /// `false`, leaving the store locked, when the database was encrypted with another key.
+    pub fn unlock(&mut self, cipher: Cipher) -> Result<bool> {
+        const CHECK: &str = "gemchat";
+        // Each reads the plaintext rows of a column and writes one back sealed
+        const COLUMNS: &[(&str, &str)] = &[
+            (
+                "SELECT id, content FROM messages WHERE NOT encrypted",
+                "UPDATE messages SET content = ?2, encrypted = 1 WHERE id = ?1",
+            ),
+            (
+                "SELECT id, title FROM sessions WHERE title IS NOT NULL AND NOT title_encrypted",
+                "UPDATE sessions SET title = ?2, title_encrypted = 1 WHERE id = ?1",
+            ),
+            (
+                "SELECT id, args FROM audit WHERE NOT encrypted",
+                "UPDATE audit SET args = ?2, encrypted = 1 WHERE id = ?1",
+            ),
+        ];
+
+        match self.meta("key_check")? {
+            Some(check) if cipher.decrypt(&check).as_deref() != Some(CHECK) => return Ok(false),
+            Some(_) => {}
+            None => self.set_meta("key_check", &cipher.encrypt(CHECK))?,
+        }
+        let mut sealed = 0;
+        let tx = self.conn.transaction()?;
+        for (select, update) in COLUMNS {
+            let plaintext: Vec<(i64, String)> = tx
+                .prepare(select)?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_>>()?;
+            for (id, text) in &plaintext {
+                tx.execute(update, params![id, cipher.encrypt(text)])?;
+            }
+            sealed += plaintext.len();
+        }
+        tx.commit()?;
+        if sealed > 0 {
+            // Deleted index entries and freed pages would still hold the plaintext
+            self.vacuum()?;
+        }
+        let _ = KEY.set(cipher.clone());
+        self.cipher = Some(cipher);
+        Ok(true)
+    }
+
+    /// Whether new contents are stored encrypted
+    pub fn is_unlocked(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Whether some messages are encrypted, so opening them needs the key
+    pub fn is_encrypted(&self) -> Result<bool> {
+        Ok(self.meta("key_check")?.is_some())
+    }
+
+    pub fn create_session(&self, title: Option<&str>) -> Result<i64> {
+        let now = Local::now();
+        let title = title.map(|title| self.seal(title));
+        self.conn.execute(
+            "INSERT INTO sessions (title, title_encrypted, created, updated)
+             VALUES (?1, ?2, ?3, ?3)",
+            params![
+                title.as_ref().map(|(title, _)| title),
+                title.as_ref().is_some_and(|(_, encrypted)| *encrypted),
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn set_title(&self, session: i64, title: &str) -> Result<()> {
+        let (title, encrypted) = self.seal(title);
+        self.conn.execute(
+            "UPDATE sessions SET title = ?2, title_encrypted = ?3 WHERE id = ?1",
+            params![session, title, encrypted],
+        )?;
+        Ok(())
+    }
+
+    /// Sessions, most recently updated first
+    pub fn sessions(&self) -> Result<Vec<Session>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, title, created, updated, pinned, model, provider, template,
+                    title_encrypted
+             FROM sessions ORDER BY updated DESC, id DESC",
+        )?;
+        stmt.query_map([], |row| self.session_row(row))?.collect()
+    }
+
+    pub fn session(&self, id: i64) -> Result<Option<Session>> {
+        self.conn
+            .query_row(
+                "SELECT id, title, created, updated, pinned, model, provider, template,
+                        title_encrypted
+                 FROM sessions WHERE id = ?1",
+                params![id],
+                |row| self.session_row(row),
+            )
+            .optional()
+    }
+
+    pub fn set_pinned(&self, session: i64, pinned: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET pinned = ?2 WHERE id = ?1",
+            params![session, pinned],
+        )?;
+        Ok(())
+    }
+
+    /// Records the model and provider the session uses, `None` for the configured ones
+    pub fn set_model(
+        &self,
+        session: i64,
+        model: Option<&str>,
+        provider: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET model = ?2, provider = ?3 WHERE id = ?1",
+            params![session, model, provider],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_template(&self, session: i64, template: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET template = ?2 WHERE id = ?1",
+            params![session, template],
+        )?;
+        Ok(())
+    }
+
+    /// Deletes unpinned sessions beyond the `keep_sessions` most recently updated, or not
+    /// updated for `keep_days`, along with their messages. Token usage is kept. Returns how
+    /// many sessions went.
+    pub fn prune(&self, keep_sessions: Option<usize>, keep_days: Option<u64>) -> Result<usize> {
+        let mut pruned = 0;
+        if let Some(keep) = keep_sessions {
+            pruned += self.conn.execute(
+                "DELETE FROM sessions WHERE NOT pinned AND id NOT IN
+                     (SELECT id FROM sessions ORDER BY updated DESC, id DESC LIMIT ?1)",
+                params![keep as i64],
+            )?;
+        }
+        if let Some(days) = keep_days {
+            let cutoff = Local::now() - chrono::Duration::days(days as i64);
+            pruned += self.conn.execute(
+                "DELETE FROM sessions WHERE NOT pinned AND updated < ?1",
+                params![cutoff],
+            )?;
+        }
+        Ok(pruned)
+    }
+
+    /// Rewrites the database file without the free pages deleted rows left
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "INSERT INTO messages_fts (messages_fts) VALUES ('optimize');
+             VACUUM;
+             PRAGMA wal_checkpoint(TRUNCATE);",
+        )
+    }
+
+    /// Records how the branches of a session fork, indexed like the slice
+    pub fn save_branches(&self, session: i64, branches: &[StoredBranch]) -> Result<()> {
+        for (index, branch) in branches.iter().enumerate() {
+            self.conn.execute(
+                "INSERT INTO branches (session, branch, name, parent, fork_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (session, branch) DO UPDATE SET
+                     name = excluded.name, parent = excluded.parent, fork_at = excluded.fork_at",
+                params![
+                    session,
+                    index as i64,
+                    branch.name,
+                    branch.parent.map(|p| p as i64),
+                    branch.fork_at as i64
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The branches of a session in index order; empty for sessions saved before branches
+    /// were recorded
+    pub fn branches(&self, session: i64) -> Result<Vec<StoredBranch>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, parent, fork_at FROM branches WHERE session = ?1 ORDER BY branch",
+        )?;
+        stmt.query_map(params![session], |row| {
+            Ok(StoredBranch {
+                name: row.get(0)?,
+                parent: row.get::<_, Option<i64>>(1)?.map(|p| p as usize),
+                fork_at: row.get::<_, i64>(2)? as usize,
+            })
+        })?
+        .collect()
+    }
+
+    /// Writes the message at `position` of a branch, replacing what was stored there
+    pub fn save_message(
+        &self,
+        session: i64,
+        branch: usize,
+        position: usize,
+        message: &StoredMessage,
+    ) -> Result<()> {
+        upsert_message(
+            &self.conn,
+            self.cipher.as_ref(),
+            session,
+            branch,
+            position,
+            message,
+        )?;
+        touch(&self.conn, session)
+    }
+
+    /// Makes the stored branch match `messages`: changed ones are rewritten and any past
+    /// its end removed
+    pub fn save_branch(
+        &mut self,
+        session: i64,
+        branch: usize,
+        messages: &[StoredMessage],
+    ) -> Result<()> {
+        // Only what changed is written; encrypted rows can't be compared in SQL
+        let stored = self.messages(session, branch)?;
+        let tx = self.conn.transaction()?;
+        for (position, message) in messages.iter().enumerate() {
+            if stored.get(position) != Some(message) {
+                upsert_message(
+                    &tx,
+                    self.cipher.as_ref(),
+                    session,
+                    branch,
+                    position,
+                    message,
+                )?;
+            }
+        }
+        tx.execute(
+            "DELETE FROM messages WHERE session = ?1 AND branch = ?2 AND position >= ?3",
+            params![session, branch as i64, messages.len() as i64],
+        )?;
+        touch(&tx, session)?;
+        tx.commit()
+    }
+
+    /// Messages of one branch, in order
+    pub fn messages(&self, session: i64, branch: usize) -> Result<Vec<StoredMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT role, content, encrypted, model, generation, created FROM messages
+             WHERE session = ?1 AND branch = ?2 ORDER BY position",
+        )?;
+        stmt.query_map(params![session, branch as i64], |row| {
+            Ok(StoredMessage {
+                role: row.get(0)?,
+                content: self.plaintext(row.get(1)?, row.get(2)?),
+                model: row.get(3)?,
+                generation: row.get(4)?,
+                created: row.get(5)?,
+            })
+        })?
+        .collect()
+    }
+
+    /// Messages from every session containing all the words of `text`, the last one as a
+    /// prefix so results follow typing; best matches first. Encrypted messages aren't in
+    /// the index; once unlocked they are decrypted and scanned after it.
+    pub fn search(&self, text: &str, limit: usize) -> Result<Vec<Hit>> {
+        let Some(query) = fts_query(text) else {
+            return Ok(Vec::new());
+        };
+        let mut hits = self.search_index(&query, limit)?;
+        if self.cipher.is_some() && hits.len() < limit {
+            hits.extend(self.search_encrypted(text, limit - hits.len())?);
+        }
+        Ok(hits)
+    }
+
+    fn search_index(&self, query: &str, limit: usize) -> Result<Vec<Hit>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT m.session, s.title, m.branch, m.position, m.role,
+                    snippet(messages_fts, 0, '[', ']', '…', 12), m.created, s.title_encrypted
+             FROM messages_fts
+             JOIN messages m ON m.id = messages_fts.rowid
+             JOIN sessions s ON s.id = m.session
+             WHERE messages_fts MATCH ?1
+             ORDER BY rank LIMIT ?2",
+        )?;
+        stmt.query_map(params![query, limit as i64], |row| {
+            Ok(Hit {
+                session: row.get(0)?,
+                title: self.title(row.get(1)?, row.get(7)?),
+                branch: row.get::<_, i64>(2)? as usize,
+                position: row.get::<_, i64>(3)? as usize,
+                role: row.get(4)?,
+                snippet: row.get(5)?,
+                created: row.get(6)?,
+            })
+        })?
+        .collect()
+    }
+
+    /// Newest first, matching every word of `text` case-insensitively
+    fn search_encrypted(&self, text: &str, limit: usize) -> Result<Vec<Hit>> {
+        let words: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+        let mut stmt = self.conn.prepare(
+            "SELECT m.session, s.title, m.branch, m.position, m.role, m.content, m.created,
+                    s.title_encrypted
+             FROM messages m JOIN sessions s ON s.id = m.session
+             WHERE m.encrypted ORDER BY m.id DESC",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut hits = Vec::new();
+        while hits.len() < limit
+            && let Some(row) = rows.next()?
+        {
+            let content = self.plaintext(row.get(5)?, true);
+            let lower = content.to_lowercase();
+            if !words.iter().all(|w| lower.contains(w.as_str())) {
+                continue;
+            }
+            hits.push(Hit {
+                session: row.get(0)?,
+                title: self.title(row.get(1)?, row.get(7)?),
+                branch: row.get::<_, i64>(2)? as usize,
+                position: row.get::<_, i64>(3)? as usize,
+                role: row.get(4)?,
+                snippet: snippet(&content, &words[0]),
+                created: row.get(6)?,
+            });
+        }
+        Ok(hits)
+    }
+
+    /// Stored contents as text, or `LOCKED` when encrypted and there's no key to open them
+    fn plaintext(&self, content: String, encrypted: bool) -> String {
+        if !encrypted {
+            return content;
+        }
+        self.cipher
+            .as_ref()
+            .and_then(|cipher| cipher.decrypt(&content))
+            .unwrap_or_else(|| LOCKED.to_string())
+    }
+
+    /// `text` as it is to be stored, and whether that is encrypted
+    fn seal(&self, text: &str) -> (String, bool) {
+        match &self.cipher {
+            Some(cipher) => (cipher.encrypt(text), true),
+            None => (text.to_string(), false),
+        }
+    }
+
+    /// A session title as stored, opened like `plaintext`
+    fn title(&self, title: Option<String>, encrypted: Option<bool>) -> Option<String> {
+        title.map(|title| self.plaintext(title, encrypted.unwrap_or(false)))
+    }
+
+    fn session_row(&self, row: &rusqlite::Row) -> Result<Session> {
+        Ok(Session {
+            id: row.get(0)?,
+            title: self.title(row.get(1)?, row.get(8)?),
+            created: row.get(2)?,
+            updated: row.get(3)?,
+            pinned: row.get(4)?,
+            model: row.get(5)?,
+            provider: row.get(6)?,
+            template: row.get(7)?,
+        })
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_usage(
+        &self,
+        session: Option<i64>,
+        model: &str,
+        prompt_tokens: i64,
+        cached_tokens: i64,
+        response_tokens: i64,
+        total_tokens: i64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO usage (session, timestamp, model, prompt_tokens, cached_tokens,
+                                response_tokens, total_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                session,
+                Local::now(),
+                model,
+                prompt_tokens,
+                cached_tokens,
+                response_tokens,
+                total_tokens
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Usage since `since`, or of all time
+    pub fn usage_totals(&self, since: Option<DateTime<Local>>) -> Result<UsageTotals> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(prompt_tokens), 0), COALESCE(SUM(cached_tokens), 0),
+                    COALESCE(SUM(response_tokens), 0), COALESCE(SUM(total_tokens), 0)
+             FROM usage WHERE ?1 IS NULL OR timestamp >= ?1",
+            params![since],
+            |row| {
+                Ok(UsageTotals {
+                    requests: row.get(0)?,
+                    prompt_tokens: row.get(1)?,
+                    cached_tokens: row.get(2)?,
+                    response_tokens: row.get(3)?,
+                    total_tokens: row.get(4)?,
+                })
+            },
+        )
+    }
+
+    /// Requests made from `from` until before `to`, oldest first
+    pub fn usage(
+        &self,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+    ) -> Result<Vec<UsageRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT u.timestamp, u.session, s.title, u.model, u.prompt_tokens,
+                    u.cached_tokens, u.response_tokens, u.total_tokens, s.title_encrypted
+             FROM usage u LEFT JOIN sessions s ON s.id = u.session
+             WHERE (?1 IS NULL OR u.timestamp >= ?1) AND (?2 IS NULL OR u.timestamp < ?2)
+             ORDER BY u.id",
+        )?;
+        stmt.query_map(params![from, to], |row| {
+            Ok(UsageRow {
+                timestamp: row.get(0)?,
+                session: row.get(1)?,
+                title: self.title(row.get(2)?, row.get(8)?),
+                model: row.get(3)?,
+                prompt_tokens: row.get(4)?,
+                cached_tokens: row.get(5)?,
+                response_tokens: row.get(6)?,
+                total_tokens: row.get(7)?,
+            })
+        })?
+        .collect()
+    }
+
+    pub fn record_audit(&self, entry: &audit::Entry) -> Result<()> {
+        let (args, encrypted) = self.seal(&entry.args.to_string());
+        self.conn.execute(
+            "INSERT INTO audit (timestamp, tool, args, encrypted, status, approval, output_bytes,
+                                output_sha256)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.timestamp,
+                entry.tool,
+                args,
+                encrypted,
+                to_json(&entry.status),
+                to_json(&entry.approval),
+                entry.output_bytes as i64,
+                entry.output_sha256
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The last `limit` audit entries, oldest first. Rows that fail to parse are skipped;
+    /// encrypted arguments are `LOCKED` while the store is.
+    pub fn recent_audit(&self, limit: usize) -> Result<Vec<audit::Entry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, tool, args, status, approval, output_bytes, output_sha256,
+                    encrypted
+             FROM (SELECT * FROM audit ORDER BY id DESC LIMIT ?1) ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            let args = self.plaintext(row.get(2)?, row.get(7)?);
+            let status: String = row.get(3)?;
+            let approval: String = row.get(4)?;
+            let args = if args == LOCKED {
+                Ok(Value::String(args))
+            } else {
+                serde_json::from_str(&args)
+            };
+            let (Ok(args), Ok(status), Ok(approval)) = (
+                args,
+                serde_json::from_str(&status),
+                serde_json::from_str(&approval),
+            ) else {
+                return Ok(None);
+            };
+            Ok(Some(audit::Entry {
+                timestamp: row.get(0)?,
+                tool: row.get(1)?,
+                args,
+                status,
+                approval,
+                output_bytes: row.get::<_, i64>(5)? as usize,
+                output_sha256: row.get(6)?,
+            }))
+        })?;
+        Ok(rows.filter_map(|row| row.ok().flatten()).collect())
+    }
+
+    /// Whether the audit table already has `entry`, the same call at the same time, so the
+    /// old log can be imported again without duplicating rows
+    pub fn has_audit_entry(&self, entry: &audit::Entry) -> Result<bool> {
+        // Encrypted arguments can't be compared in SQL
+        let args = entry.args.to_string();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT args, encrypted FROM audit WHERE timestamp = ?1 AND tool = ?2")?;
+        let mut rows = stmt.query(params![entry.timestamp, entry.tool])?;
+        while let Some(row) = rows.next()? {
+            if self.plaintext(row.get(0)?, row.get(1)?) == args {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn upsert_message(
+    conn: &Connection,
+    cipher: Option<&Cipher>,
+    session: i64,
+    branch: usize,
+    position: usize,
+    message: &StoredMessage,
+) -> Result<()> {
+    let content = match cipher {
+        Some(cipher) => cipher.encrypt(&message.content),
+        None => message.content.clone(),
+    };
+    conn.execute(
+        "INSERT INTO messages (session, branch, position, role, content, encrypted, model,
+                               generation, created)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT (session, branch, position) DO UPDATE SET
+             role = excluded.role, content = excluded.content, encrypted = excluded.encrypted,
+             model = excluded.model, generation = excluded.generation,
+             created = excluded.created",
+        params![
+            session,
+            branch as i64,
+            position as i64,
+            message.role,
+            content,
+            cipher.is_some(),
+            message.model,
+            message.generation,
+            message.created
+        ],
+    )?;
+    Ok(())
+}
+
+fn touch(conn: &Connection, session: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE sessions SET updated = ?2 WHERE id = ?1",
+        params![session, Local::now()],
+    )?;
+    Ok(())
+}
+
+/// `text` as an FTS5 query: every word quoted, so punctuation can't be read as syntax
+fn fts_query(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let mut query = words.join(" ");
+    if !text.ends_with(char::is_whitespace) {
+        query.push('*');
+    }
+    Some(query)
+}
+
+/// About a line of `content` around the first occurrence of the lowercase `word`, ignoring
+/// case and marked like FTS5 snippets
+fn snippet(content: &str, word: &str) -> String {
+    const AROUND: usize = 40;
+
+    // Lowercasing can turn one character into several, so each lowercased character
+    // remembers which one of `content` it came from
+    let chars: Vec<char> = content.chars().collect();
+    let (lower, origin): (Vec<char>, Vec<usize>) = chars
+        .iter()
+        .enumerate()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |lower| (lower, i)))
+        .unzip();
+    let word: Vec<char> = word.chars().collect();
+    let found = match word.len() {
+        0 => None,
+        len => lower.windows(len).position(|window| window == word),
+    };
+    let Some(first) = found else {
+        return chars.iter().take(2 * AROUND).collect();
+    };
+    let at = origin[first];
+    let end = origin[first + word.len() - 1] + 1;
+    let start = at.saturating_sub(AROUND);
+    let tail = (end + AROUND).min(chars.len());
+    format!(
+        "{}{}[{}]{}{}",
+        if start > 0 { "…" } else { "" },
+        chars[start..at].iter().collect::<String>(),
+        chars[at..end].iter().collect::<String>(),
+        chars[end..tail].iter().collect::<String>(),
+        if tail < chars.len() { "…" } else { "" },
+    )
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
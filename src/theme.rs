@@ -0,0 +1,299 @@
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use ratatui::buffer::Buffer;
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+
+pub const BUILTIN: &[&str] = &["dark", "light", "solarized", "high-contrast", "plain"];
+
+/// Every color the TUI draws with. Colors are names (`"light-blue"`), indexes (`"42"`)
+/// or hex (`"#268bd2"`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Theme {
+    /// Sidebar border and text
+    pub sidebar: Color,
+    /// Message text
+    pub text: Color,
+    /// Secondary text: tool output frames, code fences, hints
+    pub dim: Color,
+    pub user: Color,
+    pub ai: Color,
+    pub error: Color,
+    /// System messages and other roles
+    pub system: Color,
+    /// Spinner, tool names and prompts waiting for the user
+    pub accent: Color,
+    pub success: Color,
+    pub link: Color,
+    /// Input box border while typing
+    pub input_active: Color,
+    /// Input box border in normal mode
+    pub input_inactive: Color,
+    /// Background of your messages; `reset` leaves the terminal's
+    pub user_bg: Color,
+    /// Background of the model's messages
+    pub ai_bg: Color,
+    /// Background of tool calls and their output
+    pub tool_bg: Color,
+    /// Syntect theme for code blocks; empty leaves code unhighlighted
+    pub code: String,
+}
+
+impl Theme {
+    pub fn builtin(name: &str) -> Option<Self> {
+        let theme = match name {
+            "dark" => Self {
+                sidebar: Color::Cyan,
+                text: Color::White,
+                dim: Color::DarkGray,
+                user: Color::Blue,
+                ai: Color::Green,
+                error: Color::Red,
+                system: Color::Yellow,
+                accent: Color::Yellow,
+                success: Color::Green,
+                link: Color::Blue,
+                input_active: Color::Yellow,
+                input_inactive: Color::DarkGray,
+                user_bg: Color::Rgb(0x1b, 0x21, 0x30),
+                ai_bg: Color::Reset,
+                tool_bg: Color::Rgb(0x1c, 0x1c, 0x1c),
+                code: "base16-ocean.dark".into(),
+            },
+            "light" => Self {
+                sidebar: Color::Blue,
+                text: Color::Black,
+                dim: Color::Gray,
+                user: Color::Blue,
+                ai: Color::Rgb(0, 128, 0),
+                error: Color::Red,
+                system: Color::Rgb(160, 100, 0),
+                accent: Color::Magenta,
+                success: Color::Rgb(0, 128, 0),
+                link: Color::Blue,
+                input_active: Color::Magenta,
+                input_inactive: Color::Gray,
+                user_bg: Color::Rgb(0xea, 0xf0, 0xfa),
+                ai_bg: Color::Reset,
+                tool_bg: Color::Rgb(0xf2, 0xf2, 0xf2),
+                code: "InspiredGitHub".into(),
+            },
+            "solarized" => Self {
+                sidebar: Color::Rgb(0x2a, 0xa1, 0x98),
+                text: Color::Rgb(0x93, 0xa1, 0xa1),
+                dim: Color::Rgb(0x58, 0x6e, 0x75),
+                user: Color::Rgb(0x26, 0x8b, 0xd2),
+                ai: Color::Rgb(0x85, 0x99, 0x00),
+                error: Color::Rgb(0xdc, 0x32, 0x2f),
+                system: Color::Rgb(0xb5, 0x89, 0x00),
+                accent: Color::Rgb(0xcb, 0x4b, 0x16),
+                success: Color::Rgb(0x85, 0x99, 0x00),
+                link: Color::Rgb(0x6c, 0x71, 0xc4),
+                input_active: Color::Rgb(0xb5, 0x89, 0x00),
+                input_inactive: Color::Rgb(0x58, 0x6e, 0x75),
+                user_bg: Color::Rgb(0x07, 0x36, 0x42),
+                ai_bg: Color::Reset,
+                tool_bg: Color::Rgb(0x03, 0x30, 0x3c),
+                code: "Solarized (dark)".into(),
+            },
+            "high-contrast" => Self {
+                sidebar: Color::White,
+                text: Color::White,
+                dim: Color::Gray,
+                user: Color::LightCyan,
+                ai: Color::LightGreen,
+                error: Color::LightRed,
+                system: Color::LightYellow,
+                accent: Color::LightYellow,
+                success: Color::LightGreen,
+                link: Color::LightCyan,
+                input_active: Color::LightYellow,
+                input_inactive: Color::White,
+                // Text on shading is harder to read
+                user_bg: Color::Reset,
+                ai_bg: Color::Reset,
+                tool_bg: Color::Reset,
+                code: "base16-eighties.dark".into(),
+            },
+            // The terminal's own colors only, for screen readers and monochrome displays
+            "plain" => Self {
+                sidebar: Color::Reset,
+                text: Color::Reset,
+                dim: Color::Reset,
+                user: Color::Reset,
+                ai: Color::Reset,
+                error: Color::Reset,
+                system: Color::Reset,
+                accent: Color::Reset,
+                success: Color::Reset,
+                link: Color::Reset,
+                input_active: Color::Reset,
+                input_inactive: Color::Reset,
+                user_bg: Color::Reset,
+                ai_bg: Color::Reset,
+                tool_bg: Color::Reset,
+                code: String::new(),
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    /// The built-in theme `name` with the colors from `overrides` on top
+    pub fn resolve(name: &str, overrides: &toml::Table) -> Result<Self> {
+        let base = Self::builtin(name).ok_or_else(|| {
+            eyre!(
+                "Unknown theme `{}` (available: {})",
+                name,
+                BUILTIN.join(", ")
+            )
+        })?;
+        if overrides.is_empty() {
+            return Ok(base);
+        }
+
+        let mut table = toml::Table::try_from(&base)?;
+        table.extend(overrides.clone());
+        toml::Value::Table(table)
+            .try_into()
+            .wrap_err("Invalid color in [theme.colors]")
+    }
+}
+
+/// Colors the terminal can show. Anything richer is drawn with the nearest it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ColorDepth {
+    /// `NO_COLOR`: the terminal's default colors only
+    #[serde(rename = "none")]
+    None,
+    /// The 16 ANSI colors
+    #[serde(rename = "16")]
+    Ansi16,
+    /// The xterm 256-color palette
+    #[serde(rename = "256")]
+    Ansi256,
+    /// 24-bit RGB
+    #[serde(rename = "truecolor")]
+    TrueColor,
+}
+
+/// RGB of the 16 ANSI colors as xterm shows them
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Levels of each channel in the 6×6×6 cube of the 256-color palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl ColorDepth {
+    /// What the terminal described by the environment supports
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// [`Self::detect`] reading variables through `var`
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        // https://no-color.org: set and not empty
+        if var("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+            return ColorDepth::None;
+        }
+        let colorterm = var("COLORTERM").unwrap_or_default();
+        if matches!(colorterm.as_str(), "truecolor" | "24bit") || var("WT_SESSION").is_some() {
+            return ColorDepth::TrueColor;
+        }
+        let term = var("TERM").unwrap_or_default();
+        match term.as_str() {
+            "dumb" => ColorDepth::None,
+            t if t.contains("direct") => ColorDepth::TrueColor,
+            t if t.contains("256color") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+
+    /// `color` as the nearest one the terminal can show
+    pub fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, color) | (_, color @ Color::Reset) => color,
+            (ColorDepth::None, _) => Color::Reset,
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(nearest_256((r, g, b))),
+            (ColorDepth::Ansi256, color) => color,
+            (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => nearest_16((r, g, b)),
+            (ColorDepth::Ansi16, Color::Indexed(i)) if i < 16 => ANSI[i as usize].0,
+            (ColorDepth::Ansi16, Color::Indexed(i)) => nearest_16(indexed_rgb(i)),
+            (ColorDepth::Ansi16, color) => color,
+        }
+    }
+
+    /// Adapts every cell of a drawn frame before it is written to the terminal
+    pub fn adapt_buffer(self, buffer: &mut Buffer) {
+        if self == ColorDepth::TrueColor {
+            return;
+        }
+        for cell in &mut buffer.content {
+            cell.fg = self.adapt(cell.fg);
+            cell.bg = self.adapt(cell.bg);
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> Color {
+    ANSI.iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+/// The closest of the cube and the gray ramp of the 256-color palette
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| {
+        (0..CUBE.len())
+            .min_by_key(|&i| CUBE[i].abs_diff(c))
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+    let average = (u16::from(rgb.0) + u16::from(rgb.1) + u16::from(rgb.2)) / 3;
+    let gray = 232 + ((average.saturating_sub(3)) / 10).min(23) as u8;
+    if distance(rgb, indexed_rgb(gray)) < distance(rgb, indexed_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// RGB of palette entry `index`
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..16 => ANSI[index as usize].1,
+        16..232 => {
+            let i = index - 16;
+            (
+                CUBE[(i / 36) as usize],
+                CUBE[(i / 6 % 6) as usize],
+                CUBE[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
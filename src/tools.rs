@@ -1,27 +1,393 @@
+use crate::config::{CustomTool, SandboxConfig, ToolsConfig};
+use crate::html::{self, collapse_whitespace};
+use crate::memory;
+use futures_util::StreamExt;
+use grep::regex::{RegexMatcher, RegexMatcherBuilder};
+use grep::searcher::{BinaryDetection, SearcherBuilder, sinks::UTF8};
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
+use scraper::{Html, Selector};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::path::{Component, Path, PathBuf};
 use std::process::Stdio;
+use std::time::Duration;
 use tokio::fs;
 use tokio::process::Command;
 
+/// Tools implemented in this module; user-defined tools may not shadow them
+const BUILTIN_TOOLS: &[&str] = &[
+    "run_command",
+    "create_file",
+    "update_file",
+    "delete_file",
+    "search_google",
+    "fetch_url",
+    "grep",
+    "remember",
+    "recall",
+];
+
+/// Output of a tool together with how it ended
+pub struct Execution {
+    pub output: String,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// A spawned program exited with this code
+    Exit(i32),
+    /// A spawned program was killed by a signal
+    Signal,
+    /// The tool reported an error (bad arguments, I/O failure, timeout, ...)
+    Error,
+}
+
+impl Execution {
+    /// A tool that failed before or instead of running anything, with `output` saying why
+    pub fn error(output: String) -> Self {
+        Self {
+            output,
+            status: Status::Error,
+        }
+    }
+}
+
+impl From<Result<String, String>> for Execution {
+    /// Output of a tool that doesn't spawn a process; `Err` holds the message for the model
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(output) => Self {
+                output,
+                status: Status::Ok,
+            },
+            Err(output) => Self::error(output),
+        }
+    }
+}
+
 /// Main entry point for tool execution
-pub async fn execute_tool(name: &str, args: &str) -> String {
+pub async fn execute_tool(name: &str, args: &str, config: &ToolsConfig) -> Execution {
+    if !BUILTIN_TOOLS.contains(&name)
+        && let Some(tool) = config.custom.iter().find(|t| t.name == name)
+    {
+        return run_custom_tool(tool, args).await;
+    }
+
     match name {
-        "run_command" => run_command(args).await,
-        "create_file" => create_file(args).await,
-        "update_file" => update_file(args).await,
-        "delete_file" => delete_file(args).await,
-        "search_google" => search_google(args).await,
-        _ => format!("Error: Unknown tool '{}'", name),
+        "run_command" => run_command(args, config).await,
+        "create_file" => create_file(args).await.into(),
+        "update_file" => update_file(args).await.into(),
+        "delete_file" => delete_file(args).await.into(),
+        "search_google" => search_google(args).await.into(),
+        "fetch_url" => fetch_url(args).await.into(),
+        "remember" => remember(args).into(),
+        "recall" => recall(args).into(),
+        "grep" => grep(args).await.into(),
+        _ => Execution::error(format!("Error: Unknown tool '{}'", name)),
+    }
+}
+
+/// Shrinks a tool result to the configured line/byte budget before it is sent to the
+/// model, keeping the head and tail (where commands usually print what matters) and
+/// replacing the middle with a `[truncated N lines]` marker
+pub fn truncate_output(output: &str, config: &ToolsConfig) -> String {
+    let max_lines = config.max_output_lines();
+    let max_bytes = config.max_output_bytes();
+    if output.len() <= max_bytes && output.lines().count() <= max_lines {
+        return output.to_string();
+    }
+
+    // A single huge line (minified JS, base64, ...) must not eat the whole budget
+    let line_cap = max_bytes / 4;
+    let lines: Vec<String> = output
+        .lines()
+        .map(|line| {
+            if line.len() <= line_cap {
+                line.to_string()
+            } else {
+                let cut = line.floor_char_boundary(line_cap);
+                format!("{}… [+{} bytes]", &line[..cut], line.len() - cut)
+            }
+        })
+        .collect();
+
+    let side_lines = max_lines / 2;
+    let side_bytes = max_bytes / 2;
+
+    let mut head = 0;
+    let mut head_bytes = 0;
+    while head < lines.len() && head < side_lines && head_bytes + lines[head].len() < side_bytes {
+        head_bytes += lines[head].len() + 1;
+        head += 1;
+    }
+
+    let mut tail = 0;
+    let mut tail_bytes = 0;
+    while head + tail < lines.len()
+        && tail < side_lines
+        && tail_bytes + lines[lines.len() - 1 - tail].len() < side_bytes
+    {
+        tail_bytes += lines[lines.len() - 1 - tail].len() + 1;
+        tail += 1;
+    }
+
+    let omitted = lines.len() - head - tail;
+    let mut result = lines[..head].join("\n");
+    if omitted > 0 {
+        result.push_str(&format!("\n... [truncated {} lines] ...\n", omitted));
+    } else {
+        result.push('\n');
+    }
+    result.push_str(&lines[lines.len() - tail..].join("\n"));
+    result
+}
+
+/// Arguments a tool accepts, deserialized from the model's `functionCall.args`
+trait ToolArgs: DeserializeOwned {
+    const TOOL: &'static str;
+    /// Shape of the expected arguments, echoed back to the model on failure
+    const USAGE: &'static str;
+
+    /// Checks that go beyond what the type system enforces
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct RunCommandArgs {
+    command: String,
+}
+
+impl ToolArgs for RunCommandArgs {
+    const TOOL: &'static str = "run_command";
+    const USAGE: &'static str = r#"{"command": string}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("command", &self.command)
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateFileArgs {
+    path: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateFileArgs {
+    path: String,
+    content: String,
+}
+
+impl ToolArgs for CreateFileArgs {
+    const TOOL: &'static str = "create_file";
+    const USAGE: &'static str = r#"{"path": string, "content": string}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("path", &self.path)
+    }
+}
+
+impl ToolArgs for UpdateFileArgs {
+    const TOOL: &'static str = "update_file";
+    const USAGE: &'static str = r#"{"path": string, "content": string}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("path", &self.path)
     }
 }
 
-/// Executes a terminal command via `sh -c`
-async fn run_command(args: &str) -> String {
-    // Assuming the AI passes the raw command string, or parse JSON if formatted as {"command": "..."}
-    let command_str = extract_json_field(args, "command").unwrap_or_else(|| args.to_string());
+#[derive(Deserialize)]
+struct DeleteFileArgs {
+    path: String,
+}
+
+impl ToolArgs for DeleteFileArgs {
+    const TOOL: &'static str = "delete_file";
+    const USAGE: &'static str = r#"{"path": string}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("path", &self.path)
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchArgs {
+    query: String,
+    #[serde(default)]
+    max_results: Option<usize>,
+}
+
+impl ToolArgs for SearchArgs {
+    const TOOL: &'static str = "search_google";
+    const USAGE: &'static str = r#"{"query": string, "max_results"?: integer}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("query", &self.query)
+    }
+}
+
+#[derive(Deserialize)]
+struct FetchUrlArgs {
+    url: String,
+    #[serde(default)]
+    max_chars: Option<usize>,
+}
+
+impl ToolArgs for FetchUrlArgs {
+    const TOOL: &'static str = "fetch_url";
+    const USAGE: &'static str = r#"{"url": string, "max_chars"?: integer}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        match reqwest::Url::parse(&self.url) {
+            Ok(u) if matches!(u.scheme(), "http" | "https") => Ok(()),
+            Ok(u) => Err(format!("unsupported URL scheme `{}`", u.scheme())),
+            Err(e) => Err(format!("`url` is not a valid URL ({})", e)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GrepArgs {
+    pattern: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    glob: Option<String>,
+    #[serde(default)]
+    case_insensitive: bool,
+    #[serde(default)]
+    max_results: Option<usize>,
+}
+
+impl ToolArgs for GrepArgs {
+    const TOOL: &'static str = "grep";
+    const USAGE: &'static str = r#"{"pattern": string, "path"?: string, "glob"?: string, "case_insensitive"?: boolean, "max_results"?: integer}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("pattern", &self.pattern)?;
+        RegexMatcher::new(&self.pattern)
+            .map(|_| ())
+            .map_err(|e| format!("`pattern` is not a valid regex ({})", e))
+    }
+}
+
+#[derive(Deserialize)]
+struct RememberArgs {
+    fact: String,
+}
 
-    match Command::new("sh")
-        .arg("-c")
-        .arg(&command_str)
+impl ToolArgs for RememberArgs {
+    const TOOL: &'static str = "remember";
+    const USAGE: &'static str = r#"{"fact": string}"#;
+
+    fn validate(&self) -> Result<(), String> {
+        require_non_empty("fact", &self.fact)
+    }
+}
+
+#[derive(Deserialize)]
+struct RecallArgs {
+    #[serde(default)]
+    query: String,
+}
+
+impl ToolArgs for RecallArgs {
+    const TOOL: &'static str = "recall";
+    const USAGE: &'static str = r#"{"query"?: string}"#;
+}
+
+/// Parses and validates tool arguments. The error is phrased for the model so it
+/// can correct the call instead of the tool running with silently defaulted values.
+fn parse_args<T: ToolArgs>(args: &str) -> Result<T, String> {
+    let parsed = serde_json::from_str::<T>(args).map_err(|e| {
+        let msg = e.to_string();
+        // Positions refer to the serialized args and mean nothing to the model
+        let msg = msg
+            .rsplit_once(" at line ")
+            .map_or(msg.as_str(), |(m, _)| m);
+        invalid_args::<T>(msg)
+    })?;
+    parsed.validate().map_err(|msg| invalid_args::<T>(&msg))?;
+    Ok(parsed)
+}
+
+fn invalid_args<T: ToolArgs>(reason: &str) -> String {
+    invalid_args_for(T::TOOL, reason, T::USAGE)
+}
+
+fn invalid_args_for(tool: &str, reason: &str, usage: &str) -> String {
+    format!(
+        "Error: invalid arguments for '{}': {}. Expected arguments: {}. Fix the arguments and call the tool again.",
+        tool, reason, usage
+    )
+}
+
+fn require_non_empty(field: &str, value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err(format!("field `{}` must not be empty", field))
+    } else {
+        Ok(())
+    }
+}
+
+/// Executes a terminal command via the configured shell (`sh -c`, `cmd /C` or PowerShell)
+async fn run_command(args: &str, config: &ToolsConfig) -> Execution {
+    let args: RunCommandArgs = match parse_args(args) {
+        Ok(a) => a,
+        Err(e) => return Execution::error(e),
+    };
+
+    if config.sandbox.enabled() {
+        return match sandboxed_command(&config.sandbox, &args.command) {
+            Ok(command) => run_process(command).await,
+            Err(e) => Execution::error(format!("Error: could not start the sandbox: {}", e)),
+        };
+    }
+
+    let (program, shell_args) = config.shell().invocation();
+    let mut command = Command::new(program);
+    command.args(shell_args).arg(&args.command);
+    run_process(command).await
+}
+
+/// `<runtime> run --rm` with only the working directory mounted, running `sh -c <command>`
+fn sandboxed_command(sandbox: &SandboxConfig, command_line: &str) -> std::io::Result<Command> {
+    let project = std::env::current_dir()?;
+
+    let mut command = Command::new(sandbox.runtime());
+    command
+        .args(["run", "--rm", "-i", "-w", "/workspace"])
+        .arg("-v")
+        .arg(format!("{}:/workspace", project.display()));
+    if !sandbox.network() {
+        command.args(["--network", "none"]);
+    }
+    // Files the command creates should belong to the user, not to root
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = std::fs::metadata(&project)?;
+        command
+            .arg("--user")
+            .arg(format!("{}:{}", meta.uid(), meta.gid()));
+    }
+    command
+        .args(&sandbox.args)
+        .arg(sandbox.image())
+        .args(["sh", "-c", command_line])
+        .kill_on_drop(true);
+    Ok(command)
+}
+
+async fn run_process(mut command: Command) -> Execution {
+    match command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .output()
@@ -30,91 +396,554 @@ async fn run_command(args: &str) -> String {
         Ok(out) => {
             let stdout = String::from_utf8_lossy(&out.stdout);
             let stderr = String::from_utf8_lossy(&out.stderr);
-            format!("STDOUT:\n{}\nSTDERR:\n{}", stdout, stderr)
+            Execution {
+                output: format!("STDOUT:\n{}\nSTDERR:\n{}", stdout, stderr),
+                status: out.status.code().map_or(Status::Signal, Status::Exit),
+            }
         }
-        Err(e) => format!("Failed to execute command: {}", e),
+        Err(e) => Execution::error(format!("Failed to execute command: {}", e)),
     }
 }
 
-/// Creates a new file
-async fn create_file(args: &str) -> String {
-    let path = extract_json_field(args, "path").unwrap_or_default();
-    let content = extract_json_field(args, "content").unwrap_or_default();
+/// Function declarations for the user-defined tools in the config
+pub fn custom_declarations(config: &ToolsConfig) -> Vec<Value> {
+    config
+        .custom
+        .iter()
+        .filter(|t| !BUILTIN_TOOLS.contains(&t.name.as_str()))
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "description": t.description,
+                "parametersJsonSchema": t.parameters,
+            })
+        })
+        .collect()
+}
+
+/// Runs a user-defined tool by filling its command template with the call's arguments
+async fn run_custom_tool(tool: &CustomTool, args: &str) -> Execution {
+    let usage = tool.parameters.to_string();
+    let args = match serde_json::from_str::<Value>(args) {
+        Ok(Value::Object(map)) => map,
+        Ok(Value::Null) => Map::new(),
+        Ok(_) => {
+            return Execution::error(invalid_args_for(
+                &tool.name,
+                "arguments must be an object",
+                &usage,
+            ));
+        }
+        Err(e) => return Execution::error(invalid_args_for(&tool.name, &e.to_string(), &usage)),
+    };
+
+    let argv = match render_command(tool, &args) {
+        Ok(argv) => argv,
+        Err(CommandError::Args(reason)) => {
+            return Execution::error(invalid_args_for(&tool.name, &reason, &usage));
+        }
+        Err(CommandError::Config(reason)) => {
+            return Execution::error(format!(
+                "Error: tool '{}' is misconfigured: {}",
+                tool.name, reason
+            ));
+        }
+    };
 
-    if path.is_empty() {
-        return "Error: 'path' is required".into();
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).kill_on_drop(true);
+    let timeout = Duration::from_secs(tool.timeout_secs.unwrap_or(60));
+    match tokio::time::timeout(timeout, run_process(command)).await {
+        Ok(out) => out,
+        Err(_) => Execution::error(format!(
+            "Error: '{}' timed out after {}s",
+            tool.name,
+            timeout.as_secs()
+        )),
     }
+}
+
+enum CommandError {
+    /// The model passed bad arguments; it can retry
+    Args(String),
+    /// The template itself is broken; only the user can fix it
+    Config(String),
+}
 
-    match fs::write(&path, content).await {
-        Ok(_) => format!("Successfully created/written to {}", path),
-        Err(e) => format!("Error writing file: {}", e),
+fn render_command(
+    tool: &CustomTool,
+    args: &Map<String, Value>,
+) -> Result<Vec<String>, CommandError> {
+    let schema = &tool.parameters;
+    for field in schema["required"].as_array().into_iter().flatten() {
+        if let Some(field) = field.as_str()
+            && args.get(field).is_none_or(Value::is_null)
+        {
+            return Err(CommandError::Args(format!(
+                "missing required field `{}`",
+                field
+            )));
+        }
     }
+    for (key, value) in args {
+        if let Some(allowed) = schema["properties"][key]["enum"].as_array()
+            && !allowed.contains(value)
+        {
+            return Err(CommandError::Args(format!(
+                "`{}` must be one of {}",
+                key,
+                Value::Array(allowed.clone())
+            )));
+        }
+    }
+
+    match tool.command.first() {
+        None => return Err(CommandError::Config("`command` is empty".into())),
+        Some(program) if program.contains('{') => {
+            return Err(CommandError::Config(
+                "the program name cannot be a placeholder".into(),
+            ));
+        }
+        Some(_) => {}
+    }
+
+    let mut argv = Vec::with_capacity(tool.command.len());
+    for part in &tool.command {
+        if let Some(rendered) = substitute(part, args)? {
+            argv.push(rendered);
+        }
+    }
+    Ok(argv)
 }
 
-/// Updates an existing file (appends content)
-async fn update_file(args: &str) -> String {
-    let path = extract_json_field(args, "path").unwrap_or_default();
-    let content = extract_json_field(args, "content").unwrap_or_default();
+/// Fills `{name}` placeholders (`{{`/`}}` escape braces). Returns `None` when a referenced
+/// argument was not supplied, so optional flags like `--namespace={namespace}` disappear.
+fn substitute(template: &str, args: &Map<String, Value>) -> Result<Option<String>, CommandError> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
 
-    if path.is_empty() {
-        return "Error: 'path' is required".into();
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err(CommandError::Config(format!(
+                "unmatched '}}' in `{}`",
+                template
+            )));
+        }
+        let Some(end) = tail.find('}') else {
+            return Err(CommandError::Config(format!(
+                "unclosed '{{' in `{}`",
+                template
+            )));
+        };
+
+        let name = &tail[1..end];
+        let value = match args.get(name) {
+            None | Some(Value::Null) => return Ok(None),
+            // Values become whole argv entries, but a leading '-' would still smuggle in flags
+            Some(Value::String(s)) if s.starts_with('-') => {
+                return Err(CommandError::Args(format!(
+                    "`{}` may not start with '-'",
+                    name
+                )));
+            }
+            Some(Value::String(s)) => s.clone(),
+            Some(v @ (Value::Number(_) | Value::Bool(_))) => v.to_string(),
+            Some(_) => {
+                return Err(CommandError::Args(format!(
+                    "`{}` must be a string, number or boolean",
+                    name
+                )));
+            }
+        };
+        out.push_str(&value);
+        rest = &tail[end + 1..];
     }
+    out.push_str(rest);
+    Ok(Some(out))
+}
+
+/// Creates a new file
+async fn create_file(args: &str) -> Result<String, String> {
+    let args: CreateFileArgs = parse_args(args)?;
+    let path = resolve_path(&args.path);
+
+    match fs::write(&path, args.content).await {
+        Ok(_) => Ok(format!(
+            "Successfully created/written to {}",
+            path.display()
+        )),
+        Err(e) => Err(format!("Error writing file: {}", e)),
+    }
+}
+
+/// Updates an existing file (appends content)
+async fn update_file(args: &str) -> Result<String, String> {
+    let args: UpdateFileArgs = parse_args(args)?;
+    let path = resolve_path(&args.path);
 
     use tokio::io::AsyncWriteExt;
     match fs::OpenOptions::new().append(true).open(&path).await {
         Ok(mut file) => {
-            if let Err(e) = file.write_all(content.as_bytes()).await {
-                return format!("Error writing to file: {}", e);
+            if let Err(e) = file.write_all(args.content.as_bytes()).await {
+                return Err(format!("Error writing to file: {}", e));
             }
-            format!("Successfully updated {}", path)
+            Ok(format!("Successfully updated {}", path.display()))
         }
-        Err(e) => format!("Error opening file: {}", e),
+        Err(e) => Err(format!("Error opening file: {}", e)),
     }
 }
 
 /// Deletes a file
-async fn delete_file(args: &str) -> String {
-    let path = extract_json_field(args, "path").unwrap_or_else(|| args.to_string());
+async fn delete_file(args: &str) -> Result<String, String> {
+    let args: DeleteFileArgs = parse_args(args)?;
+    let path = resolve_path(&args.path);
 
     match fs::remove_file(&path).await {
-        Ok(_) => format!("Successfully deleted {}", path),
-        Err(e) => format!("Error deleting file: {}", e),
+        Ok(_) => Ok(format!("Successfully deleted {}", path.display())),
+        Err(e) => Err(format!("Error deleting file: {}", e)),
     }
 }
 
-/// Performs a simple google search
-async fn search_google(args: &str) -> String {
-    let query = extract_json_field(args, "query").unwrap_or_else(|| args.to_string());
+/// Performs a web search (via DuckDuckGo's HTML endpoint) and returns the top results
+async fn search_google(args: &str) -> Result<String, String> {
+    const DEFAULT_RESULTS: usize = 5;
+    const MAX_RESULTS: usize = 10;
+
+    let args: SearchArgs = parse_args(args)?;
+    let limit = args
+        .max_results
+        .unwrap_or(DEFAULT_RESULTS)
+        .clamp(1, MAX_RESULTS);
 
     let url = match reqwest::Url::parse_with_params(
         "https://html.duckduckgo.com/html/",
-        &[("q", &query)],
+        &[("q", &args.query)],
     ) {
         Ok(u) => u,
-        Err(e) => return format!("URL builder error: {}", e),
+        Err(e) => return Err(format!("URL builder error: {}", e)),
     };
-    match reqwest::get(url).await {
-        Ok(res) => {
-            if let Ok(text) = res.text().await {
-                // Return a simplified snippet of the HTML or just the success text
-                format!(
-                    "Search returned {} bytes. (Consider parsing this with scraper/visdom)",
-                    text.len()
-                )
-            } else {
-                "Failed to read response text".into()
+    let request = reqwest::Client::new().get(url).header(
+        reqwest::header::USER_AGENT,
+        "Mozilla/5.0 (compatible; gemchat)",
+    );
+    let html = match request.send().await {
+        Ok(res) => match res.text().await {
+            Ok(text) => text,
+            Err(_) => return Err("Failed to read response text".into()),
+        },
+        Err(e) => return Err(format!("Search request failed: {}", e)),
+    };
+
+    let results = parse_search_results(&html, limit);
+    if results.is_empty() {
+        return Ok(format!("No results found for \"{}\".", args.query));
+    }
+
+    let mut out = format!("Search results for \"{}\":\n", args.query);
+    for (i, r) in results.iter().enumerate() {
+        out.push_str(&format!("\n{}. {}\n   {}\n", i + 1, r.title, r.url));
+        if !r.snippet.is_empty() {
+            out.push_str(&format!("   {}\n", r.snippet));
+        }
+    }
+    Ok(out)
+}
+
+/// Downloads a page and returns it as readable markdown
+async fn fetch_url(args: &str) -> Result<String, String> {
+    const MAX_DOWNLOAD_BYTES: usize = 5 * 1024 * 1024;
+    const DEFAULT_CHARS: usize = 20_000;
+    const MAX_CHARS: usize = 100_000;
+
+    let args: FetchUrlArgs = parse_args(args)?;
+    let max_chars = args
+        .max_chars
+        .unwrap_or(DEFAULT_CHARS)
+        .clamp(500, MAX_CHARS);
+
+    let client = match reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (compatible; gemchat)")
+        .timeout(std::time::Duration::from_secs(20))
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => return Err(format!("HTTP client error: {}", e)),
+    };
+    let res = match client.get(&args.url).send().await {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Fetch failed: {}", e)),
+    };
+    if !res.status().is_success() {
+        return Err(format!("Fetch failed: HTTP {}", res.status()));
+    }
+
+    let final_url = res.url().clone();
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/html")
+        .to_ascii_lowercase();
+    let is_html = content_type.contains("html");
+    if !is_html
+        && !content_type.starts_with("text/")
+        && !content_type.contains("json")
+        && !content_type.contains("xml")
+    {
+        return Err(format!(
+            "Error: unsupported content type '{}'",
+            content_type
+        ));
+    }
+
+    // Stop reading once the cap is hit instead of buffering arbitrarily large bodies
+    let mut body = Vec::new();
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(bytes) => {
+                body.extend_from_slice(&bytes);
+                if body.len() >= MAX_DOWNLOAD_BYTES {
+                    body.truncate(MAX_DOWNLOAD_BYTES);
+                    break;
+                }
             }
+            Err(e) => return Err(format!("Fetch failed while reading body: {}", e)),
+        }
+    }
+    let body = String::from_utf8_lossy(&body);
+
+    let mut out = String::new();
+    let text = if is_html {
+        let page = html::to_markdown(&body, Some(&final_url));
+        if let Some(title) = page.title {
+            out.push_str(&format!("# {}\n", title));
+        }
+        page.text
+    } else {
+        body.into_owned()
+    };
+    out.push_str(&format!("URL: {}\n\n", final_url));
+
+    if text.chars().count() > max_chars {
+        let cut = text
+            .char_indices()
+            .nth(max_chars)
+            .map_or(text.len(), |(i, _)| i);
+        out.push_str(&text[..cut]);
+        out.push_str(&format!(
+            "\n\n[content truncated: {} more characters]",
+            text[cut..].chars().count()
+        ));
+    } else {
+        out.push_str(&text);
+    }
+    Ok(out)
+}
+
+/// Searches files under a directory for a regex, honoring .gitignore like ripgrep does
+async fn grep(args: &str) -> Result<String, String> {
+    let args: GrepArgs = parse_args(args)?;
+
+    // The walk and search are blocking filesystem work
+    match tokio::task::spawn_blocking(move || grep_blocking(&args)).await {
+        Ok(result) => result.map_err(|e| format!("Error: {}", e)),
+        Err(e) => Err(format!("Error: search task failed: {}", e)),
+    }
+}
+
+fn grep_blocking(args: &GrepArgs) -> Result<String, String> {
+    const DEFAULT_RESULTS: usize = 50;
+    const MAX_RESULTS: usize = 500;
+    const MAX_LINE_CHARS: usize = 200;
+
+    let limit = args
+        .max_results
+        .unwrap_or(DEFAULT_RESULTS)
+        .clamp(1, MAX_RESULTS);
+    let matcher = RegexMatcherBuilder::new()
+        .case_insensitive(args.case_insensitive)
+        .build(&args.pattern)
+        .map_err(|e| format!("invalid pattern: {}", e))?;
+
+    let root = resolve_path(args.path.as_deref().unwrap_or("."));
+    let mut walker = WalkBuilder::new(&root);
+    if let Some(glob) = &args.glob {
+        let mut overrides = OverrideBuilder::new(&root);
+        for g in glob.split(',').map(str::trim).filter(|g| !g.is_empty()) {
+            overrides
+                .add(g)
+                .map_err(|e| format!("invalid glob '{}': {}", g, e))?;
+        }
+        walker.overrides(
+            overrides
+                .build()
+                .map_err(|e| format!("invalid glob: {}", e))?,
+        );
+    }
+
+    let mut searcher = SearcherBuilder::new()
+        .binary_detection(BinaryDetection::quit(b'\x00'))
+        .line_number(true)
+        .build();
+
+    let mut matches = Vec::new();
+    let mut limit_hit = false;
+    for entry in walker.build().flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
         }
-        Err(e) => format!("Search request failed: {}", e),
+        let path = entry.path();
+        let shown = path
+            .strip_prefix(&root)
+            .unwrap_or(path)
+            .display()
+            .to_string();
+
+        // Unreadable or non-UTF-8 files are skipped rather than failing the whole search
+        let _ = searcher.search_path(
+            &matcher,
+            path,
+            UTF8(|line_number, line| {
+                if matches.len() >= limit {
+                    limit_hit = true;
+                    return Ok(false);
+                }
+                let line = line.trim_end();
+                let line = match line.char_indices().nth(MAX_LINE_CHARS) {
+                    Some((cut, _)) => format!("{}…", &line[..cut]),
+                    None => line.to_string(),
+                };
+                matches.push(format!("{}:{}: {}", shown, line_number, line));
+                Ok(true)
+            }),
+        );
+        if limit_hit {
+            break;
+        }
+    }
+
+    if matches.is_empty() {
+        return Ok(format!(
+            "No matches for /{}/ in {}",
+            args.pattern,
+            root.display()
+        ));
+    }
+    let mut out = matches.join("\n");
+    if limit_hit {
+        out.push_str(&format!(
+            "\n[stopped after {} matches; narrow the pattern or glob]",
+            limit
+        ));
+    }
+    Ok(out)
+}
+
+/// Stores a fact in the persistent memory
+fn remember(args: &str) -> Result<String, String> {
+    let args: RememberArgs = parse_args(args)?;
+    match memory::add(&args.fact) {
+        Ok(n) => Ok(format!("Remembered (memory #{}): {}", n, args.fact.trim())),
+        Err(e) => Err(format!("Error saving memory: {}", e)),
+    }
+}
+
+/// Looks up stored facts relevant to a query (all of them when the query is empty)
+fn recall(args: &str) -> Result<String, String> {
+    const MAX_RESULTS: usize = 20;
+
+    let args: RecallArgs = parse_args(args)?;
+    let found = memory::search(&args.query).map_err(|e| format!("Error reading memory: {}", e))?;
+    if found.is_empty() {
+        return Ok("No matching memories.".into());
+    }
+    Ok(found
+        .iter()
+        .take(MAX_RESULTS)
+        .map(|(n, m)| format!("#{}: {}", n, m.text))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+struct SearchResult {
+    title: String,
+    url: String,
+    snippet: String,
+}
+
+fn parse_search_results(html: &str, limit: usize) -> Vec<SearchResult> {
+    let doc = Html::parse_document(html);
+    let result_sel = Selector::parse("div.result").unwrap();
+    let title_sel = Selector::parse("a.result__a").unwrap();
+    let snippet_sel = Selector::parse(".result__snippet").unwrap();
+
+    doc.select(&result_sel)
+        .filter(|r| !r.value().classes().any(|c| c == "result--ad"))
+        .filter_map(|r| {
+            let link = r.select(&title_sel).next()?;
+            let title = collapse_whitespace(&link.text().collect::<String>());
+            let url = decode_result_link(link.value().attr("href")?);
+            let snippet = r
+                .select(&snippet_sel)
+                .next()
+                .map(|s| collapse_whitespace(&s.text().collect::<String>()))
+                .unwrap_or_default();
+            Some(SearchResult {
+                title,
+                url,
+                snippet,
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Result links go through a redirect (`//duckduckgo.com/l/?uddg=<target>&rut=...`);
+/// unwrap it so the model gets the real URL
+fn decode_result_link(href: &str) -> String {
+    let absolute = match href.strip_prefix("//") {
+        Some(rest) => format!("https://{}", rest),
+        None => href.to_string(),
+    };
+    reqwest::Url::parse(&absolute)
+        .ok()
+        .and_then(|u| {
+            u.query_pairs()
+                .find(|(k, _)| k == "uddg")
+                .map(|(_, v)| v.into_owned())
+        })
+        .unwrap_or(absolute)
+}
+
+/// Turns a model-supplied path into a native one: expands a leading `~` and, on
+/// Windows, rebuilds the path from its components so mixed `/` and `\` separators work
+pub fn resolve_path(raw: &str) -> PathBuf {
+    let raw = raw.trim();
+    let expanded = match raw.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => match dirs::home_dir() {
+            Some(home) => home.join(rest.trim_start_matches(['/', '\\'])),
+            None => PathBuf::from(raw),
+        },
+        _ => PathBuf::from(raw),
+    };
+
+    if cfg!(windows) {
+        normalize_separators(&expanded)
+    } else {
+        expanded
     }
 }
 
-/// Helper to parse basic tool JSON payload if the LLM uses Function Calling formatting
-fn extract_json_field(json_str: &str, field: &str) -> Option<String> {
-    // Falls back if serde_json is missing, but highly recommended to add `serde_json`
-    serde_json::from_str::<serde_json::Value>(json_str)
-        .ok()?
-        .get(field)?
-        .as_str()
-        .map(|s| s.to_string())
+fn normalize_separators(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
 }
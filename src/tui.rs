@@ -0,0 +1,117 @@
+use color_eyre::Result;
+use crossterm::event::{
+    DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange,
+};
+use crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::DefaultTerminal;
+use ratatui::backend::CrosstermBackend;
+use std::io::stdout;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the terminal is in the TUI's modes, so restoring it twice is harmless
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the terminal in the TUI's modes until dropped, which restores it on every way out
+/// of the TUI: a normal return, an early `?` or a panic unwinding past it
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Switches to raw mode and the alternate screen. Focus changes decide whether finished
+/// responses raise a notification; bracketed paste keeps newlines in pasted text from
+/// sending the message.
+pub fn enter() -> Result<(DefaultTerminal, Guard)> {
+    // Made before anything can fail, so a half-entered terminal is restored too
+    let guard = Guard(());
+    resume()?;
+    let terminal = ratatui::Terminal::new(CrosstermBackend::new(stdout()))?;
+    Ok((terminal, guard))
+}
+
+/// Turns the TUI's modes on again after the process was continued. They are set even when
+/// still on, since whatever used the terminal meanwhile may have changed them.
+pub fn resume() -> Result<()> {
+    ACTIVE.store(true, Ordering::SeqCst);
+    enable_raw_mode()?;
+    crossterm::execute!(
+        stdout(),
+        EnterAlternateScreen,
+        EnableFocusChange,
+        EnableBracketedPaste
+    )?;
+    Ok(())
+}
+
+/// Leaves the TUI's modes and stops the process like Ctrl+Z in a shell, returning with the
+/// modes back on once it is continued
+#[cfg(unix)]
+pub fn suspend() -> Result<()> {
+    restore();
+    // SIGSTOP, since gemchat catches SIGTSTP to get here
+    // SAFETY: raise only sends a signal to this process
+    if unsafe { libc::raise(libc::SIGSTOP) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    resume()
+}
+
+/// Turns SIGTSTP, e.g. from `kill -TSTP`, into a clean suspend and SIGCONT into a full
+/// redraw, forwarding them as `suspend` and `resumed`
+#[cfg(unix)]
+pub async fn job_control<A>(tx: tokio::sync::mpsc::UnboundedSender<A>, suspend: A, resumed: A)
+where
+    A: Clone,
+{
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let (Ok(mut stop), Ok(mut cont)) = (
+        signal(SignalKind::from_raw(libc::SIGTSTP)),
+        signal(SignalKind::from_raw(libc::SIGCONT)),
+    ) else {
+        tracing::warn!("could not watch job control signals");
+        return;
+    };
+    loop {
+        let action = tokio::select! {
+            Some(()) = stop.recv() => suspend.clone(),
+            Some(()) = cont.recv() => resumed.clone(),
+            else => break,
+        };
+        if tx.send(action).is_err() {
+            break;
+        }
+    }
+}
+
+/// Leaves the TUI's modes, if the terminal is in them
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let _ = crossterm::execute!(
+            stdout(),
+            DisableBracketedPaste,
+            DisableFocusChange,
+            LeaveAlternateScreen,
+            crossterm::cursor::Show
+        );
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Restores the terminal before a panic on the main thread is reported, so the report is
+/// readable and the shell usable afterwards. Panics in background tasks are caught by
+/// tokio and leave the TUI running, so they keep its screen.
+pub fn install_panic_hook() {
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().name() == Some("main") {
+            restore();
+        }
+        report(info);
+    }));
+}
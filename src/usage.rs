@@ -0,0 +1,113 @@
+use chrono::{DateTime, Days, Local, NaiveDate};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use gemchat::{config, pricing, store};
+use serde::Serialize;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// One exported request
+#[derive(Serialize)]
+struct Row {
+    timestamp: DateTime<Local>,
+    session: Option<i64>,
+    session_title: Option<String>,
+    model: String,
+    prompt_tokens: i64,
+    /// The part of `prompt_tokens` served from a context cache
+    cached_tokens: i64,
+    response_tokens: i64,
+    total_tokens: i64,
+    /// Estimated from `[pricing]` and the built-in prices; `None` for unknown models
+    cost_usd: Option<f64>,
+}
+
+/// `gemchat usage export`: writes the requests made from the start of `from` to the end of
+/// `to` to stdout
+pub fn export(
+    config: &config::Config,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: Format,
+) -> Result<()> {
+    let store = store::Store::open()?;
+    let from = from.map(start_of).transpose()?;
+    let to = to
+        .and_then(|day| day.checked_add_days(Days::new(1)))
+        .map(start_of)
+        .transpose()?;
+    let rows: Vec<Row> = store
+        .usage(from, to)?
+        .into_iter()
+        .map(|r| Row {
+            cost_usd: pricing::cost(
+                &config.pricing,
+                &r.model,
+                r.prompt_tokens,
+                r.cached_tokens,
+                r.total_tokens,
+            ),
+            timestamp: r.timestamp,
+            session: r.session,
+            session_title: r.title,
+            model: r.model,
+            prompt_tokens: r.prompt_tokens,
+            cached_tokens: r.cached_tokens,
+            response_tokens: r.response_tokens,
+            total_tokens: r.total_tokens,
+        })
+        .collect();
+
+    let mut out = io::stdout().lock();
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &rows)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(
+                out,
+                "timestamp,session,session_title,model,prompt_tokens,cached_tokens,response_tokens,total_tokens,cost_usd"
+            )?;
+            for row in &rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    row.timestamp.to_rfc3339(),
+                    row.session.map(|s| s.to_string()).unwrap_or_default(),
+                    csv_field(row.session_title.as_deref().unwrap_or_default()),
+                    csv_field(&row.model),
+                    row.prompt_tokens,
+                    row.cached_tokens,
+                    row.response_tokens,
+                    row.total_tokens,
+                    row.cost_usd
+                        .map(|c| format!("{:.6}", c))
+                        .unwrap_or_default()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Local midnight at the start of `day`
+fn start_of(day: NaiveDate) -> Result<DateTime<Local>> {
+    day.and_hms_opt(0, 0, 0)
+        .and_then(|t| t.and_local_timezone(Local).earliest())
+        .ok_or_else(|| eyre!("{} has no local midnight", day))
+}
+
+/// `text` quoted when it holds a separator, quote or line break (RFC 4180)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
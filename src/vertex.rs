@@ -0,0 +1,225 @@
+use crate::config::VertexConfig;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+struct CachedToken {
+    token: String,
+    expires: Instant,
+}
+
+/// Held across a refresh so concurrent requests share one token fetch
+static TOKEN: Mutex<Option<CachedToken>> = Mutex::const_new(None);
+
+/// Endpoint for `method` (e.g. `streamGenerateContent`) on `model`
+pub fn url(config: &VertexConfig, model: &str, method: &str) -> String {
+    format!(
+        "https://{}/v1/{}:{}",
+        host(config),
+        model_name(config, model),
+        method
+    )
+}
+
+/// Endpoint for creating context caches
+pub fn cache_url(config: &VertexConfig) -> String {
+    format!(
+        "https://{}/v1/{}/cachedContents",
+        host(config),
+        parent(config)
+    )
+}
+
+/// Full resource name of `model`, as context caches refer to it
+pub fn model_name(config: &VertexConfig, model: &str) -> String {
+    format!("{}/publishers/google/models/{}", parent(config), model)
+}
+
+fn host(config: &VertexConfig) -> String {
+    match config.location() {
+        "global" => "aiplatform.googleapis.com".to_string(),
+        region => format!("{}-aiplatform.googleapis.com", region),
+    }
+}
+
+fn parent(config: &VertexConfig) -> String {
+    format!(
+        "projects/{}/locations/{}",
+        config.project.as_deref().unwrap_or_default(),
+        config.location()
+    )
+}
+
+/// OAuth access token from Application Default Credentials, cached until shortly before
+/// it expires
+pub async fn access_token(client: &Client) -> Result<String> {
+    let mut cached = TOKEN.lock().await;
+    if let Some(token) = cached.as_ref()
+        && token.expires > Instant::now() + EXPIRY_MARGIN
+    {
+        return Ok(token.token.clone());
+    }
+
+    let fresh = fetch_token(client)
+        .await
+        .wrap_err("Could not get a Google Cloud access token")?;
+    tracing::debug!(expires_in = fresh.expires_in, "refreshed Vertex AI token");
+    let token = fresh.access_token.clone();
+    *cached = Some(CachedToken {
+        token: fresh.access_token,
+        expires: Instant::now() + Duration::from_secs(fresh.expires_in),
+    });
+    Ok(token)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// The credential file types gcloud and the Cloud console produce
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Credentials {
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        token_uri: Option<String>,
+    },
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+}
+
+/// `GOOGLE_APPLICATION_CREDENTIALS`, else the file written by
+/// `gcloud auth application-default login`
+fn credentials_file() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        return Some(path.into());
+    }
+    let dir = match std::env::var_os("CLOUDSDK_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => dirs::config_dir()?.join("gcloud"),
+        None => dirs::home_dir()?.join(".config").join("gcloud"),
+    };
+    let path = dir.join("application_default_credentials.json");
+    path.exists().then_some(path)
+}
+
+async fn fetch_token(client: &Client) -> Result<TokenResponse> {
+    let Some(path) = credentials_file() else {
+        // On Google Cloud the attached service account is available without a file
+        return metadata_token(client).await.wrap_err(
+            "No Application Default Credentials found. Run `gcloud auth application-default login` \
+             or set GOOGLE_APPLICATION_CREDENTIALS",
+        );
+    };
+    let text = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    let credentials: Credentials = serde_json::from_str(&text)
+        .wrap_err_with(|| format!("Unsupported credentials in {}", path.display()))?;
+
+    let (token_uri, body) = match credentials {
+        Credentials::ServiceAccount {
+            client_email,
+            private_key,
+            token_uri,
+        } => {
+            let token_uri = token_uri.unwrap_or_else(|| DEFAULT_TOKEN_URI.to_string());
+            let assertion = signed_jwt(&client_email, &private_key, &token_uri)?;
+            let body = json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "assertion": assertion,
+            });
+            (token_uri, body)
+        }
+        Credentials::AuthorizedUser {
+            client_id,
+            client_secret,
+            refresh_token,
+        } => {
+            let body = json!({
+                "grant_type": "refresh_token",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "refresh_token": refresh_token,
+            });
+            (DEFAULT_TOKEN_URI.to_string(), body)
+        }
+    };
+
+    let resp = client.post(token_uri).json(&body).send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        return Err(eyre!("Token request failed with {}: {}", status, text));
+    }
+    Ok(resp.json().await?)
+}
+
+async fn metadata_token(client: &Client) -> Result<TokenResponse> {
+    let resp = client
+        .get(METADATA_TOKEN_URL)
+        .header("Metadata-Flavor", "Google")
+        .timeout(Duration::from_secs(3))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.json().await?)
+}
+
+/// RS256-signed assertion exchanging a service account key for an access token
+fn signed_jwt(client_email: &str, private_key: &str, token_uri: &str) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let header = json!({ "alg": "RS256", "typ": "JWT" });
+    let claims = json!({
+        "iss": client_email,
+        "scope": SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+
+    let der: String = private_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = STANDARD
+        .decode(der.trim())
+        .wrap_err("Invalid service account private key")?;
+    let key = ring::signature::RsaKeyPair::from_pkcs8(&der)
+        .map_err(|e| eyre!("Invalid service account private key: {}", e))?;
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &ring::signature::RSA_PKCS1_SHA256,
+        &ring::rand::SystemRandom::new(),
+        message.as_bytes(),
+        &mut signature,
+    )
+    .map_err(|_| eyre!("Could not sign the token request"))?;
+
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+}
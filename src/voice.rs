@@ -0,0 +1,324 @@
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use gemchat::config::Config;
+use gemchat::live::{self, LiveEvent};
+use gemchat::{ai, theme};
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Microphone audio is sent in chunks of this many milliseconds
+const CHUNK_MS: u32 = 100;
+
+/// Model speech waiting to be played, at the output device's rate
+type Playback = Arc<Mutex<VecDeque<f32>>>;
+
+/// Who said a line of the transcript
+#[derive(Clone, Copy, PartialEq)]
+enum Speaker {
+    User,
+    Model,
+}
+
+struct Voice {
+    transcript: Vec<(Speaker, String)>,
+    ready: bool,
+    muted: bool,
+    /// Whether the model is answering, from its first words to the end of its turn
+    speaking: bool,
+    status: Option<String>,
+}
+
+impl Voice {
+    /// Adds transcribed text to the last line when `speaker` is still talking
+    fn hear(&mut self, speaker: Speaker, text: &str) {
+        match self.transcript.last_mut() {
+            Some((last, line)) if *last == speaker => line.push_str(text),
+            _ => self
+                .transcript
+                .push((speaker, text.trim_start().to_string())),
+        }
+    }
+}
+
+/// `gemchat live`: a spoken conversation through the Live API, showing the transcript until
+/// `q` or Esc
+pub async fn run(config: &Config, theme: &theme::Theme) -> Result<()> {
+    if config.vertex.enabled() {
+        return Err(eyre!(
+            "`gemchat live` works with the Gemini API only, not Vertex AI"
+        ));
+    }
+    let key = ai::api_key().ok_or_else(|| eyre!("No API key: run `gemchat auth set`"))?;
+
+    let host = cpal::default_host();
+    let microphone = host
+        .default_input_device()
+        .ok_or_else(|| eyre!("No microphone found"))?;
+    let speaker = host
+        .default_output_device()
+        .ok_or_else(|| eyre!("No audio output device found"))?;
+    let input_config = microphone.default_input_config()?;
+    let output_config = speaker.default_output_config()?;
+    let input_rate = input_config.sample_rate().0;
+    let output_rate = output_config.sample_rate().0;
+
+    let (mic_tx, mut mic_rx) = mpsc::unbounded_channel();
+    let playback: Playback = Arc::default();
+    // The streams stop when dropped, so they live until the conversation ends
+    let input = match input_config.sample_format() {
+        SampleFormat::F32 => input_stream::<f32>(&microphone, &input_config.into(), mic_tx),
+        SampleFormat::I16 => input_stream::<i16>(&microphone, &input_config.into(), mic_tx),
+        SampleFormat::U16 => input_stream::<u16>(&microphone, &input_config.into(), mic_tx),
+        format => Err(eyre!("Unsupported microphone sample format {}", format)),
+    }?;
+    let output = match output_config.sample_format() {
+        SampleFormat::F32 => output_stream::<f32>(&speaker, &output_config.into(), &playback),
+        SampleFormat::I16 => output_stream::<i16>(&speaker, &output_config.into(), &playback),
+        SampleFormat::U16 => output_stream::<u16>(&speaker, &output_config.into(), &playback),
+        format => Err(eyre!("Unsupported output sample format {}", format)),
+    }?;
+
+    let (mut sender, mut receiver) = live::connect(
+        &config.api,
+        &key,
+        &config.live,
+        config.system_prompt.as_deref(),
+    )
+    .await?;
+    input.play().wrap_err("Could not start the microphone")?;
+    output.play().wrap_err("Could not start audio output")?;
+
+    // Polled like the TUI's input thread, so quitting doesn't wait for another key press
+    let (key_tx, mut key_rx) = mpsc::unbounded_channel();
+    let reading = Arc::new(AtomicBool::new(true));
+    let keys = tokio::task::spawn_blocking({
+        let reading = reading.clone();
+        move || {
+            while reading.load(Ordering::Relaxed) {
+                match event::poll(crate::INPUT_POLL) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => break,
+                }
+                if let Ok(Event::Key(key)) = event::read()
+                    && key.kind == KeyEventKind::Press
+                    && key_tx.send(key).is_err()
+                {
+                    break;
+                }
+            }
+        }
+    });
+
+    let (mut terminal, _tui) = crate::tui::enter()?;
+    let color_depth = config.ui.color_depth();
+    let mut voice = Voice {
+        transcript: Vec::new(),
+        ready: false,
+        muted: false,
+        speaking: false,
+        status: None,
+    };
+    let chunk_len = (input_rate * CHUNK_MS / 1000) as usize;
+    let mut pending: Vec<f32> = Vec::with_capacity(chunk_len);
+    let mut dirty = true;
+    let result = loop {
+        if std::mem::take(&mut dirty)
+            && let Err(e) = terminal.draw(|frame| {
+                draw(&voice, frame, theme);
+                color_depth.adapt_buffer(frame.buffer_mut());
+            })
+        {
+            break Err(e.into());
+        }
+        tokio::select! {
+            Some(samples) = mic_rx.recv() => {
+                if !voice.ready || voice.muted {
+                    continue;
+                }
+                pending.extend(samples);
+                if pending.len() >= chunk_len {
+                    let chunk = live::resample(&pending, input_rate, live::INPUT_RATE);
+                    pending.clear();
+                    if let Err(e) = sender.send(live::audio_message(&chunk)).await {
+                        break Err(e);
+                    }
+                }
+            }
+            Some(key) = key_rx.recv() => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Ok(());
+                }
+                KeyCode::Char('m') => {
+                    voice.muted = !voice.muted;
+                    pending.clear();
+                    dirty = true;
+                }
+                _ => {}
+            },
+            message = receiver.next() => match message {
+                Some(Ok(events)) => {
+                    for event in events {
+                        handle(&mut voice, event, &playback, output_rate);
+                    }
+                    dirty = true;
+                }
+                Some(Err(e)) => break Err(e),
+                None => break Err(eyre!("The Live API closed the session")),
+            },
+        }
+    };
+    let _ = sender.close().await;
+    reading.store(false, Ordering::Relaxed);
+    let _ = keys.await;
+    result
+}
+
+fn handle(voice: &mut Voice, event: LiveEvent, playback: &Playback, output_rate: u32) {
+    let mut queue = playback.lock().unwrap_or_else(|e| e.into_inner());
+    match event {
+        LiveEvent::Ready => voice.ready = true,
+        LiveEvent::Audio(samples) => {
+            voice.speaking = true;
+            queue.extend(live::resample(&samples, live::OUTPUT_RATE, output_rate));
+        }
+        LiveEvent::Heard(text) => voice.hear(Speaker::User, &text),
+        LiveEvent::Said(text) => voice.hear(Speaker::Model, &text),
+        LiveEvent::TurnComplete => voice.speaking = false,
+        LiveEvent::Interrupted => {
+            voice.speaking = false;
+            queue.clear();
+        }
+        LiveEvent::GoingAway => {
+            voice.status = Some("The server is ending the session soon".to_string());
+        }
+    }
+}
+
+fn draw(voice: &Voice, frame: &mut Frame, theme: &theme::Theme) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(frame.area());
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let lines: Vec<Line> = voice
+        .transcript
+        .iter()
+        .flat_map(|(speaker, text)| {
+            let (name, color) = match speaker {
+                Speaker::User => ("You", theme.user),
+                Speaker::Model => ("Gemini", theme.ai),
+            };
+            [
+                Line::from(vec![
+                    Span::styled(format!("{}: ", name), bold.fg(color)),
+                    Span::raw(text.clone()),
+                ]),
+                Line::from(""),
+            ]
+        })
+        .collect();
+    // Keep the latest lines in view; wrapping is approximated by line width
+    let height = rows[0].height.saturating_sub(2) as usize;
+    let width = rows[0].width.saturating_sub(2).max(1) as usize;
+    let wrapped: usize = lines.iter().map(|l| l.width() / width + 1).sum();
+    let scroll = wrapped.saturating_sub(height) as u16;
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .scroll((scroll, 0))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Live conversation (experimental) — headphones avoid echo"),
+            ),
+        rows[0],
+    );
+
+    let state = if !voice.ready {
+        "Connecting…"
+    } else if voice.muted {
+        "Muted"
+    } else if voice.speaking {
+        "Speaking"
+    } else {
+        "Listening"
+    };
+    let mut status = vec![
+        Span::styled(
+            format!(" {} ", state),
+            bold.add_modifier(Modifier::REVERSED),
+        ),
+        Span::styled("  m: Mute  q: Quit", Style::default().fg(theme.dim)),
+    ];
+    if let Some(text) = &voice.status {
+        status.push(Span::raw(format!("  {}", text)));
+    }
+    frame.render_widget(Line::from(status), rows[1]);
+}
+
+/// Captures the microphone as mono samples in -1..1, sent in the device's chunks
+fn input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    tx: mpsc::UnboundedSender<Vec<f32>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let mono = data
+                .chunks(channels)
+                .map(|frame| {
+                    frame.iter().map(|s| s.to_sample::<f32>()).sum::<f32>() / channels as f32
+                })
+                .collect();
+            let _ = tx.send(mono);
+        },
+        |e| tracing::warn!(error = %e, "microphone error"),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Plays queued samples on every channel, and silence when there are none
+fn output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    playback: &Playback,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let playback = playback.clone();
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut queue = playback.lock().unwrap_or_else(|e| e.into_inner());
+            for frame in data.chunks_mut(channels) {
+                let sample = T::from_sample(queue.pop_front().unwrap_or(0.0));
+                frame.fill(sample);
+            }
+        },
+        |e| tracing::warn!(error = %e, "audio output error"),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,143 @@
+use crate::{ai, capture, config, redact};
+use chrono::Local;
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use globset::{Glob, GlobSetBuilder};
+use notify::{RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Bytes of one changed file sent with the prompt; the end of longer files, where logs grow
+const MAX_FILE_BYTES: u64 = 64 * 1024;
+
+pub struct Options {
+    /// Globs relative to the working directory, e.g. `src/**/*.rs`
+    pub patterns: Vec<String>,
+    pub prompt: String,
+    /// Quiet time after a change before the prompt runs, so a burst of saves runs it once
+    pub debounce: Duration,
+}
+
+/// `gemchat watch`: runs the prompt with the changed files each time files matching the
+/// patterns change, handing each request to `turn`, until interrupted
+pub async fn run<F, Fut, T>(
+    options: Options,
+    config: &config::Config,
+    redactor: &redact::Redactor,
+    mut turn: F,
+) -> Result<()>
+where
+    F: FnMut(ai::Request) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    // Canonical like the paths in events, e.g. behind macOS's /var symlink
+    let root = std::env::current_dir()?.canonicalize()?;
+    let mut globs = GlobSetBuilder::new();
+    for pattern in &options.patterns {
+        globs.add(Glob::new(pattern).wrap_err_with(|| format!("Invalid glob `{}`", pattern))?);
+    }
+    let globs = globs.build()?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "file watcher error"),
+        })?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .wrap_err_with(|| format!("Could not watch {}", root.display()))?;
+    eprintln!(
+        "Watching {} in {}; Ctrl+C stops",
+        options.patterns.join(", "),
+        root.display()
+    );
+
+    let matches = |path: &Path| {
+        path.strip_prefix(&root)
+            .is_ok_and(|relative| globs.is_match(relative))
+    };
+    while let Some(changed) = changes(&mut rx, options.debounce, matches).await {
+        let names: Vec<String> = changed
+            .iter()
+            .map(|p| p.strip_prefix(&root).unwrap_or(p).display().to_string())
+            .collect();
+        println!(
+            "\n── {} · {} ──",
+            Local::now().format("%H:%M:%S"),
+            names.join(", ")
+        );
+        let prompt = prompt(&options.prompt, &changed, &names);
+        let request = ai::Request {
+            prompt: format!("User: {}\n", redactor.redact(&prompt)),
+            models: config.models.clone(),
+            without_tools: true,
+            safety: config.safety.clone(),
+            provider: config.provider,
+            ..Default::default()
+        };
+        // A failed run is reported and the watch goes on
+        if let Err(e) = turn(request).await {
+            eprintln!("Error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// The next batch of changed paths that `matches`: from the first one on, changes are
+/// collected until none arrived for `debounce`. `None` once the watcher is gone.
+pub async fn changes(
+    rx: &mut mpsc::UnboundedReceiver<PathBuf>,
+    debounce: Duration,
+    matches: impl Fn(&Path) -> bool,
+) -> Option<BTreeSet<PathBuf>> {
+    loop {
+        let path = rx.recv().await?;
+        let mut changed = BTreeSet::new();
+        if matches(path.as_path()) {
+            changed.insert(path);
+        }
+        loop {
+            match tokio::time::timeout(debounce, rx.recv()).await {
+                Ok(Some(path)) if matches(path.as_path()) => {
+                    changed.insert(path);
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return None,
+                Err(_) => break,
+            }
+        }
+        if !changed.is_empty() {
+            return Some(changed);
+        }
+    }
+}
+
+/// The user's prompt followed by the current contents of the changed files, of long ones
+/// only the end
+pub fn prompt(prompt: &str, changed: &BTreeSet<PathBuf>, names: &[String]) -> String {
+    let mut text = format!("{}\n\nThese files just changed:\n", prompt);
+    for (path, name) in changed.iter().zip(names) {
+        match capture::tail(path, MAX_FILE_BYTES) {
+            Ok((data, cut)) => {
+                let note = if cut { " (last part)" } else { "" };
+                text.push_str(&format!(
+                    "\n--- {}{} ---\n{}\n",
+                    name,
+                    note,
+                    String::from_utf8_lossy(&data)
+                ));
+            }
+            // Deleted or renamed away since the change
+            Err(_) => text.push_str(&format!("\n--- {} (removed) ---\n", name)),
+        }
+    }
+    text
+}
@@ -0,0 +1,69 @@
+use gemchat::ai::ToolCall;
+use gemchat::approval::{Tripped, TurnGuard};
+use gemchat::config::ToolsConfig;
+
+fn call(name: &str, args: &str) -> ToolCall {
+    ToolCall {
+        id: None,
+        name: name.to_string(),
+        args: args.to_string(),
+        thought_signature: None,
+    }
+}
+
+#[test]
+fn the_same_call_made_too_often_pauses_the_loop() {
+    let tools = ToolsConfig::default();
+    let mut guard = TurnGuard::default();
+    let same = call("run_command", r#"{"command":"cargo build"}"#);
+    for round in 0..3 {
+        let other = call("grep", &format!(r#"{{"pattern":"{}"}}"#, round));
+        assert_eq!(guard.check(&[same.clone(), other], &tools), None);
+    }
+    assert_eq!(
+        guard.check(&[same], &tools),
+        Some(Tripped::Repeated {
+            tool: "run_command".to_string(),
+            times: 4
+        })
+    );
+}
+
+#[test]
+fn too_many_rounds_pause_the_loop() {
+    let tools = ToolsConfig {
+        max_rounds: Some(2),
+        ..Default::default()
+    };
+    let mut guard = TurnGuard::default();
+    assert_eq!(guard.check(&[call("grep", "{}")], &tools), None);
+    assert_eq!(guard.check(&[call("fetch_url", "{}")], &tools), None);
+    assert_eq!(
+        guard.check(&[call("recall", "{}")], &tools),
+        Some(Tripped::Rounds(3))
+    );
+}
+
+#[test]
+fn spending_pauses_the_loop_until_it_goes_on_with_fresh_limits() {
+    let tools = ToolsConfig {
+        max_rounds: Some(3),
+        max_turn_cost: Some(0.5),
+        ..Default::default()
+    };
+    let mut guard = TurnGuard::default();
+    guard.cost = 0.25;
+    assert_eq!(guard.check(&[call("grep", "{}")], &tools), None);
+    guard.cost = 0.75;
+    assert_eq!(
+        guard.check(&[call("grep", "{}")], &tools),
+        Some(Tripped::Cost(0.75))
+    );
+
+    // Keeping going clears the spend, the rounds and the repeats
+    guard.reset();
+    assert_eq!(guard.cost, 0.0);
+    for _ in 0..3 {
+        assert_eq!(guard.check(&[call("grep", "{}")], &tools), None);
+    }
+}
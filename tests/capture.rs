@@ -0,0 +1,42 @@
+use gemchat::capture;
+
+#[test]
+fn escapes_and_overwritten_text_are_stripped() {
+    let raw = "\x1b]0;~/src\x07$ cargo test\r\n\x1b[1m\x1b[32m   Compiling\x1b[0m gemchat\n\
+               Building [=>  ] 1/9\rBuilding [===] 9/9\n\x1b(Bdone  \n\n\n";
+    assert_eq!(
+        capture::clean(raw),
+        "$ cargo test\n   Compiling gemchat\nBuilding [===] 9/9\ndone"
+    );
+}
+
+#[test]
+fn only_the_last_lines_are_kept() {
+    let raw: String = (1..=1500).map(|n| format!("line {}\n", n)).collect();
+    let cleaned = capture::clean(&raw);
+    assert_eq!(cleaned.lines().count(), 1000);
+    assert_eq!(cleaned.lines().next(), Some("line 501"));
+    assert_eq!(cleaned.lines().last(), Some("line 1500"));
+}
+
+#[test]
+fn files_are_read_from_their_end() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("build.log");
+    let line = format!("{}\n", "x".repeat(99));
+    std::fs::write(&path, format!("{}last\n", line.repeat(20_000))).unwrap();
+
+    let (data, cut) = capture::tail(&path, 250).unwrap();
+    assert!(cut);
+    assert_eq!(data.len(), 250);
+    assert!(data.ends_with(b"last\n"));
+
+    let (data, cut) = capture::tail(&path, 10_000_000).unwrap();
+    assert!(!cut);
+    assert_eq!(data.len(), 2_000_005);
+
+    // The line the read started in is dropped rather than sent half
+    let captured = capture::file(&path).unwrap();
+    assert!(captured.lines().all(|l| l == "x".repeat(99) || l == "last"));
+    assert!(captured.ends_with("last"));
+}
@@ -0,0 +1,62 @@
+use gemchat::completion::{self, Candidate, Source, Token};
+
+#[test]
+fn the_word_at_the_cursor_picks_the_source() {
+    let token =
+        |line: &str, first_line| completion::token_at(line, line.chars().count(), first_line);
+    assert_eq!(
+        token("/mo", true),
+        Some(Token {
+            source: Source::Command,
+            start: 0,
+            query: "/mo".into()
+        })
+    );
+    assert_eq!(
+        token("/model gem", true),
+        Some(Token {
+            source: Source::Model,
+            start: 7,
+            query: "gem".into()
+        })
+    );
+    assert_eq!(
+        token("look at @src/ma", true),
+        Some(Token {
+            source: Source::File,
+            start: 8,
+            query: "src/ma".into()
+        })
+    );
+    // Commands only open the input
+    assert_eq!(token("/mo", false), None);
+    assert_eq!(token("see /mo", true), None);
+    assert_eq!(token("plain words", true), None);
+
+    // Only what is before the cursor counts, in characters
+    assert_eq!(
+        completion::token_at("é @ab cd", 5, true).map(|t| t.query),
+        Some("ab".into())
+    );
+}
+
+#[test]
+fn fuzzy_matches_rank_runs_and_word_starts_first() {
+    assert_eq!(completion::fuzzy_score("xyz", "src/main.rs"), None);
+    assert!(completion::fuzzy_score("MAIN", "src/main.rs").is_some());
+    assert!(
+        completion::fuzzy_score("main", "src/main.rs")
+            > completion::fuzzy_score("main", "src/m_a_i_n.rs")
+    );
+
+    let candidates: Vec<Candidate> = ["@src/render.rs", "@src/main.rs", "@tests/render.rs"]
+        .iter()
+        .map(|path| Candidate::new(*path, ""))
+        .collect();
+    let found: Vec<String> = completion::filter("srcren", &candidates, 10)
+        .into_iter()
+        .map(|c| c.text)
+        .collect();
+    assert_eq!(found, ["@src/render.rs"]);
+    assert_eq!(completion::filter("", &candidates, 2).len(), 2);
+}
@@ -0,0 +1,141 @@
+use gemchat::ai::Provider;
+use gemchat::config::{AllowRule, Config};
+
+const CONFIG: &str = r#"
+models = ["gemini-2.5-flash"]
+system_prompt = "Answer briefly."
+
+[tools.approval]
+enabled = true
+
+[profile.work]
+provider = "vertex"
+models = ["gemini-2.5-pro"]
+vertex = { project = "acme-prod" }
+api_key_env = "WORK_GEMINI_KEY"
+
+[profile.work.approval]
+enabled = false
+
+[profile.personal]
+system_prompt = "Reply in French."
+
+[template.locked.approval]
+enabled = true
+
+[template.plain]
+description = "Uses the configured approval"
+"#;
+
+fn allow(prefix: &str) -> AllowRule {
+    AllowRule {
+        tool: "run_command".into(),
+        prefix: Some(prefix.into()),
+        path: None,
+    }
+}
+
+#[test]
+fn profiles_replace_only_what_they_set() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(Some(&path)).unwrap();
+
+    config.use_profile(Some("work")).unwrap();
+    assert_eq!(config.provider, Some(Provider::Vertex));
+    assert_eq!(config.model(), "gemini-2.5-pro");
+    assert_eq!(config.vertex.project.as_deref(), Some("acme-prod"));
+    assert!(!config.tools.approval.enabled());
+    assert_eq!(config.system_prompt.as_deref(), Some("Answer briefly."));
+    assert_eq!(
+        config.profile().unwrap().api_key_env.as_deref(),
+        Some("WORK_GEMINI_KEY")
+    );
+
+    // Switching starts again from the top-level settings
+    config.use_profile(Some("personal")).unwrap();
+    assert_eq!(config.provider, None);
+    assert_eq!(config.model(), "gemini-2.5-flash");
+    assert!(config.tools.approval.enabled());
+    assert_eq!(config.system_prompt.as_deref(), Some("Reply in French."));
+
+    assert!(config.use_profile(Some("missing")).is_err());
+    assert_eq!(config.active_profile.as_deref(), Some("personal"));
+    config.use_profile(None).unwrap();
+    assert_eq!(config.system_prompt.as_deref(), Some("Answer briefly."));
+}
+
+#[test]
+fn allow_rules_are_saved_to_the_profile_that_sets_approval() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(Some(&path)).unwrap();
+    config.use_profile(Some("work")).unwrap();
+    config.save_allow_rule(allow("cargo test"), None).unwrap();
+
+    let saved = Config::load(Some(&path)).unwrap();
+    assert!(saved.tools.approval.allow.is_empty());
+    let work = saved.profile["work"].approval.as_ref().unwrap();
+    assert_eq!(work.allow[0].prefix.as_deref(), Some("cargo test"));
+
+    // The rule stays with the profile when switching away and back
+    config.use_profile(None).unwrap();
+    assert!(config.tools.approval.allow.is_empty());
+    config.use_profile(Some("work")).unwrap();
+    assert_eq!(config.tools.approval.allow.len(), 1);
+}
+
+#[test]
+fn allow_rules_are_saved_to_the_template_that_sets_approval() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let mut config = Config::load(Some(&path)).unwrap();
+    config
+        .save_allow_rule(allow("cargo test"), Some("locked"))
+        .unwrap();
+
+    // Live and saved, the rule only applies to sessions from the template
+    assert!(config.tools.approval.allow.is_empty());
+    let locked = config.template["locked"].approval.as_ref().unwrap();
+    assert_eq!(locked.allow[0].prefix.as_deref(), Some("cargo test"));
+    let saved = Config::load(Some(&path)).unwrap();
+    assert!(saved.tools.approval.allow.is_empty());
+    let locked = saved.template["locked"].approval.as_ref().unwrap();
+    assert_eq!(locked.allow[0].prefix.as_deref(), Some("cargo test"));
+
+    // A template without its own approval uses the configured rules
+    config
+        .save_allow_rule(allow("cargo build"), Some("plain"))
+        .unwrap();
+    assert_eq!(config.tools.approval.allow.len(), 1);
+    let saved = Config::load(Some(&path)).unwrap();
+    assert_eq!(
+        saved.tools.approval.allow[0].prefix.as_deref(),
+        Some("cargo build")
+    );
+}
+
+#[test]
+fn compare_targets_name_their_provider() {
+    let config: Config = toml::from_str(
+        r#"
+models = ["a", "b", "c", "d"]
+[compare]
+models = ["gemini-2.5-pro", "vertex:gemini-2.5-flash", "custom:model"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.compare.targets(&config.models),
+        [
+            (None, "gemini-2.5-pro".to_string()),
+            (Some(Provider::Vertex), "gemini-2.5-flash".to_string()),
+            (None, "custom:model".to_string()),
+        ]
+    );
+    // Without a list, the configured models are compared
+    assert_eq!(Config::default().compare.targets(&config.models).len(), 3);
+}
@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+use gemchat::diagram;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// A stand-in for `mmdc` that writes a fake image to `--output` and counts its runs
+fn fake_mmdc(dir: &Path) -> String {
+    let path = dir.join("mmdc");
+    std::fs::write(
+        &path,
+        "#!/bin/sh\n\
+         echo run >> \"$(dirname \"$0\")/runs\"\n\
+         case \"$(cat \"$2\")\" in *fail*) echo 'Parse error on line 1' >&2; exit 1;; esac\n\
+         while [ $# -gt 0 ]; do\n\
+         \x20 if [ \"$1\" = --output ]; then printf png > \"$2\"; fi\n\
+         \x20 shift\n\
+         done\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn runs(dir: &Path) -> usize {
+    std::fs::read_to_string(dir.join("runs"))
+        .map(|runs| runs.lines().count())
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn diagrams_are_rendered_once_and_cached() {
+    let bin = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    let mmdc = fake_mmdc(bin.path());
+
+    let image = diagram::render_mermaid(&mmdc, "graph TD; A-->B", cache.path())
+        .await
+        .unwrap();
+    assert!(image.starts_with(cache.path()));
+    assert_eq!(std::fs::read(&image).unwrap(), b"png");
+    assert_eq!(runs(bin.path()), 1);
+    // The source is not left behind
+    assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 1);
+
+    let again = diagram::render_mermaid(&mmdc, "graph TD; A-->B", cache.path())
+        .await
+        .unwrap();
+    assert_eq!(again, image);
+    assert_eq!(runs(bin.path()), 1);
+
+    let other = diagram::render_mermaid(&mmdc, "graph TD; B-->C", cache.path())
+        .await
+        .unwrap();
+    assert_ne!(other, image);
+    assert_eq!(runs(bin.path()), 2);
+}
+
+#[tokio::test]
+async fn missing_or_failing_mmdc_is_explained() {
+    let bin = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+
+    let missing = bin.path().join("no-such-mmdc");
+    let error = diagram::render_mermaid(&missing.to_string_lossy(), "graph TD", cache.path())
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("not found"), "{error}");
+    assert!(
+        error.contains("npm install -g @mermaid-js/mermaid-cli"),
+        "{error}"
+    );
+
+    let mmdc = fake_mmdc(bin.path());
+    let error = diagram::render_mermaid(&mmdc, "fail", cache.path())
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.ends_with("failed: Parse error on line 1"), "{error}");
+}
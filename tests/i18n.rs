@@ -0,0 +1,139 @@
+use gemchat::i18n::{self, Locale};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn detect(vars: &[(&str, &str)]) -> Locale {
+    Locale::from_env(|name| {
+        vars.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+#[test]
+fn locale_comes_from_the_environment() {
+    assert_eq!(detect(&[("LANG", "de_DE.UTF-8")]), Locale::German);
+    // LC_ALL wins, and an empty one doesn't count
+    assert_eq!(
+        detect(&[("LC_ALL", "C"), ("LANG", "de_DE.UTF-8")]),
+        Locale::English
+    );
+    assert_eq!(
+        detect(&[("LC_ALL", ""), ("LC_MESSAGES", "de_AT"), ("LANG", "en_US")]),
+        Locale::German
+    );
+    // Languages without a catalog fall back to English
+    assert_eq!(detect(&[("LANG", "fr_FR.UTF-8")]), Locale::English);
+    assert_eq!(detect(&[]), Locale::English);
+    assert_eq!(Locale::parse("de-CH"), Some(Locale::German));
+}
+
+#[test]
+fn strings_are_translated_or_left_in_english() {
+    assert_eq!(Locale::German.translate("Help"), "Hilfe");
+    assert_eq!(Locale::English.translate("Help"), "Help");
+    assert_eq!(
+        Locale::German.translate("Not in the catalog"),
+        "Not in the catalog"
+    );
+    assert_eq!(
+        i18n::fill(
+            Locale::German.translate("Could not open {}: {}"),
+            &[&"a.png", &"denied"]
+        ),
+        "a.png konnte nicht geöffnet werden: denied"
+    );
+}
+
+#[test]
+fn catalogs_are_well_formed() {
+    for locale in Locale::ALL {
+        let mut keys = HashSet::new();
+        for (english, translated) in locale.catalog() {
+            assert!(
+                keys.insert(english),
+                "{:?} translates {:?} twice",
+                locale,
+                english
+            );
+            assert_eq!(
+                english.matches("{}").count(),
+                translated.matches("{}").count(),
+                "{:?} has other placeholders than {:?}",
+                translated,
+                english
+            );
+        }
+    }
+}
+
+/// The string literals passed straight to `tr(…)` in `source`, unescaped
+fn tr_literals(source: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    for (at, _) in source.match_indices("tr(") {
+        let preceding = source[..at].chars().next_back();
+        if preceding.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            continue;
+        }
+        let Some(rest) = source[at + 3..].trim_start().strip_prefix('"') else {
+            continue;
+        };
+        let mut literal = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some(other) => literal.push(other),
+                    None => {}
+                },
+                c => literal.push(c),
+            }
+        }
+        if chars.as_str().trim_start().starts_with(')') {
+            found.push(literal);
+        }
+    }
+    found
+}
+
+fn sources(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            sources(&path, out);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            out.push(path);
+        }
+    }
+}
+
+#[test]
+fn every_translated_string_has_a_german_entry() {
+    let catalog: HashSet<&str> = Locale::German
+        .catalog()
+        .iter()
+        .map(|(english, _)| *english)
+        .collect();
+    let mut files = Vec::new();
+    sources(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+        &mut files,
+    );
+    let mut missing = Vec::new();
+    for file in files {
+        for literal in tr_literals(&fs::read_to_string(&file).unwrap()) {
+            if !catalog.contains(literal.as_str()) {
+                missing.push(format!("{}: {:?}", file.display(), literal));
+            }
+        }
+    }
+    assert!(
+        missing.is_empty(),
+        "no German entry for:\n{}",
+        missing.join("\n")
+    );
+}
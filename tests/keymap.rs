@@ -0,0 +1,84 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use gemchat::config::KeysConfig;
+use gemchat::keymap::{self, Command, Keymap, Lookup};
+
+fn key(c: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+}
+
+fn command(lookup: Lookup) -> Option<Command> {
+    match lookup {
+        Lookup::Command(command) => Some(command),
+        _ => None,
+    }
+}
+
+#[test]
+fn sequences_wait_for_their_last_key() {
+    let keymap = Keymap::new(&KeysConfig::default()).unwrap();
+    let normal = &keymap.normal;
+    assert!(matches!(
+        keymap::lookup(normal, &[key(']')]),
+        Lookup::Pending
+    ));
+    assert_eq!(
+        command(keymap::lookup(normal, &[key(']'), key('c')])),
+        Some(Command::NextCode)
+    );
+    assert!(matches!(
+        keymap::lookup(normal, &[key(']'), key('x')]),
+        Lookup::None
+    ));
+    assert_eq!(
+        command(keymap::lookup(normal, &[key('n')])),
+        Some(Command::NextMessage)
+    );
+}
+
+#[test]
+fn custom_sequences_replace_defaults() {
+    let mut config = KeysConfig::default();
+    config
+        .normal
+        .insert(Command::NextCode, vec!["g c".to_string()]);
+    config.normal.insert(Command::Quit, vec!["ZZ".to_string()]);
+    let keymap = Keymap::new(&config).unwrap();
+    let normal = &keymap.normal;
+    assert_eq!(
+        command(keymap::lookup(normal, &[key('g'), key('c')])),
+        Some(Command::NextCode)
+    );
+    assert!(matches!(
+        keymap::lookup(normal, &[key(']'), key('c')]),
+        Lookup::None
+    ));
+    assert_eq!(
+        command(keymap::lookup(normal, &[key('Z'), key('Z')])),
+        Some(Command::Quit)
+    );
+    assert!(
+        Keymap::new(&KeysConfig {
+            normal: [(Command::Quit, vec!["ctrl+nope".to_string()])].into(),
+            ..KeysConfig::default()
+        })
+        .is_err()
+    );
+}
+
+#[test]
+fn tab_cycles_focus_from_every_pane() {
+    let keymap = Keymap::new(&KeysConfig::default()).unwrap();
+    let tab = KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE);
+    // Terminals report back tab with shift held
+    let back_tab = KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT);
+    for bindings in [&keymap.normal, &keymap.editing, &keymap.sidebar] {
+        assert_eq!(
+            command(keymap::lookup(bindings, &[tab])),
+            Some(Command::FocusNext)
+        );
+        assert_eq!(
+            command(keymap::lookup(bindings, &[back_tab])),
+            Some(Command::FocusPrev)
+        );
+    }
+}
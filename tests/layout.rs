@@ -0,0 +1,89 @@
+use gemchat::config::Config;
+use gemchat::layout::{Edge, Screen, ScreenLayout, Side};
+use ratatui::layout::Rect;
+
+const SCREEN: Rect = Rect::new(0, 0, 100, 40);
+
+fn layout(sidebar: Side, sidebar_width: u16, input: Edge) -> ScreenLayout {
+    ScreenLayout {
+        sidebar,
+        sidebar_width,
+        input,
+        input_height: 5,
+        padding: 0,
+    }
+}
+
+#[test]
+fn sidebar_goes_on_either_side() {
+    assert_eq!(
+        layout(Side::Left, 25, Edge::Bottom).screen(SCREEN),
+        Screen {
+            sidebar: Some(Rect::new(0, 0, 25, 39)),
+            main: Rect::new(25, 0, 75, 39),
+            status: Rect::new(0, 39, 100, 1),
+        }
+    );
+    assert_eq!(
+        layout(Side::Right, 30, Edge::Bottom).screen(SCREEN),
+        Screen {
+            sidebar: Some(Rect::new(70, 0, 30, 39)),
+            main: Rect::new(0, 0, 70, 39),
+            status: Rect::new(0, 39, 100, 1),
+        }
+    );
+    // Hidden, the chat takes the whole width
+    let screen = layout(Side::Right, 0, Edge::Bottom).screen(SCREEN);
+    assert_eq!(screen.sidebar, None);
+    assert_eq!(screen.main, Rect::new(0, 0, 100, 39));
+}
+
+#[test]
+fn input_goes_above_or_below_the_messages() {
+    let chat = Rect::new(0, 0, 80, 30);
+    assert_eq!(
+        layout(Side::Left, 0, Edge::Bottom).chat(chat),
+        (Rect::new(0, 0, 80, 25), Rect::new(0, 25, 80, 5))
+    );
+    assert_eq!(
+        layout(Side::Left, 0, Edge::Top).chat(chat),
+        (Rect::new(0, 5, 80, 25), Rect::new(0, 0, 80, 5))
+    );
+}
+
+#[test]
+fn padding_gives_way_in_narrow_panes() {
+    let padded = ScreenLayout {
+        padding: 50,
+        ..layout(Side::Left, 0, Edge::Bottom)
+    };
+    assert_eq!(padded.padding_for(200), 50);
+    // Twenty columns stay for the text
+    assert_eq!(padded.padding_for(60), 20);
+    assert_eq!(padded.padding_for(21), 0);
+    assert_eq!(padded.padding_for(5), 0);
+}
+
+#[test]
+fn layout_is_read_from_config() {
+    let config: Config = toml::from_str(
+        r#"
+[layout]
+sidebar = "right"
+input = "top"
+input_height = 1
+padding = 2
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.layout.sidebar(), Side::Right);
+    assert_eq!(config.layout.input(), Edge::Top);
+    // Too short to show a line between the borders
+    assert_eq!(config.layout.input_height(), 3);
+    assert_eq!(config.layout.padding(), 2);
+
+    let config = Config::default();
+    assert_eq!(config.layout.sidebar(), Side::Left);
+    assert_eq!(config.layout.input(), Edge::Bottom);
+    assert_eq!(config.layout.input_height(), 3);
+}
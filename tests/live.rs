@@ -0,0 +1,76 @@
+#![cfg(feature = "live")]
+
+use gemchat::config::{ApiConfig, LiveConfig};
+use gemchat::live::{self, LiveEvent};
+use serde_json::json;
+
+#[test]
+fn setup_asks_for_speech_and_transcripts() {
+    let config = LiveConfig {
+        voice: Some("Kore".to_string()),
+        ..Default::default()
+    };
+    let setup = &live::setup_message(&config, Some("Be brief."))["setup"];
+    assert_eq!(
+        setup["model"],
+        "models/gemini-2.5-flash-native-audio-preview-09-2025"
+    );
+    assert_eq!(
+        setup["generationConfig"]["responseModalities"],
+        json!(["AUDIO"])
+    );
+    assert_eq!(
+        setup["generationConfig"]["speechConfig"]["voiceConfig"]["prebuiltVoiceConfig"]["voiceName"],
+        "Kore"
+    );
+    assert!(setup["outputAudioTranscription"].is_object());
+    assert_eq!(setup["systemInstruction"]["parts"][0]["text"], "Be brief.");
+
+    let api = ApiConfig {
+        base_url: Some("http://localhost:8080/".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        live::url(&api),
+        "ws://localhost:8080/ws/google.ai.generativelanguage.v1beta.GenerativeService.BidiGenerateContent"
+    );
+}
+
+#[test]
+fn server_messages_become_events() {
+    let audio = live::audio_message(&[0.0, 0.5])["realtimeInput"]["audio"]["data"].clone();
+    let message = json!({
+        "serverContent": {
+            "outputTranscription": { "text": "Hello" },
+            "modelTurn": { "parts": [{ "inlineData": { "mimeType": "audio/pcm;rate=24000", "data": audio } }] },
+            "turnComplete": true
+        }
+    });
+    let events = live::parse(&message);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0], LiveEvent::Said("Hello".to_string()));
+    let LiveEvent::Audio(samples) = &events[1] else {
+        panic!("expected audio, got {:?}", events[1]);
+    };
+    assert_eq!(samples[0], 0.0);
+    assert!((samples[1] - 0.5).abs() < 1e-3);
+    assert_eq!(events[2], LiveEvent::TurnComplete);
+
+    assert_eq!(
+        live::parse(&json!({ "setupComplete": {} })),
+        [LiveEvent::Ready]
+    );
+    assert_eq!(
+        live::parse(&json!({ "serverContent": { "interrupted": true } })),
+        [LiveEvent::Interrupted]
+    );
+}
+
+#[test]
+fn resampling_keeps_the_duration() {
+    let second: Vec<f32> = (0..48_000).map(|i| i as f32 / 48_000.0).collect();
+    let resampled = live::resample(&second, 48_000, live::INPUT_RATE);
+    assert_eq!(resampled.len(), 16_000);
+    assert!((resampled[8_000] - 0.5).abs() < 1e-3);
+    assert_eq!(live::resample(&second, 48_000, 48_000).len(), 48_000);
+}
@@ -0,0 +1,56 @@
+use gemchat::math::{self, Piece};
+
+fn unicode(tex: &str) -> String {
+    math::to_unicode(tex).unwrap()
+}
+
+#[test]
+fn latex_becomes_unicode() {
+    assert_eq!(unicode(r"E = mc^2"), "E = mc²");
+    assert_eq!(unicode(r"x_{i+1} = x_i^{n-1}"), "xᵢ₊₁ = xᵢⁿ⁻¹");
+    assert_eq!(unicode(r"\alpha + \beta \leq \pi"), "α + β ≤ π");
+    assert_eq!(unicode(r"\frac{1}{2} + \frac{12}{7}"), "½ + ¹²⁄₇");
+    assert_eq!(unicode(r"\frac{a+b}{2c}"), "(a+b)/2c");
+    assert_eq!(unicode(r"\sqrt{x^2 + 1}"), "√(x² + 1)");
+    assert_eq!(unicode(r"\sum_{i=1}^{n} i"), "∑ᵢ₌₁ⁿ i");
+    assert_eq!(unicode(r"x \in \mathbb{R}"), "x ∈ ℝ");
+    assert_eq!(unicode(r"f'(x) \to \infty"), "f′(x) → ∞");
+    // Without superscript characters the script is spelled out
+    assert_eq!(unicode(r"e^{\pi i}"), "e^(π i)");
+}
+
+#[test]
+fn unknown_latex_is_left_alone() {
+    assert_eq!(math::to_unicode(r"\unknowncommand{x}"), None);
+    assert_eq!(math::to_unicode(r"\frac{1}{2"), None);
+    assert_eq!(math::to_unicode(r"x}"), None);
+}
+
+#[test]
+fn dollars_delimit_math() {
+    assert_eq!(
+        math::pieces("where $x^2$ and $$\\pi$$."),
+        vec![
+            Piece::Text("where "),
+            Piece::Math {
+                raw: "$x^2$",
+                tex: "x^2"
+            },
+            Piece::Text(" and "),
+            Piece::Math {
+                raw: "$$\\pi$$",
+                tex: "\\pi"
+            },
+            Piece::Text("."),
+        ]
+    );
+    // Prices, shell variables, escaped dollars and code stay text
+    for text in [
+        "costs $5 and $10",
+        "echo $HOME/$USER",
+        r"\$x\$",
+        "`$x$` in code",
+    ] {
+        assert_eq!(math::pieces(text), vec![Piece::Text(text)], "{text}");
+    }
+}
@@ -0,0 +1,48 @@
+use gemchat::config::Price;
+use gemchat::pricing;
+use std::collections::HashMap;
+
+#[test]
+fn longest_prefix_wins_and_overrides_come_first() {
+    let none = HashMap::new();
+    assert_eq!(
+        pricing::price(&none, "gemini-2.5-flash-lite-preview"),
+        Some(Price {
+            input: 0.1,
+            output: 0.4,
+            cached_input: Some(0.01),
+        })
+    );
+    assert_eq!(pricing::price(&none, "gemma-3"), None);
+
+    let overrides = HashMap::from([(
+        "gemini-2.5".to_string(),
+        Price {
+            input: 1.0,
+            output: 2.0,
+            cached_input: None,
+        },
+    )]);
+    // A million prompt tokens and half a million more for output, thinking included
+    let cost = pricing::cost(&overrides, "gemini-2.5-flash", 1_000_000, 0, 1_500_000);
+    assert_eq!(cost, Some(2.0));
+}
+
+#[test]
+fn cached_prompt_tokens_are_billed_at_the_cached_rate() {
+    let overrides = HashMap::from([(
+        "gemini-3".to_string(),
+        Price {
+            input: 1.0,
+            output: 2.0,
+            cached_input: Some(0.25),
+        },
+    )]);
+    // Half of a million prompt tokens came from the cache
+    let cost = pricing::cost(&overrides, "gemini-3-flash", 1_000_000, 500_000, 1_000_000);
+    assert_eq!(cost, Some(0.625));
+    assert_eq!(
+        pricing::cache_savings(&overrides, "gemini-3-flash", 500_000),
+        Some(0.375)
+    );
+}
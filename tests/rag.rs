@@ -0,0 +1,88 @@
+use gemchat::ai;
+use gemchat::config::Config;
+use gemchat::rag::Index;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use wiremock::matchers::{method, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+/// A two-dimensional "embedding": fruit on one axis, everything else on the other
+fn vector(text: &str) -> Value {
+    if text.contains("apple") {
+        json!([1.0, 0.0])
+    } else {
+        json!([0.0, 1.0])
+    }
+}
+
+fn embeddings(request: &Request) -> ResponseTemplate {
+    let body: Value = request.body_json().unwrap();
+    let embeddings: Vec<Value> = body["requests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| json!({ "values": vector(r["content"]["parts"][0]["text"].as_str().unwrap()) }))
+        .collect();
+    ResponseTemplate::new(200).set_body_json(json!({ "embeddings": embeddings }))
+}
+
+#[tokio::test]
+async fn indexes_changed_files_and_retrieves_the_closest() {
+    let cache = tempfile::tempdir().unwrap();
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path_regex(":batchEmbedContents$"))
+        .respond_with(embeddings)
+        .mount(&server)
+        .await;
+    let mut config = Config::default();
+    config.api.base_url = Some(server.uri());
+    ai::configure(&config).unwrap();
+    ai::set_api_key(Some("test-key".to_string()));
+
+    let project = tempfile::tempdir().unwrap();
+    std::fs::write(project.path().join("fruit.txt"), "apple pie recipe\n").unwrap();
+    std::fs::write(project.path().join("car.txt"), "engine oil change\n").unwrap();
+    let paths = [PathBuf::from(".")];
+
+    let mut reported = Vec::new();
+    let index = Index::update(cache.path(), project.path(), &paths, |done, total| {
+        reported.push((done, total))
+    })
+    .await
+    .unwrap();
+    assert_eq!(index.file_count(), 2);
+    assert_eq!(index.chunk_count(), 2);
+    assert_eq!(reported.last(), Some(&(2, 2)));
+
+    let found = index.search("an apple", 1).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, "fruit.txt");
+    assert_eq!((found[0].start_line, found[0].end_line), (1, 1));
+
+    // Unchanged files are not embedded again; the saved index is picked up
+    std::fs::write(project.path().join("car.txt"), "tyre pressure\n").unwrap();
+    let requests_before = server.received_requests().await.unwrap().len();
+    let index = Index::update(cache.path(), project.path(), &paths, |_, _| {})
+        .await
+        .unwrap();
+    let requests = server.received_requests().await.unwrap();
+    let texts = requests[requests_before].body_json::<Value>().unwrap()["requests"]
+        .as_array()
+        .unwrap()
+        .len();
+    assert_eq!(texts, 1);
+    assert_eq!(index.chunk_count(), 2);
+
+    std::fs::remove_file(project.path().join("fruit.txt")).unwrap();
+    let index = Index::update(cache.path(), project.path(), &paths, |_, _| {})
+        .await
+        .unwrap();
+    assert_eq!(index.file_count(), 1);
+    assert!(
+        Index::load(cache.path(), project.path())
+            .unwrap()
+            .is_some_and(|saved| saved.chunk_count() == 1)
+    );
+}
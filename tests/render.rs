@@ -0,0 +1,301 @@
+use gemchat::render::{self, Highlighter};
+use gemchat::theme::Theme;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use std::sync::LazyLock;
+
+static HIGHLIGHTER: LazyLock<Highlighter> = LazyLock::new(Highlighter::new);
+
+/// One row per line between `|`s; bold spans are `*starred*`, colored ones `[bracketed]`
+fn show(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            let spans: String = line
+                .spans
+                .iter()
+                .map(|span| {
+                    let text = span.content.replace('\n', "⏎");
+                    if span.style.add_modifier.contains(Modifier::BOLD) {
+                        format!("*{}*", text)
+                    } else if span.style.fg.is_some() {
+                        format!("[{}]", text)
+                    } else {
+                        text
+                    }
+                })
+                .collect();
+            format!("|{}|", spans)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn markdown(text: &str, width: usize) -> String {
+    let theme = Theme::builtin("dark").unwrap();
+    show(&render::markdown(text, width, &HIGHLIGHTER, &theme))
+}
+
+#[test]
+fn bold_and_plain_text() {
+    insta::assert_snapshot!(markdown("Some **bold** and more **bold text**.", 0));
+}
+
+#[test]
+fn unclosed_bold_runs_to_end_of_line() {
+    insta::assert_snapshot!(markdown("a **dangling bold\nnext line", 0));
+}
+
+#[test]
+fn nested_fences() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "Outer:\n````markdown\n```rust\nfn main() {}\n```\n````\nafter";
+    let document = render::document(text, false, 0, &HIGHLIGHTER, &theme);
+    let shown: Vec<String> = document.lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(shown, text.lines().collect::<Vec<_>>());
+    assert_eq!(document.code_blocks, vec![1..6]);
+    assert_eq!(
+        render::code_blocks(text),
+        vec![render::CodeBlock {
+            lang: "markdown".into(),
+            code: "```rust\nfn main() {}\n```\n".into(),
+        }]
+    );
+}
+
+#[test]
+fn fences_follow_commonmark() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "> ~~~ sh\n> ls\n> ```\n> ~~~\n1. step\n   ```py title=x\n     x = 1\n   ```\n  ```\n  two spaces\n    ```\n  ```\n``` not `code` ```\n> ```\n> quoted\nafter the quote";
+    let document = render::document(text, false, 0, &HIGHLIGHTER, &theme);
+    let lines = &document.lines;
+    // Code keeps what was in front of it in the source, dimmed
+    assert_eq!(lines[1].to_string(), "> ls");
+    assert_eq!(lines[1].spans[0].style.fg, Some(theme.dim));
+    assert_eq!(document.code_blocks, vec![0..4, 5..8, 8..12, 13..15]);
+    let blocks = render::code_blocks(text);
+    assert_eq!(
+        blocks
+            .iter()
+            .map(|b| (b.lang.as_str(), b.code.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("sh", "ls\n```\n"),
+            ("py", "  x = 1\n"),
+            ("", "two spaces\n  ```\n"),
+            // Leaving the blockquote ends its block
+            ("", "quoted\n"),
+        ]
+    );
+    assert_eq!(lines[15].to_string(), "after the quote");
+}
+
+#[test]
+fn unclosed_fence_mid_stream() {
+    insta::assert_snapshot!(markdown("Here:\n```python\nprint('hi')\nx = 1", 0));
+}
+
+#[test]
+fn open_fences_close_tentatively_while_streaming() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "Here:\n````python\nx = 1";
+    let document = render::document(text, true, 0, &HIGHLIGHTER, &theme);
+    assert_eq!(
+        document.lines.last().unwrap().to_string(),
+        "```` streaming…"
+    );
+    assert_eq!(document.code_blocks, vec![1..4]);
+    // Once the response is complete it is drawn as it is
+    assert_eq!(render::markdown(text, 0, &HIGHLIGHTER, &theme).len(), 3);
+
+    let closed = render::streaming_markdown("```\nx\n```\nmore", 0, &HIGHLIGHTER, &theme);
+    assert!(closed.iter().all(|l| !l.to_string().contains("streaming")));
+}
+
+#[test]
+fn math_renders_as_unicode_or_dimmed_latex() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "So $a^2 + b^2 = c^2$, and $\\weird{x}$.\n$$\n\\int_0^1 x\\,dx = \\frac{1}{2}\n$$\n$$\\begin{pmatrix";
+    let lines = render::markdown(text, 0, &HIGHLIGHTER, &theme);
+    let shown: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(
+        shown,
+        [
+            "So a² + b² = c², and $\\weird{x}$.",
+            "  ∫₀¹ x dx = ½",
+            "$$\\begin{pmatrix",
+        ]
+    );
+    assert_eq!(lines[0].spans[1].style.fg, None);
+    assert_eq!(lines[0].spans[3].style.fg, Some(theme.dim));
+    assert_eq!(lines[2].spans[0].style.fg, Some(theme.dim));
+}
+
+#[test]
+fn unclosed_display_math_stops_at_a_blank_line_or_fence() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "$$ is the shell's PID\nand $! the last job's.\n\nLater prose\n```sh\necho $$\n```\n$$\nx\n```\ncode\n```";
+    let lines = render::markdown(text, 0, &HIGHLIGHTER, &theme);
+    let shown: Vec<String> = lines
+        .iter()
+        .map(|l| l.to_string().trim_end().to_string())
+        .collect();
+    // Everything stays where it was written
+    assert_eq!(shown, text.lines().collect::<Vec<_>>());
+    assert_eq!(lines[3].spans[0].style.fg, None);
+}
+
+#[test]
+fn wraps_at_word_boundaries() {
+    insta::assert_snapshot!(markdown(
+        "The quick brown fox jumps over the **lazy dog** twice.",
+        16
+    ));
+}
+
+#[test]
+fn hard_wraps_long_words() {
+    insta::assert_snapshot!(markdown(
+        "see https://example.com/a/very/long/path/that/does/not/fit",
+        12
+    ));
+}
+
+#[test]
+fn wraps_cjk_by_display_width() {
+    insta::assert_snapshot!(markdown("日本語のテキストは幅が二倍です。", 9));
+}
+
+#[test]
+fn tables_align_by_display_width() {
+    let text = "| Name | Qty |\n|------|-----|\n| **apple** | 3 |\n| 梨 | 12 |\n| 🍎 |";
+    insta::assert_snapshot!(markdown(text, 0));
+}
+
+#[test]
+fn pipes_without_a_separator_pass_through() {
+    insta::assert_snapshot!(markdown("| not | a table |\n| just | pipes |", 0));
+}
+
+#[test]
+fn truncate_and_pad_by_display_width() {
+    assert_eq!(render::truncate("日本語テキスト", 7), "日本語…");
+    assert_eq!(render::truncate("short", 7), "short");
+    assert_eq!(render::pad("梨", 4), "梨  ");
+}
+
+#[test]
+fn wrap_keeps_line_style() {
+    let line = Line::from("one two three").style(Modifier::ITALIC);
+    let wrapped = render::wrap(line, 7);
+    assert_eq!(wrapped.len(), 2);
+    assert!(
+        wrapped
+            .iter()
+            .all(|l| l.style.add_modifier.contains(Modifier::ITALIC))
+    );
+    assert!(wrapped.iter().all(|l| l.width() <= 8));
+}
+
+#[test]
+fn decorated_lines_keep_their_own_colors_over_the_shade() {
+    let line = Line::from(vec![
+        Span::raw("plain "),
+        Span::styled("code", Style::default().fg(Color::Red).bg(Color::Black)),
+    ])
+    .style(Modifier::ITALIC);
+    let shaded = render::decorate(
+        line,
+        vec![
+            Span::styled("▌", Style::default().fg(Color::Blue)),
+            Span::raw("  "),
+        ],
+        Style::default().bg(Color::DarkGray),
+    );
+    assert_eq!(shaded.to_string(), "▌  plain code");
+    assert_eq!(shaded.style.bg, Some(Color::DarkGray));
+    assert!(shaded.style.add_modifier.contains(Modifier::ITALIC));
+    // Spans are drawn over the line's style, so one with a background keeps it
+    assert_eq!(shaded.spans[3].style.bg, Some(Color::Black));
+}
+
+#[test]
+fn code_blocks_match_their_rendered_lines() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "intro\n```rust\nfn a() {}\n```\nbetween\n```\nplain\n```\n```py\nunclosed";
+    let document = render::document(text, false, 80, &HIGHLIGHTER, &theme);
+    assert_eq!(document.code_blocks, vec![1..4, 5..8, 8..10]);
+    let blocks = render::code_blocks(text);
+    assert_eq!(
+        blocks
+            .iter()
+            .map(|b| (b.lang.as_str(), b.code.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("rust", "fn a() {}\n"),
+            ("", "plain\n"),
+            ("py", "unclosed\n")
+        ]
+    );
+}
+
+#[test]
+fn code_blocks_are_found_in_wrapped_quotes_and_lists() {
+    let theme = Theme::builtin("dark").unwrap();
+    // The long code lines wrap onto lines without the quote marker or the list indent
+    let text = "> ```sh\n> echo a long line that wraps\n> ```\n- item\n\n  ```\n  another line that wraps too\n  ```\n```\nlast\n```";
+    let document = render::document(text, false, 12, &HIGHLIGHTER, &theme);
+    let shown: Vec<String> = document.lines.iter().map(|l| l.to_string()).collect();
+    assert!(shown.len() > text.lines().count());
+    assert!(!shown[2].starts_with('>'));
+    let ranges = document.code_blocks;
+    assert_eq!(ranges.len(), render::code_blocks(text).len());
+    assert_eq!(ranges.len(), 3);
+    for range in &ranges {
+        assert!(
+            shown[range.start]
+                .trim_start_matches("> ")
+                .trim_start()
+                .starts_with("```")
+        );
+        assert!(
+            shown[range.end - 1]
+                .trim_start_matches("> ")
+                .trim_start()
+                .starts_with("```")
+        );
+    }
+    assert_eq!(shown[ranges[2].start + 1].trim_end(), "last");
+}
+
+#[test]
+fn diff_fences_color_added_and_removed_lines() {
+    let theme = Theme::builtin("dark").unwrap();
+    let text = "```diff\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-old\n+new\n same\n```";
+    let lines = render::markdown(text, 0, &HIGHLIGHTER, &theme);
+    let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(
+        text,
+        [
+            "```diff",
+            "--- a/x",
+            "+++ b/x",
+            "@@ -1 +1 @@",
+            "- old",
+            "+ new",
+            "  same",
+            "```"
+        ]
+    );
+    assert_eq!(lines[4].spans[1].style.fg, Some(theme.error));
+    assert_eq!(lines[5].spans[1].style.fg, Some(theme.success));
+    assert_eq!(lines[6].spans[1].style.fg, None);
+}
+
+#[test]
+fn plain_theme_leaves_code_unhighlighted() {
+    let theme = Theme::builtin("plain").unwrap();
+    let lines = render::markdown("```rust\nfn main() {}\n```", 0, &HIGHLIGHTER, &theme);
+    assert_eq!(lines[1].to_string(), "fn main() {}");
+    assert!(lines[1].spans.iter().all(|s| s.style.fg.is_none()));
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "mock")]
+
+use gemchat::ai::{self, AiUpdate, Request, ToolOutcome};
+use gemchat::config::Config;
+use gemchat::mock;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Replay and record directories are process-wide
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+async fn collect(request: Request) -> Vec<AiUpdate> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    ai::stream_response(request, tx).await;
+    let mut updates = Vec::new();
+    while let Ok(update) = rx.try_recv() {
+        updates.push(update);
+    }
+    updates
+}
+
+fn text(updates: &[AiUpdate]) -> String {
+    updates
+        .iter()
+        .filter_map(|u| match u {
+            AiUpdate::Content(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn replays_a_recorded_tool_turn() {
+    let _serial = SERIAL.lock().await;
+    mock::set_record_dir(None);
+    mock::set_replay_dir(Some(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/weather"),
+    ));
+
+    let first = collect(Request {
+        prompt: "User: weather in Oslo?\n".to_string(),
+        ..Default::default()
+    })
+    .await;
+    let call = first
+        .into_iter()
+        .find_map(|u| match u {
+            AiUpdate::ToolCall(call) => Some(call),
+            _ => None,
+        })
+        .expect("the first fixture is a tool call");
+    assert_eq!(call.name, "fetch_url");
+
+    let second = collect(Request {
+        prompt: "User: weather in Oslo?\n".to_string(),
+        outcomes: vec![ToolOutcome {
+            call,
+            result: "Oslo: ☁️ +4°C".to_string(),
+        }],
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(text(&second), "It's 4°C and cloudy in Oslo right now.");
+
+    // Past the last fixture
+    let third = collect(Request::default()).await;
+    assert!(third.iter().any(|u| matches!(
+        u,
+        AiUpdate::Error(e) if e.contains("No recorded response")
+    )));
+    mock::set_replay_dir(None);
+}
+
+#[tokio::test]
+async fn recorded_responses_replay_identically() {
+    let _serial = SERIAL.lock().await;
+    let dir = tempfile::tempdir().unwrap();
+    let body = "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"recorded\"}]},\"finishReason\":\"STOP\"}]}\n\n";
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .mount(&server)
+        .await;
+    let mut config = Config::default();
+    config.api.base_url = Some(server.uri());
+    ai::configure(&config).unwrap();
+    ai::set_api_key(Some("test-key".to_string()));
+
+    mock::set_replay_dir(None);
+    mock::set_record_dir(Some(dir.path().to_path_buf()));
+    let live = collect(Request::default()).await;
+    mock::set_record_dir(None);
+    drop(server);
+
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("000.sse")).unwrap(),
+        body
+    );
+
+    mock::set_replay_dir(Some(dir.path().to_path_buf()));
+    let replayed = collect(Request::default()).await;
+    mock::set_replay_dir(None);
+
+    assert_eq!(text(&live), "recorded");
+    assert_eq!(text(&replayed), text(&live));
+}
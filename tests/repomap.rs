@@ -0,0 +1,41 @@
+use gemchat::repomap;
+use std::fs;
+
+fn project() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("target")).unwrap();
+    fs::write(
+        root.join("src/lib.rs"),
+        "pub struct Config {\n    pub fn nested() {}\n}\n\nimpl Config {\n}\n\npub(crate) async fn load() {}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("app.py"),
+        "class Server:\n    def handle(self):\n        pass\n\ndef main():\n    pass\n",
+    )
+    .unwrap();
+    fs::write(root.join("target/junk.rs"), "fn ignored() {}\n").unwrap();
+    fs::write(root.join(".ignore"), "target/\n").unwrap();
+    dir
+}
+
+#[test]
+fn lists_files_with_top_level_symbols() {
+    let dir = project();
+    let map = repomap::build(dir.path(), 1000);
+    assert_eq!(
+        map,
+        "app.py\n  class Server\n  def main\nsrc/lib.rs\n  pub struct Config\n  impl Config\n  pub(crate) async fn load\n"
+    );
+}
+
+#[test]
+fn drops_symbols_before_files_when_over_budget() {
+    let dir = project();
+    let map = repomap::build(dir.path(), 5);
+    assert!(map.contains("app.py\n"));
+    assert!(map.contains("src/lib.rs\n"));
+    assert!(map.len() <= 5 * 4);
+}
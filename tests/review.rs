@@ -0,0 +1,58 @@
+use gemchat::review::{Review, Severity};
+
+const REPLY: &str = "\
+Here is my review.
+
+- [low] src/main.rs:88: `unwrap` on user input
+[HIGH] `src/auth.rs:12`: the key is logged
+  in plain text at debug level.
+
+[medium] src/main.rs: no test covers the new branch
+[high] src/main.rs:40: off-by-one when the list is empty
+Summary: One real bug and a leak; otherwise fine.
+";
+
+#[test]
+fn groups_findings_by_file_most_severe_first() {
+    let review = Review::parse(REPLY);
+
+    let paths: Vec<_> = review.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["src/main.rs", "src/auth.rs"]);
+
+    let main: Vec<_> = review.files[0]
+        .findings
+        .iter()
+        .map(|f| (f.severity, f.line))
+        .collect();
+    assert_eq!(
+        main,
+        [
+            (Severity::High, Some(40)),
+            (Severity::Medium, None),
+            (Severity::Low, Some(88)),
+        ]
+    );
+
+    let auth = &review.files[1].findings[0];
+    assert_eq!(auth.severity, Severity::High);
+    assert_eq!(auth.line, Some(12));
+    assert_eq!(
+        auth.message,
+        "the key is logged in plain text at debug level."
+    );
+    assert_eq!(review.summary, "One real bug and a leak; otherwise fine.");
+}
+
+#[test]
+fn text_form_parses_back_to_the_same_review() {
+    let review = Review::parse(REPLY);
+    let again = Review::parse(&review.to_string());
+    assert_eq!(again.to_string(), review.to_string());
+}
+
+#[test]
+fn reply_without_findings() {
+    let review = Review::parse("Summary: Looks good.");
+    assert!(review.is_empty());
+    assert_eq!(review.summary, "Looks good.");
+}
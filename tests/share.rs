@@ -0,0 +1,54 @@
+use gemchat::config::ShareConfig;
+use gemchat::share;
+use serde_json::json;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn transcripts_become_secret_gists() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/gists"))
+        .and(header("authorization", "Bearer gh-token"))
+        .respond_with(
+            ResponseTemplate::new(201)
+                .set_body_json(json!({ "html_url": "https://gist.github.com/abc123" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let config = ShareConfig {
+        github_token: Some("gh-token".to_string()),
+        api_url: Some(format!("{}/", server.uri())),
+    };
+
+    let url = share::gist(&config, "chat.md", "Debugging", "# Debugging\n")
+        .await
+        .unwrap();
+
+    assert_eq!(url, "https://gist.github.com/abc123");
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = requests[0].body_json().unwrap();
+    assert_eq!(body["public"], false);
+    assert_eq!(body["description"], "Debugging");
+    assert_eq!(body["files"]["chat.md"]["content"], "# Debugging\n");
+}
+
+#[tokio::test]
+async fn github_errors_are_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/gists"))
+        .respond_with(ResponseTemplate::new(401).set_body_string("Bad credentials"))
+        .mount(&server)
+        .await;
+    let config = ShareConfig {
+        github_token: Some("expired".to_string()),
+        api_url: Some(server.uri()),
+    };
+
+    let error = share::gist(&config, "chat.md", "", "text")
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("Bad credentials"));
+}
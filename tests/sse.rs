@@ -0,0 +1,55 @@
+use gemchat::sse::{Event, Parser};
+use std::time::Duration;
+
+fn events(stream: &str) -> Vec<Event> {
+    let mut parser = Parser::default();
+    let mut events: Vec<Event> = stream.lines().filter_map(|l| parser.line(l)).collect();
+    events.extend(parser.finish());
+    events
+}
+
+#[test]
+fn data_lines_are_joined_until_a_blank_line() {
+    let stream = ": keep-alive\n\
+                  data: {\"a\":\n\
+                  data:1}\n\
+                  \n\
+                  event: error\n\
+                  id: 7\n\
+                  retry: 3000\n\
+                  data: quota\n\
+                  \n\
+                  event: ping\n\
+                  \n\
+                  data: [DONE]";
+    let mut parser = Parser::default();
+    let parsed: Vec<Event> = stream.lines().filter_map(|l| parser.line(l)).collect();
+    assert_eq!(
+        parsed,
+        [
+            Event {
+                event: None,
+                data: "{\"a\":\n1}".to_string(),
+            },
+            Event {
+                event: Some("error".to_string()),
+                data: "quota".to_string(),
+            },
+        ]
+    );
+    assert_eq!(parser.last_id.as_deref(), Some("7"));
+    assert_eq!(parser.retry, Some(Duration::from_secs(3)));
+
+    // The sentinel has no blank line after it, so it only comes out at the end
+    let last = parser.finish().unwrap();
+    assert!(last.is_done());
+}
+
+#[test]
+fn empty_data_lines_still_count() {
+    let parsed = events("data:\ndata: x\n\nevent: message\ndata: y\n");
+    assert_eq!(parsed[0].data, "\nx");
+    assert_eq!(parsed[1].event, None);
+    assert_eq!(parsed[1].data, "y");
+    assert!(events("id: 1\n\n").is_empty());
+}
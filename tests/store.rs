@@ -0,0 +1,303 @@
+use chrono::Local;
+use gemchat::audit::{self, Approval, Entry};
+use gemchat::cipher::Cipher;
+use gemchat::store::{LOCKED, Store, StoredBranch, StoredMessage};
+use gemchat::tools::Status;
+
+fn message(role: &str, content: &str) -> StoredMessage {
+    StoredMessage {
+        role: role.into(),
+        content: content.into(),
+        model: None,
+        generation: None,
+        created: Local::now(),
+    }
+}
+
+#[test]
+fn branches_are_saved_incrementally_and_searchable() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let session = store.create_session(Some("Borrow checker woes")).unwrap();
+
+    store
+        .save_branch(
+            session,
+            0,
+            &[message("You", "why does the borrow checker complain")],
+        )
+        .unwrap();
+    // A streamed response is written while it grows
+    store
+        .save_message(session, 0, 1, &message("AI", "Because"))
+        .unwrap();
+    store
+        .save_message(session, 0, 1, &message("AI", "Because of a mutable alias"))
+        .unwrap();
+    // Dropping the response, as a retry does, removes it from the index too
+    let question = store.messages(session, 0).unwrap()[..1].to_vec();
+    store.save_branch(session, 0, &question).unwrap();
+
+    let messages = store.messages(session, 0).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert!(store.search("alias", 10).unwrap().is_empty());
+    let hits = store.search("borrow", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].title.as_deref(), Some("Borrow checker woes"));
+    assert!(hits[0].snippet.contains("[borrow]"));
+}
+
+#[test]
+fn audit_entries_and_usage_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open_at(&dir.path().join("test.db")).unwrap();
+
+    for tool in ["read_file", "run_command", "write_file"] {
+        let entry = Entry::new(
+            tool,
+            r#"{"path":"a"}"#,
+            Status::Exit(1),
+            Approval::User,
+            "out",
+        );
+        store.record_audit(&entry).unwrap();
+    }
+    let recent = store.recent_audit(2).unwrap();
+    assert_eq!(
+        recent.iter().map(|e| e.tool.as_str()).collect::<Vec<_>>(),
+        ["run_command", "write_file"]
+    );
+    assert_eq!(recent[0].status, Status::Exit(1));
+    assert_eq!(recent[0].args["path"], "a");
+
+    store.record_usage(None, "gemini", 10, 8, 5, 15).unwrap();
+    store.record_usage(None, "gemini", 1, 0, 2, 3).unwrap();
+    let totals = store.usage_totals(None).unwrap();
+    assert_eq!((totals.requests, totals.total_tokens), (2, 18));
+    assert_eq!(totals.cached_tokens, 8);
+}
+
+#[test]
+fn legacy_audit_log_is_imported_once_even_after_new_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let lost = Entry::new("read_file", "{}", Status::Exit(0), Approval::Auto, "a");
+    let kept = Entry::new("write_file", "{}", Status::Exit(0), Approval::Rule, "b");
+    let log = dir.path().join("audit.jsonl");
+    let lines: Vec<String> = [&lost, &kept]
+        .iter()
+        .map(|e| serde_json::to_string(e).unwrap())
+        .collect();
+    std::fs::write(&log, format!("{}\nnot json\n", lines.join("\n"))).unwrap();
+
+    // One imported by an interrupted run, and one recorded before the import
+    let new = Entry::new("run_command", "{}", Status::Exit(0), Approval::User, "c");
+    store.record_audit(&kept).unwrap();
+    store.record_audit(&new).unwrap();
+    audit::import_legacy(&store, &log).unwrap();
+
+    let recent = store.recent_audit(10).unwrap();
+    assert_eq!(
+        recent.iter().map(|e| e.tool.as_str()).collect::<Vec<_>>(),
+        ["write_file", "run_command", "read_file"]
+    );
+    assert!(!log.exists());
+    assert!(dir.path().join("audit.jsonl.imported").exists());
+
+    // Without a log there is nothing to do
+    audit::import_legacy(&store, &log).unwrap();
+    assert_eq!(store.recent_audit(10).unwrap().len(), 3);
+}
+
+#[test]
+fn search_matches_prefixes_and_finds_the_message() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let session = store.create_session(None).unwrap();
+    store
+        .save_branch(
+            session,
+            1,
+            &[
+                message("You", "How do I parse TOML?"),
+                message("AI", "Use the toml crate's from_str."),
+            ],
+        )
+        .unwrap();
+    let branches = [
+        StoredBranch {
+            name: "main".into(),
+            parent: None,
+            fork_at: 0,
+        },
+        StoredBranch {
+            name: "fork 1".into(),
+            parent: Some(0),
+            fork_at: 1,
+        },
+    ];
+    store.save_branches(session, &branches).unwrap();
+
+    // Punctuation is searched for, not read as query syntax
+    let hits = store.search("crate's from_s", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].branch, hits[0].position), (1, 1));
+    assert!(store.search("   ", 10).unwrap().is_empty());
+    assert_eq!(store.branches(session).unwrap(), branches);
+}
+
+#[test]
+fn encryption_hides_contents_until_unlocked() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::open_at(&path).unwrap();
+    let session = store.create_session(None).unwrap();
+    store
+        .save_branch(session, 0, &[message("You", "the deploy token is hunter2")])
+        .unwrap();
+    let salt = store.salt().unwrap();
+    assert!(
+        store
+            .unlock(Cipher::from_passphrase("right", &salt))
+            .unwrap()
+    );
+    store
+        .save_message(session, 0, 1, &message("AI", "rotate hunter2 now"))
+        .unwrap();
+    drop(store);
+
+    // Neither the rows nor the full-text index hold the plaintext any more
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(7).any(|w| w == b"hunter2"));
+
+    let mut locked = Store::open_at(&path).unwrap();
+    assert!(locked.is_encrypted().unwrap());
+    assert_eq!(locked.messages(session, 0).unwrap()[0].content, LOCKED);
+    assert!(
+        !locked
+            .unlock(Cipher::from_passphrase("wrong", &salt))
+            .unwrap()
+    );
+    assert!(
+        locked
+            .unlock(Cipher::from_passphrase("right", &salt))
+            .unwrap()
+    );
+    assert_eq!(
+        locked.messages(session, 0).unwrap()[1].content,
+        "rotate hunter2 now"
+    );
+    let hits = locked.search("HUNTER", 10).unwrap();
+    assert_eq!(hits.len(), 2);
+    assert!(hits.iter().all(|h| h.snippet.contains("[hunter]")));
+}
+
+#[test]
+fn encryption_covers_titles_and_audit_arguments() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::open_at(&path).unwrap();
+    let session = store.create_session(Some("Rotating hunter2")).unwrap();
+    let entry = Entry::new(
+        "run_command",
+        r#"{"command":"echo hunter2"}"#,
+        Status::Ok,
+        Approval::Auto,
+        "hunter2\n",
+    );
+    store.record_audit(&entry).unwrap();
+    let salt = store.salt().unwrap();
+    assert!(
+        store
+            .unlock(Cipher::from_passphrase("right", &salt))
+            .unwrap()
+    );
+    store.set_title(session, "Still hunter2").unwrap();
+    drop(store);
+
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(7).any(|w| w == b"hunter2"));
+
+    let mut locked = Store::open_at(&path).unwrap();
+    assert_eq!(locked.sessions().unwrap()[0].title.as_deref(), Some(LOCKED));
+    assert_eq!(locked.recent_audit(10).unwrap()[0].args, LOCKED);
+    assert!(
+        locked
+            .unlock(Cipher::from_passphrase("right", &salt))
+            .unwrap()
+    );
+    assert_eq!(
+        locked.session(session).unwrap().unwrap().title.as_deref(),
+        Some("Still hunter2")
+    );
+    assert_eq!(locked.recent_audit(10).unwrap()[0].args, entry.args);
+    assert!(locked.has_audit_entry(&entry).unwrap());
+}
+
+#[test]
+fn snippets_of_decrypted_messages_survive_lowercasing_that_changes_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let salt = store.salt().unwrap();
+    assert!(store.unlock(Cipher::from_passphrase("pw", &salt)).unwrap());
+    let session = store.create_session(None).unwrap();
+    // İ lowercases to two characters
+    store
+        .save_branch(
+            session,
+            0,
+            &[message("You", "İİx"), message("AI", "İSTANBUL x")],
+        )
+        .unwrap();
+
+    let hits = store.search("x", 10).unwrap();
+    let snippets: Vec<&str> = hits.iter().map(|h| h.snippet.as_str()).collect();
+    assert_eq!(snippets, ["İSTANBUL [x]", "İİ[x]"]);
+}
+
+#[test]
+fn pruning_keeps_recent_and_pinned_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let sessions: Vec<i64> = (0..3)
+        .map(|_| store.create_session(None).unwrap())
+        .collect();
+    for &session in &sessions {
+        store
+            .save_branch(session, 0, &[message("You", "hello")])
+            .unwrap();
+    }
+    store.set_pinned(sessions[0], true).unwrap();
+    store
+        .record_usage(Some(sessions[1]), "gemini", 1, 0, 1, 2)
+        .unwrap();
+
+    assert_eq!(store.prune(Some(1), None).unwrap(), 1);
+    let kept: Vec<i64> = store.sessions().unwrap().iter().map(|s| s.id).collect();
+    assert_eq!(kept, [sessions[2], sessions[0]]);
+    assert!(store.messages(sessions[1], 0).unwrap().is_empty());
+    assert_eq!(store.search("hello", 10).unwrap().len(), 2);
+    assert_eq!(store.usage_totals(None).unwrap().total_tokens, 2);
+    assert_eq!(store.prune(None, Some(1)).unwrap(), 0);
+    store.vacuum().unwrap();
+}
+
+#[test]
+fn sessions_remember_their_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open_at(&dir.path().join("test.db")).unwrap();
+    let session = store.create_session(None).unwrap();
+    assert_eq!(store.session(session).unwrap().unwrap().model, None);
+
+    store
+        .set_model(session, Some("gemini-2.5-pro"), Some("vertex"))
+        .unwrap();
+    let saved = store.session(session).unwrap().unwrap();
+    assert_eq!(saved.model.as_deref(), Some("gemini-2.5-pro"));
+    assert_eq!(saved.provider.as_deref(), Some("vertex"));
+    assert_eq!(saved.template, None);
+
+    store.set_template(session, Some("Rust reviewer")).unwrap();
+    let saved = store.session(session).unwrap().unwrap();
+    assert_eq!(saved.template.as_deref(), Some("Rust reviewer"));
+}
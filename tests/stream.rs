@@ -0,0 +1,646 @@
+use gemchat::ai::{self, AiUpdate, Attachment, Request, ToolOutcome};
+use gemchat::config::{Config, GenerationConfig, HarmCategory, Threshold};
+use gemchat::tools;
+use serde_json::json;
+use tokio::sync::Mutex;
+use wiremock::matchers::{body_string_contains, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// The endpoint is process-wide, so tests pointing it at their own server take turns
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+const MODEL_PATH: &str = "/v1beta/models/gemini-3-flash-preview:streamGenerateContent";
+
+fn sse(events: &[serde_json::Value]) -> String {
+    events
+        .iter()
+        .map(|event| format!("data: {}\r\n\r\n", event))
+        .collect()
+}
+
+fn text_event(text: &str) -> serde_json::Value {
+    json!({ "candidates": [{ "content": { "role": "model", "parts": [{ "text": text }] } }] })
+}
+
+fn finish_event() -> serde_json::Value {
+    json!({
+        "candidates": [{ "content": { "role": "model", "parts": [] }, "finishReason": "STOP" }],
+        "usageMetadata": { "promptTokenCount": 7, "candidatesTokenCount": 3, "totalTokenCount": 10 }
+    })
+}
+
+fn sse_response(body: String) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(body, "text/event-stream")
+}
+
+async fn start_server() -> MockServer {
+    let server = MockServer::start().await;
+    let mut config = Config::default();
+    config.api.base_url = Some(server.uri());
+    ai::configure(&config).unwrap();
+    ai::set_api_key(Some("test-key".to_string()));
+    server
+}
+
+async fn collect(request: Request) -> Vec<AiUpdate> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    ai::stream_response(request, tx).await;
+    let mut updates = Vec::new();
+    while let Ok(update) = rx.try_recv() {
+        updates.push(update);
+    }
+    updates
+}
+
+fn text(updates: &[AiUpdate]) -> String {
+    updates
+        .iter()
+        .filter_map(|u| match u {
+            AiUpdate::Content(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn models(updates: &[AiUpdate]) -> Vec<&str> {
+    updates
+        .iter()
+        .filter_map(|u| match u {
+            AiUpdate::Model(model) => Some(model.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn prompt(text: &str) -> Request {
+    Request {
+        prompt: format!("User: {}\n", text),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn streams_text_and_usage() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(query_param("alt", "sse"))
+        .respond_with(sse_response(sse(&[
+            text_event("Hel"),
+            text_event("lo ✓"),
+            finish_event(),
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let updates = collect(prompt("hi")).await;
+
+    assert_eq!(text(&updates), "Hello ✓");
+    assert_eq!(models(&updates), ["gemini-3-flash-preview"]);
+    assert!(updates.iter().any(|u| matches!(
+        u,
+        AiUpdate::Usage(usage) if usage.total_tokens == 10
+    )));
+    assert!(matches!(updates.last(), Some(AiUpdate::Finished)));
+    assert!(
+        !updates
+            .iter()
+            .any(|u| matches!(u, AiUpdate::Interrupted(_) | AiUpdate::Error(_)))
+    );
+}
+
+#[tokio::test]
+async fn falls_back_to_the_next_model_on_quota_errors() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/primary:streamGenerateContent"))
+        .respond_with(ResponseTemplate::new(429).set_body_string("quota exceeded"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/fallback:streamGenerateContent"))
+        .respond_with(sse_response(sse(&[
+            text_event("from fallback"),
+            finish_event(),
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let updates = collect(Request {
+        models: vec!["primary".to_string(), "fallback".to_string()],
+        ..prompt("hi")
+    })
+    .await;
+
+    assert_eq!(models(&updates), ["fallback"]);
+    assert_eq!(text(&updates), "from fallback");
+}
+
+#[tokio::test]
+async fn other_errors_do_not_fall_back() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/primary:streamGenerateContent"))
+        .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/fallback:streamGenerateContent"))
+        .respond_with(sse_response(sse(&[finish_event()])))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let updates = collect(Request {
+        models: vec!["primary".to_string(), "fallback".to_string()],
+        ..prompt("hi")
+    })
+    .await;
+
+    assert!(updates.iter().any(|u| matches!(
+        u,
+        AiUpdate::Error(e) if e.contains("400") && e.contains("bad request")
+    )));
+}
+
+#[tokio::test]
+async fn stream_without_finish_reason_is_interrupted() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .respond_with(sse_response(sse(&[text_event("half an ans")])))
+        .mount(&server)
+        .await;
+
+    let updates = collect(prompt("hi")).await;
+
+    assert_eq!(text(&updates), "half an ans");
+    assert!(
+        updates
+            .iter()
+            .any(|u| matches!(u, AiUpdate::Interrupted(_)))
+    );
+}
+
+#[tokio::test]
+async fn multi_line_events_and_done_sentinels_are_understood() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    // Pretty-printed JSON spread over several data lines, comments and a sentinel
+    let pretty = serde_json::to_string_pretty(&text_event("spread out")).unwrap();
+    let data: String = pretty.lines().map(|l| format!("data: {}\n", l)).collect();
+    let body = format!(": ping\nevent: message\nid: 1\n{}\ndata: [DONE]\n\n", data);
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .respond_with(sse_response(body))
+        .mount(&server)
+        .await;
+
+    let updates = collect(prompt("hi")).await;
+
+    assert_eq!(text(&updates), "spread out");
+    assert!(
+        !updates
+            .iter()
+            .any(|u| matches!(u, AiUpdate::Interrupted(_)))
+    );
+}
+
+#[tokio::test]
+async fn tool_call_round_trip() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    let call_event = json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{
+                "functionCall": { "name": "run_command", "args": { "command": "echo tool-output" } },
+                "thoughtSignature": "sig-1"
+            }] },
+            "finishReason": "STOP"
+        }]
+    });
+    // The follow-up request carries the result; the first one doesn't
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(body_string_contains("functionResponse"))
+        .respond_with(sse_response(sse(&[text_event("done"), finish_event()])))
+        .expect(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .respond_with(sse_response(sse(&[call_event])))
+        .expect(1)
+        .with_priority(2)
+        .mount(&server)
+        .await;
+
+    let first = collect(prompt("run it")).await;
+    let calls: Vec<_> = first
+        .into_iter()
+        .filter_map(|u| match u {
+            AiUpdate::ToolCall(call) => Some(call),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].name, "run_command");
+    assert_eq!(calls[0].thought_signature.as_deref(), Some("sig-1"));
+
+    let config = Config::default();
+    let mut outcomes = Vec::new();
+    for call in calls {
+        let execution = tools::execute_tool(&call.name, &call.args, &config.tools).await;
+        assert!(execution.output.contains("tool-output"));
+        outcomes.push(ToolOutcome {
+            call,
+            result: execution.output,
+        });
+    }
+
+    let second = collect(Request {
+        outcomes,
+        ..prompt("run it")
+    })
+    .await;
+    assert_eq!(text(&second), "done");
+
+    let requests = server.received_requests().await.unwrap();
+    let follow_up: serde_json::Value = requests[1].body_json().unwrap();
+    let contents = follow_up["contents"].as_array().unwrap();
+    let call_part = &contents[1]["parts"][0];
+    assert_eq!(call_part["functionCall"]["name"], "run_command");
+    assert_eq!(call_part["thoughtSignature"], "sig-1");
+    let result = &contents[2]["parts"][0]["functionResponse"]["response"]["result"];
+    assert!(result.as_str().unwrap().contains("tool-output"));
+}
+
+#[tokio::test]
+async fn streamed_tool_arguments_are_assembled() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    let call = |function_call: serde_json::Value| {
+        json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "functionCall": function_call }] } }]
+        })
+    };
+    let events = [
+        call(json!({ "id": "call-1", "name": "write_file", "willContinue": true })),
+        call(json!({
+            "partialArgs": [
+                { "jsonPath": "$.path", "stringValue": "notes/", "willContinue": true },
+                { "jsonPath": "$.content", "stringValue": "first ", "willContinue": true }
+            ],
+            "willContinue": true
+        })),
+        call(json!({
+            "partialArgs": [
+                { "jsonPath": "$.path", "stringValue": "todo.md" },
+                { "jsonPath": "$.content", "stringValue": "line" },
+                { "jsonPath": "$.tags[1]", "stringValue": "b" },
+                { "jsonPath": "$.append", "boolValue": true }
+            ],
+            "willContinue": true
+        })),
+        call(json!({})),
+        call(json!({ "name": "run_command", "args": { "command": "ls" } })),
+        finish_event(),
+    ];
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .respond_with(sse_response(sse(&events)))
+        .mount(&server)
+        .await;
+
+    let calls: Vec<_> = collect(prompt("write it"))
+        .await
+        .into_iter()
+        .filter_map(|u| match u {
+            AiUpdate::ToolCall(call) => Some(call),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].id.as_deref(), Some("call-1"));
+    assert_eq!(calls[0].name, "write_file");
+    let args: serde_json::Value = serde_json::from_str(&calls[0].args).unwrap();
+    assert_eq!(
+        args,
+        json!({ "path": "notes/todo.md", "content": "first line", "tags": [null, "b"], "append": true })
+    );
+    assert_eq!(calls[1].name, "run_command");
+}
+
+#[tokio::test]
+async fn long_prefixes_are_served_from_a_context_cache() {
+    let _serial = SERIAL.lock().await;
+    let server = MockServer::start().await;
+    let mut config = Config::default();
+    config.api.base_url = Some(server.uri());
+    config.context.cache = Some(true);
+    ai::configure(&config).unwrap();
+    ai::set_api_key(Some("test-key".to_string()));
+
+    Mock::given(method("POST"))
+        .and(path("/v1beta/cachedContents"))
+        .and(body_string_contains("models/gemini-3-flash-preview"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "name": "cachedContents/abc" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let cached_finish = json!({
+        "candidates": [{ "content": { "role": "model", "parts": [] }, "finishReason": "STOP" }],
+        "usageMetadata": {
+            "promptTokenCount": 2000, "cachedContentTokenCount": 1800,
+            "candidatesTokenCount": 3, "totalTokenCount": 2003
+        }
+    });
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(body_string_contains("cachedContents/abc"))
+        .respond_with(sse_response(sse(&[text_event("ok"), cached_finish])))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let request = Request {
+        prefix: "System Instructions: be brief.\n".repeat(200),
+        ..prompt("hi")
+    };
+    collect(request.clone()).await;
+    let updates = collect(request).await;
+
+    assert_eq!(text(&updates), "ok");
+    assert!(updates.iter().any(|u| matches!(
+        u,
+        AiUpdate::Usage(usage) if usage.cached_tokens == 1800
+    )));
+    // The prefix and tools live in the cache, so the request itself only has the history
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = requests.last().unwrap().body_json().unwrap();
+    assert!(body.get("tools").is_none());
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "User: hi\n");
+}
+
+#[tokio::test]
+async fn safety_settings_are_sent_and_blocks_reported() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    let blocked = json!({
+        "candidates": [{
+            "finishReason": "SAFETY",
+            "safetyRatings": [
+                { "category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE" },
+                { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true }
+            ]
+        }]
+    });
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(body_string_contains("BLOCK_ONLY_HIGH"))
+        .respond_with(sse_response(sse(&[blocked])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let updates = collect(Request {
+        safety: [(HarmCategory::DangerousContent, Threshold::BlockOnlyHigh)].into(),
+        ..prompt("how do exploits work?")
+    })
+    .await;
+
+    let reports: Vec<_> = updates
+        .iter()
+        .filter_map(|u| match u {
+            AiUpdate::Safety(report) => Some(report),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].blocked.as_deref(), Some("SAFETY"));
+    assert_eq!(
+        reports[0].ratings,
+        [(
+            "HARM_CATEGORY_DANGEROUS_CONTENT".to_string(),
+            "HIGH".to_string()
+        )]
+    );
+    assert!(
+        !updates
+            .iter()
+            .any(|u| matches!(u, AiUpdate::Interrupted(_)))
+    );
+}
+
+#[tokio::test]
+async fn several_candidates_arrive_whole_as_alternatives() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    let candidate = |text: &str| json!({ "content": { "role": "model", "parts": [{ "text": text }] }, "finishReason": "STOP" });
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/gemini-3-flash-preview:generateContent"))
+        .and(body_string_contains("\"candidateCount\":2"))
+        .and(body_string_contains("\"seed\":7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [candidate("first"), candidate("second")],
+            "usageMetadata": { "promptTokenCount": 7, "candidatesTokenCount": 6, "totalTokenCount": 13 }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let updates = collect(Request {
+        generation: GenerationConfig {
+            candidates: Some(2),
+            seed: Some(7),
+            ..Default::default()
+        },
+        ..prompt("hi")
+    })
+    .await;
+
+    assert_eq!(text(&updates), "first");
+    assert!(updates.iter().any(|u| matches!(
+        u,
+        AiUpdate::Alternatives(texts) if texts == &["first", "second"]
+    )));
+    assert!(matches!(updates.last(), Some(AiUpdate::Finished)));
+}
+
+#[tokio::test]
+async fn images_are_sent_inline_after_the_prompt() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(body_string_contains("\"mimeType\":\"image/png\""))
+        .and(body_string_contains("\"data\":\"iVBORw==\""))
+        .respond_with(sse_response(sse(&[
+            text_event("A red square."),
+            finish_event(),
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let updates = collect(Request {
+        attachments: vec![Attachment {
+            mime_type: "image/png".to_string(),
+            data: b"\x89PNG".to_vec().into(),
+        }],
+        ..prompt("what is in this screenshot?\n[Attached image 1]")
+    })
+    .await;
+    assert_eq!(text(&updates), "A red square.");
+}
+
+#[tokio::test]
+async fn generated_images_are_decoded() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path(
+            "/v1beta/models/gemini-2.5-flash-image:generateContent",
+        ))
+        .and(body_string_contains(
+            "\"responseModalities\":[\"TEXT\",\"IMAGE\"]",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "text": "Here is your cat." },
+                    { "inlineData": { "mimeType": "image/png", "data": "iVBORw==" } }
+                ] },
+                "finishReason": "STOP"
+            }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let generated = ai::generate_image(ai::IMAGE_MODEL, "a cat", None)
+        .await
+        .unwrap();
+    assert_eq!(generated.text, "Here is your cat.");
+    assert_eq!(generated.images.len(), 1);
+    assert_eq!(generated.images[0].mime_type, "image/png");
+    assert_eq!(&generated.images[0].data[..], b"\x89PNG");
+}
+
+#[tokio::test]
+async fn large_recordings_are_uploaded_once() {
+    let _serial = SERIAL.lock().await;
+    let server = start_server().await;
+    Mock::given(method("POST"))
+        .and(path("/upload/v1beta/files"))
+        .respond_with(ResponseTemplate::new(200).insert_header(
+            "x-goog-upload-url",
+            format!("{}/upload-session", server.uri()),
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/upload-session"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "file": { "name": "files/meeting", "uri": "https://files.example/meeting", "state": "ACTIVE" }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(MODEL_PATH))
+        .and(body_string_contains(
+            "\"fileUri\":\"https://files.example/meeting\"",
+        ))
+        .respond_with(sse_response(sse(&[text_event("Summary."), finish_event()])))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let request = Request {
+        attachments: vec![Attachment {
+            mime_type: "audio/ogg".to_string(),
+            data: vec![0; 15_000_000].into(),
+        }],
+        ..prompt("transcribe and summarize this meeting\n[Attached audio 1]")
+    };
+    assert_eq!(text(&collect(request.clone()).await), "Summary.");
+    // The follow-up question reuses the upload
+    assert_eq!(text(&collect(request).await), "Summary.");
+}
+
+#[test]
+fn errors_are_classified_by_status_and_body() {
+    let quota = ai::classify_error(
+        r#"Error: API Error 429 Too Many Requests: {"error": {"code": 429, "message": "Resource has been exhausted (e.g. check quota).", "status": "RESOURCE_EXHAUSTED"}}"#,
+    );
+    assert_eq!(quota.kind, ai::ErrorKind::Quota);
+    assert_eq!(quota.status, Some(429));
+    assert_eq!(quota.code.as_deref(), Some("RESOURCE_EXHAUSTED"));
+    assert_eq!(
+        quota.message,
+        "Resource has been exhausted (e.g. check quota)."
+    );
+
+    // A bad key is a 400 from the Gemini API
+    let auth = ai::classify_error(
+        r#"Error: API Error 400 Bad Request: {"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}"#,
+    );
+    assert_eq!(auth.kind, ai::ErrorKind::Auth);
+
+    let network = ai::classify_error(
+        "Error: error sending request for url (https://generativelanguage.googleapis.com/)",
+    );
+    assert_eq!(network.kind, ai::ErrorKind::Network);
+    assert_eq!(network.status, None);
+
+    let other = ai::classify_error("Error: API Error 400 Bad Request: not json");
+    assert_eq!(other.kind, ai::ErrorKind::Other);
+    assert_eq!(other.message, "not json");
+}
+
+#[test]
+fn prompts_are_built_from_blocks_in_a_stable_order() {
+    use ai::{BlockKind, ContextBlock};
+
+    let request = |question: &str| {
+        Request::from_blocks(vec![
+            ContextBlock::new(BlockKind::Turn, format!("You: {}\n", question)),
+            ContextBlock::new(BlockKind::History, "You: hi\nAI: hello\n"),
+            ContextBlock::new(BlockKind::Pinned, "Repository Map:\nsrc/main.rs\n"),
+            ContextBlock::new(BlockKind::System, "Instructions\n"),
+        ])
+    };
+    let first = request("why?");
+    assert_eq!(first.prefix, "Instructions\nRepository Map:\nsrc/main.rs\n");
+    assert_eq!(first.prompt, "You: hi\nAI: hello\nYou: why?\n");
+
+    // Only the turn changed, so the prefix can come from the same cache
+    let second = request("how?");
+    assert_eq!(first.prefix_hash(), second.prefix_hash());
+    assert_ne!(first.prompt, second.prompt);
+
+    let body = ai::request_body(&second, &[], None, None);
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "Instructions\nRepository Map:\nsrc/main.rs\nYou: hi\nAI: hello\nYou: how?\n"
+    );
+    let cached = ai::request_body(&second, &[], None, Some("cachedContents/abc"));
+    assert_eq!(cached["cachedContent"], "cachedContents/abc");
+    assert_eq!(
+        cached["contents"][0]["parts"][0]["text"],
+        "You: hi\nAI: hello\nYou: how?\n"
+    );
+}
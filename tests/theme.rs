@@ -0,0 +1,46 @@
+use gemchat::theme::ColorDepth;
+use ratatui::style::Color;
+
+fn detect(vars: &[(&str, &str)]) -> ColorDepth {
+    ColorDepth::from_env(|name| {
+        vars.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+#[test]
+fn depth_comes_from_the_environment() {
+    assert_eq!(
+        detect(&[("NO_COLOR", "1"), ("COLORTERM", "truecolor")]),
+        ColorDepth::None
+    );
+    // An empty NO_COLOR doesn't count
+    assert_eq!(
+        detect(&[("NO_COLOR", ""), ("COLORTERM", "24bit")]),
+        ColorDepth::TrueColor
+    );
+    assert_eq!(detect(&[("TERM", "xterm-256color")]), ColorDepth::Ansi256);
+    assert_eq!(detect(&[("TERM", "xterm")]), ColorDepth::Ansi16);
+    assert_eq!(detect(&[("TERM", "dumb")]), ColorDepth::None);
+}
+
+#[test]
+fn rgb_falls_back_to_the_nearest_palette_color() {
+    let solarized_red = Color::Rgb(0xdc, 0x32, 0x2f);
+    assert_eq!(ColorDepth::TrueColor.adapt(solarized_red), solarized_red);
+    assert_eq!(
+        ColorDepth::Ansi256.adapt(solarized_red),
+        Color::Indexed(166)
+    );
+    assert_eq!(ColorDepth::Ansi16.adapt(solarized_red), Color::Red);
+    assert_eq!(ColorDepth::None.adapt(solarized_red), Color::Reset);
+
+    // Grays use the gray ramp rather than the cube
+    assert_eq!(
+        ColorDepth::Ansi256.adapt(Color::Rgb(0x58, 0x58, 0x58)),
+        Color::Indexed(240)
+    );
+    assert_eq!(ColorDepth::Ansi16.adapt(Color::Indexed(9)), Color::LightRed);
+    assert_eq!(ColorDepth::Ansi16.adapt(Color::Blue), Color::Blue);
+}
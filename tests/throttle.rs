@@ -0,0 +1,65 @@
+use gemchat::ai::{self, AiUpdate, Request};
+use gemchat::config::Config;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn prompt() -> Request {
+    Request {
+        prompt: "User: Hello\n".to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn a_send_over_the_request_limit_waits_for_the_window() {
+    let server = MockServer::start().await;
+    let finish = json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": "Hi" }] },
+            "finishReason": "STOP"
+        }]
+    });
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(format!("data: {}\r\n\r\n", finish), "text/event-stream"),
+        )
+        .mount(&server)
+        .await;
+    let mut config = Config::default();
+    config.api.base_url = Some(server.uri());
+    config.api.requests_per_minute = Some(1);
+    ai::configure(&config).unwrap();
+    ai::set_api_key(Some("test-key".to_string()));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    ai::stream_response(prompt(), tx).await;
+    let mut first = Vec::new();
+    while let Ok(update) = rx.try_recv() {
+        first.push(update);
+    }
+    assert!(first.iter().any(|u| matches!(u, AiUpdate::Finished)));
+    assert!(!first.iter().any(|u| matches!(u, AiUpdate::RateLimited(_))));
+
+    // The second send has to wait until the first is a minute old
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let second = tokio::spawn(ai::stream_response(prompt(), tx));
+    let wait = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(update) = rx.recv().await {
+            if let AiUpdate::RateLimited(wait) = update {
+                return Some(wait);
+            }
+        }
+        None
+    })
+    .await
+    .expect("the second send reports its wait")
+    .expect("the second send is held back");
+    assert!(wait > Duration::from_secs(55), "{:?}", wait);
+    assert!(wait <= Duration::from_secs(60), "{:?}", wait);
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    second.abort();
+}
@@ -0,0 +1,29 @@
+use gemchat::config::ToolsConfig;
+use gemchat::tools::{self, Status};
+
+#[tokio::test]
+async fn failures_are_errors_whatever_their_message_says() {
+    let tools = ToolsConfig::default();
+    // Nothing listens on port 1, so the fetch fails with "Fetch failed: …"
+    let fetched =
+        tools::execute_tool("fetch_url", r#"{"url":"http://127.0.0.1:1/"}"#, &tools).await;
+    assert!(fetched.output.starts_with("Fetch failed"));
+    assert_eq!(fetched.status, Status::Error);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing.txt");
+    let args = serde_json::json!({ "path": path }).to_string();
+    let deleted = tools::execute_tool("delete_file", &args, &tools).await;
+    assert_eq!(deleted.status, Status::Error);
+}
+
+#[tokio::test]
+async fn successes_are_ok_whatever_their_output_says() {
+    let tools = ToolsConfig::default();
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Errors.md"), "E0063: missing field\n").unwrap();
+    let args = serde_json::json!({ "pattern": "E0063", "path": dir.path() }).to_string();
+    let found = tools::execute_tool("grep", &args, &tools).await;
+    assert_eq!(found.output, "Errors.md:1: E0063: missing field");
+    assert_eq!(found.status, Status::Ok);
+}
@@ -0,0 +1,56 @@
+use gemchat::watch;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+fn paths(names: &[&str]) -> BTreeSet<PathBuf> {
+    names.iter().map(PathBuf::from).collect()
+}
+
+#[tokio::test]
+async fn changes_are_batched_until_quiet_and_filtered() {
+    let rust = |path: &Path| path.extension().is_some_and(|ext| ext == "rs");
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for name in ["a.rs", "notes.txt", "b.rs", "a.rs"] {
+        tx.send(PathBuf::from(name)).unwrap();
+    }
+    let batch = watch::changes(&mut rx, DEBOUNCE, rust).await;
+    assert_eq!(batch, Some(paths(&["a.rs", "b.rs"])));
+
+    // A burst without matches runs nothing; the next match starts a batch of its own
+    tx.send(PathBuf::from("notes.txt")).unwrap();
+    let later = tokio::spawn(async move {
+        tokio::time::sleep(DEBOUNCE * 3).await;
+        tx.send(PathBuf::from("c.rs")).unwrap();
+        tx
+    });
+    let batch = watch::changes(&mut rx, DEBOUNCE, rust).await;
+    assert_eq!(batch, Some(paths(&["c.rs"])));
+
+    // The watch ends with the watcher
+    drop(later.await.unwrap());
+    assert_eq!(watch::changes(&mut rx, DEBOUNCE, rust).await, None);
+}
+
+#[test]
+fn the_prompt_carries_the_changed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("small.txt"), "hello\n").unwrap();
+    let log = format!("{}the end", "noise\n".repeat(20_000));
+    std::fs::write(dir.path().join("big.log"), &log).unwrap();
+    let names = ["big.log", "gone.rs", "small.txt"];
+    let changed: BTreeSet<PathBuf> = names.iter().map(|n| dir.path().join(n)).collect();
+    let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+
+    let text = watch::prompt("What broke?", &changed, &names);
+    assert!(text.starts_with("What broke?\n\nThese files just changed:\n"));
+    assert!(text.contains("\n--- small.txt ---\nhello\n"));
+    assert!(text.contains("\n--- gone.rs (removed) ---\n"));
+    // Of a long file, only its end is sent
+    assert!(text.contains("\n--- big.log (last part) ---\n"));
+    assert!(text.contains("noise\nthe end\n"));
+    assert!(text.len() < log.len());
+}